@@ -0,0 +1,175 @@
+//! `hashline bench`: timing hashing, rendering, and applying payloads.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use hashline::{
+    apply_hashline_edits_report, compute_line_hash, normalize_to_lf, split_preserve_last_empty, ApplyOptions,
+};
+use serde_json::json;
+
+use crate::edit::edit_flags_to_edits;
+use crate::storage::open_storage;
+use crate::{print_envelope, RunCtx};
+
+/// A deterministic file for `bench`: indented code-like lines of varied length, with the blank lines
+/// and closing braces that make real files' hashes collide.
+pub(crate) fn synthetic_bench_text(lines: usize) -> String {
+    let mut out = String::with_capacity(lines * 40);
+    let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+    for i in 0..lines {
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        match i % 12 {
+            0 => out.push_str(&format!("fn item_{}(input: &str) -> usize {{", i)),
+            10 => out.push('}'),
+            11 => {}
+            _ => {
+                let depth = 1 + (seed >> 60) as usize % 3;
+                let width = 10 + (seed >> 32) as usize % 70;
+                out.push_str(&"    ".repeat(depth));
+                out.push_str(&format!("let value_{} = input.len() + {};", i, seed % 1000));
+                out.push_str(&" // padding".repeat(width / 20));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Median and best of `iterations` timed runs, after one untimed warm-up run. `setup` runs untimed
+/// before each.
+fn time_runs<S, T>(
+    iterations: usize,
+    mut setup: impl FnMut() -> S,
+    mut run: impl FnMut(S) -> Result<T>,
+) -> Result<(std::time::Duration, std::time::Duration)> {
+    std::hint::black_box(run(setup())?);
+    let mut times = Vec::with_capacity(iterations);
+    for _ in 0..iterations.max(1) {
+        let input = setup();
+        let started = std::time::Instant::now();
+        std::hint::black_box(run(input)?);
+        times.push(started.elapsed());
+    }
+    times.sort();
+    Ok((times[times.len() / 2], times[0]))
+}
+
+fn per_second(count: usize, elapsed: std::time::Duration) -> f64 {
+    (count as f64 / elapsed.as_secs_f64().max(1e-9)).round()
+}
+
+/// "8.1M", "23.8k", "412"
+pub(crate) fn si_rate(rate: f64) -> String {
+    match rate {
+        r if r >= 1e6 => format!("{:.1}M", r / 1e6),
+        r if r >= 1e3 => format!("{:.1}k", r / 1e3),
+        r => format!("{:.0}", r),
+    }
+}
+
+/// Time hashing, rendering `read` output, and applying exact and relocated payloads against `text`.
+pub(crate) fn bench_text(text: &str, iterations: usize, edit_count: usize) -> Result<serde_json::Value> {
+    if text.is_empty() {
+        bail!("nothing to measure in an empty file");
+    }
+    let normalized = normalize_to_lf(text);
+    let lines: Vec<String> = split_preserve_last_empty(&normalized).into_iter().map(String::from).collect();
+    let hashes: Vec<String> = lines.iter().map(|l| compute_line_hash(l)).collect();
+    let ms = |d: std::time::Duration| (d.as_secs_f64() * 1e6).round() / 1e3;
+    let timing = |median, best, count, unit: &str| {
+        json!({ "median_ms": ms(median), "best_ms": ms(best), unit: per_second(count, median) })
+    };
+    let mut results = serde_json::Map::new();
+
+    let hash_all = |()| Ok(lines.iter().map(|l| compute_line_hash(l).len()).sum::<usize>());
+    let (median, best) = time_runs(iterations, || (), hash_all)?;
+    let mut hash = timing(median, best, lines.len(), "lines_per_sec");
+    let mib = normalized.len() as f64 / 1_048_576.0;
+    hash["mib_per_sec"] = json!((mib / median.as_secs_f64().max(1e-9) * 10.0).round() / 10.0);
+    results.insert("hash".into(), hash);
+
+    let (median, best) = time_runs(iterations, || (), |()| {
+        let normalized = normalize_to_lf(text);
+        let mut out = String::with_capacity(normalized.len() + lines.len() * 12);
+        for (i, line) in split_preserve_last_empty(&normalized).into_iter().enumerate() {
+            out.push_str(&format!("{}:{}|{}\n", i + 1, compute_line_hash(line), line));
+        }
+        Ok(out.len())
+    })?;
+    results.insert("read".into(), timing(median, best, lines.len(), "lines_per_sec"));
+
+    // Edits spread evenly over lines whose hash is unique, so an anchor one line off relocates.
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for h in &hashes {
+        *seen.entry(h).or_default() += 1;
+    }
+    let unique: Vec<usize> = (0..lines.len()).filter(|&i| seen[hashes[i].as_str()] == 1).collect();
+    let count = edit_count.min(unique.len());
+    let picked: Vec<usize> = (0..count).map(|k| unique[k * unique.len() / count]).collect();
+    // `relocate` points each anchor one line past its line; the last line has nowhere to be off to.
+    for (name, offset) in [("edit", 0), ("relocate", 1)] {
+        let set: Vec<String> = picked
+            .iter()
+            .filter(|&&i| i + offset < lines.len())
+            .map(|&i| format!("{}:{}=edited line {}", i + 1 + offset, hashes[i], i))
+            .collect();
+        if set.is_empty() {
+            continue;
+        }
+        let edits = edit_flags_to_edits(&set, &[], &[])?;
+        let (median, best) = time_runs(
+            iterations,
+            || lines.clone(),
+            |lines| Ok(apply_hashline_edits_report(lines, &edits, ApplyOptions::default())?.0.len()),
+        )?;
+        let mut timing = timing(median, best, edits.len(), "edits_per_sec");
+        timing["edits"] = json!(edits.len());
+        results.insert(name.into(), timing);
+    }
+    Ok(serde_json::Value::Object(results))
+}
+
+/// `hashline bench`: time the synthetic file or the given ones, and print the medians.
+pub(crate) fn bench_command(
+    paths: Vec<PathBuf>,
+    lines: usize,
+    iterations: usize,
+    edits: usize,
+    json: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let api_version = ctx.api_version;
+    let mut targets = Vec::new();
+    if paths.is_empty() {
+        targets.push(("synthetic".to_string(), synthetic_bench_text(lines)));
+    }
+    for path in &paths {
+        let storage = open_storage(path, ctx.remote.as_deref())?;
+        let content = storage.read().with_context(|| format!("bench: failed to read {}", path.display()))?.content;
+        targets.push((path.display().to_string(), content));
+    }
+    let mut reports = Vec::with_capacity(targets.len());
+    for (name, text) in &targets {
+        let results = bench_text(text, iterations, edits).with_context(|| format!("bench: {}", name))?;
+        if !json {
+            let line_count = split_preserve_last_empty(&normalize_to_lf(text)).len();
+            println!("{}: {} lines, {:.1} MiB", name, line_count, text.len() as f64 / 1_048_576.0);
+            for (step, r) in results.as_object().into_iter().flatten() {
+                let rate = match (r["lines_per_sec"].as_f64(), r["edits_per_sec"].as_f64()) {
+                    (Some(rate), _) => format!("{} lines/s", si_rate(rate)),
+                    (_, Some(rate)) => format!("{} edits/s ({} edits)", si_rate(rate), r["edits"]),
+                    _ => String::new(),
+                };
+                let mib = r.get("mib_per_sec").map(|m| format!(", {} MiB/s", m)).unwrap_or_default();
+                println!("  {:<9} {:>9.3} ms  {}{}", step, r["median_ms"].as_f64().unwrap_or(0.0), rate, mib);
+            }
+        }
+        reports.push(json!({ "target": name, "bytes": text.len(), "results": results }));
+    }
+    if json {
+        print_envelope(api_version, "bench", Ok(json!({ "iterations": iterations, "targets": reports })))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,295 @@
+//! Checks on the lines an edit changes: bracket balance, lint, redaction, and quickfix output.
+
+use anyhow::anyhow;
+use hashline::{env_parse, kv_parse, leading_whitespace, render_mismatch_error, MismatchError};
+
+use crate::cli::EmitFormat;
+use crate::Palette;
+
+pub(crate) struct QuickfixEntry {
+    pub(crate) line: usize,
+    pub(crate) severity: &'static str,
+    pub(crate) message: String,
+}
+
+/// Mask the value of `KEY=value` / `key: value` lines for `--redact` output.
+fn redact_line(line: &str) -> String {
+    match env_parse(line).or_else(|| kv_parse(line)) {
+        Some((_, value_start)) if value_start < line.len() => format!("{}***", &line[..value_start]),
+        _ => line.to_string(),
+    }
+}
+
+/// Rebuild a stale-anchor report from redacted lines so `--redact` errors don't echo values.
+pub(crate) fn redact_error(e: anyhow::Error, lines: &[String]) -> anyhow::Error {
+    match e.downcast::<MismatchError>() {
+        Ok(m) => {
+            let redacted: Vec<String> = lines.iter().map(|l| redact_line(l)).collect();
+            let report = render_mismatch_error(&redacted, &m.mismatches);
+            MismatchError { mismatches: m.mismatches, report }.into()
+        }
+        // Drop the "did you mean" excerpt of a failed `replace`.
+        Err(e) if e.to_string().starts_with("replace.old_text not found") => anyhow!("replace.old_text not found"),
+        Err(e) if e.to_string().starts_with("replace_in_range.old_text not found") => {
+            anyhow!("{}", e.to_string().split(';').next().unwrap_or_default())
+        }
+        // The guard report quotes file content; keep only its summary line.
+        Err(e) if e.to_string().contains("found different text than expected at their anchors") => {
+            anyhow!("{}", e.to_string().lines().next().unwrap_or_default())
+        }
+        Err(e) => e,
+    }
+}
+
+/// Style checks on the lines an edit inserted or modified. Indentation is compared with the
+/// dominant style (tabs vs spaces) of the lines the edit left alone.
+pub(crate) fn lint_changed_lines(
+    old_lines: &[String],
+    new_lines: &[String],
+    max_len: usize,
+    tab_width: usize,
+) -> Vec<String> {
+    let mut changed = Vec::new();
+    let (mut tabs, mut spaces) = (0usize, 0usize);
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, old_lines, new_lines) {
+        let (tag, _, new) = op.as_tag_tuple();
+        if tag == similar::DiffTag::Equal {
+            for line in &new_lines[new] {
+                match line.chars().next() {
+                    Some('\t') => tabs += 1,
+                    Some(' ') if !line.trim().is_empty() => spaces += 1,
+                    _ => {}
+                }
+            }
+        } else {
+            changed.extend(new);
+        }
+    }
+    let dominant = match tabs.cmp(&spaces) {
+        std::cmp::Ordering::Greater => Some(('\t', "tabs", "spaces")),
+        std::cmp::Ordering::Less => Some((' ', "spaces", "tabs")),
+        std::cmp::Ordering::Equal => None,
+    };
+
+    let mut warnings = Vec::new();
+    for i in changed {
+        let line = &new_lines[i];
+        let width: usize = line.chars().map(|c| if c == '\t' { tab_width } else { 1 }).sum();
+        if width > max_len {
+            warnings.push(format!("line {}: {} columns exceeds max line length {}", i + 1, width, max_len));
+        }
+        if line.ends_with([' ', '\t']) && !line.trim().is_empty() {
+            warnings.push(format!("line {}: trailing whitespace", i + 1));
+        }
+        if let Some((c, used, other)) = dominant {
+            if leading_whitespace(line).chars().any(|w| w != c) && !line.trim().is_empty() {
+                warnings.push(format!("line {}: indented with {}, but the surrounding code uses {}", i + 1, other, used));
+            }
+        }
+    }
+    warnings
+}
+
+/// Net open-minus-close counts for `()`, `[]`, `{}` plus parity of unescaped `"` and backticks.
+/// Brackets inside string, char, and backtick literals or after a line comment marker don't count.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Balance {
+    paren: i64,
+    bracket: i64,
+    brace: i64,
+    dquote: i64,
+    backtick: i64,
+}
+
+impl Balance {
+    fn of(lines: &[String], comment: Option<&str>) -> Self {
+        let mut b = Balance::default();
+        // The quote of the string or backtick literal we're inside; both may span lines.
+        let mut open: Option<char> = None;
+        for line in lines {
+            let chars: Vec<char> = line.chars().collect();
+            let mut i = 0;
+            while i < chars.len() {
+                let c = chars[i];
+                i += 1;
+                if let Some(quote) = open {
+                    if c == '\\' {
+                        i += 1;
+                    } else if c == quote {
+                        open = None;
+                        if quote == '"' { b.dquote += 1 } else { b.backtick += 1 }
+                    }
+                    continue;
+                }
+                if comment.is_some_and(|m| m.chars().enumerate().all(|(k, mc)| chars.get(i - 1 + k) == Some(&mc))) {
+                    break;
+                }
+                match c {
+                    '\\' => i += 1,
+                    '"' => {
+                        b.dquote += 1;
+                        open = Some('"');
+                    }
+                    '`' => {
+                        b.backtick += 1;
+                        open = Some('`');
+                    }
+                    // A char literal such as `'('`, `'\''` or `'\u{7b}'`; any other `'` (a lifetime,
+                    // an apostrophe) is left alone.
+                    '\'' if chars.get(i) == Some(&'\\') => {
+                        let close = chars.get(i + 2..).and_then(|rest| rest.iter().take(10).position(|&c| c == '\''));
+                        if let Some(len) = close {
+                            i += len + 3;
+                        }
+                    }
+                    '\'' if chars.get(i + 1) == Some(&'\'') => i += 2,
+                    '(' => b.paren += 1,
+                    ')' => b.paren -= 1,
+                    '[' => b.bracket += 1,
+                    ']' => b.bracket -= 1,
+                    '{' => b.brace += 1,
+                    '}' => b.brace -= 1,
+                    _ => {}
+                }
+            }
+        }
+        b
+    }
+}
+
+/// Compare the balance of every changed region before and after the edit. Pre-existing
+/// imbalance is ignored; only a net change across all hunks is reported, with the hunks involved.
+/// `comment` is the file's line comment marker, if known.
+pub(crate) fn check_edit_balance(old_lines: &[String], new_lines: &[String], comment: Option<&str>) -> Option<String> {
+    let mut total = Balance::default();
+    let mut hunks = Vec::new();
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, old_lines, new_lines) {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == similar::DiffTag::Equal {
+            continue;
+        }
+        let (before, after) = (Balance::of(&old_lines[old], comment), Balance::of(&new_lines[new.clone()], comment));
+        if before != after {
+            hunks.push(new.start + 1);
+        }
+        total.paren += after.paren - before.paren;
+        total.bracket += after.bracket - before.bracket;
+        total.brace += after.brace - before.brace;
+        total.dquote += after.dquote - before.dquote;
+        total.backtick += after.backtick - before.backtick;
+    }
+
+    let mut problems = Vec::new();
+    for (delta, open, close) in [(total.paren, '(', ')'), (total.bracket, '[', ']'), (total.brace, '{', '}')] {
+        match delta {
+            d if d > 0 => problems.push(format!("{} unclosed '{}'", d, open)),
+            d if d < 0 => problems.push(format!("{} unmatched '{}'", -d, close)),
+            _ => {}
+        }
+    }
+    for (delta, quote) in [(total.dquote, '"'), (total.backtick, '`')] {
+        if delta % 2 != 0 {
+            problems.push(format!("odd number of {} quotes", quote));
+        }
+    }
+    if problems.is_empty() {
+        return None;
+    }
+    let lines: Vec<String> = hunks.iter().map(|l| l.to_string()).collect();
+    Some(format!("edit changes bracket/quote balance ({}) near line(s) {}", problems.join(", "), lines.join(", ")))
+}
+
+/// One quickfix entry per changed hunk, positioned on the new file.
+pub(crate) fn changed_locations(old_lines: &[String], new_lines: &[String]) -> Vec<QuickfixEntry> {
+    similar::capture_diff_slices(similar::Algorithm::Myers, old_lines, new_lines)
+        .iter()
+        .filter_map(|op| {
+            let (tag, old, new) = op.as_tag_tuple();
+            let message = match tag {
+                similar::DiffTag::Equal => return None,
+                similar::DiffTag::Delete => format!("deleted {} line(s)", old.len()),
+                similar::DiffTag::Insert => format!("inserted {} line(s)", new.len()),
+                similar::DiffTag::Replace => format!("replaced {} line(s) with {}", old.len(), new.len()),
+            };
+            let line = (new.start + 1).min(new_lines.len().max(1));
+            Some(QuickfixEntry { line, severity: "info", message })
+        })
+        .collect()
+}
+
+pub(crate) fn print_quickfix(format: EmitFormat, path: &str, entries: &[QuickfixEntry]) {
+    for e in entries {
+        match format {
+            EmitFormat::Quickfix => println!("{}:{}:1: {}", path, e.line, e.message),
+            EmitFormat::Errorformat => println!("{}:{}:1: {}: {}", path, e.line, e.severity, e.message),
+        }
+    }
+}
+
+pub(crate) fn render_basic_diff(old_lines: &[String], new_lines: &[String], palette: Palette, redact: bool) {
+    // Very basic: show removed/added lines if lengths differ, else show line-by-line changes.
+    // With `redact`, lines are still compared raw but printed with their values masked.
+    let show = |l: &String| if redact { redact_line(l) } else { l.clone() };
+    let max = old_lines.len().max(new_lines.len());
+    for i in 0..max {
+        let a = old_lines.get(i);
+        let b = new_lines.get(i);
+        match (a, b) {
+            (Some(x), Some(y)) if x == y => {}
+            (Some(x), Some(y)) => {
+                eprintln!("{}", palette.red(&format!("-{}", show(x))));
+                eprintln!("{}", palette.green(&format!("+{}", show(y))));
+            }
+            (Some(x), None) => eprintln!("{}", palette.red(&format!("-{}", show(x)))),
+            (None, Some(y)) => eprintln!("{}", palette.green(&format!("+{}", show(y)))),
+            (None, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split('\n').map(String::from).collect()
+    }
+
+    fn balance(old: &str, new: &str) -> Option<String> {
+        check_edit_balance(&lines(old), &lines(new), Some("//"))
+    }
+
+    #[test]
+    fn balance_flags_an_unclosed_brace() {
+        let problem = balance("fn f() {\n}", "fn f() {\n    if x {\n}").expect("imbalance");
+        assert!(problem.contains("1 unclosed '{'"), "{problem}");
+    }
+
+    #[test]
+    fn balance_skips_brackets_in_string_literals() {
+        assert_eq!(balance("let a = 1;", r#"let a = "(";"#), None);
+        assert_eq!(balance("let a = 1;", r#"let a = "\"{[";"#), None);
+        assert_eq!(balance("let a = 1;", "let a = `${x}) ]`;"), None);
+    }
+
+    #[test]
+    fn balance_skips_brackets_in_char_literals() {
+        assert_eq!(balance("let c = 'a';", "let c = '{';"), None);
+        assert_eq!(balance("let c = 'a';", r"let c = ('\'', '\u{7b}', ']');"), None);
+        assert_eq!(balance("fn f() {}", "fn f<'a>(x: &'a str) -> &'a str { x }"), None);
+    }
+
+    #[test]
+    fn balance_skips_line_comments() {
+        assert_eq!(balance("let a = 1;", "let a = 1; // (see below"), None);
+        assert_eq!(balance("x", "x // }"), None);
+        let problem = balance("x", "x // ok\n(").expect("imbalance");
+        assert!(problem.contains("unclosed '('"), "{problem}");
+    }
+
+    #[test]
+    fn balance_flags_an_unterminated_string() {
+        let problem = balance("let a = 1;", r#"let a = "oops;"#).expect("imbalance");
+        assert!(problem.contains("odd number of \" quotes"), "{problem}");
+    }
+}
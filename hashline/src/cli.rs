@@ -0,0 +1,685 @@
+//! Command-line arguments.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use clap::{Parser, Subcommand, ValueEnum};
+use hashline::{parse_edits_payload, parse_edits_yaml, EditRequest};
+
+#[derive(Parser, Debug)]
+#[command(name = "hashline")]
+#[command(about = "Hashline read/edit tools (LINE:HASH anchors)")]
+pub(crate) struct Cli {
+    /// Request a specific machine-output API version (fails if unsupported)
+    #[arg(long, global = true)]
+    pub(crate) api_version: Option<u32>,
+    /// Log verbosity for structured events on stderr
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Warn)]
+    pub(crate) log_level: LogLevel,
+    /// Log line format
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub(crate) log_format: LogFormat,
+    /// Only report errors (overrides --log-level, silences status messages)
+    #[arg(short, long, global = true)]
+    pub(crate) quiet: bool,
+    /// Run read/edit against a remote host over ssh (e.g. user@host); paths are remote paths
+    #[arg(long, global = true, value_name = "USER@HOST")]
+    pub(crate) remote: Option<String>,
+    /// Colorize output (auto: only on terminals, and never when NO_COLOR is set)
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub(crate) color: ColorChoice,
+    /// Project config listing `plugin` op modules
+    #[arg(long, global = true, value_name = "PATH", default_value = ".hashline/config.toml")]
+    pub(crate) config: PathBuf,
+    #[command(subcommand)]
+    pub(crate) cmd: Command,
+}
+
+// Parsed once per process, so `Edit` being much larger than the other commands costs nothing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Read a text file and print hashline-prefixed output: LINE:HASH|content
+    Read(ReadArgs),
+
+    /// Apply hashline edits to a text file
+    Edit(EditArgs),
+
+    /// Edit a file in place with a sed script, through the same checks and atomic write as `edit`
+    Sed(SedArgs),
+
+    /// Apply a unified diff (`git diff`, `diff -u`), relocating hunks whose line numbers have drifted
+    Patch {
+        /// Patch file to apply (default: stdin)
+        patch: Option<PathBuf>,
+        /// Strip N leading components from patch paths (default: 1 for git's `a/` and `b/`, else 0)
+        #[arg(short = 'p', long = "strip", value_name = "N")]
+        strip: Option<usize>,
+        /// Print a unified diff-like preview (very basic) before applying
+        #[arg(long)]
+        preview: bool,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+        /// Skip the built-in JSON/YAML/TOML parse check of the edited files
+        #[arg(long)]
+        no_validate: bool,
+    },
+
+    /// Convert an edits payload (plus the current file) to a unified diff, or a unified diff to a payload
+    Convert {
+        /// What to produce
+        #[arg(long, value_enum)]
+        to: ConvertTarget,
+        /// `--to diff`: the file to edit (omit when the payload lists `files`); `--to edits`: the patch
+        /// (default: stdin)
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        /// Strip N leading components from patch paths (default: 1 for git's `a/` and `b/`, else 0)
+        #[arg(short = 'p', long = "strip", value_name = "N")]
+        strip: Option<usize>,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the hashline edits payload that turns OLD into NEW (anchored against OLD)
+    Diff {
+        /// Current version of the file; the payload's anchors refer to it
+        old: PathBuf,
+        /// Desired version of the file
+        new: PathBuf,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check that anchors, or every anchor an edits payload refers to, still match a file, without
+    /// editing it; exits non-zero on drift
+    Verify {
+        /// File to check; omit when the payload lists `files`
+        path: Option<PathBuf>,
+        /// LINE:HASH anchors to check (comma-separated, repeatable)
+        #[arg(long, value_delimiter = ',', value_name = "ANCHORS")]
+        anchors: Vec<String>,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Dry-run an edits payload: report which edits would apply or fail, without writing anything
+    Validate {
+        /// File or glob to check; omit when the payload lists `files`
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        /// Skip the built-in JSON/YAML/TOML parse check of the result, as `edit --no-validate` does
+        #[arg(long)]
+        no_validate: bool,
+        /// Include the unified diff each file would get
+        #[arg(long)]
+        diff: bool,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the concrete operations a payload resolves to (after relocation, in apply order)
+    Plan {
+        /// File or glob to plan against; omit when the payload lists `files`
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Describe what a payload would do in plain language, quoting the lines it touches
+    Explain {
+        /// File or glob to explain against; omit when the payload lists `files`
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print files' anchors, then print them again (or only the changed ones) whenever they change
+    Watch(WatchArgs),
+
+    /// Print lines matching a regex as `LINE:HASH|content`, ready to use as edit anchors
+    Search(SearchArgs),
+
+    /// Print the current `LINE:HASH` anchors of the given lines of a file
+    Anchor {
+        path: PathBuf,
+        /// Line numbers or ranges (`12`, `40-52`; comma-separated or repeated), 1-based
+        #[arg(required = true, value_delimiter = ',')]
+        lines: Vec<String>,
+        /// Also print each line's content, as `read` does
+        #[arg(long)]
+        content: bool,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the line hash of each argument, or of each stdin line when there are none
+    Hash {
+        /// Lines to hash (one hash per argument)
+        lines: Vec<String>,
+        /// Print `LINE:HASH` anchors, numbering the lines from 1
+        #[arg(short = 'n', long)]
+        number: bool,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Revert the most recent hashline edit of a file, as recorded in its journal (`.hashline/journal`)
+    Undo {
+        path: PathBuf,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Reapply the edit the last `undo` reverted
+    Redo {
+        path: PathBuf,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List a file's recorded edits, undos, and redos, or restore the state after one of them
+    History {
+        path: PathBuf,
+        /// Restore the file as it was after entry N (0: before the first entry); undo reverts this too
+        #[arg(long, value_name = "N")]
+        revert_to: Option<usize>,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Report a file's shape: line count, duplicate hashes (ambiguous anchors), longest line, line endings
+    Stats {
+        path: PathBuf,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Measure hashing, read, and edit throughput on a synthetic file or the given files
+    Bench {
+        /// Files to measure instead of the synthetic one
+        paths: Vec<PathBuf>,
+        /// Lines in the synthetic file
+        #[arg(long, default_value_t = 100_000)]
+        lines: usize,
+        /// Timed runs per measurement; the median is reported
+        #[arg(long, default_value_t = 5)]
+        iterations: usize,
+        /// `set_line` edits per payload in the edit measurements
+        #[arg(long, default_value_t = 100)]
+        edits: usize,
+        /// Print a JSON envelope describing the result
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Serve read, edit, verify, and search as MCP (Model Context Protocol) tools over stdio
+    Mcp,
+
+    /// Serve read/edit/verify/search as JSON-RPC 2.0 requests in a long-lived process
+    Serve(ServeArgs),
+
+    /// Print hashline commands as LLM tool/function definitions (JSON)
+    Tools {
+        #[arg(long, value_enum, default_value_t = ToolFormat::Openai)]
+        format: ToolFormat,
+    },
+
+    /// Print a shell completion script (`source <(hashline completions bash)`)
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print a system-prompt snippet describing anchors, edit ops, and error recovery
+    Prompt,
+
+    /// Print the JSON Schema of the edits payload accepted by `edit`
+    Schema {
+        /// Print a compact one-line-per-op summary instead
+        #[arg(long)]
+        summary: bool,
+    },
+
+    /// Summarize local usage data (see HASHLINE_METRICS)
+    Report {
+        /// Summarize the opt-in metrics file: outcomes, stale-anchor and relocation rates, timings
+        #[arg(long)]
+        metrics: bool,
+        /// Output as a JSON envelope
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// The arguments of `hashline read`.
+#[derive(clap::Args, Debug)]
+pub(crate) struct ReadArgs {
+    /// File path, a glob (`'src/**/*.rs'`), or an http(s):// URL when built with the `http` feature;
+    /// several are read in one go, with path-prefixed output
+    #[arg(required = true)]
+    pub(crate) paths: Vec<PathBuf>,
+    /// Start line (1-indexed)
+    #[arg(long)]
+    pub(crate) offset: Option<usize>,
+    /// Max lines
+    #[arg(long)]
+    pub(crate) limit: Option<usize>,
+    /// Read the last N lines instead of starting at `--offset`
+    #[arg(long, value_name = "N", conflicts_with = "offset")]
+    pub(crate) tail: Option<usize>,
+    /// Read the lines around a LINE:HASH anchor, following it if it moved
+    #[arg(long, value_name = "ANCHOR", conflicts_with_all = ["offset", "limit", "tail", "recursive"])]
+    pub(crate) around: Option<String>,
+    /// Read from a LINE:HASH anchor (inclusive), following it if it moved
+    #[arg(long, value_name = "ANCHOR", conflicts_with_all = ["offset", "limit", "tail", "around", "recursive"])]
+    pub(crate) from_anchor: Option<String>,
+    /// Read up to a LINE:HASH anchor (inclusive), following it if it moved
+    #[arg(long, value_name = "ANCHOR", conflicts_with_all = ["offset", "limit", "tail", "around", "recursive"])]
+    pub(crate) to_anchor: Option<String>,
+    /// Print only the lines matching a regular expression, as `search` does
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        conflicts_with_all = [
+            "offset", "limit", "tail", "around", "from_anchor", "to_anchor", "format", "anchors_only", "max_width",
+            "issue_token", "session",
+        ]
+    )]
+    pub(crate) grep: Option<String>,
+    /// Lines to show on each side of `--around` (default 10) or of each `--grep` match (default 0)
+    #[arg(short = 'C', long, value_name = "N")]
+    pub(crate) context: Option<usize>,
+    /// Print a JSON envelope instead of hashline text (same as `--format json`)
+    #[arg(long)]
+    pub(crate) json: bool,
+    /// Output format
+    #[arg(long, value_enum, conflicts_with = "json")]
+    pub(crate) format: Option<ReadFormat>,
+    /// Print only `LINE:HASH` anchors, without line content (a manifest for later verification)
+    #[arg(long)]
+    pub(crate) anchors_only: bool,
+    /// Cut shown content to N characters, marking it with `…` and the line's length in bytes
+    /// (the hash still covers the whole line)
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) max_width: Option<usize>,
+    /// Start with a `#file:<xxh64>:<lines>` record, for `edit --expect-file-hash`
+    #[arg(long, conflicts_with = "grep")]
+    pub(crate) file_header: bool,
+    /// Also issue an opaque session token (path, content digest, options) for `edit --token`
+    #[arg(long)]
+    pub(crate) issue_token: bool,
+    /// Also snapshot the file under `.hashline/sessions` and print the session id, for `edit --session`
+    #[arg(long)]
+    pub(crate) session: bool,
+    /// Read every text file under PATH (a directory), skipping what `.gitignore`/`.hashlineignore` exclude
+    #[arg(short, long)]
+    pub(crate) recursive: bool,
+}
+
+/// The arguments of `hashline edit`.
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct EditArgs {
+    /// File or glob (`'src/**/*.rs'`) to edit; omit when the payload lists `files`
+    pub(crate) path: Option<PathBuf>,
+    #[command(flatten)]
+    pub(crate) payload: PayloadArgs,
+    /// Replace an anchored line without JSON: `--set '40:ab3f=new text'` (repeatable; empty text deletes)
+    #[arg(long = "set", visible_alias = "set-line", value_name = "ANCHOR=TEXT")]
+    pub(crate) set: Vec<String>,
+    /// Insert a line after an anchored line: `--insert-after '40:ab3f=text'` (repeatable)
+    #[arg(long, value_name = "ANCHOR=TEXT")]
+    pub(crate) insert_after: Vec<String>,
+    /// Delete an anchored line or inclusive range: `--delete '40:ab3f..52:9c1e'` (repeatable)
+    #[arg(long, value_name = "ANCHOR[..ANCHOR]")]
+    pub(crate) delete: Vec<String>,
+    /// Print a unified diff-like preview (very basic) before applying
+    #[arg(long)]
+    pub(crate) preview: bool,
+    /// Write nothing: check every file, print the diffs, and report each file as ok/stale/conflicting
+    #[arg(long, conflicts_with_all = ["receipt", "ed_script", "emit"])]
+    pub(crate) dry_run: bool,
+    /// Print a JSON envelope describing the result
+    #[arg(long)]
+    pub(crate) json: bool,
+    /// Write a JSON receipt (payload hash, pre/post digests, per-edit summary) after applying
+    #[arg(long)]
+    pub(crate) receipt: Option<PathBuf>,
+    /// Write the applied change as a `diff -e` style ed script (`-` for stdout)
+    #[arg(long, value_name = "PATH")]
+    pub(crate) ed_script: Option<PathBuf>,
+    /// Refuse to edit unless the file still has this 8-hex fingerprint (as printed by read)
+    #[arg(long)]
+    pub(crate) fingerprint: Option<String>,
+    /// Refuse to edit unless the file still matches this `read --file-header` record (or its hash alone)
+    #[arg(long, value_name = "#file:HASH:LINES")]
+    pub(crate) expect_file_hash: Option<String>,
+    /// Refuse to edit unless path, options, and content still match this `read --issue-token` token
+    #[arg(long)]
+    pub(crate) token: Option<String>,
+    /// Refuse to edit unless the file is exactly what this `read --session` saw (names the lines that changed)
+    #[arg(long, value_name = "ID")]
+    pub(crate) session: Option<String>,
+    /// Skip edits whose result the file already shows, so re-running a payload is a no-op
+    #[arg(long)]
+    pub(crate) idempotent: bool,
+    /// Print changed (or conflicting) locations on stdout in an editor-friendly format
+    #[arg(long, value_enum, conflicts_with = "json")]
+    pub(crate) emit: Option<EmitFormat>,
+    /// Do not normalize inserted text using the target's .editorconfig
+    #[arg(long)]
+    pub(crate) no_editorconfig: bool,
+    /// Warn when the edit changes bracket/quote balance in the modified regions
+    #[arg(long)]
+    pub(crate) check_balance: bool,
+    /// Like --check-balance, but reject the edit (nothing is written)
+    #[arg(long)]
+    pub(crate) strict_syntax: bool,
+    /// Skip the built-in JSON/YAML/TOML parse check of the edited file
+    #[arg(long)]
+    pub(crate) no_validate: bool,
+    /// Mask `KEY=value` values in previews and error reports (for secrets files)
+    #[arg(long)]
+    pub(crate) redact: bool,
+    /// Warn about style problems on inserted/modified lines (length, trailing whitespace, indentation)
+    #[arg(long)]
+    pub(crate) lint: bool,
+    /// Line length limit for --lint (default: .editorconfig max_line_length, else 120)
+    #[arg(long, value_name = "N")]
+    pub(crate) max_line_length: Option<usize>,
+}
+
+/// The arguments of `hashline sed`.
+#[derive(clap::Args, Debug)]
+pub(crate) struct SedArgs {
+    pub(crate) path: PathBuf,
+    /// sed commands (repeatable): `s/re/repl/[gNi]`, `y/abc/xyz/`, `d`, `a text`, `i text`, `c text`,
+    /// optionally prefixed by `N`, `$`, `/re/`, or `A,B` addresses and `!`
+    #[arg(short = 'e', long = "expression", value_name = "SCRIPT", required_unless_present = "file")]
+    pub(crate) expressions: Vec<String>,
+    /// Read sed commands from a file
+    #[arg(short = 'f', long)]
+    pub(crate) file: Option<PathBuf>,
+    /// Use extended regular expressions (like `sed -E`)
+    #[arg(short = 'E', short_alias = 'r', long)]
+    pub(crate) extended: bool,
+    /// Accepted for compatibility; hashline sed always edits in place
+    #[arg(short = 'i', long, hide = true)]
+    pub(crate) in_place: bool,
+    /// Print a unified diff-like preview (very basic) before applying
+    #[arg(long)]
+    pub(crate) preview: bool,
+    /// Print a JSON envelope describing the result
+    #[arg(long)]
+    pub(crate) json: bool,
+    /// Skip the built-in JSON/YAML/TOML parse check of the edited file
+    #[arg(long)]
+    pub(crate) no_validate: bool,
+}
+
+/// The arguments of `hashline watch`.
+#[derive(clap::Args, Debug)]
+pub(crate) struct WatchArgs {
+    /// Files, globs, or directories (watched recursively, honouring .gitignore and .hashlineignore)
+    #[arg(required = true)]
+    pub(crate) paths: Vec<PathBuf>,
+    /// Only watch the files under a directory or glob that match this pattern (repeatable; `*.rs`
+    /// matches at any depth, `src/*.rs` from the directory)
+    #[arg(long, value_name = "PATTERN")]
+    pub(crate) glob: Vec<String>,
+    /// After the first listing, print only the lines each change inserted or modified
+    #[arg(long)]
+    pub(crate) changed: bool,
+    /// How often to check the files, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    pub(crate) interval: u64,
+    /// Report a change once the files have been quiet this long, in milliseconds, so a burst of
+    /// saves is one listing
+    #[arg(long, value_name = "MS", default_value_t = 200)]
+    pub(crate) debounce: u64,
+    /// Exit after this many changes (default: run until interrupted)
+    #[arg(long, value_name = "N")]
+    pub(crate) count: Option<usize>,
+    /// Print one JSON envelope per listing, one per line
+    #[arg(long)]
+    pub(crate) json: bool,
+}
+
+/// The arguments of `hashline search`.
+#[derive(clap::Args, Debug)]
+pub(crate) struct SearchArgs {
+    /// File, glob, or directory (searched recursively, honouring .gitignore and .hashlineignore)
+    pub(crate) path: PathBuf,
+    /// Regular expression to look for
+    pub(crate) pattern: String,
+    /// Match case-insensitively
+    #[arg(short = 'i', long)]
+    pub(crate) ignore_case: bool,
+    /// Lines of context to show before each match
+    #[arg(short = 'B', long, value_name = "N")]
+    pub(crate) before: Option<usize>,
+    /// Lines of context to show after each match
+    #[arg(short = 'A', long, value_name = "N")]
+    pub(crate) after: Option<usize>,
+    /// Lines of context to show before and after each match
+    #[arg(short = 'C', long, value_name = "N")]
+    pub(crate) context: Option<usize>,
+    /// Print a JSON envelope describing the result
+    #[arg(long)]
+    pub(crate) json: bool,
+}
+
+/// The arguments of `hashline serve`.
+#[derive(clap::Args, Debug)]
+pub(crate) struct ServeArgs {
+    /// Take newline-delimited requests on stdin and answer on stdout
+    #[arg(long, conflicts_with_all = ["http", "socket"])]
+    pub(crate) stdio: bool,
+    /// Listen on a unix socket for newline-delimited requests, caching documents across clients
+    #[arg(long, value_name = "PATH", conflicts_with = "http")]
+    pub(crate) socket: Option<PathBuf>,
+    /// Listen for HTTP requests (`GET /read`, `POST /edit`, ...) on this address, e.g. 127.0.0.1:8080; other than
+    /// loopback addresses, only with --token, --token-file or --tls-client-ca
+    #[arg(long, value_name = "ADDR")]
+    pub(crate) http: Option<String>,
+    /// Directory that HTTP request paths are relative to, and may not leave (default: current directory)
+    #[arg(long, value_name = "DIR", requires = "http")]
+    pub(crate) root: Option<PathBuf>,
+    /// Serve HTTPS with this PEM certificate chain (leaf first); plain HTTP requests are refused
+    #[arg(long, value_name = "PEM", requires_all = ["http", "tls_key"])]
+    pub(crate) tls_cert: Option<PathBuf>,
+    /// The PEM private key of --tls-cert
+    #[arg(long, value_name = "PEM", requires = "tls_cert")]
+    pub(crate) tls_key: Option<PathBuf>,
+    /// Require HTTPS clients to present a certificate issued by one of these PEM CA certificates
+    #[arg(long, value_name = "PEM", requires = "tls_cert")]
+    pub(crate) tls_client_ca: Option<PathBuf>,
+    /// Require `Authorization: Bearer TOKEN` on HTTP requests (repeatable; 16+ characters)
+    #[arg(long, value_name = "TOKEN", requires = "http")]
+    pub(crate) token: Vec<String>,
+    /// Read bearer tokens from a file: one per line, each optionally followed by a directory under --root
+    /// that confines its requests
+    #[arg(long, value_name = "PATH", requires = "http")]
+    pub(crate) token_file: Option<PathBuf>,
+    /// Clients (socket) or requests (HTTP) served at once; more are turned away as busy
+    #[arg(long, value_name = "N", default_value_t = 16, conflicts_with = "stdio",
+          value_parser = clap::value_parser!(u16).range(1..))]
+    pub(crate) max_connections: u16,
+    /// Requests each client may send per minute (HTTP: per token or address; socket: per connection)
+    #[arg(long, value_name = "N", conflicts_with = "stdio", value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) rate_limit: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum EmitFormat {
+    /// `path:line:col: message`, the default Vim/Neovim 'errorformat'
+    Quickfix,
+    /// `path:line:col: info|error: message`, for `set efm=%f:%l:%c:\ %t%*[^:]:\ %m`
+    Errorformat,
+}
+
+/// The edits payload flags of `edit`, `convert`, `validate`, `plan`, and `explain`.
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct PayloadArgs {
+    /// JSON edits payload (either a full object or just an array of edits)
+    #[arg(long, conflicts_with_all = ["edits_file", "edits_b64"])]
+    edits_json: Option<String>,
+    /// Read the edits payload from a file
+    #[arg(long, conflicts_with = "edits_b64")]
+    edits_file: Option<PathBuf>,
+    /// Base64-encoded edits payload (standard or URL-safe alphabet), for callers that can't quote JSON
+    #[arg(long, value_name = "BASE64")]
+    edits_b64: Option<String>,
+    /// Payload format (default: yaml for an `--edits-file` ending in .yaml/.yml, else json)
+    #[arg(long, value_enum)]
+    edits_format: Option<EditsFormat>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum EditsFormat {
+    /// JSON, or JSON5 when it isn't strict JSON
+    Json,
+    /// YAML (block scalars keep multi-line text readable)
+    Yaml,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ConvertTarget {
+    /// A unified diff of what the payload would change
+    Diff,
+    /// A hashline edits payload equivalent to the patch
+    Edits,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ReadFormat {
+    /// `LINE:HASH|content` lines
+    Text,
+    /// One JSON envelope
+    Json,
+    /// A `file` object, then one `line` object per line (JSON Lines), written as they're produced
+    Ndjson,
+    /// Tab-separated `line`, `hash`, `content` rows after a header row; `\\`, tab, CR, and LF escaped
+    Tsv,
+    /// Comma-separated `line`, `hash`, `content` rows after a header row, quoted as in RFC 4180
+    Csv,
+}
+
+impl ReadFormat {
+    pub(crate) fn is_json(self) -> bool {
+        matches!(self, ReadFormat::Json | ReadFormat::Ndjson)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ToolFormat {
+    Openai,
+    Anthropic,
+    Mcp,
+}
+
+impl PayloadArgs {
+    /// A JSON payload built in-process, for commands that hand their result to `edit`.
+    pub(crate) fn inline(payload: String) -> Self {
+        PayloadArgs { edits_json: Some(payload), ..PayloadArgs::default() }
+    }
+
+    pub(crate) fn given(&self) -> bool {
+        self.edits_json.is_some() || self.edits_file.is_some() || self.edits_b64.is_some()
+    }
+
+    /// Read the payload given by `--edits-json`, `--edits-file`, or `--edits-b64` and parse it in
+    /// the chosen format; a missing payload is an error.
+    pub(crate) fn load(self, command: &str) -> Result<(EditRequest, String)> {
+        self.try_load(command)?.ok_or_else(|| anyhow!("{}: provide --edits-json, --edits-file, or --edits-b64", command))
+    }
+
+    /// Like `load`, but `None` when no payload flag was given. The format defaults to YAML for a
+    /// `.yaml`/`.yml` file. Returns the request and the raw text.
+    pub(crate) fn try_load(self, command: &str) -> Result<Option<(EditRequest, String)>> {
+        let PayloadArgs { edits_json, edits_file, edits_b64, edits_format } = self;
+        let edits_format = edits_format.unwrap_or_else(|| {
+            let yaml_ext = edits_file.as_ref().and_then(|p| p.extension()).is_some_and(|e| e == "yaml" || e == "yml");
+            if yaml_ext {
+                EditsFormat::Yaml
+            } else {
+                EditsFormat::Json
+            }
+        });
+        let text = if let Some(p) = edits_file {
+            fs::read_to_string(&p).with_context(|| format!("{}: failed to read edits file {}", command, p.display()))?
+        } else if let Some(b64) = edits_b64 {
+            decode_b64_payload(&b64)?
+        } else if let Some(text) = edits_json {
+            text
+        } else {
+            return Ok(None);
+        };
+        let request = match edits_format {
+            EditsFormat::Yaml => parse_edits_yaml(&text),
+            EditsFormat::Json => parse_edits_payload(&text),
+        };
+        let format = if edits_format == EditsFormat::Yaml { "YAML" } else { "JSON" };
+        let request = request.with_context(|| format!("{}: failed to parse edits {}", command, format))?;
+        Ok(Some((request, text)))
+    }
+}
+
+/// Decode `--edits-b64`: whitespace (from line-wrapped encoders) and padding are ignored, and
+/// either alphabet works.
+pub(crate) fn decode_b64_payload(b64: &str) -> Result<String> {
+    let compact: String = b64.chars().filter(|c| !c.is_whitespace() && *c != '=').collect();
+    let bytes = if compact.contains(['+', '/']) {
+        base64::engine::general_purpose::STANDARD_NO_PAD.decode(&compact)
+    } else {
+        URL_SAFE_NO_PAD.decode(&compact)
+    }
+    .context("edit: --edits-b64 is not valid base64")?;
+    String::from_utf8(bytes).context("edit: --edits-b64 does not decode to UTF-8 text")
+}
@@ -0,0 +1,571 @@
+//! Unified diffs and ed scripts: rendering a change as one, and turning a patch into anchored edits.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use hashline::{
+    compute_line_hash, file_fingerprint, normalize_to_lf, split_preserve_last_empty, DeleteLines, FileEdits,
+    HashlineEdit, InsertAfter, ReplaceLines, SetLine,
+};
+use serde_json::json;
+
+use crate::cli::{EditArgs, PayloadArgs};
+use crate::edit::{edit_command, payload_jobs, stage_edit, EditOptions, Staged};
+use crate::storage::{open_storage, Loaded};
+use crate::{print_envelope, RunCtx};
+
+/// Render the change from `old` to `new` as a `diff -e` script: `a`/`c`/`d` commands, last hunk
+/// first, so line numbers refer to the original file. A lone `.` text line is written as `..` and
+/// fixed up with `s/.//`, as GNU diff does.
+pub(crate) fn render_ed_script(old: &[String], new: &[String]) -> String {
+    use similar::DiffOp;
+
+    let range = |start: usize, len: usize| {
+        if len == 1 {
+            format!("{}", start + 1)
+        } else {
+            format!("{},{}", start + 1, start + len)
+        }
+    };
+    let mut out = String::new();
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, old, new).into_iter().rev() {
+        let (text, header) = match op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Delete { old_index, old_len, .. } => (&new[..0], format!("{}d", range(old_index, old_len))),
+            DiffOp::Insert { old_index, new_index, new_len } => (&new[new_index..new_index + new_len], format!("{}a", old_index)),
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                (&new[new_index..new_index + new_len], format!("{}c", range(old_index, old_len)))
+            }
+        };
+        out.push_str(&header);
+        out.push('\n');
+        if header.ends_with('d') {
+            continue;
+        }
+        let mut open = true;
+        for line in text {
+            if !open {
+                out.push_str("a\n");
+                open = true;
+            }
+            if line == "." {
+                out.push_str("..\n.\ns/.//\n");
+                open = false;
+            } else {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        if open {
+            out.push_str(".\n");
+        }
+    }
+    out
+}
+
+/// One file's hunks from a unified diff.
+struct FilePatch {
+    path: String,
+    hunks: Vec<PatchHunk>,
+}
+
+struct PatchHunk {
+    /// 0-based index in the old file where the hunk claims to start
+    start: usize,
+    /// `(' ' | '-' | '+', text)` for each context, removed, and added line
+    lines: Vec<(char, String)>,
+}
+
+/// A git-style unified diff (`a/` and `b/` headers, 3 lines of context) from `old` to `new`;
+/// `eol` says whether each version ends with a newline. Empty when nothing changed.
+pub(crate) fn render_unified_diff(path: &str, old: &[String], new: &[String], eol: (bool, bool)) -> String {
+    let ops = similar::capture_diff_slices(similar::Algorithm::Myers, old, new);
+    let groups = similar::group_diff_ops(ops, 3);
+    if groups.is_empty() {
+        return String::new();
+    }
+    let range = |start: usize, len: usize| match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        n => format!("{},{}", start + 1, n),
+    };
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let line = |out: &mut String, prefix: char, text: &str, last: bool| {
+        out.push(prefix);
+        out.push_str(text);
+        out.push('\n');
+        if last {
+            out.push_str("\\ No newline at end of file\n");
+        }
+    };
+    for group in groups {
+        let (first, last) = (group[0].as_tag_tuple(), group[group.len() - 1].as_tag_tuple());
+        let (old_span, new_span) = (first.1.start..last.1.end, first.2.start..last.2.end);
+        let (old_range, new_range) = (range(old_span.start, old_span.len()), range(new_span.start, new_span.len()));
+        out.push_str(&format!("@@ -{} +{} @@\n", old_range, new_range));
+        for op in &group {
+            let (tag, o, n) = op.as_tag_tuple();
+            if tag == similar::DiffTag::Equal {
+                for i in o {
+                    line(&mut out, ' ', &old[i], i + 1 == old.len() && !eol.0);
+                }
+                continue;
+            }
+            for i in o {
+                line(&mut out, '-', &old[i], i + 1 == old.len() && !eol.0);
+            }
+            for i in n {
+                line(&mut out, '+', &new[i], i + 1 == new.len() && !eol.1);
+            }
+        }
+    }
+    out
+}
+
+/// The patch text from `path`, or from stdin when it is omitted.
+pub(crate) fn read_patch(path: Option<&PathBuf>) -> Result<String> {
+    match path {
+        Some(p) => fs::read_to_string(p).with_context(|| format!("patch: failed to read {}", p.display())),
+        None => {
+            let mut text = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut text).context("patch: failed to read stdin")?;
+            Ok(text)
+        }
+    }
+}
+
+/// Resolve a unified diff against the files it names: anchored edits per changed file, plus the
+/// fingerprint of the last file read (what `patch` pins a single-file edit to). Hunk relocation
+/// notes go to stderr unless `quiet`.
+pub(crate) fn patch_file_edits(
+    text: &str,
+    strip: Option<usize>,
+    quiet: bool,
+    ctx: &RunCtx,
+) -> Result<(Vec<FileEdits>, Option<String>)> {
+    let patches = parse_unified_diff(text, strip)?;
+    if patches.is_empty() {
+        bail!("patch: no file hunks found in the patch");
+    }
+    let mut files = Vec::with_capacity(patches.len());
+    let mut fingerprint = None;
+    for file in &patches {
+        let path = PathBuf::from(&file.path);
+        let storage = open_storage(&path, ctx.remote.as_deref())?;
+        let Loaded { content: raw, .. } =
+            storage.read().with_context(|| format!("patch: failed to read {}", path.display()))?;
+        let normalized = normalize_to_lf(&raw);
+        let old_lines: Vec<String> = split_preserve_last_empty(&normalized).into_iter().map(String::from).collect();
+        let (new_lines, notes) = apply_patch_hunks(&file.path, &old_lines, &file.hunks)?;
+        if !quiet && !ctx.quiet {
+            for note in notes {
+                eprintln!("{}", note);
+            }
+        }
+        let edits = line_diff_edits(&old_lines, &new_lines);
+        if !edits.is_empty() {
+            fingerprint = Some(file_fingerprint(&normalized));
+            files.push(FileEdits { path: file.path.clone(), edits });
+        }
+    }
+    Ok((files, fingerprint))
+}
+
+/// Parse the `---`/`+++`/`@@` sections of a unified diff; other lines (`diff --git`, `index`, commit
+/// messages) are skipped. Without `strip`, git's `a/` and `b/` prefixes are removed.
+fn parse_unified_diff(text: &str, strip: Option<usize>) -> Result<Vec<FilePatch>> {
+    let lines: Vec<&str> = text.lines().collect();
+    let header_path = |header: &str| -> String {
+        let path = header.split('\t').next().unwrap_or(header).trim_end();
+        path.strip_prefix('"').and_then(|p| p.strip_suffix('"')).unwrap_or(path).to_string()
+    };
+    let strip_path = |path: &str, n: usize| -> String {
+        path.splitn(n + 1, '/').nth(n).unwrap_or(path).to_string()
+    };
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (Some(old), Some(new)) = (lines[i].strip_prefix("--- "), lines.get(i + 1).and_then(|l| l.strip_prefix("+++ ")))
+        else {
+            i += 1;
+            continue;
+        };
+        let (old, new) = (header_path(old), header_path(new));
+        if old == "/dev/null" || new == "/dev/null" {
+            bail!("patch: creating or deleting files is not supported ({} -> {})", old, new);
+        }
+        let n = strip.unwrap_or(usize::from(old.starts_with("a/") && new.starts_with("b/")));
+        let path = strip_path(&new, n);
+        i += 2;
+        let mut hunks = Vec::new();
+        while let Some(header) = lines.get(i).and_then(|l| l.strip_prefix("@@ ")) {
+            let ranges: Vec<&str> = header.split_whitespace().take(2).collect();
+            let range = |r: Option<&&str>, sign: char| -> Option<(usize, usize)> {
+                let r = r?.strip_prefix(sign)?;
+                match r.split_once(',') {
+                    Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+                    None => Some((r.parse().ok()?, 1)),
+                }
+            };
+            let (Some((old_start, old_len)), Some((_, new_len))) = (range(ranges.first(), '-'), range(ranges.get(1), '+'))
+            else {
+                bail!("patch: malformed hunk header on line {}: {:?}", i + 1, lines[i]);
+            };
+            let header_line = i + 1;
+            i += 1;
+            let (mut old_seen, mut new_seen) = (0, 0);
+            let mut body = Vec::new();
+            while old_seen < old_len || new_seen < new_len {
+                let Some(&line) = lines.get(i) else {
+                    bail!("patch: hunk on line {} of the patch is truncated", header_line);
+                };
+                i += 1;
+                // Some tools drop the space of an empty context line.
+                let (kind, text) = match line.chars().next() {
+                    None => (' ', ""),
+                    Some(c @ (' ' | '-' | '+')) => (c, &line[1..]),
+                    Some('\\') => continue,
+                    Some(_) => bail!("patch: unexpected line {} in hunk: {:?}", i, line),
+                };
+                match kind {
+                    ' ' => (old_seen, new_seen) = (old_seen + 1, new_seen + 1),
+                    '-' => old_seen += 1,
+                    _ => new_seen += 1,
+                }
+                body.push((kind, text.to_string()));
+            }
+            // `\ No newline at end of file` may follow the last line of a hunk.
+            while lines.get(i).is_some_and(|l| l.starts_with('\\')) {
+                i += 1;
+            }
+            let start = if old_len == 0 { old_start } else { old_start.saturating_sub(1) };
+            hunks.push(PatchHunk { start, lines: body });
+        }
+        patches.push(FilePatch { path, hunks });
+    }
+    Ok(patches)
+}
+
+/// Apply `hunks` to `lines`, matching each hunk's context and removed lines by line hash. A hunk
+/// whose lines are not at the stated position goes to the nearest place they match, after the
+/// previous hunk. Context lines keep the file's own text. Returns the new lines plus a note for
+/// every hunk that moved.
+fn apply_patch_hunks(path: &str, lines: &[String], hunks: &[PatchHunk]) -> Result<(Vec<String>, Vec<String>)> {
+    let hashes: Vec<String> = lines.iter().map(|l| compute_line_hash(l)).collect();
+    let (mut out, mut notes) = (Vec::with_capacity(lines.len()), Vec::new());
+    let (mut cursor, mut offset) = (0usize, 0isize);
+    for (n, hunk) in hunks.iter().enumerate() {
+        let old: Vec<String> =
+            hunk.lines.iter().filter(|(kind, _)| *kind != '+').map(|(_, text)| compute_line_hash(text)).collect();
+        let expected = hunk.start.saturating_add_signed(offset).clamp(cursor, lines.len());
+        let at = (cursor..=lines.len().saturating_sub(old.len()))
+            .filter(|&at| hashes[at..at + old.len()] == old[..])
+            .min_by_key(|&at| at.abs_diff(expected))
+            .ok_or_else(|| {
+                anyhow!("patch: hunk {} of {} does not match the file (expected at line {})", n + 1, path, hunk.start + 1)
+            })?;
+        if at != hunk.start {
+            let moved = at as isize - hunk.start as isize;
+            notes.push(format!("patch: hunk {} of {} applied at line {} (offset {:+})", n + 1, path, at + 1, moved));
+        }
+        offset = at as isize - hunk.start as isize;
+        out.extend_from_slice(&lines[cursor..at]);
+        let mut src = at;
+        for (kind, text) in &hunk.lines {
+            match kind {
+                ' ' => {
+                    out.push(lines[src].clone());
+                    src += 1;
+                }
+                '-' => src += 1,
+                _ => out.push(text.clone()),
+            }
+        }
+        cursor = src;
+    }
+    out.extend_from_slice(&lines[cursor..]);
+    Ok((out, notes))
+}
+
+/// Express the change from `old` to `new` as anchored edits against `old`, one per hunk. Hunks that
+/// no op can express on their own (a lone blank result line, an insertion before line 1) borrow an
+/// adjacent unchanged line.
+pub(crate) fn line_diff_edits(old: &[String], new: &[String]) -> Vec<HashlineEdit> {
+    use similar::DiffOp;
+
+    // (old_start, old_end, new_start, new_end), half-open.
+    let mut hunks: Vec<(usize, usize, usize, usize)> = Vec::new();
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, old, new) {
+        let (os, oe, ns, ne) = match op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Delete { old_index, old_len, new_index } => (old_index, old_index + old_len, new_index, new_index),
+            DiffOp::Insert { old_index, new_index, new_len } => (old_index, old_index, new_index, new_index + new_len),
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                (old_index, old_index + old_len, new_index, new_index + new_len)
+            }
+        };
+        let blank_result = ne - ns == 1 && new[ns].is_empty();
+        let (os, oe, ns, ne) = if blank_result || (os == oe && os == 0) {
+            if os > 0 {
+                (os - 1, oe, ns - 1, ne)
+            } else if oe < old.len() {
+                (os, oe + 1, ns, ne + 1)
+            } else {
+                (os, oe, ns, ne)
+            }
+        } else {
+            (os, oe, ns, ne)
+        };
+        match hunks.last_mut() {
+            Some(prev) if os < prev.1 || (os == prev.1 && (os == oe || prev.0 == prev.1)) => {
+                prev.1 = prev.1.max(oe);
+                prev.3 = prev.3.max(ne);
+            }
+            _ => hunks.push((os, oe, ns, ne)),
+        }
+    }
+
+    let anchor = |idx: usize| format!("{}:{}", idx + 1, compute_line_hash(&old[idx]));
+    hunks
+        .into_iter()
+        .map(|(os, oe, ns, ne)| {
+            let text = new[ns..ne].join("\n");
+            if ns == ne {
+                let end_anchor = (oe - os > 1).then(|| anchor(oe - 1));
+                HashlineEdit::DeleteLines { delete_lines: DeleteLines { start_anchor: anchor(os), end_anchor, skip_if_applied: false } }
+            } else if os == oe {
+                HashlineEdit::InsertAfter {
+                    insert_after: InsertAfter { anchor: anchor(os - 1), text, auto_indent: false, expected_text: None, expected_prefix: None, skip_if_applied: false },
+                }
+            } else if oe - os == 1 {
+                HashlineEdit::SetLine {
+                    set_line: SetLine { anchor: anchor(os), new_text: text, auto_indent: false, expected_text: None, expected_prefix: None, skip_if_applied: false },
+                }
+            } else {
+                HashlineEdit::ReplaceLines {
+                    replace_lines: ReplaceLines {
+                        start_anchor: anchor(os),
+                        end_anchor: anchor(oe - 1),
+                        new_text: text,
+                        auto_indent: false,
+                        expected_text: None,
+                        expected_prefix: None,
+                        skip_if_applied: false,
+                    },
+                }
+            }
+        })
+        .collect()
+}
+
+/// `hashline patch`: turn the hunks into anchored edits and hand them to `edit`.
+pub(crate) fn patch_command(
+    patch: Option<PathBuf>,
+    strip: Option<usize>,
+    preview: bool,
+    json: bool,
+    no_validate: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let api_version = ctx.api_version;
+    let (mut files, fingerprint) = patch_file_edits(&read_patch(patch.as_ref())?, strip, json, ctx)?;
+    if files.is_empty() {
+        if json {
+            print_envelope(api_version, "patch", Ok(json!({ "files": [], "noop": true })))?;
+        } else if !ctx.quiet {
+            eprintln!("patch: no changes");
+        }
+        return Ok(());
+    }
+    // Hand the result to `edit`; a single file stays pinned to the content the hunks matched.
+    let (path, payload, fingerprint) = if files.len() == 1 {
+        let file = files.remove(0);
+        (Some(PathBuf::from(file.path)), json!({ "edits": file.edits }), fingerprint)
+    } else {
+        (None, json!({ "files": files }), None)
+    };
+    edit_command(
+        EditArgs {
+            path,
+            payload: PayloadArgs::inline(serde_json::to_string(&payload)?),
+            preview,
+            json,
+            fingerprint,
+            no_editorconfig: true,
+            no_validate,
+            ..EditArgs::default()
+        },
+        ctx,
+    )
+}
+
+/// `hashline convert --to edits`: print the payload equivalent to a patch.
+pub(crate) fn convert_to_edits(
+    path: Option<PathBuf>,
+    payload: PayloadArgs,
+    strip: Option<usize>,
+    json: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let api_version = ctx.api_version;
+    if payload.given() {
+        bail!("convert: --to edits reads a patch; the --edits-* flags only apply to --to diff");
+    }
+    let (files, _) = patch_file_edits(&read_patch(path.as_ref())?, strip, json, ctx)?;
+    let mut payload = match <[FileEdits; 1]>::try_from(files) {
+        Ok([file]) => json!({ "edits": file.edits }),
+        Err(files) => json!({ "files": files }),
+    };
+    compact_payload(&mut payload);
+    if json {
+        print_envelope(api_version, "convert", Ok(json!({ "payload": payload })))?;
+    } else {
+        println!("{}", serde_json::to_string_pretty(&payload)?);
+    }
+    Ok(())
+}
+
+/// `hashline convert --to diff`: print the unified diff a payload would make.
+pub(crate) fn convert_to_diff(path: Option<PathBuf>, payload: PayloadArgs, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let (request, _) = payload.load("convert")?;
+    let jobs = payload_jobs("convert", path, request.edits, request.files, ctx)?;
+    let opts = EditOptions::plain(false, json);
+    let mut diff = String::new();
+    for (path, edits) in &jobs {
+        let staged = stage_edit(path, edits, request.idempotency_key.clone(), &opts, ctx)
+            .with_context(|| format!("convert: edits to {} would fail", path.display()))?;
+        if let Staged::Write(staged) = staged {
+            let name = path.display().to_string();
+            let name = name.strip_prefix("./").unwrap_or(&name);
+            let eol = (staged.raw.ends_with('\n'), staged.out.ends_with('\n'));
+            diff.push_str(&render_unified_diff(name, &staged.old_lines, &staged.new_lines, eol));
+        }
+    }
+    if json {
+        print_envelope(api_version, "convert", Ok(json!({ "diff": diff })))?;
+    } else {
+        print!("{}", diff);
+    }
+    Ok(())
+}
+
+/// `hashline diff`: print the payload that turns `old` into `new`.
+pub(crate) fn diff_command(old: PathBuf, new: PathBuf, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let read_lines = |path: &PathBuf| -> Result<(Vec<String>, bool)> {
+        let storage = open_storage(path, ctx.remote.as_deref())?;
+        let Loaded { content, .. } =
+            storage.read().with_context(|| format!("diff: failed to read {}", path.display()))?;
+        let normalized = normalize_to_lf(&content);
+        let lines = split_preserve_last_empty(&normalized).into_iter().map(String::from).collect();
+        Ok((lines, normalized.ends_with('\n')))
+    };
+    let ((old_lines, old_eol), (new_lines, new_eol)) = (read_lines(&old)?, read_lines(&new)?);
+    let edits = line_diff_edits(&old_lines, &new_lines);
+    let mut warnings = Vec::new();
+    if old_eol != new_eol {
+        // `edit` keeps the target's final newline, so no payload can add or drop it.
+        let (a, b) = (old.display(), new.display());
+        warnings.push(format!("{} and {} differ in their final newline; the payload leaves it as is", a, b));
+    }
+    let mut payload = json!({ "edits": edits });
+    compact_payload(&mut payload);
+    if json {
+        let result = json!({
+            "path": old.display().to_string(),
+            "edits": edits.len(),
+            "payload": payload,
+            "warnings": warnings,
+        });
+        print_envelope(api_version, "diff", Ok(result))?;
+    } else {
+        println!("{}", serde_json::to_string_pretty(&payload)?);
+        for w in &warnings {
+            eprintln!("{} {}", ctx.stderr.yellow("warning:"), w);
+        }
+        if edits.is_empty() && !ctx.quiet {
+            eprintln!("no differences between {} and {}", old.display(), new.display());
+        }
+    }
+    Ok(())
+}
+
+/// Leave out op fields at their defaults (`null`, `false`) so a generated payload stays minimal.
+pub(crate) fn compact_payload(payload: &mut serde_json::Value) {
+    let compact = |edits: Option<&mut serde_json::Value>| {
+        for edit in edits.and_then(|e| e.as_array_mut()).into_iter().flatten() {
+            for body in edit.as_object_mut().into_iter().flat_map(|o| o.values_mut()) {
+                if let Some(fields) = body.as_object_mut() {
+                    fields.retain(|_, v| !v.is_null() && *v != json!(false));
+                }
+            }
+        }
+    };
+    compact(payload.get_mut("edits"));
+    for file in payload.get_mut("files").and_then(|f| f.as_array_mut()).into_iter().flatten() {
+        compact(file.get_mut("edits"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashline::{apply_hashline_edits, ApplyOptions};
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split('\n').map(String::from).collect()
+    }
+
+    #[test]
+    fn line_diff_edits_reproduce_the_new_text() {
+        let pairs = [
+            ("a\nb\nc", "a\nB\nc"),
+            ("a\nb\nc", "top\na\nb\nc"),
+            ("a\nb\nc", "a\nb\nc\nend"),
+            ("a\nb\nc\nd", "a\nd"),
+            ("a\nb\nc", "a\n\nc"),
+            ("a\nb\nc", "\na\nb\nc"),
+            ("a\nb\nc", "a\nb\nc\n"),
+            ("a\nb\nc", "c"),
+            ("a\nb\nc\nd\ne", "x\nb\ny\nd\nz\nw"),
+            ("a\n\n\nb", "a\nb"),
+            ("a", "b\nc"),
+        ];
+        for (old, new) in pairs {
+            let (old, new) = (lines(old), lines(new));
+            let edits = line_diff_edits(&old, &new);
+            let applied = apply_hashline_edits(old.clone(), &edits, ApplyOptions::default()).unwrap();
+            assert_eq!(applied, new, "{old:?} -> {new:?} via {}", serde_json::to_string(&edits).unwrap());
+        }
+        assert!(line_diff_edits(&lines("a\nb"), &lines("a\nb")).is_empty());
+    }
+
+    #[test]
+    fn a_patch_hunk_applies_where_its_lines_moved_to() {
+        let patch = "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n";
+        let patches = parse_unified_diff(patch, None).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, "f.txt");
+
+        let old = lines("a\nb\nc\nd\ne");
+        let (new, notes) = apply_patch_hunks("f.txt", &old, &patches[0].hunks).unwrap();
+        assert_eq!(new, lines("a\nb\nC\nd\ne"));
+        assert!(notes.is_empty());
+
+        let shifted = lines("new\nnew\na\nb\nc\nd\ne");
+        let (new, notes) = apply_patch_hunks("f.txt", &shifted, &patches[0].hunks).unwrap();
+        assert_eq!(new, lines("new\nnew\na\nb\nC\nd\ne"));
+        assert_eq!(notes, ["patch: hunk 1 of f.txt applied at line 4 (offset +2)"]);
+
+        let err = apply_patch_hunks("f.txt", &lines("a\nb\nX\nd"), &patches[0].hunks).unwrap_err();
+        assert_eq!(err.to_string(), "patch: hunk 1 of f.txt does not match the file (expected at line 2)");
+    }
+
+    #[test]
+    fn ed_scripts_list_hunks_last_first() {
+        let script = render_ed_script(&lines("a\nb\nc\nd"), &lines("a\nB\nc"));
+        assert_eq!(script, "4d\n2c\nB\n.\n");
+    }
+}
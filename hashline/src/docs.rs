@@ -0,0 +1,249 @@
+//! The payload described for agents: JSON Schema, per-op docs, the prompt, and tool manifests.
+
+use hashline::{EditRequest, HashlineEdit};
+use schemars::{JsonSchema, Schema};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::cli::ToolFormat;
+use crate::API_VERSION;
+
+/// Input of the `read` tool (and the server's `read` method).
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct ReadToolInput {
+    /// Path of the file to read
+    pub(crate) path: String,
+    /// Start line (1-indexed)
+    pub(crate) offset: Option<usize>,
+    /// Max lines
+    pub(crate) limit: Option<usize>,
+}
+
+/// Input of the `edit` tool.
+#[derive(Debug, JsonSchema)]
+#[allow(dead_code)]
+struct EditToolInput {
+    /// Path of the file to edit
+    path: String,
+    /// Hash-verified edits, applied atomically
+    edits: Vec<HashlineEdit>,
+    /// Retry-safe key: if the journal shows it already produced the current file, edit is a no-op
+    idempotency_key: Option<String>,
+}
+
+/// Input of the `verify` tool.
+#[derive(Debug, JsonSchema)]
+#[allow(dead_code)]
+struct VerifyToolInput {
+    /// Path of the file to check
+    path: String,
+    /// LINE:HASH anchors to check
+    anchors: Option<Vec<String>>,
+    /// Edits whose anchors to check (as for `edit`)
+    edits: Option<Vec<HashlineEdit>>,
+}
+
+/// Input of the `search` tool (and the server's `search` method).
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct SearchToolInput {
+    /// File, glob, or directory to search
+    pub(crate) path: String,
+    /// Regular expression to look for
+    pub(crate) pattern: String,
+    /// Match case-insensitively
+    #[serde(default)]
+    pub(crate) ignore_case: bool,
+    /// Lines of context around each match
+    #[serde(default)]
+    pub(crate) context: usize,
+}
+
+fn tool_input_schema<T: JsonSchema>() -> Schema {
+    let generator = schemars::generate::SchemaSettings::draft07()
+        .with(|s| {
+            s.inline_subschemas = true;
+            s.meta_schema = None;
+        })
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<T>();
+    schema.remove("title");
+    schema.remove("description");
+    schema
+}
+
+struct OpDoc {
+    name: String,
+    description: String,
+    fields: Vec<FieldDoc>,
+}
+
+struct FieldDoc {
+    name: String,
+    ty: String,
+    required: bool,
+    description: String,
+}
+
+/// Walk the derived `HashlineEdit` schema so docs always describe what the parser accepts.
+fn edit_op_docs() -> Vec<OpDoc> {
+    let schema = tool_input_schema::<HashlineEdit>();
+    let str_of = |v: &serde_json::Value, key: &str| v.get(key).and_then(|d| d.as_str()).unwrap_or("").to_string();
+    let mut ops = Vec::new();
+    for variant in schema.get("anyOf").and_then(|v| v.as_array()).into_iter().flatten() {
+        let Some(props) = variant.get("properties").and_then(|p| p.as_object()) else { continue };
+        for (op, body) in props {
+            let required: Vec<&str> = body
+                .get("required")
+                .and_then(|r| r.as_array())
+                .map(|r| r.iter().filter_map(|v| v.as_str()).collect())
+                .unwrap_or_default();
+            let fields = body
+                .get("properties")
+                .and_then(|p| p.as_object())
+                .map(|fields| {
+                    fields
+                        .iter()
+                        .map(|(name, f)| {
+                            let ty = match f.get("type") {
+                                Some(serde_json::Value::Array(tys)) => {
+                                    tys.iter().filter_map(|t| t.as_str()).find(|t| *t != "null").unwrap_or("").to_string()
+                                }
+                                Some(t) => t.as_str().unwrap_or("").to_string(),
+                                None => "any".to_string(),
+                            };
+                            FieldDoc {
+                                name: name.clone(),
+                                ty,
+                                required: required.contains(&name.as_str()),
+                                description: str_of(f, "description"),
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            ops.push(OpDoc { name: op.clone(), description: str_of(body, "description"), fields });
+        }
+    }
+    ops
+}
+
+pub(crate) fn render_prompt() -> String {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "# hashline edit protocol (api v{}, hashline {})\n\n",
+        API_VERSION,
+        env!("CARGO_PKG_VERSION")
+    ));
+    out.push_str(
+        "Read files with `hashline read <path>`. Each line is printed as `LINE:HASH|content`, where LINE is \
+         1-indexed and HASH is 4 hex chars derived from the line's non-whitespace content. \
+         `LINE:HASH` (e.g. `12:1a2b`) is an anchor. A `*` after the hash (`12:1a2b*`) means another line has \
+         the same hash, so that anchor only holds at its own line number.\n\n",
+    );
+    out.push_str(
+        "Edit with `hashline edit <path> --edits-json '<payload>'`, where the payload is a JSON array of edits \
+         (or `{\"edits\": [...]}`). Each edit is an object with exactly one of these keys:\n\n",
+    );
+    for op in edit_op_docs() {
+        out.push_str(&format!("- `{}`: {}\n", op.name, op.description));
+        for f in op.fields {
+            let req = if f.required { "required" } else { "optional" };
+            out.push_str(&format!("    - `{}` ({}, {}): {}\n", f.name, f.ty, req, f.description));
+        }
+    }
+    out.push_str(
+        "\nAll anchors are validated before anything is written; edits apply bottom-up so line numbers in one \
+         payload refer to the file as you read it.\n\n",
+    );
+    out.push_str(
+        "Error recovery: if an edit fails with \"line(s) have changed since last read\", nothing was written. \
+         The error lists each stale anchor with its current `LINE:HASH|content`; re-read the file (or use the \
+         listed replacements) and retry with updated anchors. Never guess hashes.\n",
+    );
+    out
+}
+
+/// Standalone draft-07 schema for an edits payload: a bare array of edits, or an `EditRequest`.
+pub(crate) fn payload_schema() -> serde_json::Value {
+    let mut root = schemars::generate::SchemaSettings::draft07().into_generator().into_root_schema_for::<EditRequest>();
+    let definitions = root.remove("definitions").unwrap_or_else(|| json!({}));
+    let edits = root.get("properties").and_then(|p| p.get("edits")).cloned().unwrap_or_else(|| json!({ "type": "array" }));
+    let request = json!({
+        "type": "object",
+        "properties": root.get("properties"),
+        "required": root.get("required").cloned().unwrap_or_else(|| json!([])),
+    });
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "hashline edits payload",
+        "description": "Edits for `hashline edit`, as an array, an object with `edits`, or an object with `files`. Each edit is `{\"<op>\": {...}}`; \
+                        `{\"type\": \"<op>\", ...}` is also accepted.",
+        "anyOf": [edits, request],
+        "definitions": definitions,
+    })
+}
+
+pub(crate) fn render_schema_summary() -> String {
+    let mut out = String::from(
+        "payload: [edit, ...] | {\"edits\": [edit, ...], \"idempotency_key\"?: string}\n\
+         payload: {\"files\": [{\"path\": string, \"edits\": [edit, ...]}, ...], \"idempotency_key\"?: string}\n\
+         edit:    {\"<op>\": {fields}} | {\"type\": \"<op>\", fields}\n\n",
+    );
+    let ops = edit_op_docs();
+    let width = ops.iter().map(|op| op.name.len()).max().unwrap_or(0);
+    for op in ops {
+        let fields: Vec<String> = op
+            .fields
+            .iter()
+            .map(|f| format!("{}{}: {}", f.name, if f.required { "" } else { "?" }, f.ty))
+            .collect();
+        out.push_str(&format!("{:width$}  {}\n", op.name, fields.join(", "), width = width));
+    }
+    out
+}
+
+pub(crate) fn render_tool_manifest(format: ToolFormat) -> serde_json::Value {
+    let tools = [
+        (
+            "hashline_read",
+            "Read a text file as LINE:HASH|content records. Use the LINE:HASH anchors in hashline_edit.",
+            tool_input_schema::<ReadToolInput>(),
+        ),
+        (
+            "hashline_edit",
+            "Apply hash-verified, line-addressed edits. Fails without writing if any anchor is stale; re-read and retry.",
+            tool_input_schema::<EditToolInput>(),
+        ),
+        (
+            "hashline_verify",
+            "Check LINE:HASH anchors (or a payload's anchors) against the current file without editing it.",
+            tool_input_schema::<VerifyToolInput>(),
+        ),
+        (
+            "hashline_search",
+            "Find lines matching a regex; matches come back as LINE:HASH anchors ready for hashline_edit.",
+            tool_input_schema::<SearchToolInput>(),
+        ),
+    ];
+
+    let defs: Vec<serde_json::Value> = tools
+        .into_iter()
+        .map(|(name, description, schema)| match format {
+            ToolFormat::Openai => json!({
+                "type": "function",
+                "function": { "name": name, "description": description, "parameters": schema },
+            }),
+            ToolFormat::Anthropic => json!({
+                "name": name, "description": description, "input_schema": schema,
+            }),
+            ToolFormat::Mcp => json!({
+                "name": name, "description": description, "inputSchema": schema,
+            }),
+        })
+        .collect();
+
+    match format {
+        ToolFormat::Mcp => json!({ "tools": defs }),
+        _ => json!(defs),
+    }
+}
@@ -0,0 +1,1312 @@
+//! Staging, checking, and writing edits to one or several files.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, bail, Context, Result};
+use hashline::{
+    apply_hashline_edits_report, detect_line_ending, file_fingerprint, front_matter_end, normalize_to_lf,
+    restore_line_endings, split_dst_lines, split_preserve_last_empty, ApplyOptions, ApplyReport, DeleteLines,
+    EditConflict, EditRequest, FileEdits, HashlineEdit, InsertAfter, MismatchError, PlanStep, SetLine,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::checks::{
+    changed_locations, check_edit_balance, lint_changed_lines, print_quickfix, redact_error, render_basic_diff,
+    QuickfixEntry,
+};
+use crate::cli::{EditArgs, EmitFormat, PayloadArgs};
+use crate::diff::{render_ed_script, render_unified_diff};
+use crate::editorconfig::EditorConfig;
+use crate::journal::{
+    append_journal_entry, journal_has_applied, journal_summary, store_journal_object, JournalAction, JournalEntry,
+};
+use crate::session::{check_session, check_session_token, session_snapshot, write_session};
+use crate::storage::{open_storage, Loaded, Storage};
+use crate::walk::glob_targets;
+use crate::{print_envelope, unix_timestamp, AlreadyReported, Envelope, RunCtx, API_VERSION};
+
+/// Flags of `edit` that apply to each file it touches.
+pub(crate) struct EditOptions {
+    pub(crate) preview: bool,
+    pub(crate) json: bool,
+    pub(crate) receipt: Option<PathBuf>,
+    pub(crate) ed_script: Option<PathBuf>,
+    pub(crate) fingerprint: Option<String>,
+    pub(crate) expect_file_hash: Option<String>,
+    pub(crate) token: Option<String>,
+    pub(crate) session: Option<String>,
+    pub(crate) idempotent: bool,
+    pub(crate) emit: Option<EmitFormat>,
+    pub(crate) no_editorconfig: bool,
+    pub(crate) check_balance: bool,
+    pub(crate) strict_syntax: bool,
+    pub(crate) no_validate: bool,
+    pub(crate) redact: bool,
+    pub(crate) lint: bool,
+    pub(crate) max_line_length: Option<usize>,
+}
+
+/// Outcome of checking one file's edits, before anything is written.
+pub(crate) enum Staged {
+    /// Nothing to write; the JSON result and message say why.
+    Noop { result: serde_json::Value, message: String },
+    Write(Box<StagedEdit>),
+}
+
+/// New content for one file that passed every check, plus what reporting needs after the write.
+pub(crate) struct StagedEdit {
+    path: PathBuf,
+    storage: Box<dyn Storage>,
+    target: String,
+    pub(crate) raw: String,
+    version: Option<String>,
+    pub(crate) out: String,
+    pub(crate) old_lines: Vec<String>,
+    pub(crate) new_lines: Vec<String>,
+    edits: Vec<HashlineEdit>,
+    idempotency_key: Option<String>,
+    warnings: Vec<String>,
+    pub(crate) apply_report: ApplyReport,
+}
+
+impl EditOptions {
+    /// Every optional check and output off: `validate`, `plan`, `convert`, and the server.
+    pub(crate) fn plain(no_validate: bool, json: bool) -> Self {
+        EditOptions {
+            preview: false,
+            json,
+            receipt: None,
+            ed_script: None,
+            fingerprint: None,
+            expect_file_hash: None,
+            token: None,
+            session: None,
+            idempotent: false,
+            emit: None,
+            no_editorconfig: false,
+            check_balance: false,
+            strict_syntax: false,
+            no_validate,
+            redact: false,
+            lint: false,
+            max_line_length: None,
+        }
+    }
+}
+
+/// Pair a positional path (possibly a glob) or a payload's `files` entries with their edits.
+pub(crate) fn payload_jobs(
+    command: &str,
+    path: Option<PathBuf>,
+    edits: Vec<HashlineEdit>,
+    files: Vec<FileEdits>,
+    ctx: &RunCtx,
+) -> Result<Vec<(PathBuf, Vec<HashlineEdit>)>> {
+    let files = match (path, files.is_empty()) {
+        (Some(path), true) => vec![FileEdits { path: path.display().to_string(), edits }],
+        (None, true) => bail!("{}: missing <PATH> (or list `files` in the payload)", command),
+        (Some(_), false) => bail!("{}: the payload lists `files`; omit the positional path", command),
+        (None, false) => files,
+    };
+    let mut jobs = Vec::with_capacity(files.len());
+    for file in files {
+        match glob_targets(std::path::Path::new(&file.path), ctx.remote.as_deref())? {
+            Some(paths) => jobs.extend(paths.into_iter().map(|p| (p, file.edits.clone()))),
+            None => jobs.push((PathBuf::from(file.path), file.edits)),
+        }
+    }
+    Ok(jobs)
+}
+
+/// Dry-run `jobs` and report each file as ok, stale (anchors no longer match), conflicting (edits in the
+/// payload overlap), or failed, optionally with the diff it would get; errors if any file would fail.
+pub(crate) fn validate_jobs(
+    command: &'static str,
+    jobs: &[(PathBuf, Vec<HashlineEdit>)],
+    idempotency_key: Option<String>,
+    single: bool,
+    diff: bool,
+    opts: &EditOptions,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let json = opts.json;
+    let mut counts = [0usize; 4];
+    let mut results = Vec::with_capacity(jobs.len());
+    for (path, edits) in jobs {
+        let storage = open_storage(path, ctx.remote.as_deref())?;
+        let content = storage
+            .read()
+            .with_context(|| format!("{}: failed to read {}", command, path.display()))?
+            .content;
+        let lines: Vec<String> = split_preserve_last_empty(&normalize_to_lf(&content))
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect();
+        let apply_opts = ApplyOptions {
+            idempotent: opts.idempotent,
+            plugins: Some(&ctx.plugins),
+            ..ApplyOptions::for_target(&storage.id())
+        };
+        let prefix = if single { String::new() } else { format!("{}: ", path.display()) };
+        // Each edit alone shows which ones fail; the whole payload also catches conflicts
+        // between edits and problems with the combined result.
+        let mut records = Vec::with_capacity(edits.len());
+        for (i, edit) in edits.iter().enumerate() {
+            let op = summarize_edit(edit)["op"].as_str().unwrap_or("edit").to_string();
+            let outcome = apply_hashline_edits_report(lines.clone(), std::slice::from_ref(edit), apply_opts);
+            let error = outcome.err().map(|e| format!("{:#}", e));
+            if !json {
+                let status = match &error {
+                    None => ctx.stdout.green("ok"),
+                    Some(e) => format!("{} {}", ctx.stdout.red("fails:"), e.trim_end()),
+                };
+                println!("{}edit {} ({}): {}", prefix, i, op, status);
+            }
+            records.push(json!({ "edit": i, "op": op, "ok": error.is_none(), "error": error }));
+        }
+        let mut file_diff = None;
+        let (status, whole) = match stage_edit(path, edits, idempotency_key.clone(), opts, ctx) {
+            Ok(Staged::Noop { message, .. }) => (0, Ok(message)),
+            Ok(Staged::Write(staged)) => {
+                if diff {
+                    let name = path.display().to_string();
+                    let name = name.strip_prefix("./").unwrap_or(&name);
+                    let eol = (staged.raw.ends_with('\n'), staged.out.ends_with('\n'));
+                    file_diff = Some(render_unified_diff(name, &staged.old_lines, &staged.new_lines, eol));
+                }
+                let summary = format!(
+                    "would update {} ({} -> {} lines)",
+                    path.display(),
+                    staged.old_lines.len(),
+                    staged.new_lines.len()
+                );
+                (0, Ok(summary))
+            }
+            Err(e) if e.downcast_ref::<MismatchError>().is_some() => (1, Err(format!("{:#}", e))),
+            Err(e) if e.downcast_ref::<EditConflict>().is_some() => (2, Err(format!("{:#}", e))),
+            Err(e) => (3, Err(format!("{:#}", e))),
+        };
+        counts[status] += 1;
+        let status = ["ok", "stale", "conflicting", "failed"][status];
+        if !json {
+            match &whole {
+                Ok(summary) => println!("{}payload: {} {}", prefix, ctx.stdout.green("ok,"), summary),
+                Err(e) => println!("{}payload: {} {}", prefix, ctx.stdout.red(&format!("{}:", status)), e.trim_end()),
+            }
+            if let Some(file_diff) = &file_diff {
+                print!("{}", file_diff);
+            }
+        }
+        results.push(json!({
+            "path": path.display().to_string(),
+            "ok": whole.is_ok(),
+            "status": status,
+            "summary": whole.as_ref().ok(),
+            "error": whole.as_ref().err(),
+            "edits": records,
+            "diff": file_diff,
+        }));
+    }
+
+    let [ok, stale, conflicting, failed] = counts;
+    let failing = stale + conflicting + failed;
+    let breakdown: Vec<String> = [(stale, "stale"), (conflicting, "conflicting"), (failed, "failed")]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, label)| format!("{} {}", n, label))
+        .collect();
+    let summary = format!("{} of {} file(s) would fail ({})", failing, results.len(), breakdown.join(", "));
+    if json {
+        let result = if single {
+            results.remove(0)
+        } else {
+            let files = results.len();
+            let counts = json!({ "files": files, "ok": ok, "stale": stale, "conflicting": conflicting, "failed": failed });
+            json!({ "files": results, "summary": counts })
+        };
+        let error = (failing > 0).then(|| summary.clone());
+        let env = Envelope { api_version: ctx.api_version, command, ok: failing == 0, result: Some(result), error };
+        println!("{}", serde_json::to_string(&env)?);
+        if failing > 0 {
+            return Err(AlreadyReported.into());
+        }
+    } else if failing > 0 {
+        bail!("{}: {}; nothing was written", command, summary);
+    }
+    Ok(())
+}
+
+/// Apply `edits` to one file and return its JSON result; in text mode, also report to stderr.
+pub(crate) fn edit_file(
+    path: &std::path::Path,
+    edits: Vec<HashlineEdit>,
+    idempotency_key: Option<String>,
+    edits_payload: &str,
+    opts: &EditOptions,
+    ctx: &RunCtx,
+) -> Result<serde_json::Value> {
+    match stage_edit(path, &edits, idempotency_key, opts, ctx)? {
+        Staged::Noop { result, message } => Ok(finish_noop(result, &message, opts, ctx)),
+        Staged::Write(staged) => {
+            write_staged(&staged)?;
+            finish_staged(*staged, edits_payload, opts, ctx)
+        }
+    }
+}
+
+/// Apply every file's edits or none: all files are checked and staged first (in parallel unless
+/// `--preview`/`--emit` output must stay in order), then written in order, and a failed write
+/// restores the files already written.
+pub(crate) fn edit_files(
+    jobs: Vec<(PathBuf, Vec<HashlineEdit>)>,
+    idempotency_key: Option<String>,
+    edits_payload: &str,
+    opts: &EditOptions,
+    ctx: &RunCtx,
+) -> Result<Vec<serde_json::Value>> {
+    let stage = |(path, edits): &(PathBuf, Vec<HashlineEdit>)| {
+        stage_edit(path, edits, idempotency_key.clone(), opts, ctx)
+            .map_err(|e| e.context(format!("edit: no files were changed ({} failed its checks)", path.display())))
+    };
+    let staged: Vec<Staged> = if opts.preview || opts.emit.is_some() {
+        jobs.iter().map(stage).collect::<Result<_>>()?
+    } else {
+        parallel_map(&jobs, stage).into_iter().collect::<Result<_>>()?
+    };
+    let mut seen = std::collections::HashSet::new();
+    for entry in &staged {
+        if let Staged::Write(edit) = entry {
+            if !seen.insert(edit.target.as_str()) {
+                bail!("edit: {} is listed more than once; put all of its edits in one entry", edit.path.display());
+            }
+        }
+    }
+    // Each file written so far, with the version its new content has, so a rollback is held to
+    // the same "unchanged since written" check as the write itself.
+    let mut written: Vec<(&StagedEdit, Option<String>)> = Vec::new();
+    for entry in &staged {
+        let Staged::Write(edit) = entry else { continue };
+        match write_staged(edit) {
+            Ok(version) => written.push((edit, version)),
+            Err(e) => {
+                let mut failed = Vec::new();
+                let mut restored = 0;
+                for (done, version) in written.iter().rev() {
+                    match done.storage.write(&done.raw, version.as_deref()) {
+                        Ok(_) => restored += 1,
+                        Err(err) => failed.push(format!("  {}: {:#}", done.path.display(), err)),
+                    }
+                }
+                if !failed.is_empty() {
+                    return Err(e.context(format!(
+                        "edit: rolled back {} file(s), but could not restore {}:\n{}",
+                        restored,
+                        failed.len(),
+                        failed.join("\n")
+                    )));
+                }
+                return Err(e.context(format!("edit: rolled back {} file(s); no files were changed", restored)));
+            }
+        }
+    }
+    staged
+        .into_iter()
+        .map(|entry| match entry {
+            Staged::Noop { result, message } => Ok(finish_noop(result, &message, opts, ctx)),
+            Staged::Write(edit) => finish_staged(*edit, edits_payload, opts, ctx),
+        })
+        .collect()
+}
+
+fn finish_noop(result: serde_json::Value, message: &str, opts: &EditOptions, ctx: &RunCtx) -> serde_json::Value {
+    if !opts.json && !ctx.quiet {
+        eprintln!("{}", message);
+    }
+    result
+}
+
+/// Map `f` over `items` on a scoped thread pool, keeping input order. The pool has one thread per
+/// CPU, or `HASHLINE_THREADS` if set.
+pub(crate) fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::env::var("HASHLINE_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = std::iter::repeat_with(|| None).take(items.len()).collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else { break };
+                        done.push((i, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (i, r) in worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e)) {
+                results[i] = Some(r);
+            }
+        }
+    });
+    results.into_iter().map(|r| r.expect("every item is mapped once")).collect()
+}
+
+/// Read `path`, apply `edits`, and run every check on the result without writing it.
+pub(crate) fn stage_edit(
+    path: &std::path::Path,
+    edits: &[HashlineEdit],
+    idempotency_key: Option<String>,
+    opts: &EditOptions,
+    ctx: &RunCtx,
+) -> Result<Staged> {
+    let api_version = ctx.api_version;
+    let storage = open_storage(path, ctx.remote.as_deref())?;
+    if !storage.writable() {
+        bail!("edit: {} is read-only", path.display());
+    }
+    let Loaded { content: raw, version } = storage
+        .read()
+        .with_context(|| format!("edit: failed to read {}", path.display()))?;
+    let target = storage.id();
+    let editorconfig = match storage.local_path() {
+        Some(p) if !opts.no_editorconfig => EditorConfig::for_file(p)?,
+        _ => EditorConfig::default(),
+    };
+    let mut line_ending = detect_line_ending(&raw);
+    if !raw.contains('\n') {
+        line_ending = editorconfig.end_of_line.unwrap_or(line_ending);
+    }
+    let had_final_newline = raw.ends_with('\n');
+    let normalized = normalize_to_lf(&raw);
+
+    let edits: Vec<HashlineEdit> = edits.iter().map(|e| editorconfig.normalize_edit(e.clone())).collect();
+
+    if let Some(key) = &idempotency_key {
+        if journal_has_applied(&target, key, &content_digest(&raw))? {
+            info!(path = %path.display(), idempotency_key = %key, "already applied; skipping");
+            return Ok(Staged::Noop {
+                result: json!({ "path": path.display().to_string(), "edits": edits.len(), "noop": true }),
+                message: format!("already applied {} (idempotency key {})", path.display(), key),
+            });
+        }
+    }
+
+    if let Some(expected) = &opts.fingerprint {
+        let actual = file_fingerprint(&normalized);
+        if !expected.trim().eq_ignore_ascii_case(&actual) {
+            bail!(
+                "file changed since last read (fingerprint {}, expected {}). Re-read the file and retry.",
+                actual,
+                expected.trim()
+            );
+        }
+    }
+    if let Some(expected) = &opts.expect_file_hash {
+        let expected = expected.trim();
+        let actual = file_hash_record(&normalized);
+        // A bare hash skips the line count; the full record must match exactly.
+        let matched = match expected.strip_prefix("#file:") {
+            Some(_) => expected.eq_ignore_ascii_case(&actual),
+            None => expected.eq_ignore_ascii_case(&content_digest(&normalized)),
+        };
+        if !matched {
+            bail!("file changed since last read ({}, expected {}). Re-read the file and retry.", actual, expected);
+        }
+    }
+
+    if let Some(token) = &opts.token {
+        check_session_token(token, &target, &raw, api_version)?;
+    }
+    if let Some(id) = &opts.session {
+        check_session(id, &target, &raw, api_version)?;
+    }
+
+    let old_lines: Vec<String> = split_preserve_last_empty(&normalized)
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    let apply_opts =
+        ApplyOptions { idempotent: opts.idempotent, plugins: Some(&ctx.plugins), ..ApplyOptions::for_target(&target) };
+    let (new_lines, apply_report) = match apply_hashline_edits_report(old_lines.clone(), &edits, apply_opts) {
+        Ok(applied) => applied,
+        Err(e) => {
+            if let (Some(format), Some(m)) = (opts.emit, e.downcast_ref::<MismatchError>()) {
+                let entries: Vec<QuickfixEntry> = m
+                    .mismatches
+                    .iter()
+                    .map(|(line, expected, actual)| QuickfixEntry {
+                        line: *line,
+                        severity: "error",
+                        message: format!("stale anchor {}:{} (line is now {}:{})", line, expected, line, actual),
+                    })
+                    .collect();
+                print_quickfix(format, &path.display().to_string(), &entries);
+            }
+            let e = if opts.redact { redact_error(e, &old_lines) } else { e };
+            return Err(e.context(format!("edit: failed to apply edits to {}", path.display())));
+        }
+    };
+
+    ctx.metrics.relocations.fetch_add(apply_report.relocations, Ordering::Relaxed);
+
+    if opts.preview {
+        eprintln!("--- {}\n+++ {}\n", path.display(), path.display());
+        render_basic_diff(&old_lines, &new_lines, ctx.stderr, opts.redact);
+    }
+
+    let not_run = apply_report.skipped.len() + apply_report.already_applied.len();
+    if not_run > 0 && not_run == edits.len() {
+        return Ok(Staged::Noop {
+            result: json!({
+                "path": path.display().to_string(),
+                "edits": edits.len(),
+                "skipped": apply_report.skipped,
+                "already_applied": apply_report.already_applied,
+                "noop": true,
+            }),
+            message: format!("no changes to {} (every edit skipped or already applied)", path.display()),
+        });
+    }
+    if old_lines == new_lines {
+        bail!("no changes made (edits produced identical content)");
+    }
+
+    let mut warnings: Vec<String> = Vec::new();
+    if opts.check_balance || opts.strict_syntax {
+        if let Some(problem) = check_edit_balance(&old_lines, &new_lines, apply_opts.line_comment) {
+            if opts.strict_syntax {
+                bail!("edit rejected by --strict-syntax: {}", problem);
+            }
+            warnings.push(problem);
+        }
+    }
+    if opts.lint {
+        let max = opts.max_line_length.or(editorconfig.max_line_length).unwrap_or(120);
+        warnings.extend(lint_changed_lines(&old_lines, &new_lines, max, editorconfig.tab_width.unwrap_or(4)));
+    }
+
+    // The final newline belongs to the file, so `insert_final_newline` only steps in when the edit
+    // rewrote the last line anyway.
+    let touches_last_line = old_lines.last() != new_lines.last();
+    let final_newline = match editorconfig.insert_final_newline {
+        Some(wanted) if touches_last_line => wanted,
+        _ => had_final_newline,
+    };
+    let mut out = new_lines.join("\n");
+    if final_newline {
+        out.push('\n');
+    }
+    out = restore_line_endings(&out, line_ending);
+
+    if !opts.no_validate {
+        if let Some(format) = StructuredFormat::for_target(&target) {
+            if let Err(e) = validate_structured(format, &out) {
+                let e = if opts.redact { anyhow!("parser error details hidden by --redact") } else { e };
+                return Err(e.context(format!(
+                    "edit: result is not valid {}; {} left unchanged (pass --no-validate to skip)",
+                    format.name(),
+                    path.display()
+                )));
+            }
+        }
+        if let Some(close) = front_matter_end(&new_lines).filter(|_| apply_opts.front_matter) {
+            if let Err(e) = validate_structured(StructuredFormat::Yaml, &new_lines[1..close].join("\n")) {
+                let e = if opts.redact { anyhow!("parser error details hidden by --redact") } else { e };
+                return Err(e.context(format!(
+                    "edit: front matter is not valid YAML; {} left unchanged (pass --no-validate to skip)",
+                    path.display()
+                )));
+            }
+        }
+    }
+
+    Ok(Staged::Write(Box::new(StagedEdit {
+        path: path.to_path_buf(),
+        storage,
+        target,
+        raw,
+        version,
+        out,
+        old_lines,
+        new_lines,
+        edits,
+        idempotency_key,
+        warnings,
+        apply_report,
+    })))
+}
+
+fn write_staged(staged: &StagedEdit) -> Result<Option<String>> {
+    staged
+        .storage
+        .write(&staged.out, staged.version.as_deref())
+        .with_context(|| format!("edit: failed to write {}", staged.path.display()))
+}
+
+/// Receipts, ed script, journal entry, and reporting for a file `write_staged` just wrote.
+fn finish_staged(staged: StagedEdit, edits_payload: &str, opts: &EditOptions, ctx: &RunCtx) -> Result<serde_json::Value> {
+    let StagedEdit {
+        path, target, raw, out, old_lines, new_lines, edits, idempotency_key, mut warnings, apply_report, ..
+    } = staged;
+    let path = path.as_path();
+    let post_digest = content_digest(&out);
+    let post_fingerprint = file_fingerprint(&out);
+    if let Some(receipt_path) = &opts.receipt {
+        let r = build_receipt(path, edits_payload, &raw, &post_digest, &edits);
+        fs::write(receipt_path, serde_json::to_string_pretty(&r)?)
+            .with_context(|| format!("edit: failed to write receipt {}", receipt_path.display()))?;
+    }
+    if let Some(ed_path) = &opts.ed_script {
+        let script = render_ed_script(&old_lines, &new_lines);
+        if ed_path.as_os_str() == "-" {
+            print!("{}", script);
+        } else {
+            fs::write(ed_path, script)
+                .with_context(|| format!("edit: failed to write ed script {}", ed_path.display()))?;
+        }
+    }
+    let keyed = idempotency_key.is_some();
+    // `--redact` keeps file content out of `.hashline`, so such edits can't be undone.
+    let stored = if opts.redact {
+        Ok(())
+    } else {
+        store_journal_object(&target, &raw).and_then(|()| store_journal_object(&target, &out))
+    };
+    let journaled = stored.and_then(|()| {
+        append_journal_entry(&JournalEntry {
+            timestamp: unix_timestamp(),
+            path: target.clone(),
+            pre_digest: content_digest(&raw),
+            post_digest,
+            idempotency_key,
+            summary: Some(journal_summary(&edits, old_lines.len(), new_lines.len())),
+            action: JournalAction::Edit,
+        })
+    });
+    // The session follows the file through its own edits, so the next edit can use it too.
+    if let Some(id) = &opts.session {
+        if let Err(e) = write_session(id, &session_snapshot(&target, &out, ctx.api_version)) {
+            warnings.push(format!("session {} not updated, so the next edit with it will fail: {:#}", id, e));
+        }
+    }
+    match journaled {
+        // Without the entry, a retry with the same key would apply the edit twice.
+        Err(e) if keyed => return Err(e),
+        Err(e) => warnings.push(format!("edit history not recorded, so `undo` can't revert this edit: {:#}", e)),
+        Ok(()) => {}
+    }
+    info!(
+        path = %path.display(),
+        edits = edits.len(),
+        lines_before = old_lines.len(),
+        lines_after = new_lines.len(),
+        "file updated"
+    );
+    if let Some(format) = opts.emit {
+        print_quickfix(format, &path.display().to_string(), &changed_locations(&old_lines, &new_lines));
+    }
+    let result = json!({
+        "path": path.display().to_string(),
+        "edits": edits.len(),
+        "lines_before": old_lines.len(),
+        "lines_after": new_lines.len(),
+        "fingerprint": post_fingerprint,
+        "file_hash": file_hash_record(&normalize_to_lf(&out)),
+        "warnings": warnings,
+        "replacements": apply_report
+            .replacements
+            .iter()
+            .map(|(edit, count)| json!({ "edit": edit, "count": count }))
+            .collect::<Vec<_>>(),
+        "skipped": apply_report.skipped,
+        "already_applied": apply_report.already_applied,
+    });
+    if !opts.json && !ctx.quiet {
+        for w in &warnings {
+            eprintln!("{} {}", ctx.stderr.yellow("warning:"), w);
+        }
+        for (edit, count) in &apply_report.replacements {
+            eprintln!("edit {}: replaced {} occurrence(s)", edit, count);
+        }
+        for edit in &apply_report.skipped {
+            eprintln!("edit {}: skipped (only_if did not hold)", edit);
+        }
+        for edit in &apply_report.already_applied {
+            eprintln!("edit {}: already applied", edit);
+        }
+        eprintln!("updated {} (fingerprint {})", path.display(), post_fingerprint);
+    }
+    Ok(result)
+}
+
+/// One sentence per edit, in payload order: its steps, with the lines they touch quoted from the file
+/// as read. Returns `(op, sentence, steps as JSON)` for each edit.
+pub(crate) fn explain_edits(
+    edits: &[HashlineEdit],
+    report: &ApplyReport,
+    lines: &[String],
+) -> Vec<(String, String, Vec<serde_json::Value>)> {
+    let quote = |n: usize| {
+        let text = lines.get(n - 1).map_or("", |l| l.trim());
+        match text.char_indices().nth(40) {
+            Some((cut, _)) => format!("`{}…`", &text[..cut]),
+            None => format!("`{}`", text),
+        }
+    };
+    edits
+        .iter()
+        .enumerate()
+        .map(|(n, edit)| {
+            let op = summarize_edit(edit)["op"].as_str().unwrap_or("edit").to_string();
+            // Steps are recorded bottom-up; an edit with several (a move) reads better top-down.
+            let steps: Vec<&PlanStep> = report.steps.iter().rev().filter(|s| s.edit == n).collect();
+            // Quote the lines right after the action names them: "replace line 42 (`fn foo()`) with 2 lines".
+            let parts: Vec<String> = steps
+                .iter()
+                .map(|step| {
+                    let Some((start, end)) = step.lines else { return step.action.clone() };
+                    let (target, quoted) = match step.action.starts_with("insert") || start == end {
+                        true => (format!("line {}", start), quote(start)),
+                        false => (format!("lines {}-{}", start, end), format!("{} … {}", quote(start), quote(end))),
+                    };
+                    // An insert names its line last ("insert 2 lines after line 7").
+                    let at = match step.action.starts_with("insert") {
+                        true => step.action.rfind(&target),
+                        false => step.action.find(&target),
+                    };
+                    match at {
+                        Some(at) => {
+                            let (head, tail) = step.action.split_at(at + target.len());
+                            format!("{} ({}){}", head, quoted, tail)
+                        }
+                        None => format!("{} ({})", step.action, quoted),
+                    }
+                })
+                .collect();
+            let sentence = if report.skipped.contains(&n) {
+                "skipped: its only_if condition does not hold".to_string()
+            } else if report.already_applied.contains(&n) {
+                "skipped: the file already shows its result".to_string()
+            } else if parts.is_empty() {
+                "changes nothing".to_string()
+            } else {
+                parts.join("; then ")
+            };
+            let steps = steps
+                .iter()
+                .map(|step| {
+                    let text: Option<&[String]> = step.lines.map(|(start, end)| &lines[start - 1..end.min(lines.len())]);
+                    json!({ "action": step.action, "lines": step.lines.map(|(start, end)| [start, end]), "text": text })
+                })
+                .collect();
+            (op, sentence, steps)
+        })
+        .collect()
+}
+
+/// Build an edit receipt. `checksum` covers every other field so accidental or careless tampering
+/// is detectable; it is not a cryptographic signature.
+fn build_receipt(
+    path: &std::path::Path,
+    payload: &str,
+    pre: &str,
+    post_digest: &str,
+    edits: &[HashlineEdit],
+) -> serde_json::Value {
+    let mut receipt = json!({
+        "api_version": API_VERSION,
+        "path": path.display().to_string(),
+        "timestamp": unix_timestamp(),
+        "payload_digest": content_digest(payload),
+        "pre_digest": content_digest(pre),
+        "post_digest": post_digest,
+        "edits": edits.iter().map(summarize_edit).collect::<Vec<_>>(),
+    });
+    let checksum = content_digest(&receipt.to_string());
+    receipt["checksum"] = json!(checksum);
+    receipt
+}
+
+pub(crate) fn summarize_edit(edit: &HashlineEdit) -> serde_json::Value {
+    match edit {
+        HashlineEdit::SetLine { set_line } => json!({
+            "op": "set_line",
+            "anchor": set_line.anchor,
+            "new_lines": split_dst_lines(&set_line.new_text).len(),
+        }),
+        HashlineEdit::ReplaceLines { replace_lines } => json!({
+            "op": "replace_lines",
+            "start_anchor": replace_lines.start_anchor,
+            "end_anchor": replace_lines.end_anchor,
+            "new_lines": split_dst_lines(&replace_lines.new_text).len(),
+        }),
+        HashlineEdit::InsertAfter { insert_after } => json!({
+            "op": "insert_after",
+            "anchor": insert_after.anchor,
+            "new_lines": split_dst_lines(&insert_after.text).len(),
+        }),
+        HashlineEdit::DeleteLines { delete_lines } => json!({
+            "op": "delete_lines",
+            "start_anchor": delete_lines.start_anchor,
+            "end_anchor": delete_lines.end_anchor,
+        }),
+        HashlineEdit::MoveLines { move_lines } => json!({
+            "op": "move_lines",
+            "start_anchor": move_lines.start_anchor,
+            "end_anchor": move_lines.end_anchor,
+            "after": move_lines.after,
+            "before": move_lines.before,
+        }),
+        HashlineEdit::CopyLines { copy_lines } => json!({
+            "op": "copy_lines",
+            "start_anchor": copy_lines.start_anchor,
+            "end_anchor": copy_lines.end_anchor,
+            "after": copy_lines.after,
+            "before": copy_lines.before,
+        }),
+        HashlineEdit::IndentRange { indent_range } => json!({
+            "op": "indent_range",
+            "start_anchor": indent_range.start_anchor,
+            "end_anchor": indent_range.end_anchor,
+            "width": indent_range.width,
+        }),
+        HashlineEdit::DedentRange { dedent_range } => json!({
+            "op": "dedent_range",
+            "start_anchor": dedent_range.start_anchor,
+            "end_anchor": dedent_range.end_anchor,
+            "width": dedent_range.width,
+        }),
+        HashlineEdit::CommentRange { comment_range } => json!({
+            "op": "comment_range",
+            "start_anchor": comment_range.start_anchor,
+            "end_anchor": comment_range.end_anchor,
+        }),
+        HashlineEdit::UncommentRange { uncomment_range } => json!({
+            "op": "uncomment_range",
+            "start_anchor": uncomment_range.start_anchor,
+            "end_anchor": uncomment_range.end_anchor,
+        }),
+        HashlineEdit::SortRange { sort_range } => json!({
+            "op": "sort_range",
+            "start_anchor": sort_range.start_anchor,
+            "end_anchor": sort_range.end_anchor,
+            "unique": sort_range.unique,
+        }),
+        HashlineEdit::DedupeRange { dedupe_range } => json!({
+            "op": "dedupe_range",
+            "start_anchor": dedupe_range.start_anchor,
+            "end_anchor": dedupe_range.end_anchor,
+            "all": dedupe_range.all,
+        }),
+        HashlineEdit::SwapLines { swap_lines } => json!({
+            "op": "swap_lines",
+            "start_anchor": swap_lines.start_anchor,
+            "end_anchor": swap_lines.end_anchor,
+            "with_start_anchor": swap_lines.with_start_anchor,
+            "with_end_anchor": swap_lines.with_end_anchor,
+        }),
+        HashlineEdit::Append { append } => json!({ "op": "append", "new_lines": split_dst_lines(&append.text).len() }),
+        HashlineEdit::Prepend { prepend } => json!({ "op": "prepend", "new_lines": split_dst_lines(&prepend.text).len() }),
+        HashlineEdit::ReplaceInRange { replace_in_range } => json!({
+            "op": "replace_in_range",
+            "start_anchor": replace_in_range.start_anchor,
+            "end_anchor": replace_in_range.end_anchor,
+            "all": replace_in_range.all.unwrap_or(false),
+        }),
+        HashlineEdit::RegexReplace { regex_replace } => json!({
+            "op": "regex_replace",
+            "pattern": regex_replace.pattern,
+            "max_replacements": regex_replace.max_replacements,
+        }),
+        HashlineEdit::Replace { replace } => json!({
+            "op": "replace",
+            "all": replace.all.unwrap_or(false),
+            "occurrence": replace.occurrence,
+            "max_count": replace.max_count,
+        }),
+        HashlineEdit::ReplaceSection { replace_section } => json!({
+            "op": "replace_section",
+            "section": replace_section.section,
+            "new_lines": split_dst_lines(&replace_section.new_text).len(),
+        }),
+        HashlineEdit::AppendToSection { append_to_section } => json!({
+            "op": "append_to_section",
+            "section": append_to_section.section,
+            "new_lines": split_dst_lines(&append_to_section.text).len(),
+        }),
+        HashlineEdit::CsvSetCell { csv_set_cell } => json!({ "op": "csv_set_cell", "row_anchor": csv_set_cell.row_anchor }),
+        HashlineEdit::CsvAddColumn { csv_add_column } => json!({ "op": "csv_add_column", "name": csv_add_column.name }),
+        HashlineEdit::KvSet { kv_set } => json!({ "op": "kv_set", "section": kv_set.section, "key": kv_set.key }),
+        HashlineEdit::EnvSet { env_set } => json!({ "op": "env_set", "key": env_set.key }),
+        HashlineEdit::Script { script } => json!({ "op": "script", "start_anchor": script.start_anchor }),
+        HashlineEdit::OnlyIf { only_if } => json!({ "op": "only_if", "edit": summarize_edit(&only_if.edit) }),
+        HashlineEdit::Plugin { plugin } => json!({ "op": "plugin", "name": plugin.op, "start_anchor": plugin.start_anchor }),
+        HashlineEdit::JsonSet { json_set } => json!({ "op": "json_set", "path": json_set.path }),
+        HashlineEdit::JsonDelete { json_delete } => json!({ "op": "json_delete", "path": json_delete.path }),
+        HashlineEdit::YamlSet { yaml_set } => json!({ "op": "yaml_set", "path": yaml_set.path }),
+        HashlineEdit::YamlDelete { yaml_delete } => json!({ "op": "yaml_delete", "path": yaml_delete.path }),
+        HashlineEdit::TomlSet { toml_set } => json!({ "op": "toml_set", "path": toml_set.path }),
+        HashlineEdit::TomlRemove { toml_remove } => json!({ "op": "toml_remove", "path": toml_remove.path }),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StructuredFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl StructuredFormat {
+    /// Pick a validator from the target's extension (archive members and object keys included).
+    fn for_target(target: &str) -> Option<Self> {
+        let ext = target.rsplit_once('.')?.1.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Json => "JSON",
+            Self::Yaml => "YAML",
+            Self::Toml => "TOML",
+        }
+    }
+}
+
+/// Full-file parse of the edited content. Runs before writing, so a failure leaves the file untouched.
+fn validate_structured(format: StructuredFormat, content: &str) -> Result<()> {
+    match format {
+        StructuredFormat::Json => {
+            serde_json::from_str::<serde_json::Value>(content)?;
+        }
+        StructuredFormat::Yaml => {
+            for doc in serde_yaml_ng::Deserializer::from_str(content) {
+                serde_yaml_ng::Value::deserialize(doc)?;
+            }
+        }
+        StructuredFormat::Toml => {
+            content.parse::<toml::Table>()?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn content_digest(s: &str) -> String {
+    format!("{:016x}", xxh64(s.as_bytes(), 0))
+}
+
+/// `#file:<xxh64>:<lines>` for LF-normalized content: what `read --file-header` starts with and
+/// `edit --expect-file-hash` checks.
+pub(crate) fn file_hash_record(normalized: &str) -> String {
+    format!("#file:{}:{}", content_digest(normalized), split_preserve_last_empty(normalized).len())
+}
+
+/// `hashline edit`: a payload or `--set`/`--insert-after`/`--delete` flags, applied to one file, a
+/// glob, or the files a payload lists.
+pub(crate) fn edit_command(args: EditArgs, ctx: &RunCtx) -> Result<()> {
+    let EditArgs {
+        path,
+        payload,
+        set,
+        insert_after,
+        delete,
+        preview,
+        dry_run,
+        json,
+        receipt,
+        ed_script,
+        fingerprint,
+        expect_file_hash,
+        token,
+        session,
+        idempotent,
+        emit,
+        no_editorconfig,
+        check_balance,
+        strict_syntax,
+        no_validate,
+        redact,
+        lint,
+        max_line_length,
+    } = args;
+    let api_version = ctx.api_version;
+    let flag_edits = edit_flags_to_edits(&set, &insert_after, &delete)?;
+    let payload = payload.try_load("edit")?;
+    if payload.is_none() && flag_edits.is_empty() {
+        bail!("provide --edits-json, --edits-file, --edits-b64, or --set/--insert-after/--delete");
+    }
+    let (mut request, edits_payload) = payload.unwrap_or_else(|| {
+        (
+            EditRequest { edits: Vec::new(), idempotency_key: None, files: Vec::new() },
+            json!({ "set": set, "insert_after": insert_after, "delete": delete }).to_string(),
+        )
+    });
+    let opts = EditOptions {
+        preview,
+        json,
+        receipt,
+        ed_script,
+        fingerprint,
+        expect_file_hash,
+        token,
+        session,
+        idempotent,
+        emit,
+        no_editorconfig,
+        check_balance,
+        strict_syntax,
+        no_validate,
+        redact,
+        lint,
+        max_line_length,
+    };
+    if dry_run {
+        if !request.files.is_empty() && !flag_edits.is_empty() {
+            bail!("edit: --set/--insert-after/--delete need a single file, not a `files` payload");
+        }
+        request.edits.extend(flag_edits);
+        let single = path.is_some() && request.files.is_empty();
+        let jobs = payload_jobs("edit", path, request.edits, request.files, ctx)?;
+        let opts = EditOptions { preview: false, ..opts };
+        return validate_jobs("edit", &jobs, request.idempotency_key, single && jobs.len() == 1, true, &opts, ctx);
+    }
+    // A glob target is a `files` payload with the same edits for every match.
+    let mut path = path;
+    let glob = match &path {
+        Some(p) if request.files.is_empty() => glob_targets(p, ctx.remote.as_deref())?,
+        _ => None,
+    };
+    if let Some(paths) = glob {
+        let edits = std::mem::take(&mut request.edits);
+        request.files =
+            paths.into_iter().map(|p| FileEdits { path: p.display().to_string(), edits: edits.clone() }).collect();
+        path = None;
+    }
+    if request.files.is_empty() {
+        let Some(path) = path else {
+            bail!("edit: missing <PATH> (or list `files` in the payload)");
+        };
+        request.edits.extend(flag_edits);
+        ctx.metrics.edits.fetch_add(request.edits.len(), Ordering::Relaxed);
+        let result = edit_file(&path, request.edits, request.idempotency_key, &edits_payload, &opts, ctx)?;
+        if json {
+            print_envelope(api_version, "edit", Ok(result))?;
+        }
+    } else {
+        if path.is_some() {
+            bail!("edit: the payload lists `files`; omit the positional path");
+        }
+        if !flag_edits.is_empty() {
+            bail!("edit: --set/--insert-after/--delete need a single file, not a glob or a `files` payload");
+        }
+        let pinned = opts.fingerprint.is_some()
+            || opts.expect_file_hash.is_some()
+            || opts.token.is_some()
+            || opts.session.is_some();
+        if opts.receipt.is_some() || opts.ed_script.is_some() || pinned {
+            bail!(
+                "edit: --receipt, --ed-script, --fingerprint, --expect-file-hash, --token, and --session apply to \
+                 single-file edits"
+            );
+        }
+        let mut jobs = Vec::with_capacity(request.files.len());
+        for file in request.files {
+            match glob_targets(std::path::Path::new(&file.path), ctx.remote.as_deref())? {
+                Some(paths) => jobs.extend(paths.into_iter().map(|p| (p, file.edits.clone()))),
+                None => jobs.push((PathBuf::from(file.path), file.edits)),
+            }
+        }
+        let edits: usize = jobs.iter().map(|(_, edits)| edits.len()).sum();
+        ctx.metrics.edits.fetch_add(edits, Ordering::Relaxed);
+        let results = edit_files(jobs, request.idempotency_key, &edits_payload, &opts, ctx)?;
+        let updated = results.iter().filter(|r| r.get("noop").is_none()).count();
+        let summary = json!({
+            "files": results.len(),
+            "updated": updated,
+            "unchanged": results.len() - updated,
+            "edits": edits,
+        });
+        if json {
+            print_envelope(api_version, "edit", Ok(json!({ "files": results, "summary": summary })))?;
+        } else if !ctx.quiet {
+            eprintln!("updated {} of {} file(s)", updated, results.len());
+        }
+    }
+    Ok(())
+}
+
+/// `hashline validate`: stage every file of a payload and report how each would fare.
+pub(crate) fn validate_command(
+    path: Option<PathBuf>,
+    payload: PayloadArgs,
+    no_validate: bool,
+    diff: bool,
+    json: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let (request, _) = payload.load("validate")?;
+    let single = path.is_some();
+    let jobs = payload_jobs("validate", path, request.edits, request.files, ctx)?;
+    let single = single && jobs.len() == 1;
+    let opts = EditOptions::plain(no_validate, json);
+    validate_jobs("validate", &jobs, request.idempotency_key, single, diff, &opts, ctx)?;
+    Ok(())
+}
+
+/// `hashline plan`: list the steps each file's edits resolve to, in apply order.
+pub(crate) fn plan_command(path: Option<PathBuf>, payload: PayloadArgs, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let (request, _) = payload.load("plan")?;
+    let single = path.is_some();
+    let jobs = payload_jobs("plan", path, request.edits, request.files, ctx)?;
+    let single = single && jobs.len() == 1;
+    let opts = EditOptions::plain(false, json);
+    let mut results = Vec::with_capacity(jobs.len());
+    for (path, edits) in &jobs {
+        let ops: Vec<String> = edits
+            .iter()
+            .map(|e| summarize_edit(e)["op"].as_str().unwrap_or("edit").to_string())
+            .collect();
+        if !json && !single {
+            println!("{}:", path.display());
+        }
+        let staged = stage_edit(path, edits, request.idempotency_key.clone(), &opts, ctx)
+            .with_context(|| format!("plan: edits to {} would fail", path.display()))?;
+        let (steps, report) = match staged {
+            Staged::Noop { message, .. } => {
+                if !json {
+                    println!("  {}", message);
+                }
+                results.push(json!({ "path": path.display().to_string(), "steps": [], "noop": true }));
+                continue;
+            }
+            Staged::Write(staged) => (staged.apply_report.steps.clone(), staged.apply_report),
+        };
+        let records: Vec<serde_json::Value> = steps
+            .iter()
+            .map(|step| json!({ "edit": step.edit, "op": ops[step.edit], "action": step.action }))
+            .collect();
+        if !json {
+            for (n, step) in steps.iter().enumerate() {
+                let edit = ctx.stdout.dim(&format!("edit {} ({}):", step.edit, ops[step.edit]));
+                println!("{:>3}. {} {}", n + 1, edit, step.action);
+            }
+            let notes = report.skipped.iter().map(|e| (e, "skipped, only_if does not hold"));
+            for (edit, note) in notes.chain(report.already_applied.iter().map(|e| (e, "already applied"))) {
+                println!("     {}", ctx.stdout.dim(&format!("edit {} ({}): {}", edit, ops[*edit], note)));
+            }
+        }
+        results.push(json!({
+            "path": path.display().to_string(),
+            "steps": records,
+            "skipped": report.skipped,
+            "already_applied": report.already_applied,
+        }));
+    }
+    if json {
+        let result = if single { results.remove(0) } else { json!({ "files": results }) };
+        print_envelope(api_version, "plan", Ok(result))?;
+    }
+    Ok(())
+}
+
+/// `hashline explain`: describe each edit of a payload in a sentence.
+pub(crate) fn explain_command(path: Option<PathBuf>, payload: PayloadArgs, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let (request, _) = payload.load("explain")?;
+    let single = path.is_some();
+    let jobs = payload_jobs("explain", path, request.edits, request.files, ctx)?;
+    let single = single && jobs.len() == 1;
+    let opts = EditOptions::plain(false, json);
+    let mut results = Vec::with_capacity(jobs.len());
+    for (path, edits) in &jobs {
+        if !json && !single {
+            println!("{}:", path.display());
+        }
+        let staged = stage_edit(path, edits, request.idempotency_key.clone(), &opts, ctx)
+            .with_context(|| format!("explain: edits to {} would fail", path.display()))?;
+        let staged = match staged {
+            Staged::Noop { message, .. } => {
+                if !json {
+                    println!("  {}", message);
+                }
+                results.push(json!({ "path": path.display().to_string(), "edits": [], "noop": true }));
+                continue;
+            }
+            Staged::Write(staged) => staged,
+        };
+        let explained = explain_edits(edits, &staged.apply_report, &staged.old_lines);
+        if !json {
+            for (n, (op, sentence, _)) in explained.iter().enumerate() {
+                println!("  {} {}", ctx.stdout.dim(&format!("edit {} ({}):", n, op)), sentence);
+            }
+            let (before, after) = (staged.old_lines.len(), staged.new_lines.len());
+            println!("  {}", ctx.stdout.dim(&format!("{} lines before, {} after", before, after)));
+        }
+        let records: Vec<serde_json::Value> = explained
+            .into_iter()
+            .enumerate()
+            .map(|(n, (op, description, steps))| {
+                json!({ "edit": n, "op": op, "description": description, "steps": steps })
+            })
+            .collect();
+        results.push(json!({
+            "path": path.display().to_string(),
+            "edits": records,
+            "lines_before": staged.old_lines.len(),
+            "lines_after": staged.new_lines.len(),
+        }));
+    }
+    if json {
+        let result = if single { results.remove(0) } else { json!({ "files": results }) };
+        print_envelope(api_version, "explain", Ok(result))?;
+    }
+    Ok(())
+}
+
+/// Compile `edit --set/--insert-after/--delete` flags into the same ops a JSON payload would use.
+pub(crate) fn edit_flags_to_edits(set: &[String], insert_after: &[String], delete: &[String]) -> Result<Vec<HashlineEdit>> {
+    fn split_assignment<'a>(flag: &str, arg: &'a str) -> Result<(&'a str, &'a str)> {
+        match arg.split_once('=') {
+            Some((anchor, text)) if !anchor.trim().is_empty() => Ok((anchor.trim(), text)),
+            _ => bail!("--{} expects ANCHOR=TEXT (e.g. '40:ab3f=new text'), got {:?}", flag, arg),
+        }
+    }
+
+    let mut edits = Vec::new();
+    for arg in set {
+        let (anchor, text) = split_assignment("set", arg)?;
+        edits.push(HashlineEdit::SetLine {
+            set_line: SetLine {
+                anchor: anchor.to_string(),
+                new_text: text.to_string(),
+                auto_indent: false,
+                expected_text: None,
+                expected_prefix: None,
+                skip_if_applied: false,
+            },
+        });
+    }
+    for arg in insert_after {
+        let (anchor, text) = split_assignment("insert-after", arg)?;
+        edits.push(HashlineEdit::InsertAfter {
+            insert_after: InsertAfter {
+                anchor: anchor.to_string(),
+                text: text.to_string(),
+                auto_indent: false,
+                expected_text: None,
+                expected_prefix: None,
+                skip_if_applied: false,
+            },
+        });
+    }
+    for arg in delete {
+        let (start, end) = match arg.split_once("..") {
+            Some((start, end)) => (start, Some(end.trim().to_string())),
+            None => (arg.as_str(), None),
+        };
+        edits.push(HashlineEdit::DeleteLines {
+            delete_lines: DeleteLines { start_anchor: start.trim().to_string(), end_anchor: end, skip_if_applied: false },
+        });
+    }
+    Ok(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashline::{parse_edits_payload, Anchor};
+
+    /// A scratch directory holding `files`, each as (name, content).
+    fn scratch(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hashline-edit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    fn set_line(line: usize, old: &str, new: &str) -> Vec<HashlineEdit> {
+        let anchor = Anchor::for_line(line, old);
+        let payload = format!(r#"[{{"set_line": {{"anchor": "{}", "new_text": "{}"}}}}]"#, anchor, new);
+        parse_edits_payload(&payload).unwrap().edits
+    }
+
+    #[test]
+    fn a_stale_anchor_in_any_file_leaves_every_file_untouched() {
+        let dir = scratch("stale", &[("a.txt", "one\ntwo\n"), ("b.txt", "three\nfour\n")]);
+        let jobs = vec![
+            (dir.join("a.txt"), set_line(1, "one", "ONE")),
+            (dir.join("b.txt"), set_line(2, "two", "FOUR")),
+        ];
+        let ctx = RunCtx::for_tests();
+        let err = edit_files(jobs, None, "", &EditOptions::plain(true, true), &ctx).unwrap_err();
+        assert!(format!("{err:#}").contains("no files were changed"), "unexpected error: {err:#}");
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "one\ntwo\n");
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "three\nfour\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_write_rolls_back_the_files_already_written() {
+        let dir = scratch("rollback", &[("a.txt", "one\ntwo\n"), ("b.txt", "three\nfour\n")]);
+        // `write_atomically` creates this temp file with `create_new`, so the write of b.txt fails.
+        fs::write(dir.join(format!(".b.txt.{}.hashline.tmp", std::process::id())), "").unwrap();
+        let jobs = vec![
+            (dir.join("a.txt"), set_line(1, "one", "ONE")),
+            (dir.join("b.txt"), set_line(2, "four", "FOUR")),
+        ];
+        let ctx = RunCtx::for_tests();
+        let err = edit_files(jobs, None, "", &EditOptions::plain(true, true), &ctx).unwrap_err();
+        assert!(format!("{err:#}").starts_with("edit: rolled back 1 file(s); no files were changed"), "{err:#}");
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "one\ntwo\n");
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "three\nfour\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_idempotency_key_skips_a_repeat_and_refuses_a_changed_file() {
+        let dir = scratch("idempotency", &[("a.txt", "one\ntwo\n")]);
+        let path = dir.join("a.txt");
+        let ctx = RunCtx::for_tests();
+        let opts = EditOptions::plain(true, true);
+        let key = Some("k1".to_string());
+        let first = edit_file(&path, set_line(1, "one", "ONE"), key.clone(), "", &opts, &ctx).unwrap();
+        assert!(first.get("noop").is_none(), "{first}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ONE\ntwo\n");
+
+        let again = edit_file(&path, set_line(1, "one", "ONE"), key.clone(), "", &opts, &ctx).unwrap();
+        assert_eq!(again["noop"], true);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ONE\ntwo\n");
+
+        fs::write(&path, "ONE\nTWO\n").unwrap();
+        let err = edit_file(&path, set_line(1, "one", "ONE"), key, "", &opts, &ctx).unwrap_err();
+        assert!(err.to_string().contains("idempotency key k1 was already applied"), "{err:#}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! `.editorconfig` lookup, and normalizing inserted text to it.
+
+use std::collections::HashMap;
+use std::fs;
+
+use anyhow::Result;
+use hashline::HashlineEdit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IndentStyle {
+    Tab,
+    Space,
+}
+
+/// The subset of EditorConfig properties hashline applies to edits.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EditorConfig {
+    indent_style: Option<IndentStyle>,
+    indent_size: Option<usize>,
+    pub(crate) tab_width: Option<usize>,
+    pub(crate) end_of_line: Option<&'static str>,
+    pub(crate) insert_final_newline: Option<bool>,
+    pub(crate) max_line_length: Option<usize>,
+}
+
+impl EditorConfig {
+    /// Resolve properties for `file` from `.editorconfig` files in its directory and ancestors,
+    /// stopping at one with `root = true`. Nearer files and later sections win.
+    pub(crate) fn for_file(file: &std::path::Path) -> Result<Self> {
+        let abs = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let mut configs = Vec::new();
+        let mut dir = abs.parent();
+        while let Some(d) = dir {
+            let candidate = d.join(".editorconfig");
+            if let Ok(raw) = fs::read_to_string(&candidate) {
+                let is_root = parse_editorconfig(&raw).0;
+                configs.push((d.to_path_buf(), raw));
+                if is_root {
+                    break;
+                }
+            }
+            dir = d.parent();
+        }
+
+        let mut props: HashMap<String, String> = HashMap::new();
+        for (dir, raw) in configs.iter().rev() {
+            let rel = abs.strip_prefix(dir).unwrap_or(&abs).to_string_lossy().replace('\\', "/");
+            for (pattern, section) in parse_editorconfig(raw).1 {
+                if rel_glob_matches(&pattern, &rel) {
+                    props.extend(section);
+                }
+            }
+        }
+
+        let size = |key: &str| props.get(key).and_then(|v| v.parse::<usize>().ok()).filter(|n| *n > 0);
+        let tab_width = size("tab_width");
+        let indent_size = match props.get("indent_size").map(String::as_str) {
+            Some("tab") => tab_width,
+            _ => size("indent_size"),
+        };
+        Ok(EditorConfig {
+            indent_style: match props.get("indent_style").map(String::as_str) {
+                Some("tab") => Some(IndentStyle::Tab),
+                Some("space") => Some(IndentStyle::Space),
+                _ => None,
+            },
+            indent_size,
+            tab_width: tab_width.or(indent_size),
+            end_of_line: match props.get("end_of_line").map(String::as_str) {
+                Some("lf") => Some("\n"),
+                Some("crlf") => Some("\r\n"),
+                _ => None,
+            },
+            insert_final_newline: match props.get("insert_final_newline").map(String::as_str) {
+                Some("true") => Some(true),
+                Some("false") => Some(false),
+                _ => None,
+            },
+            max_line_length: size("max_line_length"),
+        })
+    }
+
+    /// Re-indent the leading whitespace of every line in `text` to the configured style.
+    fn normalize_text(&self, text: &str) -> String {
+        let Some(style) = self.indent_style else {
+            return text.to_string();
+        };
+        let tab_width = self.tab_width.unwrap_or(4);
+        let indent_size = self.indent_size.unwrap_or(tab_width);
+        text.split('\n')
+            .map(|line| {
+                let body = line.trim_start_matches([' ', '\t']);
+                let ws = &line[..line.len() - body.len()];
+                let width = ws.chars().fold(0, |w, c| if c == '\t' { w + tab_width - w % tab_width } else { w + 1 });
+                let indent = match style {
+                    IndentStyle::Space => " ".repeat(width),
+                    IndentStyle::Tab => format!("{}{}", "\t".repeat(width / indent_size), " ".repeat(width % indent_size)),
+                };
+                format!("{}{}", indent, body)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub(crate) fn normalize_edit(&self, edit: HashlineEdit) -> HashlineEdit {
+        match edit {
+            HashlineEdit::SetLine { mut set_line } => {
+                set_line.new_text = self.normalize_text(&set_line.new_text);
+                HashlineEdit::SetLine { set_line }
+            }
+            HashlineEdit::ReplaceLines { mut replace_lines } => {
+                replace_lines.new_text = self.normalize_text(&replace_lines.new_text);
+                HashlineEdit::ReplaceLines { replace_lines }
+            }
+            HashlineEdit::InsertAfter { mut insert_after } => {
+                insert_after.text = self.normalize_text(&insert_after.text);
+                HashlineEdit::InsertAfter { insert_after }
+            }
+            HashlineEdit::ReplaceSection { mut replace_section } => {
+                replace_section.new_text = self.normalize_text(&replace_section.new_text);
+                HashlineEdit::ReplaceSection { replace_section }
+            }
+            HashlineEdit::Append { mut append } => {
+                append.text = self.normalize_text(&append.text);
+                HashlineEdit::Append { append }
+            }
+            HashlineEdit::Prepend { mut prepend } => {
+                prepend.text = self.normalize_text(&prepend.text);
+                HashlineEdit::Prepend { prepend }
+            }
+            HashlineEdit::AppendToSection { mut append_to_section } => {
+                append_to_section.text = self.normalize_text(&append_to_section.text);
+                HashlineEdit::AppendToSection { append_to_section }
+            }
+            HashlineEdit::OnlyIf { mut only_if } => {
+                only_if.edit = self.normalize_edit(only_if.edit);
+                HashlineEdit::OnlyIf { only_if }
+            }
+            other => other,
+        }
+    }
+}
+
+type EditorConfigSection = (String, Vec<(String, String)>);
+
+/// Parse an `.editorconfig` file into (root flag, [(glob, properties)]). Keys and values are
+/// lowercased as the spec requires.
+fn parse_editorconfig(raw: &str) -> (bool, Vec<EditorConfigSection>) {
+    let mut root = false;
+    let mut sections: Vec<EditorConfigSection> = Vec::new();
+    for line in raw.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((glob.to_string(), Vec::new()));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let (key, value) = (key.trim().to_ascii_lowercase(), value.trim().to_ascii_lowercase());
+        match sections.last_mut() {
+            Some((_, props)) => props.push((key, value)),
+            None if key == "root" => root = value == "true",
+            None => {}
+        }
+    }
+    (root, sections)
+}
+
+/// EditorConfig-style matching of a `/`-separated relative path (also used by `watch --glob`):
+/// globs without `/` match the file name at any depth; others are anchored at the directory the
+/// path is relative to.
+pub(crate) fn rel_glob_matches(pattern: &str, rel_path: &str) -> bool {
+    let pattern = pattern.strip_prefix('/').map(str::to_string).unwrap_or_else(|| {
+        if pattern.contains('/') {
+            pattern.to_string()
+        } else {
+            format!("**/{}", pattern)
+        }
+    });
+    expand_braces(&pattern).iter().any(|p| glob_match(p.as_bytes(), rel_path.as_bytes()))
+}
+
+/// Expand `{a,b}` alternatives (nested allowed) into separate patterns.
+pub(crate) fn expand_braces(pattern: &str) -> Vec<String> {
+    let bytes = pattern.as_bytes();
+    let Some(open) = pattern.find('{') else {
+        return vec![pattern.to_string()];
+    };
+    let mut depth = 0;
+    let mut close = None;
+    let mut splits = Vec::new();
+    for (i, b) in bytes.iter().enumerate().skip(open) {
+        match b {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            b',' if depth == 1 => splits.push(i),
+            _ => {}
+        }
+    }
+    let Some(close) = close else {
+        return vec![pattern.to_string()];
+    };
+    let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+    let mut bounds = vec![open];
+    bounds.extend(splits);
+    bounds.push(close);
+    bounds
+        .windows(2)
+        .flat_map(|w| expand_braces(&format!("{}{}{}", prefix, &pattern[w[0] + 1..w[1]], suffix)))
+        .collect()
+}
+
+/// Glob match where `*` and `?` stay within a path segment, `**` spans segments, and `[...]`
+/// (optionally negated with `!`) matches one character.
+pub(crate) fn glob_match(p: &[u8], t: &[u8]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some(b'*') if p.get(1) == Some(&b'*') => {
+            let rest = &p[2..];
+            let rest_no_slash = rest.strip_prefix(b"/").unwrap_or(rest);
+            glob_match(rest_no_slash, t) || (0..=t.len()).any(|i| glob_match(rest, &t[i..]))
+        }
+        Some(b'*') => (0..=t.len())
+            .take_while(|&i| i == 0 || t[i - 1] != b'/')
+            .any(|i| glob_match(&p[1..], &t[i..])),
+        Some(b'?') => !t.is_empty() && t[0] != b'/' && glob_match(&p[1..], &t[1..]),
+        Some(b'[') => {
+            let Some(end) = p.iter().skip(1).position(|&b| b == b']').map(|i| i + 1) else {
+                return t.first() == Some(&b'[') && glob_match(&p[1..], &t[1..]);
+            };
+            let Some(&c) = t.first() else { return false };
+            let (negate, class) = match p[1..end].strip_prefix(b"!") {
+                Some(rest) => (true, rest),
+                None => (false, &p[1..end]),
+            };
+            let mut hit = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    hit |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    hit |= class[i] == c;
+                    i += 1;
+                }
+            }
+            hit != negate && c != b'/' && glob_match(&p[end + 1..], &t[1..])
+        }
+        Some(&c) => t.first() == Some(&c) && glob_match(&p[1..], &t[1..]),
+    }
+}
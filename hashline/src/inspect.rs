@@ -0,0 +1,259 @@
+//! `verify`, `anchor`, `stats`, and `hash`: reporting on a file's anchors and lines without editing it.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use hashline::{
+    compute_line_hash, edit_anchors, normalize_to_lf, parse_line_ref, split_preserve_last_empty, HashlineEdit,
+    HASH_SCHEME,
+};
+use serde_json::json;
+
+use crate::cli::PayloadArgs;
+use crate::read::verify_records;
+use crate::storage::{open_storage, Loaded};
+use crate::{print_envelope, AlreadyReported, Envelope, RunCtx};
+
+/// `hashline verify`: check anchors, given directly or taken from a payload, without editing.
+pub(crate) fn verify_command(
+    path: Option<PathBuf>,
+    anchors: Vec<String>,
+    payload: PayloadArgs,
+    json: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let api_version = ctx.api_version;
+    let mut jobs: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    if let Some((request, _)) = payload.try_load("verify")? {
+        let anchors_of = |edits: &[HashlineEdit]| -> Vec<String> {
+            edits.iter().flat_map(edit_anchors).map(str::to_string).collect()
+        };
+        match (&path, request.files.is_empty()) {
+            (Some(path), true) => jobs.push((path.clone(), anchors_of(&request.edits))),
+            (None, true) => bail!("verify: missing <PATH> (or list `files` in the payload)"),
+            (Some(_), false) => bail!("verify: the payload lists `files`; omit the positional path"),
+            (None, false) => {
+                jobs.extend(request.files.iter().map(|f| (PathBuf::from(&f.path), anchors_of(&f.edits))))
+            }
+        }
+    }
+    if !anchors.is_empty() {
+        let Some(path) = &path else { bail!("verify: --anchors needs a <PATH>") };
+        match jobs.iter_mut().find(|(p, _)| p == path) {
+            Some((_, listed)) => listed.extend(anchors),
+            None => jobs.push((path.clone(), anchors)),
+        }
+    }
+    if jobs.is_empty() {
+        bail!("provide --anchors, --edits-json, or --edits-file");
+    }
+
+    let single = path.is_some() && jobs.len() == 1;
+    let (mut total, mut drifted) = (0, 0);
+    let mut files = Vec::with_capacity(jobs.len());
+    for (path, anchors) in &jobs {
+        let content = open_storage(path, ctx.remote.as_deref())?
+            .read()
+            .with_context(|| format!("verify: failed to read {}", path.display()))?
+            .content;
+        let lines: Vec<String> = split_preserve_last_empty(&normalize_to_lf(&content))
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect();
+        let parsed = anchors
+            .iter()
+            .map(|a| parse_line_ref(a).with_context(|| format!("verify: invalid anchor {:?}", a)))
+            .collect::<Result<Vec<_>>>()?;
+        let prefix = if single { String::new() } else { format!("{}: ", path.display()) };
+        let records = verify_records(&lines, &parsed);
+        for record in &records {
+            let current = record["current"].as_str().unwrap_or_default();
+            total += 1;
+            let text = match record["status"].as_str().unwrap_or_default() {
+                "ok" => ctx.stdout.green("ok"),
+                "relocated" => format!("{} to {}", ctx.stdout.yellow("relocated"), current),
+                "stale" => format!("{}, line is now {}", ctx.stdout.red("stale"), current),
+                _ => format!("{} (file has {} lines)", ctx.stdout.red("out of range"), lines.len()),
+            };
+            if record["status"] != "ok" {
+                drifted += 1;
+            }
+            if !json {
+                println!("{}{} {}", prefix, record["anchor"].as_str().unwrap_or_default(), text);
+            }
+        }
+        files.push(json!({ "path": path.display().to_string(), "anchors": records }));
+    }
+
+    let summary = format!("{} of {} anchor(s) drifted", drifted, total);
+    if json {
+        let mut result = if single { files.remove(0) } else { json!({ "files": files }) };
+        result["drifted"] = json!(drifted);
+        let error = (drifted > 0).then(|| summary.clone());
+        let env = Envelope { api_version, command: "verify", ok: drifted == 0, result: Some(result), error };
+        println!("{}", serde_json::to_string(&env)?);
+        if drifted > 0 {
+            return Err(AlreadyReported.into());
+        }
+    } else if drifted > 0 {
+        bail!("verify: {}; re-read before editing", summary);
+    } else if !ctx.quiet {
+        eprintln!("all {} anchor(s) match", total);
+    }
+    Ok(())
+}
+
+/// `hashline anchor`: print the current anchors of the given lines.
+pub(crate) fn anchor_command(path: PathBuf, specs: Vec<String>, content: bool, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let storage = open_storage(&path, ctx.remote.as_deref())?;
+    let Loaded { content: raw, .. } =
+        storage.read().with_context(|| format!("anchor: failed to read {}", path.display()))?;
+    let normalized = normalize_to_lf(&raw);
+    let lines = split_preserve_last_empty(&normalized);
+    let mut wanted = Vec::new();
+    for spec in &specs {
+        let (start, end) = match spec.trim().split_once('-') {
+            Some((a, b)) => (a.trim().parse::<usize>(), b.trim().parse::<usize>()),
+            None => (spec.trim().parse(), spec.trim().parse()),
+        };
+        let (Ok(start), Ok(end)) = (start, end) else {
+            bail!("anchor: invalid line or range {:?} (expected N or A-B)", spec);
+        };
+        if start == 0 || start > end || end > lines.len() {
+            bail!("anchor: {} is out of range ({} has {} line(s))", spec, path.display(), lines.len());
+        }
+        wanted.extend(start..=end);
+    }
+    let records: Vec<(usize, String, &str)> =
+        wanted.into_iter().map(|n| (n, compute_line_hash(lines[n - 1]), lines[n - 1])).collect();
+    if json {
+        let records: Vec<serde_json::Value> = records
+            .iter()
+            .map(|(n, hash, text)| {
+                let mut record = json!({ "line": n, "hash": hash, "anchor": format!("{}:{}", n, hash) });
+                if content {
+                    record["text"] = json!(text);
+                }
+                record
+            })
+            .collect();
+        let result = json!({ "path": path.display().to_string(), "anchors": records });
+        print_envelope(api_version, "anchor", Ok(result))?;
+    } else {
+        let mut stdout = std::io::stdout().lock();
+        for (n, hash, text) in &records {
+            if content {
+                writeln!(stdout, "{}:{}|{}", n, hash, text)?;
+            } else {
+                writeln!(stdout, "{}:{}", n, hash)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `hashline stats`: line count, duplicate hashes, longest line, and line endings.
+pub(crate) fn stats_command(path: PathBuf, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let storage = open_storage(&path, ctx.remote.as_deref())?;
+    let Loaded { content: raw, .. } =
+        storage.read().with_context(|| format!("stats: failed to read {}", path.display()))?;
+    let normalized = normalize_to_lf(&raw);
+    let lines = if normalized.is_empty() { Vec::new() } else { split_preserve_last_empty(&normalized) };
+    let mut by_hash: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        by_hash.entry(compute_line_hash(line)).or_default().push(i + 1);
+    }
+    // Most repeated first, then by first occurrence, so the output is stable.
+    let mut duplicates: Vec<(String, Vec<usize>)> = by_hash.into_iter().filter(|(_, at)| at.len() > 1).collect();
+    duplicates.sort_by_key(|(_, at)| (std::cmp::Reverse(at.len()), at[0]));
+    let duplicate_lines: usize = duplicates.iter().map(|(_, at)| at.len()).sum();
+    let longest = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.chars().count()))
+        .max_by_key(|&(n, len)| (len, std::cmp::Reverse(n)));
+    let crlf = raw.matches("\r\n").count();
+    let lf = raw.matches('\n').count() - crlf;
+    let final_newline = raw.ends_with('\n');
+    if json {
+        let duplicates: Vec<serde_json::Value> =
+            duplicates.iter().map(|(hash, at)| json!({ "hash": hash, "lines": at })).collect();
+        let result = json!({
+            "path": path.display().to_string(),
+            "lines": lines.len(),
+            "duplicate_lines": duplicate_lines,
+            "duplicate_hashes": duplicates,
+            "longest_line": longest.map(|(line, length)| json!({ "line": line, "length": length })),
+            "line_endings": { "lf": lf, "crlf": crlf },
+            "final_newline": final_newline,
+        });
+        print_envelope(api_version, "stats", Ok(result))?;
+    } else {
+        const SHOWN: usize = 5;
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "lines: {}", lines.len())?;
+        writeln!(
+            stdout,
+            "duplicate hashes: {} line(s) share {} hash(es) and can't be relocation targets",
+            duplicate_lines,
+            duplicates.len()
+        )?;
+        for (hash, at) in duplicates.iter().take(SHOWN) {
+            let shown: Vec<String> = at.iter().take(8).map(|n| n.to_string()).collect();
+            let more = if at.len() > 8 { format!(", ... ({} more)", at.len() - 8) } else { String::new() };
+            writeln!(stdout, "  {} x{}: lines {}{}", hash, at.len(), shown.join(", "), more)?;
+        }
+        if duplicates.len() > SHOWN {
+            writeln!(stdout, "  ... and {} more (see --json)", duplicates.len() - SHOWN)?;
+        }
+        match longest {
+            Some((line, length)) => writeln!(stdout, "longest line: {} chars (line {})", length, line)?,
+            None => writeln!(stdout, "longest line: none (empty file)")?,
+        }
+        let endings = match (lf, crlf) {
+            (0, 0) => "none".to_string(),
+            (_, 0) => format!("lf ({})", lf),
+            (0, _) => format!("crlf ({})", crlf),
+            _ => format!("mixed (lf {}, crlf {})", lf, crlf),
+        };
+        writeln!(stdout, "line endings: {}", endings)?;
+        writeln!(stdout, "final newline: {}", if final_newline { "yes" } else { "no" })?;
+    }
+    Ok(())
+}
+
+/// `hashline hash`: hash each argument, or each stdin line.
+pub(crate) fn hash_command(lines: Vec<String>, number: bool, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let lines = if lines.is_empty() {
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut text).context("hash: failed to read stdin")?;
+        split_preserve_last_empty(&normalize_to_lf(&text)).into_iter().map(String::from).collect()
+    } else {
+        lines
+    };
+    let hashes: Vec<String> = lines.iter().map(|l| compute_line_hash(l)).collect();
+    if json {
+        let records: Vec<serde_json::Value> = lines
+            .iter()
+            .zip(&hashes)
+            .enumerate()
+            .map(|(i, (text, hash))| json!({ "line": i + 1, "hash": hash, "text": text }))
+            .collect();
+        print_envelope(api_version, "hash", Ok(json!({ "hash_scheme": HASH_SCHEME, "lines": records })))?;
+    } else {
+        let mut stdout = std::io::stdout().lock();
+        for (i, hash) in hashes.iter().enumerate() {
+            if number {
+                writeln!(stdout, "{}:{}", i + 1, hash)?;
+            } else {
+                writeln!(stdout, "{}", hash)?;
+            }
+        }
+    }
+    Ok(())
+}
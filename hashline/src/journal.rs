@@ -0,0 +1,606 @@
+//! The undo/redo journal and the content objects it refers to.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use hashline::{file_fingerprint, HashlineEdit};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, info};
+use xxhash_rust::xxh64::xxh64;
+
+use crate::edit::{content_digest, summarize_edit};
+use crate::storage::{open_storage, write_atomically, Loaded};
+use crate::{print_envelope, unix_timestamp, RunCtx};
+
+/// One applied edit, appended as a JSON line to `<journal_dir>/<path-key>.jsonl`. The content
+/// before and after it is kept in `<journal_dir>/objects/<digest>`.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct JournalEntry {
+    pub(crate) timestamp: u64,
+    pub(crate) path: String,
+    pub(crate) pre_digest: String,
+    pub(crate) post_digest: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) idempotency_key: Option<String>,
+    /// What changed, for `history`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) summary: Option<String>,
+    #[serde(default, skip_serializing_if = "JournalAction::is_edit")]
+    pub(crate) action: JournalAction,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JournalAction {
+    /// An edit, sed script, or patch
+    #[default]
+    Edit,
+    /// `undo` reverting the latest entry still in effect
+    Undo,
+    /// `redo` reapplying the latest undone entry
+    Redo,
+}
+
+impl JournalAction {
+    fn is_edit(&self) -> bool {
+        *self == JournalAction::Edit
+    }
+}
+
+/// The journal directory for `target`: `.hashline/journal` at the root of the git work tree that
+/// holds it, otherwise `.hashline/journal` next to the file (for an archive member, next to the
+/// archive). Remote targets have no directory of their own and use `$XDG_STATE_HOME/hashline/journal`
+/// (default `~/.local/state`). The path never depends on the working directory, so `undo` finds an
+/// edit wherever it was run from.
+fn journal_dir(target: &str) -> Result<PathBuf> {
+    if let Some(dir) = local_dir(target) {
+        let root = dir.ancestors().find(|d| d.join(".git").exists()).unwrap_or(&dir);
+        return Ok(root.join(".hashline").join("journal"));
+    }
+    let state = match std::env::var_os("XDG_STATE_HOME").filter(|v| !v.is_empty()) {
+        Some(d) => PathBuf::from(d),
+        None => {
+            let home = std::env::var_os("HOME").ok_or_else(|| {
+                anyhow!("journal: {} has no local directory, and neither XDG_STATE_HOME nor HOME is set", target)
+            })?;
+            PathBuf::from(home).join(".local/state")
+        }
+    };
+    Ok(state.join("hashline").join("journal"))
+}
+
+/// The canonical directory of a local `target`, or None for stdin and remote targets. A target
+/// that names no existing file is either `ARCHIVE!MEMBER` or a file yet to be created, whose
+/// directory is resolved instead (`a.txt` is in `.`).
+fn local_dir(target: &str) -> Option<PathBuf> {
+    if target == "-" || target.contains("://") {
+        return None;
+    }
+    let path = Path::new(target);
+    if path.is_file() {
+        return fs::canonicalize(path).ok()?.parent().map(Path::to_path_buf);
+    }
+    if let Some(archive) = target.match_indices('!').map(|(i, _)| Path::new(&target[..i])).find(|a| a.is_file()) {
+        return fs::canonicalize(archive).ok()?.parent().map(Path::to_path_buf);
+    }
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::canonicalize(parent).ok()
+}
+
+fn journal_file_for(target: &str) -> Result<PathBuf> {
+    let key = format!("{:016x}", xxh64(target.as_bytes(), 0));
+    Ok(journal_dir(target)?.join(format!("{key}.jsonl")))
+}
+
+pub(crate) fn read_journal(target: &str) -> Result<Vec<JournalEntry>> {
+    let journal = journal_file_for(target)?;
+    parse_journal(&journal)
+}
+
+fn parse_journal(journal: &std::path::Path) -> Result<Vec<JournalEntry>> {
+    let raw = match fs::read_to_string(journal) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("journal: failed to read {}", journal.display())),
+    };
+    raw.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).with_context(|| format!("journal: corrupt entry in {}", journal.display())))
+        .collect()
+}
+
+pub(crate) fn append_journal_entry(entry: &JournalEntry) -> Result<()> {
+    use std::io::Write;
+
+    let journal = journal_file_for(&entry.path)?;
+    if let Some(dir) = journal.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("journal: failed to create {}", dir.display()))?;
+    }
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&journal)
+        .with_context(|| format!("journal: failed to open {}", journal.display()))?;
+    writeln!(f, "{}", serde_json::to_string(entry)?)?;
+    drop(f);
+    prune_journal(&journal, journal_keep())
+}
+
+/// Entries each target's journal keeps: `HASHLINE_JOURNAL_KEEP`, default 200, where 0 keeps all.
+fn journal_keep() -> usize {
+    std::env::var("HASHLINE_JOURNAL_KEEP").ok().and_then(|v| v.parse().ok()).unwrap_or(200)
+}
+
+/// Once `journal` holds a quarter more than `keep` entries, drop the oldest (see `journal_cut`)
+/// and then every content object that no journal in the directory refers to any more.
+fn prune_journal(journal: &std::path::Path, keep: usize) -> Result<()> {
+    let entries = parse_journal(journal)?;
+    if keep == 0 || entries.len() <= keep + keep / 4 {
+        return Ok(());
+    }
+    let cut = journal_cut(&entries, keep);
+    if cut == 0 {
+        return Ok(());
+    }
+    let mut kept = String::new();
+    for entry in &entries[cut..] {
+        kept.push_str(&serde_json::to_string(entry)?);
+        kept.push('\n');
+    }
+    write_atomically(journal, kept.as_bytes()).with_context(|| format!("journal: failed to prune {}", journal.display()))?;
+    debug!(journal = %journal.display(), dropped = cut, "pruned journal");
+
+    let dir = journal.parent().unwrap_or(std::path::Path::new("."));
+    let mut live = std::collections::HashSet::new();
+    for file in fs::read_dir(dir)?.flatten() {
+        if file.path().extension().is_some_and(|ext| ext == "jsonl") {
+            for entry in parse_journal(&file.path())? {
+                live.insert(entry.pre_digest);
+                live.insert(entry.post_digest);
+            }
+        }
+    }
+    // An object written in the last hour may belong to an edit whose entry is not appended yet.
+    let settled =
+        |meta: &fs::Metadata| meta.modified().ok().and_then(|t| t.elapsed().ok()).is_some_and(|age| age.as_secs() > 3_600);
+    for object in fs::read_dir(dir.join("objects")).into_iter().flatten().flatten() {
+        let name = object.file_name().to_string_lossy().to_string();
+        if !live.contains(&name) && object.metadata().is_ok_and(|m| settled(&m)) {
+            let _ = fs::remove_file(object.path());
+        }
+    }
+    Ok(())
+}
+
+/// Where to cut `entries` so that about `keep` remain: the latest index at or before
+/// `len - keep` that no later undo or redo reaches behind, so `journal_stacks` over what remains
+/// pairs every move as it did over the whole journal. 0 when there is no such index.
+fn journal_cut(entries: &[JournalEntry], keep: usize) -> usize {
+    if entries.len() <= keep {
+        return 0;
+    }
+    let (mut done, mut undone) = (Vec::new(), Vec::new());
+    // The entry each undo or redo moved.
+    let mut moved = vec![None; entries.len()];
+    for (i, entry) in entries.iter().enumerate() {
+        match entry.action {
+            JournalAction::Edit => {
+                done.push(i);
+                undone.clear();
+            }
+            JournalAction::Undo => {
+                moved[i] = done.pop();
+                undone.extend(moved[i]);
+            }
+            JournalAction::Redo => {
+                moved[i] = undone.pop();
+                done.extend(moved[i]);
+            }
+        }
+    }
+    let mut lowest = usize::MAX;
+    for cut in (1..entries.len()).rev() {
+        lowest = lowest.min(moved[cut].unwrap_or(usize::MAX));
+        if cut <= entries.len() - keep && lowest >= cut {
+            return cut;
+        }
+    }
+    0
+}
+
+fn journal_object_path(target: &str, digest: &str) -> Result<PathBuf> {
+    Ok(journal_dir(target)?.join("objects").join(digest))
+}
+
+/// Keep `content` under its digest (once) in `target`'s journal, so it can be restored later.
+pub(crate) fn store_journal_object(target: &str, content: &str) -> Result<()> {
+    let object = journal_object_path(target, &content_digest(content))?;
+    if object.exists() {
+        return Ok(());
+    }
+    if let Some(dir) = object.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("journal: failed to create {}", dir.display()))?;
+    }
+    fs::write(&object, content).with_context(|| format!("journal: failed to write {}", object.display()))
+}
+
+fn load_journal_object(target: &str, digest: &str) -> Result<String> {
+    let object = journal_object_path(target, digest)?;
+    let content = fs::read_to_string(&object)
+        .with_context(|| format!("journal: the content with digest {} was not recorded ({})", digest, object.display()))?;
+    if content_digest(&content) != digest {
+        bail!("journal: {} is corrupt (its digest is no longer {})", object.display(), digest);
+    }
+    Ok(content)
+}
+
+/// Report a journal move to stdout (`--json`) or stderr.
+fn print_journal_move(
+    api_version: u32,
+    command: &'static str,
+    result: serde_json::Value,
+    json: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    if json {
+        return print_envelope(api_version, command, Ok(result));
+    }
+    if !ctx.quiet {
+        let path = result["path"].as_str().unwrap_or_default();
+        let summary = result["summary"].as_str().unwrap_or_default();
+        match result["noop"].as_bool() {
+            Some(true) => eprintln!("{} is already in that state ({})", path, summary),
+            _ => eprintln!("{}: {} (fingerprint {})", path, summary, result["fingerprint"].as_str().unwrap_or_default()),
+        }
+    }
+    Ok(())
+}
+
+/// Replay the journal: the entries still in effect, oldest first, and the undone ones `redo` can
+/// reapply, most recently undone last (both as indices into `entries`). A new edit clears the latter.
+pub(crate) fn journal_stacks(entries: &[JournalEntry]) -> (Vec<usize>, Vec<usize>) {
+    let (mut done, mut undone) = (Vec::new(), Vec::new());
+    for (i, entry) in entries.iter().enumerate() {
+        match entry.action {
+            JournalAction::Edit => {
+                done.push(i);
+                undone.clear();
+            }
+            JournalAction::Undo => undone.extend(done.pop()),
+            JournalAction::Redo => done.extend(undone.pop()),
+        }
+    }
+    (done, undone)
+}
+
+/// How `undo`, `redo` and `history --revert-to` move a file through its journal.
+#[derive(Clone, Copy)]
+pub(crate) enum JournalMove {
+    Undo,
+    Redo,
+    /// To the state after entry N (0: before the first one)
+    RevertTo(usize),
+}
+
+impl JournalMove {
+    /// The command that makes this move, for messages and envelopes.
+    fn command(self) -> &'static str {
+        match self {
+            JournalMove::Undo => "undo",
+            JournalMove::Redo => "redo",
+            JournalMove::RevertTo(_) => "history",
+        }
+    }
+}
+
+/// Restore one of the states recorded in `path`'s journal and journal that too. Refuses if the file
+/// changed since the journal last touched it, so hand edits are never overwritten.
+fn journal_move(path: &Path, how: JournalMove, ctx: &RunCtx) -> Result<serde_json::Value> {
+    let command = how.command();
+    let storage = open_storage(path, ctx.remote.as_deref())?;
+    let target = storage.id();
+    let entries = read_journal(&target)?;
+    let Some(last) = entries.last() else {
+        bail!("{}: no recorded edits of {}", command, path.display());
+    };
+    let Loaded { content, version } =
+        storage.read().with_context(|| format!("{}: failed to read {}", command, path.display()))?;
+    let current = content_digest(&content);
+    if current != last.post_digest {
+        bail!(
+            "{}: {} has changed since its last hashline edit (digest {} -> {}); refusing to overwrite it",
+            command,
+            path.display(),
+            last.post_digest,
+            current
+        );
+    }
+    let (done, undone) = journal_stacks(&entries);
+    let (to, action, moved) = match how {
+        JournalMove::Undo => {
+            let Some(&i) = done.last() else {
+                bail!("undo: no edit of {} left to revert", path.display());
+            };
+            (entries[i].pre_digest.clone(), JournalAction::Undo, Some(i))
+        }
+        JournalMove::Redo => {
+            let Some(&i) = undone.last() else {
+                bail!("redo: no undone edit of {} to reapply", path.display());
+            };
+            (entries[i].post_digest.clone(), JournalAction::Redo, Some(i))
+        }
+        JournalMove::RevertTo(0) => (entries[0].pre_digest.clone(), JournalAction::Edit, None),
+        JournalMove::RevertTo(n) => match entries.get(n - 1) {
+            Some(entry) => (entry.post_digest.clone(), JournalAction::Edit, None),
+            None => bail!("history: {} has no state {} (the journal has {} entries)", path.display(), n, entries.len()),
+        },
+    };
+    let summary = match (how, moved) {
+        (JournalMove::RevertTo(n), _) => format!("revert to state {}", n),
+        (_, Some(i)) => format!("{} #{}: {}", command, i + 1, entries[i].summary.as_deref().unwrap_or("edit")),
+        (_, None) => unreachable!("undo and redo always move an entry"),
+    };
+    let mut result = json!({ "path": path.display().to_string(), "summary": summary });
+    if let Some(i) = moved {
+        let entry = &entries[i];
+        result["entry"] = json!({
+            "number": i + 1,
+            "timestamp": entry.timestamp,
+            "summary": entry.summary,
+            "idempotency_key": entry.idempotency_key,
+        });
+    }
+    if to == current {
+        result["noop"] = json!(true);
+        result["fingerprint"] = json!(file_fingerprint(&content));
+        return Ok(result);
+    }
+    let restored = load_journal_object(&target, &to)?;
+    storage
+        .write(&restored, version.as_deref())
+        .with_context(|| format!("{}: failed to write {}", command, path.display()))?;
+    append_journal_entry(&JournalEntry {
+        timestamp: unix_timestamp(),
+        path: target,
+        pre_digest: current,
+        post_digest: to,
+        idempotency_key: None,
+        summary: Some(summary),
+        action,
+    })?;
+    info!(path = %path.display(), command, "journal state restored");
+    result["fingerprint"] = json!(file_fingerprint(&restored));
+    Ok(result)
+}
+
+/// A journal summary of an edit: its ops (with counts) and the line counts before and after.
+pub(crate) fn journal_summary(edits: &[HashlineEdit], lines_before: usize, lines_after: usize) -> String {
+    let mut ops: Vec<(String, usize)> = Vec::new();
+    for edit in edits {
+        let op = summarize_edit(edit)["op"].as_str().unwrap_or("edit").to_string();
+        match ops.iter_mut().find(|(name, _)| *name == op) {
+            Some((_, count)) => *count += 1,
+            None => ops.push((op, 1)),
+        }
+    }
+    let ops: Vec<String> =
+        ops.into_iter().map(|(op, n)| if n == 1 { op } else { format!("{} x{}", op, n) }).collect();
+    format!("{} ({} -> {} lines)", ops.join(", "), lines_before, lines_after)
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC.
+pub(crate) fn format_utc(timestamp: u64) -> String {
+    let (days, secs) = ((timestamp / 86_400) as i64, timestamp % 86_400);
+    // Civil-from-days (Howard Hinnant's algorithm), for dates since 1970.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, secs / 3_600, secs / 60 % 60, secs % 60)
+}
+
+/// True if `key` was already applied and the file is still exactly what that edit produced.
+/// A key whose result has since been modified is an error: re-applying could double-apply.
+/// Edits that were undone don't count.
+pub(crate) fn journal_has_applied(target: &str, key: &str, current_digest: &str) -> Result<bool> {
+    let entries = read_journal(target)?;
+    let (done, _) = journal_stacks(&entries);
+    let Some(entry) = done.into_iter().rev().map(|i| &entries[i]).find(|e| e.idempotency_key.as_deref() == Some(key)) else {
+        return Ok(false);
+    };
+    if entry.post_digest == current_digest {
+        return Ok(true);
+    }
+    bail!(
+        "idempotency key {} was already applied to {}, but the file has changed since (digest {} -> {})",
+        key,
+        target,
+        entry.post_digest,
+        current_digest
+    );
+}
+
+/// `hashline undo`, `redo`, and `history --revert-to`: make the move and report it.
+pub(crate) fn journal_command(path: &Path, how: JournalMove, json: bool, ctx: &RunCtx) -> Result<()> {
+    let result = journal_move(path, how, ctx)?;
+    print_journal_move(ctx.api_version, how.command(), result, json, ctx)
+}
+
+/// `hashline history` without `--revert-to`: list the journal of `path`.
+pub(crate) fn history_command(path: PathBuf, json: bool, ctx: &RunCtx) -> Result<()> {
+    let api_version = ctx.api_version;
+    let storage = open_storage(&path, ctx.remote.as_deref())?;
+    let entries = read_journal(&storage.id())?;
+    let Loaded { content, .. } =
+        storage.read().with_context(|| format!("history: failed to read {}", path.display()))?;
+    // The file is at the last state only if nothing changed it outside hashline since.
+    let current = entries.last().filter(|e| e.post_digest == content_digest(&content)).map(|_| entries.len());
+    let (done, undone) = journal_stacks(&entries);
+    if json {
+        let records: Vec<serde_json::Value> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                json!({
+                    "number": i + 1,
+                    "timestamp": e.timestamp,
+                    "action": e.action,
+                    "summary": e.summary,
+                    "idempotency_key": e.idempotency_key,
+                    "pre_digest": e.pre_digest,
+                    "post_digest": e.post_digest,
+                    "in_effect": done.contains(&i),
+                })
+            })
+            .collect();
+        let result = json!({
+            "path": path.display().to_string(),
+            "entries": records,
+            "current_state": current,
+            "redo_available": !undone.is_empty(),
+        });
+        print_envelope(api_version, "history", Ok(result))?;
+    } else {
+        let mut stdout = std::io::stdout().lock();
+        if entries.is_empty() {
+            writeln!(stdout, "no recorded edits of {}", path.display())?;
+            return Ok(());
+        }
+        for (i, e) in entries.iter().enumerate() {
+            let action = match e.action {
+                JournalAction::Edit => "edit",
+                JournalAction::Undo => "undo",
+                JournalAction::Redo => "redo",
+            };
+            let key = e.idempotency_key.as_ref().map(|k| format!(" [key {}]", k)).unwrap_or_default();
+            let undone = if done.contains(&i) || e.action != JournalAction::Edit { "" } else { " (undone)" };
+            let summary = e.summary.as_deref().unwrap_or(action);
+            let when = format_utc(e.timestamp);
+            writeln!(stdout, "#{:<3} {}  {:<4}  {}{}{}", i + 1, when, action, summary, key, undone)?;
+        }
+        match current {
+            Some(n) => {
+                let path = path.display();
+                writeln!(stdout, "{} is at state {}; `--revert-to N` restores the one after #N", path, n)?
+            }
+            None => writeln!(
+                stdout,
+                "{} has changed since the last entry; undo, redo, and --revert-to refuse to overwrite it",
+                path.display()
+            )?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal(actions: &str) -> Vec<JournalEntry> {
+        actions
+            .chars()
+            .map(|c| JournalEntry {
+                timestamp: 0,
+                path: String::new(),
+                pre_digest: String::new(),
+                post_digest: String::new(),
+                idempotency_key: None,
+                summary: None,
+                action: match c {
+                    'E' => JournalAction::Edit,
+                    'U' => JournalAction::Undo,
+                    _ => JournalAction::Redo,
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn journals_live_in_the_work_tree_root_or_next_to_the_file() {
+        let root = std::env::temp_dir().join(format!("hashline-journal-dir-{}", std::process::id()));
+        fs::create_dir_all(root.join("repo/.git")).unwrap();
+        fs::create_dir_all(root.join("repo/src")).unwrap();
+        fs::create_dir_all(root.join("loose")).unwrap();
+        fs::write(root.join("repo/src/a.txt"), "a\n").unwrap();
+        fs::write(root.join("loose/b.txt"), "b\n").unwrap();
+        fs::write(root.join("loose/c.zip"), "").unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        let at = |p: &str| root.join(p).display().to_string();
+
+        assert_eq!(journal_dir(&at("repo/src/a.txt")).unwrap(), root.join("repo/.hashline/journal"));
+        assert_eq!(journal_dir(&at("repo/src/new.txt")).unwrap(), root.join("repo/.hashline/journal"));
+        assert_eq!(journal_dir(&at("loose/b.txt")).unwrap(), root.join("loose/.hashline/journal"));
+        assert_eq!(journal_dir(&at("loose/./b.txt")).unwrap(), root.join("loose/.hashline/journal"));
+        let member = format!("{}!dir/d.txt", at("loose/c.zip"));
+        assert_eq!(journal_dir(&member).unwrap(), root.join("loose/.hashline/journal"));
+        assert_eq!(local_dir("https://example.com/a.txt"), None);
+        assert_eq!(local_dir("ssh://host/a.txt"), None);
+        assert_eq!(local_dir("-"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn journal_cut_keeps_the_newest_entries() {
+        assert_eq!(journal_cut(&journal("EEEEE"), 2), 3);
+        assert_eq!(journal_cut(&journal("EEEEE"), 5), 0);
+        assert_eq!(journal_cut(&journal("EEUEE"), 2), 3);
+    }
+
+    #[test]
+    fn journal_cut_never_strands_an_undo_or_redo() {
+        // The last two undos and the redo reach back to the first edit, so everything stays.
+        assert_eq!(journal_cut(&journal("EEUUR"), 2), 0);
+        // Cutting at 2 would strand the undo of entry 1; cutting at 1 keeps everything it needs.
+        assert_eq!(journal_cut(&journal("EEEUU"), 3), 1);
+        // What remains pairs as before, minus the edits cut off.
+        let entries = journal("EEEUUREE");
+        let cut = journal_cut(&entries, 3);
+        assert_eq!(cut, 1);
+        let after: Vec<usize> = journal_stacks(&entries[cut..]).0.iter().map(|i| i + cut).collect();
+        assert_eq!(after, journal_stacks(&entries).0.into_iter().filter(|&i| i >= cut).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn undo_restores_the_exact_bytes_and_redo_the_edit() {
+        use crate::edit::{edit_file, EditOptions};
+
+        let dir = std::env::temp_dir().join(format!("hashline-journal-undo-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = fs::canonicalize(&dir).unwrap().join("a.txt");
+        let original = "one\r\ntwo\r\nthree";
+        fs::write(&path, original).unwrap();
+        let ctx = RunCtx::for_tests();
+        let payload = format!(
+            r#"[{{"set_line": {{"anchor": "{}", "new_text": "TWO"}}}}]"#,
+            hashline::Anchor::for_line(2, "two")
+        );
+        let edits = hashline::parse_edits_payload(&payload).unwrap().edits;
+        edit_file(&path, edits, None, &payload, &EditOptions::plain(true, true), &ctx).unwrap();
+        let edited = fs::read_to_string(&path).unwrap();
+        assert_eq!(edited, "one\r\nTWO\r\nthree");
+
+        journal_move(&path, JournalMove::Undo, &ctx).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), original.as_bytes());
+        let err = journal_move(&path, JournalMove::Undo, &ctx).unwrap_err();
+        assert_eq!(err.to_string(), format!("undo: no edit of {} left to revert", path.display()));
+
+        journal_move(&path, JournalMove::Redo, &ctx).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), edited);
+
+        fs::write(&path, "by hand").unwrap();
+        let err = journal_move(&path, JournalMove::Undo, &ctx).unwrap_err();
+        assert!(err.to_string().contains("has changed since its last hashline edit"), "{err:#}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "by hand");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,4078 @@
+//! Core of the `hashline` CLI: `LINE:HASH` anchors, line hashing, and hash-verified edits.
+//!
+//! A [`Document`] is text split into addressable lines. [`Document::anchor`] gives the anchor
+//! an agent would see from `hashline read`, and [`Document::apply`] applies a list of [`Edit`]s,
+//! failing if an anchor no longer matches its line. [`apply`] does both for a plain string.
+//! Edits deserialize from the same JSON the CLI accepts (see [`parse_edits_payload`]).
+
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "plugins")]
+use std::fs;
+#[cfg(feature = "plugins")]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "lua")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "plugins")]
+use serde_json::json;
+#[cfg(feature = "plugins")]
+use tracing::debug;
+use tracing::info;
+use xxhash_rust::xxh32::xxh32;
+
+/// Line-hash scheme; part of session tokens so anchors from a different scheme are rejected.
+pub const HASH_SCHEME: &str = "xxh32-16";
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct EditRequest {
+    #[serde(default)]
+    pub edits: Vec<HashlineEdit>,
+    /// Retry-safe key: if the journal shows it already produced the current file, edit is a no-op
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Edits for several files, applied in order; use instead of `edits` (and the positional path)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileEdits>,
+}
+
+/// One file's share of a multi-file payload.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct FileEdits {
+    /// Path of the file to edit
+    pub path: String,
+    #[serde(default)]
+    pub edits: Vec<HashlineEdit>,
+}
+
+/// Declares `HashlineEdit` and the op-name table its `Deserialize` impl dispatches on, from one list.
+macro_rules! edit_ops {
+    ($($variant:ident { $key:ident: $ty:ty },)*) => {
+        #[derive(Debug, Serialize, Clone, JsonSchema)]
+        #[serde(untagged)]
+        pub enum HashlineEdit {
+            $($variant { $key: $ty },)*
+        }
+
+        /// Every edit op name, as used for the wrapper key or the tagged form's `"type"`.
+        pub const EDIT_OPS: &[&str] = &[$(stringify!($key)),*];
+
+        impl HashlineEdit {
+            /// The op name, e.g. `set_line`.
+            pub fn op(&self) -> &'static str {
+                match self {
+                    $(HashlineEdit::$variant { .. } => stringify!($key),)*
+                }
+            }
+        }
+
+        fn edit_from_op(op: &str, body: serde_json::Value) -> Option<serde_json::Result<HashlineEdit>> {
+            match op {
+                $(stringify!($key) => Some(serde_json::from_value(body).map(|$key| HashlineEdit::$variant { $key })),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+edit_ops! {
+    SetLine { set_line: SetLine },
+    ReplaceLines { replace_lines: ReplaceLines },
+    InsertAfter { insert_after: InsertAfter },
+    DeleteLines { delete_lines: DeleteLines },
+    MoveLines { move_lines: MoveLines },
+    CopyLines { copy_lines: CopyLines },
+    IndentRange { indent_range: IndentRange },
+    DedentRange { dedent_range: DedentRange },
+    CommentRange { comment_range: CommentRange },
+    UncommentRange { uncomment_range: UncommentRange },
+    SortRange { sort_range: SortRange },
+    DedupeRange { dedupe_range: DedupeRange },
+    SwapLines { swap_lines: SwapLines },
+    Append { append: AppendText },
+    Prepend { prepend: PrependText },
+    Replace { replace: ReplaceText },
+    ReplaceInRange { replace_in_range: ReplaceInRange },
+    RegexReplace { regex_replace: RegexReplace },
+    JsonSet { json_set: JsonSet },
+    JsonDelete { json_delete: JsonDelete },
+    YamlSet { yaml_set: YamlSet },
+    YamlDelete { yaml_delete: YamlDelete },
+    TomlSet { toml_set: TomlSet },
+    TomlRemove { toml_remove: TomlRemove },
+    ReplaceSection { replace_section: ReplaceSection },
+    AppendToSection { append_to_section: AppendToSection },
+    CsvSetCell { csv_set_cell: CsvSetCell },
+    CsvAddColumn { csv_add_column: CsvAddColumn },
+    KvSet { kv_set: KvSet },
+    EnvSet { env_set: EnvSet },
+    Plugin { plugin: PluginOp },
+    Script { script: ScriptOp },
+    OnlyIf { only_if: Box<OnlyIf> },
+}
+
+/// Edits come either wrapped (`{"set_line": {...}}`) or tagged (`{"type": "set_line", ...}`). Both are
+/// dispatched on the op name, so a malformed edit reports the op's own field errors instead of
+/// serde's "did not match any variant".
+impl<'de> Deserialize<'de> for HashlineEdit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error;
+        let serde_json::Value::Object(mut obj) = serde_json::Value::deserialize(deserializer)? else {
+            return Err(D::Error::custom("an edit must be an object"));
+        };
+        let (op, body) = match obj.remove("type") {
+            Some(serde_json::Value::String(op)) => (op, serde_json::Value::Object(obj)),
+            Some(other) => return Err(D::Error::custom(format!("edit `type` must be a string, got {}", other))),
+            None => {
+                let mut ops = obj.keys().filter(|k| EDIT_OPS.contains(&k.as_str())).cloned();
+                match (ops.next(), ops.next()) {
+                    (Some(op), None) => {
+                        let body = obj.remove(&op).unwrap_or_default();
+                        (op, body)
+                    }
+                    (Some(a), Some(b)) => {
+                        return Err(D::Error::custom(format!("edit has more than one op (`{}`, `{}`); use one object per edit", a, b)))
+                    }
+                    (None, _) => {
+                        if let Some((key, op)) = obj.keys().find_map(|k| nearest_op(k).map(|op| (k, op))) {
+                            return Err(D::Error::custom(format!("unknown edit op `{}`; did you mean `{}`?", key, op)));
+                        }
+                        let keys: Vec<&str> = obj.keys().map(String::as_str).collect();
+                        return Err(D::Error::custom(format!(
+                            "no edit op in {{{}}}; use `\"type\": \"<op>\"` with one of: {}",
+                            keys.join(", "),
+                            EDIT_OPS.join(", ")
+                        )));
+                    }
+                }
+            }
+        };
+        match edit_from_op(&op, body) {
+            Some(parsed) => parsed.map_err(|e| D::Error::custom(format!("{}: {}", op, e))),
+            None => Err(D::Error::custom(match nearest_op(&op) {
+                Some(near) => format!("unknown edit op `{}`; did you mean `{}`?", op, near),
+                None => format!("unknown edit op `{}`; expected one of: {}", op, EDIT_OPS.join(", ")),
+            })),
+        }
+    }
+}
+
+/// Replace a single anchored line.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SetLine {
+    /// LINE:HASH anchor of the line to replace (or `section:"## Title"` for a Markdown heading)
+    pub anchor: String,
+    /// Replacement text; may contain `\n` for multiple lines, `""` deletes the line
+    pub new_text: String,
+    /// Re-indent `new_text` (written at column 0) to the replaced line's indentation
+    #[serde(default)]
+    pub auto_indent: bool,
+    /// Exact current text of the anchored line; the edit fails if it differs (guards against hash collisions)
+    #[serde(default)]
+    pub expected_text: Option<String>,
+    /// Like `expected_text`, but only the start of the text must match
+    #[serde(default)]
+    pub expected_prefix: Option<String>,
+    /// Skip this edit if the file already shows its result (as `edit --idempotent` does for every edit)
+    #[serde(default)]
+    pub skip_if_applied: bool,
+}
+
+/// Replace an inclusive range of anchored lines.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReplaceLines {
+    /// LINE:HASH anchor of the first line in the range (or `section:"## Title"`: its heading)
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line in the range (or `section:"## Title"`: its last line)
+    pub end_anchor: String,
+    /// Replacement text; `""` deletes the whole range
+    pub new_text: String,
+    /// Re-indent `new_text` (written at column 0) to the first replaced line's indentation
+    #[serde(default)]
+    pub auto_indent: bool,
+    /// Exact current text of the anchored lines (the whole range, joined with `\n`); the edit fails if it differs (guards against hash collisions)
+    #[serde(default)]
+    pub expected_text: Option<String>,
+    /// Like `expected_text`, but only the start of the text must match
+    #[serde(default)]
+    pub expected_prefix: Option<String>,
+    /// Skip this edit if the file already shows its result (as `edit --idempotent` does for every edit)
+    #[serde(default)]
+    pub skip_if_applied: bool,
+}
+
+/// Insert text after an anchored line.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InsertAfter {
+    /// LINE:HASH anchor of the line to insert after (or `section:"## Title"`: its heading)
+    pub anchor: String,
+    /// Text to insert (non-empty); may contain `\n`
+    pub text: String,
+    /// Re-indent `text` (written at column 0) to the anchor's block: the following line's
+    /// indentation if the anchor opens a deeper block, otherwise the anchor's own
+    #[serde(default)]
+    pub auto_indent: bool,
+    /// Exact current text of the anchored line; the edit fails if it differs (guards against hash collisions)
+    #[serde(default)]
+    pub expected_text: Option<String>,
+    /// Like `expected_text`, but only the start of the text must match
+    #[serde(default)]
+    pub expected_prefix: Option<String>,
+    /// Skip this edit if the file already shows its result (as `edit --idempotent` does for every edit)
+    #[serde(default)]
+    pub skip_if_applied: bool,
+}
+
+/// Delete an anchored line or an inclusive range of lines.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteLines {
+    /// LINE:HASH anchor of the (first) line to delete (or `section:"## Title"`: its heading); alias `anchor`
+    #[serde(alias = "anchor")]
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line to delete (default: `start_anchor`; `section:` means its last line)
+    pub end_anchor: Option<String>,
+    /// Skip this edit if the file already shows its result (as `edit --idempotent` does for every edit)
+    #[serde(default)]
+    pub skip_if_applied: bool,
+}
+
+/// Move an anchored range of lines to just after (or before) another anchored line.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MoveLines {
+    /// LINE:HASH anchor of the first line to move (or `section:"## Title"`: its heading)
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line to move (default: `start_anchor`; `section:` means its last line)
+    pub end_anchor: Option<String>,
+    /// Put the lines after this LINE:HASH anchor (give exactly one of `after` / `before`)
+    pub after: Option<String>,
+    /// Put the lines before this LINE:HASH anchor
+    pub before: Option<String>,
+}
+
+/// Duplicate an anchored range of lines (as read) just after (or before) another anchored line.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CopyLines {
+    /// LINE:HASH anchor of the first line to copy (or `section:"## Title"`: its heading)
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line to copy (default: `start_anchor`; `section:` means its last line)
+    pub end_anchor: Option<String>,
+    /// Put the copy after this LINE:HASH anchor (give exactly one of `after` / `before`)
+    pub after: Option<String>,
+    /// Put the copy before this LINE:HASH anchor
+    pub before: Option<String>,
+}
+
+/// Shift an anchored range of lines right; blank lines are left as they are.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IndentRange {
+    /// LINE:HASH anchor of the first line to indent
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line (default: `start_anchor`)
+    pub end_anchor: Option<String>,
+    /// Prefix to add to each line, e.g. `"\t"` (give exactly one of `indent` / `width`)
+    pub indent: Option<String>,
+    /// Number of spaces to add to each line
+    pub width: Option<usize>,
+}
+
+/// Shift an anchored range of lines left; lines without enough indentation lose what they have.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DedentRange {
+    /// LINE:HASH anchor of the first line to dedent
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line (default: `start_anchor`)
+    pub end_anchor: Option<String>,
+    /// Prefix to strip from each line that starts with it (give exactly one of `indent` / `width`)
+    pub indent: Option<String>,
+    /// Number of leading spaces (a tab counts as one) to strip from each line
+    pub width: Option<usize>,
+}
+
+/// Turn an anchored range of lines into line comments, aligned at the block's smallest indent.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CommentRange {
+    /// LINE:HASH anchor of the first line to comment out
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line (default: `start_anchor`)
+    pub end_anchor: Option<String>,
+    /// Comment marker such as `"#"` or `"//"` (default: picked from the file extension)
+    pub comment: Option<String>,
+}
+
+/// Strip line comment markers (and one following space) from an anchored range of lines.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UncommentRange {
+    /// LINE:HASH anchor of the first line to uncomment
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line (default: `start_anchor`)
+    pub end_anchor: Option<String>,
+    /// Comment marker such as `"#"` or `"//"` (default: picked from the file extension)
+    pub comment: Option<String>,
+}
+
+/// Sort the lines between two anchors (inclusive); the sort is stable.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SortRange {
+    /// LINE:HASH anchor of the first line to sort
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line to sort
+    pub end_anchor: String,
+    /// Compare lines ignoring case
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Keep only the first of lines that compare equal
+    #[serde(default)]
+    pub unique: bool,
+}
+
+/// Drop repeated lines within an anchored range, keeping the first of each.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DedupeRange {
+    /// LINE:HASH anchor of the first line of the range
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line of the range
+    pub end_anchor: String,
+    /// Remove every repeat in the range, not only adjacent ones (blank lines are kept)
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Exchange two anchored lines or non-overlapping ranges (which may differ in length).
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SwapLines {
+    /// LINE:HASH anchor of the first line of one range
+    pub start_anchor: String,
+    /// LINE:HASH anchor of its last line (default: `start_anchor`)
+    pub end_anchor: Option<String>,
+    /// LINE:HASH anchor of the first line of the range to swap with
+    pub with_start_anchor: String,
+    /// LINE:HASH anchor of its last line (default: `with_start_anchor`)
+    pub with_end_anchor: Option<String>,
+}
+
+/// Add lines at the end of the file (no anchor), after anything other edits insert there.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AppendText {
+    /// Text to append (non-empty); may contain `\n`
+    pub text: String,
+    /// Skip this edit if the file already shows its result (as `edit --idempotent` does for every edit)
+    #[serde(default)]
+    pub skip_if_applied: bool,
+}
+
+/// Add lines at the start of the file (no anchor), before anything other edits insert there.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PrependText {
+    /// Text to prepend (non-empty); may contain `\n`
+    pub text: String,
+    /// Skip this edit if the file already shows its result (as `edit --idempotent` does for every edit)
+    #[serde(default)]
+    pub skip_if_applied: bool,
+}
+
+/// Literal content replacement (no anchors); runs after anchored edits.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReplaceText {
+    /// Exact text to find (non-empty)
+    pub old_text: String,
+    /// Replacement text
+    pub new_text: String,
+    /// Replace every occurrence instead of only the first
+    #[serde(default)]
+    pub all: Option<bool>,
+    /// 1-based match to start at (default 1); earlier matches are left alone
+    #[serde(default)]
+    pub occurrence: Option<usize>,
+    /// Replace at most this many matches from `occurrence` on (default 1, or unlimited with `all`)
+    #[serde(default)]
+    pub max_count: Option<usize>,
+    /// Skip this edit if the file already shows its result (as `edit --idempotent` does for every edit)
+    #[serde(default)]
+    pub skip_if_applied: bool,
+}
+
+/// Literal content replacement limited to an anchored range of lines.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReplaceInRange {
+    /// LINE:HASH anchor of the first line searched (or `section:"## Title"`: its heading)
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line searched (or `section:"## Title"`: its last line)
+    pub end_anchor: String,
+    /// Exact text to find within the range (non-empty); may span lines
+    pub old_text: String,
+    /// Replacement text
+    pub new_text: String,
+    /// Replace every occurrence in the range instead of only the first
+    #[serde(default)]
+    pub all: Option<bool>,
+}
+
+/// Regular-expression replacement over the whole file (no anchors); runs after anchored edits.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegexReplace {
+    /// Pattern (Rust `regex` syntax); matched against the file with lines joined by `\n`
+    pub pattern: String,
+    /// Replacement template: `$1` / `${name}` insert captures, `$$` is a literal `$`
+    pub replacement: String,
+    /// Match case-insensitively
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Let `^` and `$` match at line boundaries instead of only at the start/end of the file
+    #[serde(default)]
+    pub multiline: bool,
+    /// Replace at most this many matches (default: all)
+    pub max_replacements: Option<usize>,
+}
+
+/// Replace the body of a Markdown section (up to the next heading of the same or higher level).
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReplaceSection {
+    /// Heading, e.g. `## Installation` (exact level) or `Installation` (any level)
+    pub section: String,
+    /// New section body; `""` empties it
+    pub new_text: String,
+    /// Replace the heading line too
+    #[serde(default)]
+    pub include_heading: bool,
+}
+
+/// Append text after the last non-blank line of a Markdown section.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AppendToSection {
+    /// Heading, e.g. `## Installation` (exact level) or `Installation` (any level)
+    pub section: String,
+    /// Text to append (non-empty); may contain `\n`
+    pub text: String,
+}
+
+/// CSV column: 1-indexed position, or a name looked up in the header (first) line.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum CsvColumn {
+    Index(usize),
+    Name(String),
+}
+
+/// Rewrite one field of an anchored CSV row, quoting as needed; other fields are kept byte-for-byte.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CsvSetCell {
+    /// LINE:HASH anchor of the row
+    pub row_anchor: String,
+    /// 1-indexed column number or header name
+    pub column: CsvColumn,
+    /// New (unquoted) field value
+    pub value: String,
+    /// Field delimiter (default `,`; `\t` for tab)
+    pub delimiter: Option<String>,
+    /// Quote character (default `"`)
+    pub quote: Option<String>,
+}
+
+/// Add a column to every row: `name` in the header (first) line, `default` elsewhere.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CsvAddColumn {
+    /// Header of the new column
+    pub name: String,
+    /// Value for the data rows (default empty)
+    #[serde(default)]
+    pub default: String,
+    /// Insert after this column (number or header name); default is the end
+    pub after: Option<CsvColumn>,
+    /// Field delimiter (default `,`; `\t` for tab)
+    pub delimiter: Option<String>,
+    /// Quote character (default `"`)
+    pub quote: Option<String>,
+}
+
+/// Set a key in an INI/.properties/.conf file wherever it currently is, or append it to its section.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct KvSet {
+    /// Key name (matched after trimming, case-sensitive)
+    pub key: String,
+    /// New value (single line, written verbatim)
+    pub value: String,
+    /// `[section]` to look in (created if missing); omit for keys before the first section
+    pub section: Option<String>,
+}
+
+/// Set a variable in a dotenv file, quoting and escaping the value as dotenv parsers expect.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EnvSet {
+    /// Variable name (`[A-Za-z_][A-Za-z0-9_.-]*`)
+    pub key: String,
+    /// Raw (unquoted) value; may contain quotes, `$`, or newlines
+    pub value: String,
+}
+
+/// Run a custom op provided by a `.wasm` plugin (listed in `.hashline/config.toml`) on an anchored range.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PluginOp {
+    /// Op name registered by a plugin
+    pub op: String,
+    /// LINE:HASH anchor of the first line the op receives
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line (default: `start_anchor`)
+    pub end_anchor: Option<String>,
+    /// Op-specific arguments, passed through to the plugin
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+/// Transform an anchored range with a sandboxed Lua 5.4 chunk (needs the `lua` build feature).
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptOp {
+    /// LINE:HASH anchor of the first line the script receives
+    pub start_anchor: String,
+    /// LINE:HASH anchor of the last line (default: `start_anchor`)
+    pub end_anchor: Option<String>,
+    /// Lua chunk; sees `lines` and `start_line`, returns the new lines (table or string) or a `function(lines, start_line)`
+    pub lua: String,
+}
+
+/// Apply `edit` only if every given condition holds on the file as read; otherwise skip it, without failing the payload.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OnlyIf {
+    /// LINE:HASH that must still be at exactly that line (no relocation)
+    pub anchor: Option<String>,
+    /// Text the file must contain
+    pub contains: Option<String>,
+    /// Text the file must not contain
+    pub not_contains: Option<String>,
+    /// The edit to apply, in the usual `{"op": {...}}` form
+    #[schemars(with = "serde_json::Value")]
+    pub edit: HashlineEdit,
+}
+
+impl OnlyIf {
+    fn holds(&self, lines: &[String]) -> Result<bool> {
+        if self.anchor.is_none() && self.contains.is_none() && self.not_contains.is_none() {
+            bail!("only_if needs at least one of `anchor` / `contains` / `not_contains`");
+        }
+        if let Some(anchor) = &self.anchor {
+            let r = parse_line_ref(anchor)?;
+            if lines.get(r.line - 1).is_none_or(|l| compute_line_hash(l) != r.hash) {
+                return Ok(false);
+            }
+        }
+        if self.contains.is_some() || self.not_contains.is_some() {
+            let text = lines.join("\n");
+            if self.contains.as_ref().is_some_and(|c| !text.contains(c.as_str())) {
+                return Ok(false);
+            }
+            if self.not_contains.as_ref().is_some_and(|c| text.contains(c.as_str())) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Set a value in a JSON document by path, rewriting only that value's text; runs after anchored edits.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JsonSet {
+    /// JSON Pointer (`/a/0/b`, `-` appends to an array) or simple JSONPath (`$.a[0].b`)
+    pub path: String,
+    /// New value; a missing last key (or array index == length) is created
+    pub value: serde_json::Value,
+}
+
+/// Remove a member or array element from a JSON document by path.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JsonDelete {
+    /// JSON Pointer or simple JSONPath of the value to remove
+    pub path: String,
+}
+
+/// Set a value in a block-style YAML document by path (same syntax as `json_set`), keeping comments.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct YamlSet {
+    /// JSON Pointer or simple JSONPath; numeric segments index sequences
+    pub path: String,
+    /// New value; a missing last key (or `-`) is created
+    pub value: serde_json::Value,
+}
+
+/// Remove a key or sequence item (with its nested block) from a block-style YAML document.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct YamlDelete {
+    /// JSON Pointer or simple JSONPath of the value to remove
+    pub path: String,
+}
+
+/// Set a value in a TOML document by path via toml_edit, keeping comments and layout.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TomlSet {
+    /// JSON Pointer or simple JSONPath, e.g. `$.dependencies.serde.version`; `-` appends to an array
+    pub path: String,
+    /// New value (objects become inline tables, or replace a `[table]` in place); null is not allowed
+    pub value: serde_json::Value,
+}
+
+/// Remove a key, array element, or `[[table]]` entry from a TOML document.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TomlRemove {
+    /// JSON Pointer or simple JSONPath of the value to remove
+    pub path: String,
+}
+
+/// Stale anchors found while validating a payload; displays as the agent-facing report.
+#[derive(Debug)]
+pub struct MismatchError {
+    /// (line, expected hash, actual hash)
+    pub mismatches: Vec<(usize, String, String)>,
+    pub report: String,
+}
+
+impl std::fmt::Display for MismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.report)
+    }
+}
+
+impl std::error::Error for MismatchError {}
+
+/// Edits in one payload that touch the same lines; re-reading will not help, the payload must change.
+#[derive(Debug)]
+pub struct EditConflict(pub String);
+
+impl std::fmt::Display for EditConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for EditConflict {}
+
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    pub line: usize,
+    pub hash: String,
+}
+
+pub fn parse_edits_payload(s: &str) -> Result<EditRequest> {
+    let value = match serde_json::from_str(s) {
+        Ok(value) => value,
+        // Trailing commas, comments, single quotes, unquoted keys: retry as JSON5, but report the
+        // strict error if that fails too.
+        Err(strict) => match json5::from_str::<serde_json::Value>(s) {
+            Ok(value) => {
+                info!("edits payload parsed as JSON5");
+                value
+            }
+            Err(_) => return Err(strict.into()),
+        },
+    };
+    parse_edits_value(value)
+}
+
+/// Like `parse_edits_payload`, for a YAML document of the same shape.
+pub fn parse_edits_yaml(s: &str) -> Result<EditRequest> {
+    parse_edits_value(serde_yaml_ng::from_str(s)?)
+}
+
+/// Accept `{"edits": [ ... ]}`, `[ ... ]`, or `{"files": [{"path": ..., "edits": [ ... ]}, ...]}`.
+/// Each edit is checked on its own, so one error lists every bad edit by index.
+pub fn parse_edits_value(value: serde_json::Value) -> Result<EditRequest> {
+    let (items, idempotency_key, files) = match value {
+        serde_json::Value::Array(items) => (items, None, None),
+        serde_json::Value::Object(mut obj) => {
+            let key = match obj.remove("idempotency_key") {
+                None | Some(serde_json::Value::Null) => None,
+                Some(serde_json::Value::String(k)) => Some(k),
+                Some(_) => bail!("idempotency_key must be a string"),
+            };
+            let items = match obj.remove("edits") {
+                None => Vec::new(),
+                Some(serde_json::Value::Array(items)) => items,
+                Some(_) => bail!("`edits` must be an array of edit objects"),
+            };
+            let files = match obj.remove("files") {
+                None => None,
+                Some(_) if !items.is_empty() => bail!("a payload has either top-level `edits` or `files`, not both"),
+                Some(serde_json::Value::Array(files)) => Some(files),
+                Some(_) => bail!("`files` must be an array of {{\"path\": ..., \"edits\": [...]}} objects"),
+            };
+            (items, key, files)
+        }
+        _ => bail!("edits payload must be an array of edits or an object with an `edits` array"),
+    };
+    let mut problems = Vec::new();
+    let mut total = 0;
+    let Some(files) = files else {
+        total = items.len();
+        let edits = parse_edit_items(items, "edits", &mut problems);
+        if !problems.is_empty() {
+            bail!("{} of {} edit(s) are invalid:\n  {}", problems.len(), total, problems.join("\n  "));
+        }
+        return Ok(EditRequest { edits, idempotency_key, files: Vec::new() });
+    };
+    if files.is_empty() {
+        bail!("`files` must list at least one file");
+    }
+    let mut parsed = Vec::with_capacity(files.len());
+    for (i, file) in files.into_iter().enumerate() {
+        let serde_json::Value::Object(mut obj) = file else {
+            bail!("files[{}] must be an object with `path` and `edits`", i);
+        };
+        let path = match obj.remove("path") {
+            Some(serde_json::Value::String(p)) if !p.is_empty() => p,
+            _ => bail!("files[{}].path must be a non-empty string", i),
+        };
+        let items = match obj.remove("edits") {
+            Some(serde_json::Value::Array(items)) => items,
+            _ => bail!("files[{}].edits must be an array of edit objects", i),
+        };
+        if let Some(field) = obj.keys().next() {
+            bail!("files[{}]: unknown field `{}` (expected `path` and `edits`)", i, field);
+        }
+        total += items.len();
+        let edits = parse_edit_items(items, &format!("files[{}].edits", i), &mut problems);
+        parsed.push(FileEdits { path, edits });
+    }
+    if !problems.is_empty() {
+        bail!("{} of {} edit(s) are invalid:\n  {}", problems.len(), total, problems.join("\n  "));
+    }
+    Ok(EditRequest { edits: Vec::new(), idempotency_key, files: parsed })
+}
+
+fn parse_edit_items(items: Vec<serde_json::Value>, label: &str, problems: &mut Vec<String>) -> Vec<HashlineEdit> {
+    let mut edits = Vec::with_capacity(items.len());
+    for (i, item) in items.into_iter().enumerate() {
+        match serde_json::from_value::<HashlineEdit>(item) {
+            Ok(edit) => edits.push(edit),
+            Err(e) => problems.push(format!("{}[{}]: {}", label, i, e)),
+        }
+    }
+    edits
+}
+
+/// The op name closest to `name`, if it is plausibly a typo of it.
+fn nearest_op(name: &str) -> Option<&'static str> {
+    EDIT_OPS
+        .iter()
+        .map(|op| (levenshtein(name, op), *op))
+        .filter(|(d, op)| *d <= (op.len() / 3).max(1))
+        .min()
+        .map(|(_, op)| op)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb { prev } else { 1 + prev.min(row[j]).min(cur) };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Short whole-file fingerprint (8 hex), computed over LF-normalized content like the line hashes.
+pub fn file_fingerprint(s: &str) -> String {
+    format!("{:08x}", xxh32(normalize_to_lf(s).as_bytes(), 0))
+}
+
+pub fn is_markdown_target(target: &str) -> bool {
+    target.rsplit_once('.').is_some_and(|(_, ext)| matches!(ext.to_ascii_lowercase().as_str(), "md" | "markdown" | "mdx"))
+}
+
+/// Line comment marker for a target, from its extension (or name, for `Makefile` and friends).
+pub fn line_comment_for_target(target: &str) -> Option<&'static str> {
+    let name = target.rsplit(['/', '\\']).next().unwrap_or(target);
+    if matches!(name, "Makefile" | "makefile" | "GNUmakefile" | "Dockerfile" | "Containerfile" | "CMakeLists.txt") {
+        return Some("#");
+    }
+    let (_, ext) = name.rsplit_once('.')?;
+    Some(match ext.to_ascii_lowercase().as_str() {
+        "rs" | "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "cs" | "java" | "js" | "jsx" | "mjs" | "cjs" | "ts"
+        | "tsx" | "go" | "swift" | "kt" | "kts" | "scala" | "dart" | "php" | "proto" | "zig" | "groovy" | "gradle"
+        | "sol" | "scss" | "less" | "jsonc" | "json5" => "//",
+        "py" | "pyi" | "sh" | "bash" | "zsh" | "fish" | "rb" | "pl" | "pm" | "r" | "yaml" | "yml" | "toml" | "conf"
+        | "cfg" | "mk" | "cmake" | "dockerfile" | "tf" | "hcl" | "nix" | "ps1" | "jl" | "ex" | "exs" | "cr" | "nim"
+        | "env" | "gitignore" | "dockerignore" | "properties" => "#",
+        "sql" | "lua" | "hs" | "elm" | "ada" | "adb" | "ads" => "--",
+        "lisp" | "lsp" | "el" | "clj" | "cljs" | "scm" | "rkt" | "asm" | "s" | "ini" => ";",
+        "tex" | "sty" | "erl" | "hrl" => "%",
+        "vim" => "\"",
+        _ => return None,
+    })
+}
+
+pub fn detect_line_ending(s: &str) -> &'static str {
+    if s.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+pub fn normalize_to_lf(s: &str) -> String {
+    s.replace("\r\n", "\n")
+}
+
+pub fn restore_line_endings(s: &str, ending: &str) -> String {
+    if ending == "\n" {
+        s.to_string()
+    } else {
+        s.replace("\n", ending)
+    }
+}
+
+pub fn split_preserve_last_empty(s: &str) -> Vec<&str> {
+    // Like JS `content.split("\n")`: keeps trailing empty line if file ends with \n.
+    // But we do NOT want to treat a trailing newline as an extra addressable empty line.
+    // So we drop exactly one final empty segment if the file ends with "\n".
+    let mut parts: Vec<&str> = s.split('\n').collect();
+    if s.ends_with('\n') {
+        if let Some(last) = parts.last() {
+            if last.is_empty() {
+                parts.pop();
+            }
+        }
+    }
+    parts
+}
+
+pub fn compute_line_hash(line: &str) -> String {
+    let mut normalized = String::with_capacity(line.len());
+    for ch in line.chars() {
+        if ch == '\r' {
+            continue;
+        }
+        if ch.is_whitespace() {
+            continue;
+        }
+        normalized.push(ch);
+    }
+
+    let h = xxh32(normalized.as_bytes(), 0);
+    let truncated = (h as u32) & 0xffff;
+    format!("{:04x}", truncated)
+}
+
+pub fn parse_line_ref(s: &str) -> Result<Anchor> {
+    let mut it = s.split(':');
+    let line_s = it.next().ok_or_else(|| anyhow!("invalid anchor: {s}"))?;
+    let hash_s = it.next().ok_or_else(|| anyhow!("invalid anchor: {s}"))?;
+    if it.next().is_some() {
+        bail!("invalid anchor (too many ':'): {s}");
+    }
+
+    let line: usize = line_s
+        .parse()
+        .map_err(|_| anyhow!("invalid line number in anchor: {s}"))?;
+    if line == 0 {
+        bail!("anchors are 1-indexed (line must be >= 1): {s}");
+    }
+
+    // `read` marks hashes shared with another line as `ab3f*`; the marker isn't part of the hash.
+    let hash = hash_s.trim().trim_end_matches('*').to_ascii_lowercase();
+    if hash.is_empty() {
+        bail!("invalid hash in anchor: {s}");
+    }
+
+    Ok(Anchor { line, hash })
+}
+
+/// One edit of a payload; alias of [`HashlineEdit`].
+pub type Edit = HashlineEdit;
+
+impl Anchor {
+    /// Anchor for `content` at 1-indexed `line`.
+    pub fn for_line(line: usize, content: &str) -> Anchor {
+        Anchor { line, hash: compute_line_hash(content) }
+    }
+}
+
+impl std::str::FromStr for Anchor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Anchor> {
+        parse_line_ref(s)
+    }
+}
+
+impl std::fmt::Display for Anchor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.hash)
+    }
+}
+
+/// Text split into addressable lines. Remembers the line ending (`\n` or `\r\n`) and whether
+/// the text ended with a newline, so [`Document::render`] round-trips unchanged content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    lines: Vec<String>,
+    line_ending: &'static str,
+    final_newline: bool,
+}
+
+impl Document {
+    pub fn parse(text: &str) -> Document {
+        let normalized = normalize_to_lf(text);
+        Document {
+            lines: split_preserve_last_empty(&normalized).into_iter().map(String::from).collect(),
+            line_ending: detect_line_ending(text),
+            final_newline: normalized.ends_with('\n'),
+        }
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Anchor of 1-indexed `line`, or `None` past the end.
+    pub fn anchor(&self, line: usize) -> Option<Anchor> {
+        let content = self.lines.get(line.checked_sub(1)?)?;
+        Some(Anchor::for_line(line, content))
+    }
+
+    pub fn anchors(&self) -> impl Iterator<Item = Anchor> + '_ {
+        self.lines.iter().enumerate().map(|(i, l)| Anchor::for_line(i + 1, l))
+    }
+
+    /// Whole-document fingerprint, as printed by `hashline read`.
+    pub fn fingerprint(&self) -> String {
+        file_fingerprint(&self.render())
+    }
+
+    /// Apply `edits` (all anchors refer to this document) and return the result.
+    pub fn apply(&self, edits: &[Edit]) -> Result<Document> {
+        self.apply_with(edits, ApplyOptions::default())
+    }
+
+    pub fn apply_with(&self, edits: &[Edit], opts: ApplyOptions) -> Result<Document> {
+        Ok(Document {
+            lines: apply_hashline_edits(self.lines.clone(), edits, opts)?,
+            line_ending: self.line_ending,
+            final_newline: self.final_newline,
+        })
+    }
+
+    /// The document as text, with its original line ending and final newline.
+    pub fn render(&self) -> String {
+        let mut out = self.lines.join("\n");
+        if self.final_newline {
+            out.push('\n');
+        }
+        restore_line_endings(&out, self.line_ending)
+    }
+}
+
+/// Apply `edits` to `text`, preserving its line endings and final newline.
+pub fn apply(text: &str, edits: &[Edit]) -> Result<String> {
+    Ok(Document::parse(text).apply(edits)?.render())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CsvDialect {
+    delimiter: char,
+    quote: char,
+}
+
+impl CsvDialect {
+    fn from_options(delimiter: Option<&str>, quote: Option<&str>) -> Result<Self> {
+        let single = |s: Option<&str>, default: char, what: &str| -> Result<char> {
+            let Some(s) = s else { return Ok(default) };
+            let s = if s == "\\t" { "\t" } else { s };
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => bail!("csv {} must be a single character, got {:?}", what, s),
+            }
+        };
+        Ok(Self { delimiter: single(delimiter, ',', "delimiter")?, quote: single(quote, '"', "quote")? })
+    }
+
+    /// Byte spans of the raw (possibly quoted) fields of one record line.
+    fn fields(&self, line: &str) -> Result<Vec<(usize, usize)>> {
+        let mut spans = Vec::new();
+        let mut start = 0;
+        let mut in_quotes = false;
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if in_quotes {
+                if c == self.quote {
+                    if chars.peek().map(|(_, n)| *n) == Some(self.quote) {
+                        chars.next();
+                    } else {
+                        in_quotes = false;
+                    }
+                }
+            } else if c == self.quote && i == start {
+                in_quotes = true;
+            } else if c == self.delimiter {
+                spans.push((start, i));
+                start = i + c.len_utf8();
+            }
+        }
+        if in_quotes {
+            bail!("unterminated quoted field (records spanning several lines are not supported)");
+        }
+        spans.push((start, line.len()));
+        Ok(spans)
+    }
+
+    fn unquote(&self, raw: &str) -> String {
+        let q = self.quote;
+        match raw.strip_prefix(q).and_then(|r| r.strip_suffix(q)) {
+            Some(inner) => inner.replace(&format!("{q}{q}"), &q.to_string()),
+            None => raw.to_string(),
+        }
+    }
+
+    /// Quote only when the value would otherwise be misread.
+    fn quote_field(&self, value: &str) -> String {
+        let q = self.quote;
+        let needs = value.contains([self.delimiter, q, '\n', '\r']) || value.starts_with(' ') || value.ends_with(' ');
+        if needs { format!("{q}{}{q}", value.replace(q, &format!("{q}{q}"))) } else { value.to_string() }
+    }
+
+    /// 0-based field index of `column`, resolving names against the header (first) line.
+    fn column_index(&self, column: &CsvColumn, header: Option<&String>) -> Result<usize> {
+        match column {
+            CsvColumn::Index(0) => bail!("csv columns are 1-indexed"),
+            CsvColumn::Index(n) => Ok(n - 1),
+            CsvColumn::Name(name) => {
+                let header = header.ok_or_else(|| anyhow!("csv column {:?}: file has no header line", name))?;
+                self.fields(header)?
+                    .iter()
+                    .position(|&(s, e)| self.unquote(&header[s..e]).trim() == name)
+                    .ok_or_else(|| anyhow!("csv column {:?} not found in header", name))
+            }
+        }
+    }
+
+    /// Insert `value` as a new field at `index` (padding short rows) without touching other fields.
+    fn insert_field(&self, line: &str, index: usize, value: &str) -> Result<String> {
+        let spans = self.fields(line)?;
+        let field = self.quote_field(value);
+        let d = self.delimiter;
+        Ok(match spans.get(index) {
+            Some(&(start, _)) => format!("{}{}{}{}", &line[..start], field, d, &line[start..]),
+            None => format!("{}{}{}", line, d.to_string().repeat(index + 1 - spans.len()), field),
+        })
+    }
+
+    /// Replace the field at `index` (padding short rows) without touching other fields.
+    fn set_field(&self, line: &str, index: usize, value: &str) -> Result<String> {
+        let spans = self.fields(line)?;
+        let field = self.quote_field(value);
+        Ok(match spans.get(index) {
+            Some(&(start, end)) => format!("{}{}{}", &line[..start], field, &line[end..]),
+            None => format!("{}{}{}", line, self.delimiter.to_string().repeat(index + 1 - spans.len()), field),
+        })
+    }
+}
+
+/// `key = value` / `key: value` line -> (key, byte offset where the value starts). Comments,
+/// section headers, and lines without a separator are not key lines.
+pub fn kv_parse(line: &str) -> Option<(&str, usize)> {
+    let t = line.trim_start();
+    if t.is_empty() || t.starts_with(['#', ';', '!', '[']) {
+        return None;
+    }
+    let sep = line.find(['=', ':'])?;
+    let key = line[..sep].trim();
+    if key.is_empty() {
+        return None;
+    }
+    let after = &line[sep + 1..];
+    Some((key, line.len() - after.trim_start_matches([' ', '\t']).len()))
+}
+
+/// Set `key` in `section` (or the section-less preamble) of an INI/.properties/.conf file. The
+/// last existing occurrence is rewritten in place; otherwise the key is appended after the
+/// section's last key line using a sibling's indentation and separator style.
+fn kv_set(lines: &mut Vec<String>, section: Option<&str>, key: &str, value: &str) -> Result<()> {
+    if value.contains('\n') {
+        bail!("kv_set.value must be a single line");
+    }
+    let is_header = |l: &str| l.trim().starts_with('[') && l.trim().ends_with(']');
+    let (start, end) = match section {
+        None => (0, lines.iter().position(|l| is_header(l)).unwrap_or(lines.len())),
+        Some(name) => match lines.iter().position(|l| is_header(l) && l.trim()[1..l.trim().len() - 1].trim() == name) {
+            Some(h) => (h + 1, lines[h + 1..].iter().position(|l| is_header(l)).map_or(lines.len(), |n| h + 1 + n)),
+            None => {
+                if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push(format!("[{}]", name));
+                lines.push(format!("{} = {}", key, value));
+                return Ok(());
+            }
+        },
+    };
+
+    let key_lines: Vec<usize> = (start..end).filter(|&i| kv_parse(&lines[i]).is_some()).collect();
+    if let Some(&i) = key_lines.iter().rev().find(|&&i| kv_parse(&lines[i]).is_some_and(|(k, _)| k == key)) {
+        let value_start = kv_parse(&lines[i]).map_or(0, |(_, v)| v);
+        lines[i] = format!("{}{}", &lines[i][..value_start], value);
+        return Ok(());
+    }
+
+    // Reuse a sibling's layout: indentation plus the exact text between key and value.
+    let sibling = key_lines.last().copied().or_else(|| lines.iter().position(|l| kv_parse(l).is_some()));
+    let line = match sibling.map(|i| &lines[i]) {
+        Some(l) => {
+            let (k, value_start) = kv_parse(l).unwrap_or(("", 0));
+            let indent = leading_whitespace(l);
+            let sep = &l[indent.len() + k.len()..value_start];
+            format!("{}{}{}{}", indent, key, sep, value)
+        }
+        None => format!("{} = {}", key, value),
+    };
+    let at = key_lines.last().map_or_else(
+        || {
+            // No keys yet: after the header, or before the trailing blank lines of the preamble.
+            let mut at = end;
+            while at > start && lines[at - 1].trim().is_empty() {
+                at -= 1;
+            }
+            if section.is_some() { start.max(at) } else { at }
+        },
+        |&i| i + 1,
+    );
+    lines.insert(at, line);
+    Ok(())
+}
+
+/// dotenv assignment (`KEY=value`, optional `export `) -> (key, byte offset where the value starts).
+pub fn env_parse(line: &str) -> Option<(&str, usize)> {
+    let t = line.trim_start();
+    let body = t.strip_prefix("export ").map_or(t, |b| b.trim_start());
+    let key_len = body.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-'))?;
+    let key = &body[..key_len];
+    if key.is_empty() || key.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let rest = body[key_len..].trim_start_matches([' ', '\t']).strip_prefix('=')?;
+    Some((key, line.len() - rest.trim_start_matches([' ', '\t']).len()))
+}
+
+/// Quote a dotenv value: bare when safe, single quotes (literal) when possible, otherwise
+/// double quotes with `\`, `"`, `$`, backtick, and newline escaped.
+fn env_quote(value: &str) -> String {
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "_./:@%+,-".contains(c)) {
+        return value.to_string();
+    }
+    if !value.contains(['\'', '\n', '\r']) {
+        return format!("'{}'", value);
+    }
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' | '"' | '$' | '`' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Last line (index) of the value starting at `lines[i][value_start..]`, following quoted values
+/// that continue onto later lines.
+fn env_value_end(lines: &[String], i: usize, value_start: usize) -> usize {
+    let value = &lines[i][value_start..];
+    let Some(q) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else { return i };
+    let closes = |s: &str| {
+        let mut escaped = false;
+        s.chars().any(|c| {
+            let hit = c == q && !escaped;
+            escaped = q == '"' && c == '\\' && !escaped;
+            hit
+        })
+    };
+    if closes(&value[1..]) {
+        return i;
+    }
+    (i + 1..lines.len()).find(|&j| closes(&lines[j])).unwrap_or(i)
+}
+
+/// Set `key` in a dotenv file: rewrite the last assignment in place (keeping `export ` and the
+/// `=` spacing), or append `KEY=value`.
+fn env_set(lines: &mut Vec<String>, key: &str, value: &str) -> Result<()> {
+    if env_parse(&format!("{}=", key)).is_none_or(|(k, _)| k != key) {
+        bail!("env_set.key {:?} is not a valid variable name", key);
+    }
+    let quoted = env_quote(value);
+    match lines.iter().rposition(|l| env_parse(l).is_some_and(|(k, _)| k == key)) {
+        Some(i) => {
+            let value_start = env_parse(&lines[i]).map_or(0, |(_, v)| v);
+            let end = env_value_end(lines, i, value_start);
+            let line = format!("{}{}", &lines[i][..value_start], quoted);
+            lines.splice(i..=end, [line]);
+        }
+        None => {
+            let mut at = lines.len();
+            while at > 0 && lines[at - 1].is_empty() {
+                at -= 1;
+            }
+            lines.insert(at, format!("{}={}", key, quoted));
+        }
+    }
+    Ok(())
+}
+
+/// ATX heading (`## Title ##`, up to 3 leading spaces) -> (level, title).
+fn markdown_heading(line: &str) -> Option<(usize, &str)> {
+    let t = line.strip_prefix("   ").or_else(|| line.strip_prefix("  ")).or_else(|| line.strip_prefix(' ')).unwrap_or(line);
+    let level = t.bytes().take_while(|&b| b == b'#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &t[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let title = rest.trim();
+    let title = match title.trim_end_matches('#') {
+        stripped if stripped.is_empty() || stripped.ends_with([' ', '\t']) => stripped.trim_end(),
+        _ => title,
+    };
+    Some((level, title))
+}
+
+/// Resolve a section spec (`## Installation`, `section:"## Installation"`, or a bare title matching
+/// any level) to its 1-indexed (heading line, last non-blank line before the next heading of the
+/// same or higher level). Headings inside fenced code blocks are ignored.
+fn find_markdown_section(lines: &[String], spec: &str) -> Result<(usize, usize)> {
+    let spec = spec.strip_prefix("section:").unwrap_or(spec).trim();
+    let spec = match spec.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(_) => serde_json::from_str::<String>(spec).unwrap_or_else(|_| spec[1..spec.len() - 1].to_string()),
+        None => spec.to_string(),
+    };
+    let wanted = markdown_heading(&spec);
+
+    let mut headings: Vec<(usize, usize, &str)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    let body_start = front_matter_end(lines).map_or(0, |close| close + 1);
+    for (i, line) in lines.iter().enumerate().skip(body_start) {
+        let t = line.trim_start();
+        match fence {
+            Some(f) if t.starts_with(f) => fence = None,
+            Some(_) => {}
+            None if t.starts_with("```") => fence = Some("```"),
+            None if t.starts_with("~~~") => fence = Some("~~~"),
+            None => {
+                if let Some((level, title)) = markdown_heading(line) {
+                    headings.push((i, level, title));
+                }
+            }
+        }
+    }
+
+    let matches: Vec<usize> = (0..headings.len())
+        .filter(|&n| {
+            let (_, level, title) = headings[n];
+            match wanted {
+                Some((l, t)) => level == l && title == t,
+                None => title == spec,
+            }
+        })
+        .collect();
+    let n = match matches.as_slice() {
+        [n] => *n,
+        [] => bail!("section {:?} not found", spec),
+        many => {
+            let at: Vec<String> = many.iter().map(|&n| (headings[n].0 + 1).to_string()).collect();
+            bail!("section {:?} is ambiguous (headings at lines {})", spec, at.join(", "));
+        }
+    };
+    let (start, level, _) = headings[n];
+    let next = headings[n + 1..].iter().find(|(_, l, _)| *l <= level).map_or(lines.len(), |(i, _, _)| *i);
+    let last = (start + 1..next).rev().find(|&i| !lines[i].trim().is_empty()).unwrap_or(start);
+    Ok((start + 1, last + 1))
+}
+
+/// `LINE:HASH`, or `section:"## Title"` resolved against the current file (its heading line,
+/// or the section's last line when `end` is set).
+fn resolve_anchor(s: &str, lines: &[String], end: bool) -> Result<Anchor> {
+    if !s.starts_with("section:") {
+        return parse_line_ref(s);
+    }
+    let (heading, last) = find_markdown_section(lines, s)?;
+    let line = if end { last } else { heading };
+    Ok(Anchor { line, hash: compute_line_hash(&lines[line - 1]) })
+}
+
+/// Project settings read from a `.hashline/config.toml`.
+#[cfg(feature = "plugins")]
+#[derive(Debug, Default, Deserialize)]
+struct ProjectConfig {
+    /// `.wasm` (or `.wat`) plugin modules that provide custom edit ops.
+    #[serde(default)]
+    plugins: Vec<PathBuf>,
+}
+
+/// Compiled plugin modules with the op names each registered via `hashline_ops`.
+///
+/// Guest ABI: export `memory`, `hashline_alloc(len: i32) -> i32`, `hashline_ops() -> i64`
+/// (JSON array of op names) and `hashline_apply(ptr: i32, len: i32) -> i64`, which receives
+/// `{"op", "args", "start_line", "lines"}` and returns `{"lines": [...]}` or `{"error": "..."}`.
+/// Results are returned as `(ptr << 32) | len` into guest memory.
+#[cfg(feature = "plugins")]
+struct PluginHost {
+    engine: wasmtime::Engine,
+    plugins: Vec<(PathBuf, wasmtime::Module, Vec<String>)>,
+}
+
+/// The plugins `plugin` edits may call: those listed in one project config file, loaded on first use.
+pub struct Plugins {
+    config: PathBuf,
+    #[cfg(feature = "plugins")]
+    host: std::sync::OnceLock<PluginHost>,
+}
+
+impl Plugins {
+    /// Plugins listed in `config` (a `.hashline/config.toml`); a missing file lists none.
+    pub fn from_config(config: impl Into<PathBuf>) -> Self {
+        Plugins {
+            config: config.into(),
+            #[cfg(feature = "plugins")]
+            host: std::sync::OnceLock::new(),
+        }
+    }
+
+    #[cfg(feature = "plugins")]
+    fn host(&self) -> Result<&PluginHost> {
+        if let Some(host) = self.host.get() {
+            return Ok(host);
+        }
+        let host = PluginHost::load(&self.config)?;
+        Ok(self.host.get_or_init(|| host))
+    }
+}
+
+impl std::fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugins").field("config", &self.config).finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "plugins")]
+impl PluginHost {
+    fn load(config_path: &Path) -> Result<Self> {
+        let config: ProjectConfig = match fs::read_to_string(config_path) {
+            Ok(raw) => toml::from_str(&raw).with_context(|| format!("plugins: invalid {}", config_path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ProjectConfig::default(),
+            Err(e) => return Err(e).with_context(|| format!("plugins: failed to read {}", config_path.display())),
+        };
+        let engine = wasmtime::Engine::default();
+        let mut plugins = Vec::new();
+        for path in config.plugins {
+            let module = wasmtime::Module::from_file(&engine, &path)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("plugins: failed to load {}", path.display()))?;
+            let ops = Self::invoke(&engine, &module, "hashline_ops", None)
+                .and_then(|out| Ok(serde_json::from_slice::<Vec<String>>(&out)?))
+                .with_context(|| format!("plugins: {} did not register its ops", path.display()))?;
+            debug!(plugin = %path.display(), ?ops, "loaded plugin");
+            plugins.push((path, module, ops));
+        }
+        Ok(Self { engine, plugins })
+    }
+
+    /// Call `export` on a fresh instance of `module`, copying `input` into guest memory first.
+    fn invoke(engine: &wasmtime::Engine, module: &wasmtime::Module, export: &str, input: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut store = wasmtime::Store::new(engine, ());
+        let instance = wasmtime::Instance::new(&mut store, module, &[])?;
+        let memory = instance.get_memory(&mut store, "memory").ok_or_else(|| anyhow!("plugin does not export `memory`"))?;
+        let packed = match input {
+            None => instance.get_typed_func::<(), i64>(&mut store, export)?.call(&mut store, ())?,
+            Some(bytes) => {
+                let len = i32::try_from(bytes.len())?;
+                let ptr = instance.get_typed_func::<i32, i32>(&mut store, "hashline_alloc")?.call(&mut store, len)?;
+                memory.write(&mut store, ptr as u32 as usize, bytes)?;
+                instance.get_typed_func::<(i32, i32), i64>(&mut store, export)?.call(&mut store, (ptr, len))?
+            }
+        } as u64;
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        memory
+            .data(&store)
+            .get(ptr..ptr + len)
+            .map(|b| b.to_vec())
+            .ok_or_else(|| anyhow!("plugin returned an out-of-bounds result"))
+    }
+}
+
+/// Run plugin op `op` on `lines` (the anchored region starting at 1-indexed `start_line`).
+#[cfg(feature = "plugins")]
+fn run_plugin_op(
+    plugins: Option<&Plugins>,
+    op: &str,
+    args: &serde_json::Value,
+    lines: &[String],
+    start_line: usize,
+) -> Result<Vec<String>> {
+    #[derive(Deserialize)]
+    struct PluginOutput {
+        lines: Option<Vec<String>>,
+        error: Option<String>,
+    }
+
+    let plugins = plugins.ok_or_else(|| anyhow!("plugin op {:?}: no plugin config was given", op))?;
+    let host = plugins.host()?;
+    let (path, module, _) = host
+        .plugins
+        .iter()
+        .find(|(_, _, ops)| ops.iter().any(|o| o == op))
+        .ok_or_else(|| anyhow!("plugin op {:?}: no plugin in {} registers it", op, plugins.config.display()))?;
+    let input = serde_json::to_vec(&json!({ "op": op, "args": args, "start_line": start_line, "lines": lines }))?;
+    let out = PluginHost::invoke(&host.engine, module, "hashline_apply", Some(&input))
+        .with_context(|| format!("plugin op {:?}: {} failed", op, path.display()))?;
+    let out: PluginOutput =
+        serde_json::from_slice(&out).with_context(|| format!("plugin op {:?}: {} returned invalid JSON", op, path.display()))?;
+    match (out.lines, out.error) {
+        (_, Some(e)) => bail!("plugin op {:?}: {}", op, e),
+        (Some(lines), None) => Ok(lines),
+        (None, None) => bail!("plugin op {:?}: {} returned neither `lines` nor `error`", op, path.display()),
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+fn run_plugin_op(
+    _plugins: Option<&Plugins>,
+    op: &str,
+    _args: &serde_json::Value,
+    _lines: &[String],
+    _start_line: usize,
+) -> Result<Vec<String>> {
+    bail!("plugin op {:?}: this hashline was built without plugin support (cargo feature `plugins`)", op)
+}
+
+/// Instruction budget for `script` ops, in units of `SCRIPT_HOOK_EVERY` instructions.
+#[cfg(feature = "lua")]
+const SCRIPT_MAX_TICKS: u32 = 10_000;
+#[cfg(feature = "lua")]
+const SCRIPT_HOOK_EVERY: u32 = 10_000;
+/// Memory a `script` op's Lua state may allocate, in bytes.
+#[cfg(feature = "lua")]
+const SCRIPT_MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Evaluate a `script` op's Lua chunk over `lines` in a sandbox (string/table/math/utf8 only,
+/// bounded instruction count and memory). The chunk sees `lines` and `start_line` as globals and returns a
+/// table of lines, a string, or a function that is then called as `f(lines, start_line)`.
+#[cfg(feature = "lua")]
+fn run_script_op(code: &str, lines: &[String], start_line: usize) -> Result<Vec<String>> {
+    use mlua::{HookTriggers, Lua, LuaOptions, StdLib, Value, VmState};
+
+    let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8, LuaOptions::default())?;
+    lua.set_memory_limit(SCRIPT_MAX_MEMORY)?;
+    let ticks = AtomicUsize::new(0);
+    lua.set_hook(HookTriggers::new().every_nth_instruction(SCRIPT_HOOK_EVERY), move |_, _| {
+        if ticks.fetch_add(1, Ordering::Relaxed) >= SCRIPT_MAX_TICKS as usize {
+            return Err(mlua::Error::runtime("script exceeded its instruction budget"));
+        }
+        Ok(VmState::Continue)
+    })?;
+    let input = lua.create_sequence_from(lines.iter().map(String::as_str))?;
+    lua.globals().set("lines", input.clone())?;
+    lua.globals().set("start_line", start_line)?;
+
+    let mut result: Value = lua.load(code).set_name("script").eval()?;
+    if let Value::Function(f) = &result {
+        result = f.call((input, start_line))?;
+    }
+    match result {
+        Value::Table(t) => Ok(t.sequence_values::<String>().collect::<mlua::Result<Vec<_>>>()?),
+        Value::String(s) => Ok(split_dst_lines(&s.to_str()?)),
+        other => bail!("script must return a table of lines or a string (got {})", other.type_name()),
+    }
+}
+
+#[cfg(not(feature = "lua"))]
+fn run_script_op(_code: &str, _lines: &[String], _start_line: usize) -> Result<Vec<String>> {
+    bail!("script op: this hashline was built without Lua support (cargo feature `lua`)")
+}
+
+/// Per-target behavior for `apply_hashline_edits`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ApplyOptions<'a> {
+    /// Markdown target: a leading `---` block is YAML front matter (yaml ops edit it; line
+    /// ranges may not straddle it; `replace` skips it).
+    pub front_matter: bool,
+    /// Line comment marker `comment_range` / `uncomment_range` use when the edit doesn't give one.
+    pub line_comment: Option<&'static str>,
+    /// Treat every edit as `skip_if_applied`.
+    pub idempotent: bool,
+    /// Where `plugin` edits find their ops; without it they fail.
+    pub plugins: Option<&'a Plugins>,
+}
+
+impl ApplyOptions<'_> {
+    /// Options implied by a target's name.
+    pub fn for_target(target: &str) -> Self {
+        ApplyOptions {
+            front_matter: is_markdown_target(target),
+            line_comment: line_comment_for_target(target),
+            ..ApplyOptions::default()
+        }
+    }
+}
+
+/// What `apply_hashline_edits_report` did beyond producing the new lines.
+#[derive(Debug, Default, Clone)]
+pub struct ApplyReport {
+    /// `(edit index, matches replaced)` for each replace-style edit, in payload order.
+    pub replacements: Vec<(usize, usize)>,
+    /// Indices of `only_if` edits whose condition did not hold.
+    pub skipped: Vec<usize>,
+    /// Indices of edits skipped because the file already showed their result.
+    pub already_applied: Vec<usize>,
+    /// Every resolved step, in the bottom-up order they were applied.
+    pub steps: Vec<PlanStep>,
+    /// Anchors that no longer matched their line and were moved to the unique line with their hash.
+    pub relocations: usize,
+}
+
+/// One concrete operation an edit resolved to. Line numbers refer to the file as read.
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    /// Index of the edit in the payload
+    pub edit: usize,
+    /// "replace lines 40-52 with 3 lines", "insert 1 line after line 7", ...
+    pub action: String,
+    /// The lines it replaces or transforms, or the line it inserts after (1-based, inclusive)
+    pub lines: Option<(usize, usize)>,
+}
+
+fn line_count(n: usize) -> String {
+    if n == 1 {
+        "1 line".to_string()
+    } else {
+        format!("{} lines", n)
+    }
+}
+
+/// "line 7", "lines 40-52"
+fn describe_lines(start: usize, end: usize) -> String {
+    if start == end {
+        format!("line {}", start)
+    } else {
+        format!("lines {}-{}", start, end)
+    }
+}
+
+/// "replace lines 40-52 with 3 lines", "delete line 7", ...
+fn describe_splice(start: usize, end: usize, count: usize) -> String {
+    let target = describe_lines(start, end);
+    match count {
+        0 => format!("delete {}", target),
+        n => format!("replace {} with {}", target, line_count(n)),
+    }
+}
+
+fn describe_insert(at: usize, count: usize) -> String {
+    match at {
+        0 => format!("insert {} at the top", line_count(count)),
+        at => format!("insert {} after line {}", line_count(count), at),
+    }
+}
+
+/// 0-based index of the closing `---`/`...` of a leading front matter block.
+pub fn front_matter_end(lines: &[String]) -> Option<usize> {
+    if lines.first()?.trim_end() != "---" {
+        return None;
+    }
+    lines.iter().skip(1).position(|l| matches!(l.trim_end(), "---" | "...")).map(|n| n + 1)
+}
+
+pub fn apply_hashline_edits(lines: Vec<String>, edits: &[HashlineEdit], opts: ApplyOptions) -> Result<Vec<String>> {
+    Ok(apply_hashline_edits_report(lines, edits, opts)?.0)
+}
+
+/// An edit resolved against the file: anchors parsed, options checked, defaults filled in.
+#[derive(Clone)]
+enum ParsedSpec {
+    Single { r: Anchor, dst: String, auto_indent: bool },
+    Range { start: Anchor, end: Anchor, dst: String, auto_indent: bool },
+    InsertAfter { after: Anchor, dst: String, auto_indent: bool },
+    ReplaceText { old: String, new_: String, occurrence: usize, max: Option<usize> },
+    Regex { re: regex::Regex, replacement: String, max: Option<usize> },
+    Json { path: Vec<String>, value: Option<serde_json::Value> },
+    Yaml { path: Vec<String>, value: Option<serde_json::Value> },
+    Toml { path: Vec<String>, value: Option<serde_json::Value> },
+    CsvCell { r: Anchor, column: CsvColumn, value: String, dialect: CsvDialect },
+    CsvAddColumn { name: String, default: String, after: Option<CsvColumn>, dialect: CsvDialect },
+    Kv { section: Option<String>, key: String, value: String },
+    Env { key: String, value: String },
+    Plugin { start: Anchor, end: Anchor, op: String, args: serde_json::Value },
+    Script { start: Anchor, end: Anchor, code: String },
+    Indent { start: Anchor, end: Anchor, unit: IndentUnit, dedent: bool },
+    Comment { start: Anchor, end: Anchor, marker: String, uncomment: bool },
+    Sort { start: Anchor, end: Anchor, case_insensitive: bool, unique: bool },
+    Dedupe { start: Anchor, end: Anchor, all: bool },
+    Swap { start: Anchor, end: Anchor, with_start: Anchor, with_end: Anchor },
+    Move { start: Anchor, end: Anchor, dest: Anchor, before: bool, copy: bool },
+    // Lines spliced in at a 0-indexed position; produced from `Move`.
+    Insert { at: usize, lines: Vec<String> },
+    // Lines start..=end replaced by exactly these lines (an empty line stays a line).
+    Splice { start: Anchor, end: Anchor, lines: Vec<String> },
+    ReplaceInRange { start: Anchor, end: Anchor, old: String, new_: String, all: bool },
+    Append { lines: Vec<String> },
+    Prepend { lines: Vec<String> },
+}
+
+impl ParsedSpec {
+    /// Where the edit applies, for ordering: edits are applied bottom-up so earlier ones keep
+    /// later line numbers valid. Replacements and structural ops don't use anchors and run last,
+    /// in payload order.
+    fn apply_order(&self) -> (usize, usize) {
+        match self {
+            ParsedSpec::Single { r, .. } | ParsedSpec::CsvCell { r, .. } => (r.line, 0),
+            ParsedSpec::Range { end, .. }
+            | ParsedSpec::Splice { end, .. }
+            | ParsedSpec::Plugin { end, .. }
+            | ParsedSpec::Script { end, .. }
+            | ParsedSpec::Indent { end, .. }
+            | ParsedSpec::Comment { end, .. }
+            | ParsedSpec::Sort { end, .. }
+            | ParsedSpec::Dedupe { end, .. }
+            | ParsedSpec::Swap { end, .. }
+            | ParsedSpec::ReplaceInRange { end, .. } => (end.line, 0),
+            ParsedSpec::InsertAfter { after, .. } => (after.line, 1),
+            ParsedSpec::Insert { at, .. } => (*at, 1),
+            // Append goes first (so it ends up below anything inserted at EOF), prepend last of the
+            // anchored ops (so it ends up above anything inserted before line 1).
+            ParsedSpec::Append { .. } => (usize::MAX, 0),
+            ParsedSpec::Prepend { .. } => (0, 0),
+            ParsedSpec::Move { end, .. } => (end.line, 0),
+            ParsedSpec::ReplaceText { .. }
+            | ParsedSpec::Regex { .. }
+            | ParsedSpec::Json { .. }
+            | ParsedSpec::Yaml { .. }
+            | ParsedSpec::Toml { .. }
+            | ParsedSpec::CsvAddColumn { .. }
+            | ParsedSpec::Kv { .. }
+            | ParsedSpec::Env { .. } => (0, 9),
+        }
+    }
+
+    /// The plan step the edit stands for, and the lines it covers. Text and regex replacements
+    /// describe themselves once their match counts are known.
+    fn describe(&self) -> Option<(String, Option<(usize, usize)>)> {
+        let path_of = |path: &[String]| path.join(".");
+        let span = match self {
+            ParsedSpec::Single { r, .. } | ParsedSpec::CsvCell { r, .. } => Some((r.line, r.line)),
+            ParsedSpec::InsertAfter { after: r, .. } if r.line > 0 => Some((r.line, r.line)),
+            ParsedSpec::Insert { at, .. } if *at > 0 => Some((*at, *at)),
+            ParsedSpec::Range { start, end, .. }
+            | ParsedSpec::Splice { start, end, .. }
+            | ParsedSpec::Plugin { start, end, .. }
+            | ParsedSpec::Script { start, end, .. }
+            | ParsedSpec::Indent { start, end, .. }
+            | ParsedSpec::Comment { start, end, .. }
+            | ParsedSpec::Sort { start, end, .. }
+            | ParsedSpec::Dedupe { start, end, .. }
+            | ParsedSpec::Swap { start, end, .. }
+            | ParsedSpec::ReplaceInRange { start, end, .. } => Some((start.line, end.line)),
+            _ => None,
+        };
+        let action = match self {
+            ParsedSpec::Single { r, dst, .. } => describe_splice(r.line, r.line, split_dst_lines(dst).len()),
+            ParsedSpec::Range { start, end, dst, .. } => describe_splice(start.line, end.line, split_dst_lines(dst).len()),
+            ParsedSpec::Splice { start, end, lines } => describe_splice(start.line, end.line, lines.len()),
+            ParsedSpec::InsertAfter { after, dst, .. } => describe_insert(after.line, split_dst_lines(dst).len()),
+            ParsedSpec::Insert { at, lines } => describe_insert(*at, lines.len()),
+            ParsedSpec::Append { lines } => format!("append {} at the end", line_count(lines.len())),
+            ParsedSpec::Prepend { lines } => describe_insert(0, lines.len()),
+            ParsedSpec::CsvCell { r, .. } => format!("set a csv cell on line {}", r.line),
+            ParsedSpec::CsvAddColumn { name, .. } => format!("add csv column {:?}", name),
+            ParsedSpec::Json { path, value: Some(_) } => format!("set json {}", path_of(path)),
+            ParsedSpec::Json { path, value: None } => format!("delete json {}", path_of(path)),
+            ParsedSpec::Yaml { path, value: Some(_) } => format!("set yaml {}", path_of(path)),
+            ParsedSpec::Yaml { path, value: None } => format!("delete yaml {}", path_of(path)),
+            ParsedSpec::Toml { path, value: Some(_) } => format!("set toml {}", path_of(path)),
+            ParsedSpec::Toml { path, value: None } => format!("remove toml {}", path_of(path)),
+            ParsedSpec::Kv { key, .. } => format!("set key {:?}", key),
+            ParsedSpec::Env { key, .. } => format!("set {}", key),
+            ParsedSpec::Plugin { start, end, op, .. } => {
+                format!("run plugin op {} on {}", op, describe_lines(start.line, end.line))
+            }
+            ParsedSpec::Script { start, end, .. } => format!("run a script on {}", describe_lines(start.line, end.line)),
+            ParsedSpec::Indent { start, end, dedent, .. } => {
+                format!("{} {}", if *dedent { "dedent" } else { "indent" }, describe_lines(start.line, end.line))
+            }
+            ParsedSpec::Comment { start, end, uncomment, .. } => {
+                format!("{} {}", if *uncomment { "uncomment" } else { "comment out" }, describe_lines(start.line, end.line))
+            }
+            ParsedSpec::Sort { start, end, unique, .. } => {
+                let unique = if *unique { ", dropping duplicates" } else { "" };
+                format!("sort {}{}", describe_lines(start.line, end.line), unique)
+            }
+            ParsedSpec::Dedupe { start, end, .. } => {
+                format!("drop duplicate lines in {}", describe_lines(start.line, end.line))
+            }
+            ParsedSpec::Swap { start, end, with_start, with_end } => format!(
+                "swap {} with {}",
+                describe_lines(start.line, end.line),
+                describe_lines(with_start.line, with_end.line)
+            ),
+            ParsedSpec::ReplaceInRange { start, end, old, all, .. } => format!(
+                "replace {} {:?} in {}",
+                if *all { "every" } else { "the first" },
+                old,
+                describe_lines(start.line, end.line)
+            ),
+            _ => return None,
+        };
+        Some((action, span))
+    }
+}
+
+/// Like `apply_hashline_edits`, also reporting per-edit details such as replacement counts.
+pub fn apply_hashline_edits_report(
+    mut lines: Vec<String>,
+    edits: &[HashlineEdit],
+    opts: ApplyOptions,
+) -> Result<(Vec<String>, ApplyReport)> {
+    let mut report = ApplyReport::default();
+    if edits.is_empty() {
+        return Ok((lines, report));
+    }
+
+    // Parse and validate all anchors before mutating. Relocate if hash is uniquely found elsewhere.
+    let mut guards = Vec::new();
+    let mut parsed = parse_specs(edits, &lines, opts, &mut guards, &mut report)?;
+    verify_specs(&mut parsed, &guards, &lines, &mut report)?;
+    resolve_range_ops(&mut parsed, &lines, opts, &mut report)?;
+    check_conflicts(&parsed, edits, &lines, opts)?;
+    let mut parsed = expand_moves_and_swaps(parsed, &lines);
+
+    // Descending by line, then precedence; the sort is stable, so ties keep payload order.
+    parsed.sort_by_key(|(_, spec)| std::cmp::Reverse(spec.apply_order()));
+    for (idx, spec) in parsed {
+        if let Some((action, lines)) = spec.describe() {
+            report.steps.push(PlanStep { edit: idx, action, lines });
+        }
+        apply_spec(&mut lines, idx, spec, opts, &mut report)?;
+    }
+
+    report.replacements.sort_unstable();
+    Ok((lines, report))
+}
+
+/// (position in the specs, op name, guard) for an edit with `expected_text` / `expected_prefix`.
+type SpecGuard = (usize, &'static str, TextGuard);
+
+/// Resolve each edit to a `ParsedSpec`, in payload order, noting the ones `only_if` or
+/// `skip_if_applied` leave out, and collect their content guards.
+fn parse_specs(
+    edits: &[HashlineEdit],
+    lines: &[String],
+    opts: ApplyOptions,
+    guards: &mut Vec<SpecGuard>,
+    report: &mut ApplyReport,
+) -> Result<Vec<(usize, ParsedSpec)>> {
+    let mut parsed: Vec<(usize, ParsedSpec)> = Vec::new();
+
+    for (idx, mut edit) in edits.iter().enumerate() {
+        while let HashlineEdit::OnlyIf { only_if } = edit {
+            if !only_if.holds(lines)? {
+                break;
+            }
+            edit = &only_if.edit;
+        }
+        if (opts.idempotent || skip_if_applied(edit)) && already_applied(edit, lines) {
+            report.already_applied.push(idx);
+            continue;
+        }
+        match edit {
+            HashlineEdit::OnlyIf { .. } => report.skipped.push(idx),
+            HashlineEdit::SetLine { set_line } => {
+                let r = resolve_anchor(&set_line.anchor, lines, false)?;
+                if let Some(guard) = TextGuard::new("set_line", &set_line.expected_text, &set_line.expected_prefix)? {
+                    guards.push((parsed.len(), "set_line", guard));
+                }
+                parsed.push((
+                    idx,
+                    ParsedSpec::Single { r, dst: set_line.new_text.clone(), auto_indent: set_line.auto_indent },
+                ));
+            }
+            HashlineEdit::ReplaceLines { replace_lines } => {
+                let start = resolve_anchor(&replace_lines.start_anchor, lines, false)?;
+                let end = resolve_anchor(&replace_lines.end_anchor, lines, true)?;
+                if let Some(guard) = TextGuard::new("replace_lines", &replace_lines.expected_text, &replace_lines.expected_prefix)? {
+                    guards.push((parsed.len(), "replace_lines", guard));
+                }
+                parsed.push((
+                    idx,
+                    ParsedSpec::Range {
+                        start,
+                        end,
+                        dst: replace_lines.new_text.clone(),
+                        auto_indent: replace_lines.auto_indent,
+                    },
+                ));
+            }
+            HashlineEdit::DeleteLines { delete_lines } => {
+                let start = resolve_anchor(&delete_lines.start_anchor, lines, false)?;
+                let end = match &delete_lines.end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => start.clone(),
+                };
+                parsed.push((idx, ParsedSpec::Range { start, end, dst: String::new(), auto_indent: false }));
+            }
+            HashlineEdit::MoveLines { move_lines: MoveLines { start_anchor, end_anchor, after, before } }
+            | HashlineEdit::CopyLines { copy_lines: CopyLines { start_anchor, end_anchor, after, before } } => {
+                let copy = matches!(edit, HashlineEdit::CopyLines { .. });
+                let start = resolve_anchor(start_anchor, lines, false)?;
+                let end = match end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => start.clone(),
+                };
+                let (dest, before) = match (after, before) {
+                    (Some(a), None) => (resolve_anchor(a, lines, true)?, false),
+                    (None, Some(b)) => (resolve_anchor(b, lines, false)?, true),
+                    _ => bail!("{} needs exactly one of `after` / `before`", if copy { "copy_lines" } else { "move_lines" }),
+                };
+                parsed.push((idx, ParsedSpec::Move { start, end, dest, before, copy }));
+            }
+            HashlineEdit::Append { append: AppendText { text, .. } } | HashlineEdit::Prepend { prepend: PrependText { text, .. } } => {
+                let append = matches!(edit, HashlineEdit::Append { .. });
+                if text.is_empty() {
+                    bail!("{}.text must be non-empty", if append { "append" } else { "prepend" });
+                }
+                let lines = split_dst_lines(text);
+                parsed.push((idx, if append { ParsedSpec::Append { lines } } else { ParsedSpec::Prepend { lines } }));
+            }
+            HashlineEdit::InsertAfter { insert_after } => {
+                let after = resolve_anchor(&insert_after.anchor, lines, false)?;
+                if insert_after.text.is_empty() {
+                    bail!("insert_after.text must be non-empty");
+                }
+                if let Some(guard) = TextGuard::new("insert_after", &insert_after.expected_text, &insert_after.expected_prefix)? {
+                    guards.push((parsed.len(), "insert_after", guard));
+                }
+                parsed.push((
+                    idx,
+                    ParsedSpec::InsertAfter {
+                        after,
+                        dst: insert_after.text.clone(),
+                        auto_indent: insert_after.auto_indent,
+                    },
+                ));
+            }
+            HashlineEdit::ReplaceInRange { replace_in_range: r } => {
+                if r.old_text.is_empty() {
+                    bail!("replace_in_range.old_text must be non-empty");
+                }
+                let start = resolve_anchor(&r.start_anchor, lines, false)?;
+                let end = resolve_anchor(&r.end_anchor, lines, true)?;
+                parsed.push((
+                    idx,
+                    ParsedSpec::ReplaceInRange {
+                        start,
+                        end,
+                        old: r.old_text.clone(),
+                        new_: r.new_text.clone(),
+                        all: r.all.unwrap_or(false),
+                    },
+                ));
+            }
+            HashlineEdit::RegexReplace { regex_replace: r } => {
+                let re = regex::RegexBuilder::new(&r.pattern)
+                    .case_insensitive(r.case_insensitive)
+                    .multi_line(r.multiline)
+                    .build()
+                    .with_context(|| format!("regex_replace: invalid pattern {:?}", r.pattern))?;
+                if r.max_replacements == Some(0) {
+                    bail!("regex_replace.max_replacements must be at least 1");
+                }
+                parsed.push((idx, ParsedSpec::Regex { re, replacement: r.replacement.clone(), max: r.max_replacements }));
+            }
+            HashlineEdit::Replace { replace } => {
+                if replace.old_text.is_empty() {
+                    bail!("replace.old_text must be non-empty");
+                }
+                if replace.occurrence == Some(0) || replace.max_count == Some(0) {
+                    bail!("replace.occurrence and replace.max_count are 1-based and must be at least 1");
+                }
+                let max = match replace.max_count {
+                    Some(n) => Some(n),
+                    None if replace.all.unwrap_or(false) => None,
+                    None => Some(1),
+                };
+                parsed.push((
+                    idx,
+                    ParsedSpec::ReplaceText {
+                        old: replace.old_text.clone(),
+                        new_: replace.new_text.clone(),
+                        occurrence: replace.occurrence.unwrap_or(1),
+                        max,
+                    },
+                ));
+            }
+            HashlineEdit::ReplaceSection { replace_section } => {
+                let (heading, last) = find_markdown_section(lines, &replace_section.section)?;
+                // Keep the blank lines between the heading and its body.
+                let first = if replace_section.include_heading {
+                    heading
+                } else {
+                    (heading + 1..=last).find(|&l| !lines[l - 1].trim().is_empty()).unwrap_or(heading + 1)
+                };
+                let at = |line: usize| Anchor { line, hash: compute_line_hash(&lines[line - 1]) };
+                let dst = replace_section.new_text.clone();
+                if first <= last {
+                    parsed.push((idx, ParsedSpec::Range { start: at(first), end: at(last), dst, auto_indent: false }));
+                } else if !dst.is_empty() {
+                    parsed.push((idx, ParsedSpec::InsertAfter { after: at(heading), dst, auto_indent: false }));
+                }
+            }
+            HashlineEdit::AppendToSection { append_to_section } => {
+                if append_to_section.text.is_empty() {
+                    bail!("append_to_section.text must be non-empty");
+                }
+                let (_, last) = find_markdown_section(lines, &append_to_section.section)?;
+                let after = Anchor { line: last, hash: compute_line_hash(&lines[last - 1]) };
+                parsed.push((idx, ParsedSpec::InsertAfter { after, dst: append_to_section.text.clone(), auto_indent: false }));
+            }
+            HashlineEdit::CsvSetCell { csv_set_cell } => {
+                let r = resolve_anchor(&csv_set_cell.row_anchor, lines, false)?;
+                let dialect = CsvDialect::from_options(csv_set_cell.delimiter.as_deref(), csv_set_cell.quote.as_deref())?;
+                parsed.push((
+                    idx,
+                    ParsedSpec::CsvCell {
+                        r,
+                        column: csv_set_cell.column.clone(),
+                        value: csv_set_cell.value.clone(),
+                        dialect,
+                    },
+                ));
+            }
+            HashlineEdit::CsvAddColumn { csv_add_column } => {
+                let dialect = CsvDialect::from_options(csv_add_column.delimiter.as_deref(), csv_add_column.quote.as_deref())?;
+                parsed.push((
+                    idx,
+                    ParsedSpec::CsvAddColumn {
+                        name: csv_add_column.name.clone(),
+                        default: csv_add_column.default.clone(),
+                        after: csv_add_column.after.clone(),
+                        dialect,
+                    },
+                ));
+            }
+            HashlineEdit::KvSet { kv_set } => {
+                if kv_set.key.trim().is_empty() {
+                    bail!("kv_set.key must be non-empty");
+                }
+                parsed.push((
+                    idx,
+                    ParsedSpec::Kv { section: kv_set.section.clone(), key: kv_set.key.trim().to_string(), value: kv_set.value.clone() },
+                ));
+            }
+            HashlineEdit::Plugin { plugin } => {
+                let start = resolve_anchor(&plugin.start_anchor, lines, false)?;
+                let end = match &plugin.end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => start.clone(),
+                };
+                parsed.push((idx, ParsedSpec::Plugin { start, end, op: plugin.op.clone(), args: plugin.args.clone() }));
+            }
+            HashlineEdit::Script { script } => {
+                let start = resolve_anchor(&script.start_anchor, lines, false)?;
+                let end = match &script.end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => start.clone(),
+                };
+                parsed.push((idx, ParsedSpec::Script { start, end, code: script.lua.clone() }));
+            }
+            HashlineEdit::IndentRange { indent_range: IndentRange { start_anchor, end_anchor, indent, width } }
+            | HashlineEdit::DedentRange { dedent_range: DedentRange { start_anchor, end_anchor, indent, width } } => {
+                let dedent = matches!(edit, HashlineEdit::DedentRange { .. });
+                let op = if dedent { "dedent_range" } else { "indent_range" };
+                let unit = match (indent, width) {
+                    (Some(s), None) if !s.is_empty() => IndentUnit::Prefix(s.clone()),
+                    (None, Some(w)) if *w > 0 => IndentUnit::Width(*w),
+                    _ => bail!("{} needs exactly one of a non-empty `indent` or a positive `width`", op),
+                };
+                let start = resolve_anchor(start_anchor, lines, false)?;
+                let end = match end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => start.clone(),
+                };
+                parsed.push((idx, ParsedSpec::Indent { start, end, unit, dedent }));
+            }
+            HashlineEdit::CommentRange { comment_range: CommentRange { start_anchor, end_anchor, comment } }
+            | HashlineEdit::UncommentRange { uncomment_range: UncommentRange { start_anchor, end_anchor, comment } } => {
+                let uncomment = matches!(edit, HashlineEdit::UncommentRange { .. });
+                let op = if uncomment { "uncomment_range" } else { "comment_range" };
+                let marker = match comment.as_deref().map(str::trim) {
+                    Some("") => bail!("{}.comment must be non-empty", op),
+                    Some(m) => m.to_string(),
+                    None => match opts.line_comment {
+                        Some(m) => m.to_string(),
+                        None => bail!("{}: no line comment syntax known for this file; set `comment`", op),
+                    },
+                };
+                let start = resolve_anchor(start_anchor, lines, false)?;
+                let end = match end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => start.clone(),
+                };
+                parsed.push((idx, ParsedSpec::Comment { start, end, marker, uncomment }));
+            }
+            HashlineEdit::SortRange { sort_range } => {
+                let start = resolve_anchor(&sort_range.start_anchor, lines, false)?;
+                let end = resolve_anchor(&sort_range.end_anchor, lines, true)?;
+                parsed.push((
+                    idx,
+                    ParsedSpec::Sort { start, end, case_insensitive: sort_range.case_insensitive, unique: sort_range.unique },
+                ));
+            }
+            HashlineEdit::DedupeRange { dedupe_range } => {
+                let start = resolve_anchor(&dedupe_range.start_anchor, lines, false)?;
+                let end = resolve_anchor(&dedupe_range.end_anchor, lines, true)?;
+                parsed.push((idx, ParsedSpec::Dedupe { start, end, all: dedupe_range.all }));
+            }
+            HashlineEdit::SwapLines { swap_lines } => {
+                let start = resolve_anchor(&swap_lines.start_anchor, lines, false)?;
+                let end = match &swap_lines.end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => start.clone(),
+                };
+                let with_start = resolve_anchor(&swap_lines.with_start_anchor, lines, false)?;
+                let with_end = match &swap_lines.with_end_anchor {
+                    Some(a) => resolve_anchor(a, lines, true)?,
+                    None => with_start.clone(),
+                };
+                parsed.push((idx, ParsedSpec::Swap { start, end, with_start, with_end }));
+            }
+            HashlineEdit::EnvSet { env_set } => {
+                parsed.push((idx, ParsedSpec::Env { key: env_set.key.clone(), value: env_set.value.clone() }));
+            }
+            HashlineEdit::JsonSet { json_set } => {
+                let path = parse_doc_path(&json_set.path)?;
+                parsed.push((idx, ParsedSpec::Json { path, value: Some(json_set.value.clone()) }));
+            }
+            HashlineEdit::JsonDelete { json_delete } => {
+                let path = parse_doc_path(&json_delete.path)?;
+                parsed.push((idx, ParsedSpec::Json { path, value: None }));
+            }
+            HashlineEdit::YamlSet { yaml_set } => {
+                let path = parse_doc_path(&yaml_set.path)?;
+                parsed.push((idx, ParsedSpec::Yaml { path, value: Some(yaml_set.value.clone()) }));
+            }
+            HashlineEdit::YamlDelete { yaml_delete } => {
+                let path = parse_doc_path(&yaml_delete.path)?;
+                parsed.push((idx, ParsedSpec::Yaml { path, value: None }));
+            }
+            HashlineEdit::TomlSet { toml_set } => {
+                let path = parse_doc_path(&toml_set.path)?;
+                parsed.push((idx, ParsedSpec::Toml { path, value: Some(toml_set.value.clone()) }));
+            }
+            HashlineEdit::TomlRemove { toml_remove } => {
+                let path = parse_doc_path(&toml_remove.path)?;
+                parsed.push((idx, ParsedSpec::Toml { path, value: None }));
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+/// Check every anchor against `lines`, moving the ones whose hash is now on a single other line,
+/// then check the content guards against the lines the anchors ended up on.
+fn verify_specs(
+    parsed: &mut [(usize, ParsedSpec)],
+    guards: &[SpecGuard],
+    lines: &[String],
+    report: &mut ApplyReport,
+) -> Result<()> {
+    let unique = unique_line_hashes(lines);
+    let mut mismatches: Vec<(usize, String, String)> = Vec::new();
+    for (_idx, spec) in parsed.iter_mut() {
+        match spec {
+            ParsedSpec::Single { r, .. } | ParsedSpec::CsvCell { r, .. } => {
+                validate_or_relocate(r, lines, &unique, &mut mismatches, &mut report.relocations)?
+            }
+            ParsedSpec::Range { start, end, .. }
+            | ParsedSpec::Plugin { start, end, .. }
+            | ParsedSpec::Script { start, end, .. }
+            | ParsedSpec::Indent { start, end, .. }
+            | ParsedSpec::Comment { start, end, .. }
+            | ParsedSpec::Sort { start, end, .. }
+            | ParsedSpec::Dedupe { start, end, .. }
+            | ParsedSpec::ReplaceInRange { start, end, .. } => {
+                validate_or_relocate(start, lines, &unique, &mut mismatches, &mut report.relocations)?;
+                validate_or_relocate(end, lines, &unique, &mut mismatches, &mut report.relocations)?;
+                if start.line > end.line {
+                    bail!("start_anchor line must be <= end_anchor line");
+                }
+            }
+            ParsedSpec::InsertAfter { after, .. } => {
+                validate_or_relocate(after, lines, &unique, &mut mismatches, &mut report.relocations)?
+            }
+            ParsedSpec::Swap { start, end, with_start, with_end } => {
+                for r in [&mut *start, &mut *end, &mut *with_start, &mut *with_end] {
+                    validate_or_relocate(r, lines, &unique, &mut mismatches, &mut report.relocations)?;
+                }
+                if start.line > end.line || with_start.line > with_end.line {
+                    bail!("start_anchor line must be <= end_anchor line");
+                }
+            }
+            ParsedSpec::Move { start, end, dest, .. } => {
+                validate_or_relocate(start, lines, &unique, &mut mismatches, &mut report.relocations)?;
+                validate_or_relocate(end, lines, &unique, &mut mismatches, &mut report.relocations)?;
+                validate_or_relocate(dest, lines, &unique, &mut mismatches, &mut report.relocations)?;
+                if start.line > end.line {
+                    bail!("start_anchor line must be <= end_anchor line");
+                }
+            }
+            ParsedSpec::Insert { .. }
+            | ParsedSpec::Splice { .. }
+            | ParsedSpec::Append { .. }
+            | ParsedSpec::Prepend { .. }
+            | ParsedSpec::ReplaceText { .. }
+            | ParsedSpec::Regex { .. }
+            | ParsedSpec::Json { .. }
+            | ParsedSpec::Yaml { .. }
+            | ParsedSpec::Toml { .. }
+            | ParsedSpec::CsvAddColumn { .. }
+            | ParsedSpec::Kv { .. }
+            | ParsedSpec::Env { .. } => {}
+        }
+    }
+
+    if !mismatches.is_empty() {
+        let report = render_mismatch_error(lines, &mismatches);
+        return Err(MismatchError { mismatches, report }.into());
+    }
+
+    // Content guards run on the verified (possibly relocated) anchors.
+    let mut guard_failures: Vec<(usize, usize, &str, &TextGuard)> = Vec::new();
+    for (pos, op, guard) in guards {
+        let (idx, spec) = &parsed[*pos];
+        let (line, actual) = match spec {
+            ParsedSpec::Single { r, .. } | ParsedSpec::InsertAfter { after: r, .. } => (r.line, lines[r.line - 1].clone()),
+            ParsedSpec::Range { start, end, .. } => (start.line, lines[start.line - 1..end.line].join("\n")),
+            _ => continue,
+        };
+        if !guard.matches(&actual) {
+            guard_failures.push((*idx, line, op, guard));
+        }
+    }
+    if !guard_failures.is_empty() {
+        bail!("{}", render_guard_failures(lines, &guard_failures));
+    }
+    Ok(())
+}
+
+/// Turn the verified plugin, script, and range ops into the splices they amount to.
+fn resolve_range_ops(
+    parsed: &mut [(usize, ParsedSpec)],
+    lines: &[String],
+    opts: ApplyOptions,
+    report: &mut ApplyReport,
+) -> Result<()> {
+    for (idx, spec) in parsed.iter_mut() {
+        let (start, end, dst) = match spec {
+            ParsedSpec::ReplaceInRange { start, end, old, new_, all } => {
+                let region = &lines[start.line - 1..end.line];
+                let joined = region.join("\n");
+                if !joined.contains(old.as_str()) {
+                    let scope = format!("replace_in_range.old_text not found in lines {}-{}", start.line, end.line);
+                    match suggest_replacement_target(region, old, start.line - 1) {
+                        Some(hint) => bail!("{}; did you mean {}", scope, hint),
+                        None => bail!("{}", scope),
+                    }
+                }
+                let out = if *all { joined.replace(old.as_str(), new_) } else { joined.replacen(old.as_str(), new_, 1) };
+                let count = if *all { joined.matches(old.as_str()).count() } else { 1 };
+                report.replacements.push((*idx, count));
+                (start.clone(), end.clone(), out.split('\n').map(String::from).collect())
+            }
+            ParsedSpec::Plugin { start, end, op, args } => {
+                let dst = run_plugin_op(opts.plugins, op, args, &lines[start.line - 1..end.line], start.line)?;
+                (start.clone(), end.clone(), dst)
+            }
+            ParsedSpec::Script { start, end, code } => {
+                let dst = run_script_op(code, &lines[start.line - 1..end.line], start.line)
+                    .with_context(|| format!("script op at line {}", start.line))?;
+                (start.clone(), end.clone(), dst)
+            }
+            ParsedSpec::Indent { start, end, unit, dedent } => {
+                let region = &lines[start.line - 1..end.line];
+                let dst = region.iter().map(|l| if *dedent { unit.dedent(l) } else { unit.indent(l) }).collect();
+                (start.clone(), end.clone(), dst)
+            }
+            ParsedSpec::Comment { start, end, marker, uncomment } => {
+                let region = &lines[start.line - 1..end.line];
+                let dst = if *uncomment { uncomment_lines(region, marker) } else { comment_lines(region, marker) };
+                (start.clone(), end.clone(), dst)
+            }
+            ParsedSpec::Sort { start, end, case_insensitive, unique } => {
+                let key = |l: &String| if *case_insensitive { l.to_lowercase() } else { l.clone() };
+                let mut dst = lines[start.line - 1..end.line].to_vec();
+                dst.sort_by_cached_key(key);
+                if *unique {
+                    dst.dedup_by(|a, b| key(a) == key(b));
+                }
+                (start.clone(), end.clone(), dst)
+            }
+            ParsedSpec::Dedupe { start, end, all } => {
+                let mut dst = lines[start.line - 1..end.line].to_vec();
+                if *all {
+                    let mut seen = HashSet::new();
+                    dst.retain(|l| l.trim().is_empty() || seen.insert(l.clone()));
+                } else {
+                    dst.dedup();
+                }
+                (start.clone(), end.clone(), dst)
+            }
+            _ => continue,
+        };
+        *spec = ParsedSpec::Splice { start, end, lines: dst };
+    }
+    Ok(())
+}
+
+/// Reject edits that can't all apply to the lines as read: overlapping rewrites, a move into its
+/// own lines, a swap of overlapping ranges, and, for markdown, a range straddling the front matter.
+fn check_conflicts(
+    parsed: &[(usize, ParsedSpec)],
+    edits: &[HashlineEdit],
+    lines: &[String],
+    opts: ApplyOptions,
+) -> Result<()> {
+    // Swaps, moves, and range ops (now splices) rewrite lines as they were read, so no other
+    // anchored edit may touch those lines, and a move's destination must lie outside them.
+    let rewritten = |spec: &ParsedSpec| -> Vec<(usize, usize)> {
+        match spec {
+            ParsedSpec::Splice { start, end, .. } | ParsedSpec::Move { start, end, copy: false, .. } => {
+                vec![(start.line, end.line)]
+            }
+            ParsedSpec::Swap { start, end, with_start, with_end } => {
+                vec![(start.line, end.line), (with_start.line, with_end.line)]
+            }
+            _ => Vec::new(),
+        }
+    };
+    for (idx, spec) in parsed {
+        if let ParsedSpec::Move { start, end, dest, copy: false, .. } = spec {
+            if (start.line..=end.line).contains(&dest.line) {
+                bail!(
+                    "move_lines (edit {}): destination line {} is inside the moved lines {}-{}",
+                    idx + 1,
+                    dest.line,
+                    start.line,
+                    end.line
+                );
+            }
+        }
+        for (first, last) in rewritten(spec) {
+            for (other_idx, other) in parsed {
+                let touched = match other {
+                    _ if other_idx == idx => continue,
+                    ParsedSpec::Single { r, .. } | ParsedSpec::CsvCell { r, .. } => vec![(r.line, r.line)],
+                    ParsedSpec::Range { start, end, .. } | ParsedSpec::Splice { start, end, .. } => {
+                        vec![(start.line, end.line)]
+                    }
+                    // Inserting after the last rewritten line is fine: that spot stays where it is.
+                    ParsedSpec::InsertAfter { after, .. } if after.line < last => vec![(after.line, after.line)],
+                    other => rewritten(other),
+                };
+                let dest_inside = matches!(other, ParsedSpec::Move { dest, .. } if (first..=last).contains(&dest.line));
+                if dest_inside || touched.iter().any(|&(s, e)| s <= last && first <= e) {
+                    return Err(EditConflict(format!(
+                        "{} (edit {}) conflicts with edit {}: both touch lines {}-{}",
+                        edits[*idx].op(),
+                        idx + 1,
+                        other_idx + 1,
+                        first,
+                        last
+                    ))
+                    .into());
+                }
+            }
+        }
+    }
+
+    for (idx, spec) in parsed {
+        let ParsedSpec::Swap { start, end, with_start, with_end } = spec else { continue };
+        let (first, second) = if start.line <= with_start.line {
+            ((start, end), (with_start, with_end))
+        } else {
+            ((with_start, with_end), (start, end))
+        };
+        if first.1.line >= second.0.line {
+            return Err(EditConflict(format!(
+                "swap_lines (edit {}): ranges {}-{} and {}-{} overlap",
+                idx + 1,
+                first.0.line,
+                first.1.line,
+                second.0.line,
+                second.1.line
+            ))
+            .into());
+        }
+    }
+
+    // A range op rewrites whole lines, so it may not mix front matter with the body.
+    if let Some(close) = front_matter_end(lines).filter(|_| opts.front_matter) {
+        for (_idx, spec) in parsed {
+            let ranges = match spec {
+                ParsedSpec::Range { start, end, .. } => vec![(start.line, end.line)],
+                spec => rewritten(spec),
+            };
+            for (start, end) in ranges {
+                if (start <= close + 1) != (end <= close + 1) {
+                    bail!(
+                        "replace_lines {}..{} straddles the front matter (lines 1-{}); edit it separately or use yaml_set",
+                        start,
+                        end,
+                        close + 1
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace each swap with two splices, each taking the other range's lines as read, and each move
+/// with a deletion (unless it copies) and an insertion.
+fn expand_moves_and_swaps(parsed: Vec<(usize, ParsedSpec)>, lines: &[String]) -> Vec<(usize, ParsedSpec)> {
+    let mut expanded = Vec::with_capacity(parsed.len());
+    for (idx, spec) in parsed {
+        match spec {
+            ParsedSpec::Swap { start, end, with_start, with_end } => {
+                // `check_conflicts` made sure the ranges don't overlap.
+                let (first, second) = if start.line <= with_start.line {
+                    ((start, end), (with_start, with_end))
+                } else {
+                    ((with_start, with_end), (start, end))
+                };
+                let first_lines = lines[first.0.line - 1..first.1.line].to_vec();
+                let second_lines = lines[second.0.line - 1..second.1.line].to_vec();
+                expanded.push((idx, ParsedSpec::Splice { start: first.0, end: first.1, lines: second_lines }));
+                expanded.push((idx, ParsedSpec::Splice { start: second.0, end: second.1, lines: first_lines }));
+            }
+            spec => expanded.push((idx, spec)),
+        }
+    }
+    let parsed = expanded;
+
+    let mut expanded = Vec::with_capacity(parsed.len());
+    for (idx, spec) in parsed {
+        match spec {
+            ParsedSpec::Move { start, end, dest, before, copy } => {
+                let moved = lines[start.line - 1..end.line].to_vec();
+                let at = if before { dest.line - 1 } else { dest.line };
+                if !copy {
+                    expanded.push((idx, ParsedSpec::Range { start, end, dst: String::new(), auto_indent: false }));
+                }
+                expanded.push((idx, ParsedSpec::Insert { at, lines: moved }));
+            }
+            spec => expanded.push((idx, spec)),
+        }
+    }
+    expanded
+}
+
+/// Apply one spec to `lines`. Anchored specs must come bottom-up, so their line numbers still
+/// refer to the file as read.
+fn apply_spec(
+    lines: &mut Vec<String>,
+    idx: usize,
+    spec: ParsedSpec,
+    opts: ApplyOptions,
+    report: &mut ApplyReport,
+) -> Result<()> {
+    match spec {
+        ParsedSpec::Single { r, dst, auto_indent } => {
+            let mut dst_lines = split_dst_lines(&dst);
+            let at = r.line - 1;
+            if at >= lines.len() {
+                bail!("line {} does not exist (file has {} lines)", r.line, lines.len());
+            }
+            if auto_indent {
+                dst_lines = reindent(&dst_lines, leading_whitespace(&lines[at]));
+            }
+            lines.splice(at..at + 1, dst_lines);
+        }
+        ParsedSpec::CsvCell { r, column, value, dialect } => {
+            let at = r.line - 1;
+            if at >= lines.len() {
+                bail!("line {} does not exist (file has {} lines)", r.line, lines.len());
+            }
+            let index = dialect.column_index(&column, lines.first())?;
+            lines[at] = dialect.set_field(&lines[at], index, &value).with_context(|| format!("csv_set_cell: line {}", r.line))?;
+        }
+        ParsedSpec::Range { start, end, dst, auto_indent } => {
+            let mut dst_lines = split_dst_lines(&dst);
+            let s = start.line - 1;
+            let e = end.line - 1;
+            if s >= lines.len() || e >= lines.len() {
+                bail!("range out of bounds (file has {} lines)", lines.len());
+            }
+            if s > e {
+                bail!("invalid range: start > end");
+            }
+            if auto_indent {
+                dst_lines = reindent(&dst_lines, leading_whitespace(&lines[s]));
+            }
+            lines.splice(s..e + 1, dst_lines);
+        }
+        ParsedSpec::InsertAfter { after, dst, auto_indent } => {
+            let mut dst_lines = split_dst_lines(&dst);
+            let at = after.line; // insert after => index is line (1-indexed) as 0-index insert point
+            if after.line > lines.len() {
+                bail!("line {} does not exist (file has {} lines)", after.line, lines.len());
+            }
+            if auto_indent {
+                let anchor_indent = leading_whitespace(&lines[at - 1]);
+                let next_indent = lines[at..].iter().find(|l| !l.trim().is_empty()).map(|l| leading_whitespace(l));
+                let base = match next_indent {
+                    Some(next) if next.len() > anchor_indent.len() => next,
+                    _ => anchor_indent,
+                };
+                dst_lines = reindent(&dst_lines, base);
+            }
+            lines.splice(at..at, dst_lines);
+        }
+        ParsedSpec::Splice { start, end, lines: new } => {
+            if end.line > lines.len() {
+                bail!("range out of bounds (file has {} lines)", lines.len());
+            }
+            lines.splice(start.line - 1..end.line, new);
+        }
+        ParsedSpec::ReplaceInRange { start, .. } => bail!("replace_in_range at line {} was not resolved", start.line),
+        ParsedSpec::Insert { at, lines: moved } => {
+            if at > lines.len() {
+                bail!("line {} does not exist (file has {} lines)", at, lines.len());
+            }
+            lines.splice(at..at, moved);
+        }
+        ParsedSpec::Move { start, .. } => bail!("move_lines at line {} was not expanded", start.line),
+        // A lone empty line is an empty file: replace it rather than keep a leading/trailing blank.
+        ParsedSpec::Append { lines: added } if lines.len() == 1 && lines[0].is_empty() => *lines = added,
+        ParsedSpec::Append { lines: mut added } => lines.append(&mut added),
+        ParsedSpec::Prepend { lines: added } if lines.len() == 1 && lines[0].is_empty() => *lines = added,
+        ParsedSpec::Prepend { lines: added } => {
+            lines.splice(0..0, added);
+        }
+        ParsedSpec::ReplaceText { old, new_, occurrence, max } => {
+            // Front matter is only reachable through anchors or the yaml ops.
+            let skip = if opts.front_matter { front_matter_end(lines).map_or(0, |close| close + 1) } else { 0 };
+            let body = lines.split_off(skip);
+            let joined = body.join("\n");
+            let found: Vec<usize> = joined.match_indices(&old).map(|(pos, _)| pos).collect();
+            if found.is_empty() {
+                match suggest_replacement_target(&body, &old, skip) {
+                    Some(hint) => bail!("replace.old_text not found; did you mean {}", hint),
+                    None => bail!("replace.old_text not found"),
+                }
+            }
+            if occurrence > found.len() {
+                bail!("replace.old_text occurrence {} not found (only {} match(es))", occurrence, found.len());
+            }
+            let chosen = &found[occurrence - 1..];
+            let chosen = &chosen[..max.map_or(chosen.len(), |m| m.min(chosen.len()))];
+            let mut out = String::with_capacity(joined.len() + chosen.len() * new_.len());
+            let mut prev = 0;
+            for &pos in chosen {
+                out.push_str(&joined[prev..pos]);
+                out.push_str(&new_);
+                prev = pos + old.len();
+            }
+            out.push_str(&joined[prev..]);
+            report.replacements.push((idx, chosen.len()));
+            let action = format!("replace {} occurrence(s) of {:?}", chosen.len(), old);
+            report.steps.push(PlanStep { edit: idx, action, lines: None });
+            lines.extend(out.split('\n').map(String::from));
+        }
+        ParsedSpec::Regex { re, replacement, max } => {
+            let skip = if opts.front_matter { front_matter_end(lines).map_or(0, |close| close + 1) } else { 0 };
+            let body = lines.split_off(skip).join("\n");
+            if !re.is_match(&body) {
+                bail!("regex_replace.pattern {:?} matched nothing", re.as_str());
+            }
+            let out = re.replacen(&body, max.unwrap_or(0), replacement.as_str());
+            let matched = re.find_iter(&body).count();
+            let replaced = max.map_or(matched, |m| m.min(matched));
+            report.replacements.push((idx, replaced));
+            let action = format!("replace {} match(es) of /{}/", replaced, re.as_str());
+            report.steps.push(PlanStep { edit: idx, action, lines: None });
+            lines.extend(out.split('\n').map(String::from));
+        }
+        ParsedSpec::Json { path, value } => {
+            let out = json_edit(&lines.join("\n"), &path, value.as_ref())?;
+            *lines = out.split('\n').map(|s| s.to_string()).collect();
+        }
+        ParsedSpec::Yaml { path, value } if opts.front_matter => match front_matter_end(lines) {
+            Some(close) => {
+                let mut inner = lines[1..close].to_vec();
+                yaml_edit(&mut inner, &path, value.as_ref())?;
+                lines.splice(1..close, inner);
+            }
+            None if value.is_some() => {
+                let mut inner = Vec::new();
+                yaml_edit(&mut inner, &path, value.as_ref())?;
+                let block: Vec<String> = std::iter::once("---".to_string())
+                    .chain(inner)
+                    .chain(std::iter::once("---".to_string()))
+                    .collect();
+                lines.splice(0..0, block);
+            }
+            None => bail!("yaml_delete: file has no front matter"),
+        },
+        ParsedSpec::Yaml { path, value } => yaml_edit(lines, &path, value.as_ref())?,
+        ParsedSpec::Kv { section, key, value } => kv_set(lines, section.as_deref(), &key, &value)?,
+        ParsedSpec::Env { key, value } => env_set(lines, &key, &value)?,
+        ParsedSpec::Plugin { op, .. } => bail!("plugin op {:?} was not resolved", op),
+        ParsedSpec::Script { start, .. } => bail!("script op at line {} was not resolved", start.line),
+        ParsedSpec::Indent { start, .. } => bail!("indent op at line {} was not resolved", start.line),
+        ParsedSpec::Comment { start, .. } => bail!("comment op at line {} was not resolved", start.line),
+        ParsedSpec::Sort { start, .. } => bail!("sort_range at line {} was not resolved", start.line),
+        ParsedSpec::Dedupe { start, .. } => bail!("dedupe_range at line {} was not resolved", start.line),
+        ParsedSpec::Swap { start, .. } => bail!("swap_lines at line {} was not resolved", start.line),
+        ParsedSpec::CsvAddColumn { name, default, after, dialect } => {
+            let index = match &after {
+                Some(column) => dialect.column_index(column, lines.first())? + 1,
+                None => dialect.fields(lines.first().map_or("", |h| h.as_str()))?.len(),
+            };
+            for (i, line) in lines.iter_mut().enumerate() {
+                if i > 0 && line.is_empty() {
+                    continue;
+                }
+                let value = if i == 0 { &name } else { &default };
+                *line = dialect.insert_field(line, index, value).with_context(|| format!("csv_add_column: line {}", i + 1))?;
+            }
+        }
+        ParsedSpec::Toml { path, value } => {
+            let out = toml_edit(&lines.join("\n"), &path, value.as_ref())?;
+            *lines = out.split('\n').map(|s| s.to_string()).collect();
+        }
+    }
+    Ok(())
+}
+
+/// Closest match for a failed `replace`: the window of lines (same line count as `old`) that
+/// best matches it after collapsing whitespace, rendered as anchored lines. `offset` is the
+/// line number of `lines[0]` minus one.
+fn suggest_replacement_target(lines: &[String], old: &str, offset: usize) -> Option<String> {
+    let collapse = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+    let wanted = collapse(old);
+    let k = old.split('\n').count();
+    if wanted.is_empty() || lines.len() < k || lines.len() > 20_000 {
+        return None;
+    }
+    let mut best: Option<(f32, usize)> = None;
+    for start in 0..=lines.len() - k {
+        let window = collapse(&lines[start..start + k].join("\n"));
+        let score = if window.contains(&wanted) {
+            1.0
+        } else {
+            similar::TextDiff::from_chars(wanted.as_str(), window.as_str()).ratio()
+        };
+        if best.is_none_or(|(b, _)| score > b) {
+            best = Some((score, start));
+        }
+    }
+    let (score, start) = best.filter(|(score, _)| *score >= 0.6)?;
+    let mut out = format!("(closest match, {:.0}% similar after collapsing whitespace):\n", score * 100.0);
+    for (i, line) in lines[start..start + k].iter().enumerate() {
+        out.push_str(&format!("  {}:{}|{}\n", offset + start + i + 1, compute_line_hash(line), line));
+    }
+    Some(out.trim_end().to_string())
+}
+
+pub fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Strip the snippet's common indentation and re-base every non-blank line on `base`.
+fn reindent(lines: &[String], base: &str) -> Vec<String> {
+    let common = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| leading_whitespace(l).len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| if l.trim().is_empty() { String::new() } else { format!("{}{}", base, &l[common..]) })
+        .collect()
+}
+
+fn skip_if_applied(edit: &HashlineEdit) -> bool {
+    match edit {
+        HashlineEdit::SetLine { set_line: SetLine { skip_if_applied, .. } }
+        | HashlineEdit::ReplaceLines { replace_lines: ReplaceLines { skip_if_applied, .. } }
+        | HashlineEdit::InsertAfter { insert_after: InsertAfter { skip_if_applied, .. } }
+        | HashlineEdit::DeleteLines { delete_lines: DeleteLines { skip_if_applied, .. } }
+        | HashlineEdit::Append { append: AppendText { skip_if_applied, .. } }
+        | HashlineEdit::Prepend { prepend: PrependText { skip_if_applied, .. } }
+        | HashlineEdit::Replace { replace: ReplaceText { skip_if_applied, .. } } => *skip_if_applied,
+        _ => false,
+    }
+}
+
+/// Whether the file already shows what `edit` would produce. While an anchored rewrite's anchors
+/// still match, it counts as applied only if the whole range already equals its new text. Once
+/// they don't (line numbers may have shifted since the payload was built), it counts as applied
+/// when its new text sits at the anchor, or when the anchor's hash is gone from the file and the
+/// new text appears elsewhere. Ops without a clear "done" state never count as applied.
+fn already_applied(edit: &HashlineEdit, lines: &[String]) -> bool {
+    let matches = |got: &[String], want: &[String], auto_indent: bool| {
+        got.iter().zip(want).all(|(g, w)| if auto_indent { g.trim_start() == w.trim_start() } else { g == w })
+    };
+    let shows_at = |line: usize, want: &[String], auto_indent: bool| {
+        lines.get(line - 1..line - 1 + want.len()).is_some_and(|got| matches(got, want, auto_indent))
+    };
+    let shows_anywhere =
+        |want: &[String], auto_indent: bool| lines.windows(want.len()).any(|got| matches(got, want, auto_indent));
+    let gone = |r: &Anchor| !lines.iter().any(|l| compute_line_hash(l) == r.hash);
+    let live = |r: &Anchor| lines.get(r.line - 1).is_some_and(|l| compute_line_hash(l) == r.hash);
+    // `Some((anchor, new lines, auto_indent))` for an anchored rewrite of a line or range.
+    let rewrite = match edit {
+        HashlineEdit::SetLine { set_line: e } => Some((&e.anchor, &e.new_text, e.auto_indent, None)),
+        HashlineEdit::ReplaceLines { replace_lines: e } => {
+            Some((&e.start_anchor, &e.new_text, e.auto_indent, Some(&e.end_anchor)))
+        }
+        HashlineEdit::DeleteLines { delete_lines: e } => Some((&e.start_anchor, &String::new(), false, e.end_anchor.as_ref())),
+        _ => None,
+    };
+    if let Some((anchor, text, auto_indent, end_anchor)) = rewrite {
+        let Ok(r) = parse_line_ref(anchor) else { return false };
+        let want = split_dst_lines(text);
+        if want.is_empty() {
+            let end_gone = end_anchor.is_none_or(|a| parse_line_ref(a).is_ok_and(|e| gone(&e)));
+            return gone(&r) && end_gone;
+        }
+        let end = match end_anchor.map(|a| parse_line_ref(a)) {
+            Some(Ok(e)) => e,
+            Some(Err(_)) => return false,
+            None => r.clone(),
+        };
+        if live(&r) && live(&end) {
+            // The anchored lines are still there, so the edit is only done if they already read
+            // as the new text, line for line.
+            return lines
+                .get(r.line - 1..end.line.max(r.line))
+                .is_some_and(|got| got.len() == want.len() && matches(got, &want, auto_indent));
+        }
+        return shows_at(r.line, &want, auto_indent) || (gone(&r) && shows_anywhere(&want, auto_indent));
+    }
+    match edit {
+        HashlineEdit::InsertAfter { insert_after: e } => {
+            let Ok(r) = parse_line_ref(&e.anchor) else { return false };
+            let want = split_dst_lines(&e.text);
+            let hashes: Vec<usize> =
+                (1..=lines.len()).filter(|&ln| compute_line_hash(&lines[ln - 1]) == r.hash).collect();
+            if lines.get(r.line - 1).is_some_and(|l| compute_line_hash(l) == r.hash) {
+                shows_at(r.line + 1, &want, e.auto_indent)
+            } else if let [only] = hashes[..] {
+                shows_at(only + 1, &want, e.auto_indent)
+            } else {
+                hashes.is_empty() && shows_anywhere(&want, e.auto_indent)
+            }
+        }
+        HashlineEdit::Append { append: e } => lines.ends_with(&split_dst_lines(&e.text)),
+        HashlineEdit::Prepend { prepend: e } => lines.starts_with(&split_dst_lines(&e.text)),
+        HashlineEdit::Replace { replace: e } => {
+            let text = lines.join("\n");
+            !text.contains(e.old_text.as_str()) && text.contains(e.new_text.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// `expected_text` / `expected_prefix` on an anchored edit.
+struct TextGuard {
+    text: String,
+    prefix: bool,
+}
+
+impl TextGuard {
+    fn new(op: &str, expected_text: &Option<String>, expected_prefix: &Option<String>) -> Result<Option<Self>> {
+        Ok(match (expected_text, expected_prefix) {
+            (None, None) => None,
+            (Some(t), None) => Some(TextGuard { text: t.clone(), prefix: false }),
+            (None, Some(p)) => Some(TextGuard { text: p.clone(), prefix: true }),
+            (Some(_), Some(_)) => bail!("{} takes at most one of `expected_text` / `expected_prefix`", op),
+        })
+    }
+
+    fn matches(&self, actual: &str) -> bool {
+        if self.prefix {
+            actual.starts_with(&self.text)
+        } else {
+            actual == self.text
+        }
+    }
+}
+
+/// Report for edits whose anchors verified but whose `expected_text` / `expected_prefix` did not.
+fn render_guard_failures(lines: &[String], failures: &[(usize, usize, &str, &TextGuard)]) -> String {
+    let mut out = format!(
+        "{} edit(s) found different text than expected at their anchors (likely a hash collision). Re-read the file and retry.\n\n",
+        failures.len()
+    );
+    for (idx, line, op, guard) in failures {
+        let content = &lines[line - 1];
+        out.push_str(&format!(
+            ">>> {}:{}|{}\n    edit {} ({}) expected {} {:?}\n\n",
+            line,
+            compute_line_hash(content),
+            content,
+            idx + 1,
+            op,
+            if guard.prefix { "prefix" } else { "text" },
+            guard.text
+        ));
+    }
+    out.trim_end().to_string()
+}
+
+/// How much `indent_range` / `dedent_range` shift each line by.
+#[derive(Clone)]
+enum IndentUnit {
+    Prefix(String),
+    Width(usize),
+}
+
+impl IndentUnit {
+    fn indent(&self, line: &str) -> String {
+        if line.trim().is_empty() {
+            return line.to_string();
+        }
+        match self {
+            IndentUnit::Prefix(p) => format!("{}{}", p, line),
+            IndentUnit::Width(w) => format!("{}{}", " ".repeat(*w), line),
+        }
+    }
+
+    fn dedent(&self, line: &str) -> String {
+        match self {
+            IndentUnit::Prefix(p) => line.strip_prefix(p.as_str()).unwrap_or(line).to_string(),
+            IndentUnit::Width(w) => {
+                let ws = leading_whitespace(line);
+                let cut = ws.char_indices().nth(*w).map_or(ws.len(), |(i, _)| i);
+                line[cut..].to_string()
+            }
+        }
+    }
+}
+
+/// Prefix every non-blank line with `marker `, inserted at the block's smallest indentation.
+fn comment_lines(lines: &[String], marker: &str) -> Vec<String> {
+    let common = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| leading_whitespace(l).len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| if l.trim().is_empty() { l.clone() } else { format!("{}{} {}", &l[..common], marker, &l[common..]) })
+        .collect()
+}
+
+/// Remove `marker` (and one space after it) from lines whose first non-blank text is the marker.
+fn uncomment_lines(lines: &[String], marker: &str) -> Vec<String> {
+    lines
+        .iter()
+        .map(|l| {
+            let ws = leading_whitespace(l);
+            match l[ws.len()..].strip_prefix(marker) {
+                Some(rest) => format!("{}{}", ws, rest.strip_prefix(' ').unwrap_or(rest)),
+                None => l.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Lines of a `new_text`/`text` field: split on `\n`, and `""` is no lines.
+pub fn split_dst_lines(dst: &str) -> Vec<String> {
+    if dst.is_empty() {
+        Vec::new()
+    } else {
+        dst.split('\n').map(|s| s.to_string()).collect()
+    }
+}
+
+/// Parse a JSON Pointer (`/a/0`, RFC 6901 escapes) or a simple JSONPath (`$.a[0]['b c']`) into segments.
+fn parse_doc_path(path: &str) -> Result<Vec<String>> {
+    if path.is_empty() || path == "$" {
+        return Ok(Vec::new());
+    }
+    if let Some(rest) = path.strip_prefix('/') {
+        return Ok(rest.split('/').map(|t| t.replace("~1", "/").replace("~0", "~")).collect());
+    }
+    let Some(mut rest) = path.strip_prefix('$') else {
+        bail!("path must be a JSON Pointer (`/a/0`) or JSONPath (`$.a[0]`): {}", path);
+    };
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('.') {
+            let end = r.find(['.', '[']).unwrap_or(r.len());
+            if end == 0 {
+                bail!("empty segment in path: {}", path);
+            }
+            segments.push(r[..end].to_string());
+            rest = &r[end..];
+        } else if let Some(r) = rest.strip_prefix("['") {
+            let end = r.find("']").ok_or_else(|| anyhow!("unterminated ['...'] in path: {}", path))?;
+            segments.push(r[..end].to_string());
+            rest = &r[end + 2..];
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']').ok_or_else(|| anyhow!("unterminated [...] in path: {}", path))?;
+            segments.push(r[..end].trim().to_string());
+            rest = &r[end + 1..];
+        } else {
+            bail!("unexpected {:?} in path: {}", rest, path);
+        }
+    }
+    Ok(segments)
+}
+
+/// Byte span of a JSON value; containers keep their children so edits can splice exact ranges.
+struct JsonNode {
+    start: usize,
+    end: usize,
+    kind: JsonKind,
+}
+
+enum JsonKind {
+    Scalar,
+    /// (key, byte offset of the key's opening quote, value)
+    Object(Vec<(String, usize, JsonNode)>),
+    Array(Vec<JsonNode>),
+}
+
+/// Span-recording JSON scanner. Tolerates `//` and `/* */` comments and trailing commas (JSONC).
+struct JsonScanner<'a> {
+    text: &'a str,
+    i: usize,
+}
+
+impl JsonScanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.i).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        let s = self.text.as_bytes();
+        loop {
+            while self.i < s.len() && s[self.i].is_ascii_whitespace() {
+                self.i += 1;
+            }
+            if s[self.i..].starts_with(b"//") {
+                self.i = self.text[self.i..].find('\n').map_or(s.len(), |n| self.i + n);
+            } else if s[self.i..].starts_with(b"/*") {
+                self.i = self.text[self.i + 2..].find("*/").map_or(s.len(), |n| self.i + n + 4);
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        self.skip_ws();
+        if self.peek() != Some(c) {
+            bail!("expected '{}' at byte {} of JSON document", c as char, self.i);
+        }
+        self.i += 1;
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let start = self.i;
+        self.expect(b'"')?;
+        let s = self.text.as_bytes();
+        loop {
+            match s.get(self.i) {
+                Some(b'\\') => self.i += 2,
+                Some(b'"') => break,
+                Some(_) => self.i += 1,
+                None => bail!("unterminated string at byte {} of JSON document", start),
+            }
+        }
+        self.i += 1;
+        serde_json::from_str(&self.text[start..self.i]).with_context(|| format!("invalid string at byte {}", start))
+    }
+
+    fn value(&mut self) -> Result<JsonNode> {
+        self.skip_ws();
+        let start = self.i;
+        let kind = match self.peek() {
+            Some(b'{') => {
+                self.i += 1;
+                let mut members = Vec::new();
+                loop {
+                    self.skip_ws();
+                    if self.peek() == Some(b'}') {
+                        self.i += 1;
+                        break;
+                    }
+                    let key_start = self.i;
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, key_start, self.value()?));
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(b',') => self.i += 1,
+                        Some(b'}') => {}
+                        _ => bail!("expected ',' or '}}' at byte {} of JSON document", self.i),
+                    }
+                }
+                JsonKind::Object(members)
+            }
+            Some(b'[') => {
+                self.i += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_ws();
+                    if self.peek() == Some(b']') {
+                        self.i += 1;
+                        break;
+                    }
+                    items.push(self.value()?);
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(b',') => self.i += 1,
+                        Some(b']') => {}
+                        _ => bail!("expected ',' or ']' at byte {} of JSON document", self.i),
+                    }
+                }
+                JsonKind::Array(items)
+            }
+            Some(b'"') => {
+                self.string()?;
+                JsonKind::Scalar
+            }
+            Some(_) => {
+                let s = self.text.as_bytes();
+                while self.i < s.len() && !matches!(s[self.i], b',' | b'}' | b']' | b'/') && !s[self.i].is_ascii_whitespace() {
+                    self.i += 1;
+                }
+                if self.i == start {
+                    bail!("unexpected character at byte {} of JSON document", start);
+                }
+                JsonKind::Scalar
+            }
+            None => bail!("unexpected end of JSON document"),
+        };
+        Ok(JsonNode { start, end: self.i, kind })
+    }
+}
+
+fn line_indent_at(text: &str, pos: usize) -> &str {
+    let line_start = text[..pos].rfind('\n').map_or(0, |n| n + 1);
+    leading_whitespace(&text[line_start..])
+}
+
+/// Compact scalars; containers pretty-printed with continuation lines re-based on `indent`.
+fn render_json_value(value: &serde_json::Value, indent: &str) -> Result<String> {
+    let compound = match value {
+        serde_json::Value::Array(a) => !a.is_empty(),
+        serde_json::Value::Object(o) => !o.is_empty(),
+        _ => false,
+    };
+    if !compound {
+        return Ok(serde_json::to_string(value)?);
+    }
+    Ok(serde_json::to_string_pretty(value)?.replace('\n', &format!("\n{}", indent)))
+}
+
+/// Apply a `json_set` (`value` is Some) or `json_delete` to `text`, touching only the addressed span.
+fn json_edit(text: &str, path: &[String], value: Option<&serde_json::Value>) -> Result<String> {
+    let mut scanner = JsonScanner { text, i: 0 };
+    let root = scanner.value()?;
+    let splice = |start: usize, end: usize, with: &str| format!("{}{}{}", &text[..start], with, &text[end..]);
+
+    let Some((last, parents)) = path.split_last() else {
+        let Some(value) = value else { bail!("json_delete: cannot delete the document root") };
+        return Ok(splice(root.start, root.end, &render_json_value(value, line_indent_at(text, root.start))?));
+    };
+    let mut node = &root;
+    for seg in parents {
+        node = match &node.kind {
+            JsonKind::Object(members) => members.iter().rev().find(|(k, ..)| k == seg).map(|(_, _, v)| v),
+            JsonKind::Array(items) => seg.parse::<usize>().ok().and_then(|i| items.get(i)),
+            JsonKind::Scalar => None,
+        }
+        .ok_or_else(|| anyhow!("json path segment {:?} not found", seg))?;
+    }
+
+    // Whitespace that separates existing children (after the comma), reused for appended ones.
+    // `spans` are (start, end) of each child; with a single child, the gap after the opening bracket.
+    let sep_for = |open: usize, spans: &[(usize, usize)]| {
+        let gap = match spans {
+            [.., (_, prev_end), (start, _)] => text[*prev_end..*start].split_once(',').map_or("", |(_, g)| g),
+            [(start, _)] if *start > open + 1 => &text[open + 1..*start],
+            _ => " ",
+        };
+        if gap.trim().is_empty() { gap.to_string() } else { " ".to_string() }
+    };
+    match &node.kind {
+        JsonKind::Object(members) => {
+            let found = members.iter().rposition(|(k, ..)| k == last);
+            match (found, value) {
+                (Some(i), Some(value)) => {
+                    let v = &members[i].2;
+                    Ok(splice(v.start, v.end, &render_json_value(value, line_indent_at(text, members[i].1))?))
+                }
+                (Some(i), None) => Ok(if members.len() == 1 {
+                    splice(node.start + 1, node.end - 1, "")
+                } else if i + 1 < members.len() {
+                    splice(members[i].1, members[i + 1].1, "")
+                } else {
+                    splice(members[i - 1].2.end, members[i].2.end, "")
+                }),
+                (None, Some(value)) => {
+                    let key = serde_json::to_string(last)?;
+                    match members.last() {
+                        Some((_, key_start, v)) => {
+                            let indent = line_indent_at(text, *key_start);
+                            let member = format!("{}: {}", key, render_json_value(value, indent)?);
+                            let spans: Vec<(usize, usize)> = members.iter().map(|(_, ks, v)| (*ks, v.end)).collect();
+                            Ok(splice(v.end, v.end, &format!(",{}{}", sep_for(node.start, &spans), member)))
+                        }
+                        None => Ok(splice(node.start + 1, node.end - 1, &format!("{}: {}", key, render_json_value(value, "")?))),
+                    }
+                }
+                (None, None) => bail!("json_delete: key {:?} not found", last),
+            }
+        }
+        JsonKind::Array(items) => {
+            let index = if last == "-" {
+                items.len()
+            } else {
+                last.parse::<usize>().map_err(|_| anyhow!("json path segment {:?} is not an array index", last))?
+            };
+            match (items.get(index), value) {
+                (Some(v), Some(value)) => Ok(splice(v.start, v.end, &render_json_value(value, line_indent_at(text, v.start))?)),
+                (Some(_), None) => Ok(if items.len() == 1 {
+                    splice(node.start + 1, node.end - 1, "")
+                } else if index + 1 < items.len() {
+                    splice(items[index].start, items[index + 1].start, "")
+                } else {
+                    splice(items[index - 1].end, items[index].end, "")
+                }),
+                (None, Some(value)) if index == items.len() => match items.last() {
+                    Some(v) => {
+                        let rendered = render_json_value(value, line_indent_at(text, v.start))?;
+                        let spans: Vec<(usize, usize)> = items.iter().map(|v| (v.start, v.end)).collect();
+                        Ok(splice(v.end, v.end, &format!(",{}{}", sep_for(node.start, &spans), rendered)))
+                    }
+                    None => Ok(splice(node.start + 1, node.end - 1, &render_json_value(value, "")?)),
+                },
+                _ => bail!("json path index {} out of bounds (array has {} items)", index, items.len()),
+            }
+        }
+        JsonKind::Scalar => bail!("json path segment {:?}: parent is not an object or array", last),
+    }
+}
+
+fn json_to_toml(value: &serde_json::Value) -> Result<toml_edit::Value> {
+    Ok(match value {
+        serde_json::Value::Null => bail!("TOML has no null; use toml_remove to delete a key"),
+        serde_json::Value::Bool(b) => (*b).into(),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().unwrap_or_default().into(),
+        },
+        serde_json::Value::String(s) => s.as_str().into(),
+        serde_json::Value::Array(items) => {
+            toml_edit::Value::Array(items.iter().map(json_to_toml).collect::<Result<toml_edit::Array>>()?)
+        }
+        serde_json::Value::Object(members) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (k, v) in members {
+                table.insert(k, json_to_toml(v)?);
+            }
+            toml_edit::Value::InlineTable(table)
+        }
+    })
+}
+
+/// Child lookup that never creates entries (toml_edit's `IndexMut` inserts missing keys).
+fn toml_child<'a>(item: &'a mut toml_edit::Item, seg: &str) -> Option<&'a mut toml_edit::Item> {
+    if let Ok(i) = seg.parse::<usize>() {
+        if item.is_array() || item.is_array_of_tables() {
+            return item.get_mut(i);
+        }
+    }
+    item.get(seg)?;
+    item.get_mut(seg)
+}
+
+/// Apply a `toml_set` (`value` is Some) or `toml_remove` via toml_edit, which keeps comments and layout.
+fn toml_edit(text: &str, path: &[String], value: Option<&serde_json::Value>) -> Result<String> {
+    use toml_edit::{Item, Value};
+
+    let mut doc: toml_edit::DocumentMut = text.parse().context("toml edit: failed to parse document")?;
+    let (last, parents) = path.split_last().ok_or_else(|| anyhow!("toml path must not be empty"))?;
+    let mut item = doc.as_item_mut();
+    for seg in parents {
+        item = toml_child(item, seg).ok_or_else(|| anyhow!("toml path segment {:?} not found", seg))?;
+    }
+
+    let index = |len: usize| -> Result<usize> {
+        if last == "-" {
+            return Ok(len);
+        }
+        last.parse::<usize>().map_err(|_| anyhow!("toml path segment {:?} is not an array index", last))
+    };
+    let Some(value) = value else {
+        let removed = match item {
+            Item::Table(t) => t.remove(last).is_some(),
+            Item::Value(Value::InlineTable(t)) => t.remove(last).is_some(),
+            Item::Value(Value::Array(a)) => {
+                let i = index(a.len())?;
+                i < a.len() && {
+                    a.remove(i);
+                    true
+                }
+            }
+            Item::ArrayOfTables(a) => {
+                let i = index(a.len())?;
+                i < a.len() && {
+                    a.remove(i);
+                    true
+                }
+            }
+            _ => bail!("toml path segment {:?}: parent is not a table or array", last),
+        };
+        if !removed {
+            bail!("toml_remove: {:?} not found", last);
+        }
+        return Ok(toml_render(&doc, text));
+    };
+
+    let mut new = json_to_toml(value)?;
+    match item {
+        Item::Table(t) => match t.get(last) {
+            // Keep `[section]` tables as sections instead of collapsing them into inline tables.
+            Some(Item::Table(_)) => match new {
+                Value::InlineTable(inline) => {
+                    t.insert(last, Item::Table(inline.into_table()));
+                }
+                _ => bail!("toml_set: {:?} is a table; value must be an object", last),
+            },
+            old => {
+                if let Some(old) = old.and_then(|o| o.as_value()) {
+                    *new.decor_mut() = old.decor().clone();
+                }
+                t.insert(last, Item::Value(new));
+            }
+        },
+        Item::Value(Value::InlineTable(t)) => {
+            if let Some(old) = t.get(last) {
+                *new.decor_mut() = old.decor().clone();
+            }
+            t.insert(last, new);
+        }
+        Item::Value(Value::Array(a)) => {
+            let i = index(a.len())?;
+            if i < a.len() {
+                if let Some(old) = a.get(i) {
+                    *new.decor_mut() = old.decor().clone();
+                }
+                a.replace_formatted(i, new);
+            } else if i == a.len() {
+                a.push(new);
+            } else {
+                bail!("toml path index {} out of bounds (array has {} items)", i, a.len());
+            }
+        }
+        Item::ArrayOfTables(a) => {
+            let i = index(a.len())?;
+            let Value::InlineTable(inline) = new else { bail!("toml_set: [[{}]] entries must be objects", last) };
+            if i < a.len() {
+                a.replace(i, inline.into_table());
+            } else if i == a.len() {
+                a.push(inline.into_table());
+            } else {
+                bail!("toml path index {} out of bounds (array has {} tables)", i, a.len());
+            }
+        }
+        _ => bail!("toml path segment {:?}: parent is not a table or array", last),
+    }
+    Ok(toml_render(&doc, text))
+}
+
+/// toml_edit terminates every key/value line; keep the caller's (newline-less) tail as it was.
+fn toml_render(doc: &toml_edit::DocumentMut, original: &str) -> String {
+    let out = doc.to_string();
+    match out.strip_suffix('\n') {
+        Some(trimmed) if !original.ends_with('\n') => trimmed.to_string(),
+        _ => out,
+    }
+}
+
+/// Lines `first..end` of a YAML block whose entries start at column `col`. Line `first` may
+/// begin mid-line (the mapping inside a `- key: value` sequence item).
+struct YamlBlock {
+    first: usize,
+    col: usize,
+    end: usize,
+}
+
+/// One mapping key or sequence item: head line, inline value span on that line, and the
+/// exclusive end of its nested lines (trailing blank/comment lines excluded).
+struct YamlEntry {
+    line: usize,
+    end: usize,
+    key: Option<String>,
+    value_col: usize,
+    value_end: usize,
+    /// Sequence item whose content is itself a mapping (`- name: x`).
+    inline_map: bool,
+}
+
+fn yaml_is_content(line: &str) -> bool {
+    let t = line.trim_start();
+    !t.is_empty() && !t.starts_with('#')
+}
+
+/// Strip a trailing ` # comment` (outside quotes) and trailing whitespace.
+fn yaml_strip_comment(s: &str) -> &str {
+    let (mut single, mut double) = (false, false);
+    let bytes = s.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'\'' if !double => single = !single,
+            b'"' if !single => double = !double,
+            b'#' if !single && !double && (i == 0 || bytes[i - 1] == b' ') => return s[..i].trim_end(),
+            _ => {}
+        }
+    }
+    s.trim_end()
+}
+
+/// `key: ...` at the start of `s` -> (key, offset just past the colon).
+fn yaml_key(s: &str) -> Option<(String, usize)> {
+    let after_colon = |rest: &str, at: usize| {
+        let r = &rest[at..];
+        if r.starts_with(':') && (r.len() == 1 || r[1..].starts_with([' ', '\t'])) { Some(at + 1) } else { None }
+    };
+    if let Some(inner) = s.strip_prefix('"') {
+        let mut escaped = false;
+        let close = inner.char_indices().find_map(|(i, c)| match c {
+            _ if escaped => {
+                escaped = false;
+                None
+            }
+            '\\' => {
+                escaped = true;
+                None
+            }
+            '"' => Some(i + 1),
+            _ => None,
+        })?;
+        let key = serde_json::from_str(&s[..=close]).ok()?;
+        return Some((key, after_colon(s, close + 1)?));
+    }
+    if let Some(inner) = s.strip_prefix('\'') {
+        let close = inner.find('\'')? + 1;
+        return Some((s[1..close].to_string(), after_colon(s, close + 1)?));
+    }
+    if s.starts_with(['{', '[', '-', '#', '|', '>']) {
+        return None;
+    }
+    let colon = s.match_indices(':').find(|(i, _)| after_colon(s, *i).is_some())?.0;
+    Some((s[..colon].trim_end().to_string(), colon + 1))
+}
+
+fn yaml_root(lines: &[String]) -> YamlBlock {
+    let first = lines
+        .iter()
+        .position(|l| yaml_is_content(l) && !l.starts_with("---") && !l.starts_with('%'))
+        .unwrap_or(lines.len());
+    let col = lines.get(first).map_or(0, |l| leading_whitespace(l).len());
+    YamlBlock { first, col, end: lines.len() }
+}
+
+fn yaml_entries(lines: &[String], block: &YamlBlock) -> Vec<YamlEntry> {
+    let starts: Vec<usize> = (block.first..block.end)
+        .filter(|&i| {
+            let col = if i == block.first { block.col } else { leading_whitespace(&lines[i]).len() };
+            col == block.col && yaml_is_content(&lines[i][col.min(lines[i].len())..])
+        })
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, &line)| {
+            let mut end = starts.get(n + 1).copied().unwrap_or(block.end);
+            while end > line + 1 && !yaml_is_content(&lines[end - 1]) {
+                end -= 1;
+            }
+            let text = &lines[line];
+            let content = &text[block.col..];
+            let (key, value_col, inline_map) = if content == "-" || content.starts_with("- ") {
+                let rest = content[1..].trim_start();
+                let value_col = text.len() - rest.len();
+                (None, value_col, yaml_key(rest).is_some())
+            } else if let Some((key, off)) = yaml_key(content) {
+                let after = &content[off..];
+                (Some(key), text.len() - after.trim_start().len(), false)
+            } else {
+                (None, block.col, false)
+            };
+            let value_end = value_col + yaml_strip_comment(&text[value_col..]).len();
+            YamlEntry { line, end, key, value_col, value_end, inline_map }
+        })
+        .collect()
+}
+
+fn yaml_is_item(lines: &[String], block: &YamlBlock, e: &YamlEntry) -> bool {
+    e.key.is_none() && lines[e.line][block.col..].starts_with('-')
+}
+
+fn yaml_find<'a>(lines: &[String], block: &YamlBlock, entries: &'a [YamlEntry], seg: &str) -> Option<&'a YamlEntry> {
+    if let Some(e) = entries.iter().rev().find(|e| e.key.as_deref() == Some(seg)) {
+        return Some(e);
+    }
+    let index = seg.parse::<usize>().ok()?;
+    entries.iter().filter(|e| yaml_is_item(lines, block, e)).nth(index)
+}
+
+/// Plain YAML scalar when unambiguous, otherwise a JSON (double-quoted / flow) literal; None for non-empty containers.
+fn yaml_scalar(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => {
+            let plain = !s.is_empty()
+                && s.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '/' || c == '.')
+                && s.chars().all(|c| c.is_ascii_alphanumeric() || " _-./".contains(c))
+                && !s.ends_with(' ')
+                && s.parse::<f64>().is_err()
+                && !["true", "false", "null", "yes", "no", "on", "off", "y", "n"].contains(&s.to_ascii_lowercase().as_str());
+            Some(if plain { s.clone() } else { serde_json::to_string(s).ok()? })
+        }
+        serde_json::Value::Array(a) if !a.is_empty() => None,
+        serde_json::Value::Object(o) if !o.is_empty() => None,
+        other => serde_json::to_string(other).ok(),
+    }
+}
+
+fn yaml_block_lines(value: &serde_json::Value) -> Result<Vec<String>> {
+    Ok(serde_yaml_ng::to_string(value)?.lines().map(|l| l.to_string()).collect())
+}
+
+/// Render `head` (e.g. `  key:` or `  -`) followed by `value`, with nested lines indented to `child_indent`.
+fn yaml_render(head: &str, value: &serde_json::Value, child_indent: usize, item: bool) -> Result<Vec<String>> {
+    if let Some(scalar) = yaml_scalar(value) {
+        return Ok(vec![format!("{} {}", head, scalar)]);
+    }
+    let block = yaml_block_lines(value)?;
+    let pad = " ".repeat(child_indent);
+    let mut out = Vec::new();
+    let mut rest = block.iter();
+    if item {
+        out.push(format!("{} {}", head, rest.next().map_or("", |l| l.as_str())));
+    } else {
+        out.push(head.to_string());
+    }
+    out.extend(rest.map(|l| format!("{}{}", pad, l)));
+    Ok(out)
+}
+
+/// Apply a `yaml_set` (`value` is Some) or `yaml_delete` to block-style YAML, line by line.
+fn yaml_edit(lines: &mut Vec<String>, path: &[String], value: Option<&serde_json::Value>) -> Result<()> {
+    let (last, parents) = path.split_last().ok_or_else(|| anyhow!("yaml path must not be empty"))?;
+    let mut block = yaml_root(lines);
+    for seg in parents {
+        let entries = yaml_entries(lines, &block);
+        let e = yaml_find(lines, &block, &entries, seg).ok_or_else(|| anyhow!("yaml path segment {:?} not found", seg))?;
+        block = if e.inline_map {
+            YamlBlock { first: e.line, col: e.value_col, end: e.end }
+        } else if e.value_col < e.value_end {
+            bail!("yaml path segment {:?} (line {}) has an inline value; set it as a whole instead", seg, e.line + 1);
+        } else {
+            let first = (e.line + 1..e.end)
+                .find(|&i| yaml_is_content(&lines[i]))
+                .ok_or_else(|| anyhow!("yaml path segment {:?} (line {}) has no nested block", seg, e.line + 1))?;
+            YamlBlock { first, col: leading_whitespace(&lines[first]).len(), end: e.end }
+        };
+    }
+
+    let entries = yaml_entries(lines, &block);
+    match (yaml_find(lines, &block, &entries, last), value) {
+        (Some(e), None) => {
+            lines.drain(e.line..e.end);
+        }
+        (Some(e), Some(value)) => {
+            let text = &lines[e.line];
+            let head = text[..e.value_col].trim_end();
+            let item = e.key.is_none();
+            let child_indent = if item { head.len() + 1 } else { block.col + 2 };
+            let mut rendered = yaml_render(head, value, child_indent, item)?;
+            if let Some(first) = rendered.first_mut() {
+                first.push_str(&text[e.value_end..]);
+            }
+            lines.splice(e.line..e.end, rendered);
+        }
+        (None, None) => bail!("yaml_delete: {:?} not found", last),
+        (None, Some(value)) => {
+            let at = entries.last().map_or(block.end, |e| e.end);
+            let pad = " ".repeat(block.col);
+            let items = entries.iter().filter(|e| yaml_is_item(lines, &block, e)).count();
+            let rendered = if last == "-" || (last.parse::<usize>() == Ok(items) && entries.len() == items) {
+                yaml_render(&format!("{}-", pad), value, block.col + 2, true)?
+            } else {
+                let key = yaml_scalar(&serde_json::Value::String(last.clone())).unwrap_or_default();
+                yaml_render(&format!("{}{}:", pad, key), value, block.col + 2, false)?
+            };
+            lines.splice(at..at, rendered);
+        }
+    }
+    Ok(())
+}
+
+/// Hash -> line (1-indexed) for every hash that occurs on exactly one line.
+fn unique_line_hashes(lines: &[String]) -> HashMap<String, usize> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut first_seen: HashMap<String, usize> = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        let ln = i + 1;
+        let h = compute_line_hash(line);
+        *counts.entry(h.clone()).or_insert(0) += 1;
+        first_seen.entry(h).or_insert(ln);
+    }
+    let mut unique: HashMap<String, usize> = HashMap::new();
+    for (h, c) in counts {
+        if c == 1 {
+            if let Some(ln) = first_seen.get(&h) {
+                unique.insert(h, *ln);
+            }
+        }
+    }
+    unique
+}
+
+/// How an anchor relates to the current file, as `verify_anchors` reports it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnchorStatus {
+    /// The line still has this hash.
+    Ok,
+    /// The line changed, but the hash is unique elsewhere; edits would relocate to that line.
+    Relocated(usize),
+    /// The line changed and the hash is nowhere else unique; holds the line's current hash.
+    Stale(String),
+    /// The line number is past the end of the file.
+    OutOfRange,
+}
+
+/// Check `anchors` against `lines` the way `apply_hashline_edits` does, without editing.
+pub fn verify_anchors(lines: &[String], anchors: &[Anchor]) -> Vec<AnchorStatus> {
+    let unique = unique_line_hashes(lines);
+    anchors
+        .iter()
+        .map(|a| {
+            if a.line < 1 || a.line > lines.len() {
+                return AnchorStatus::OutOfRange;
+            }
+            let actual = compute_line_hash(&lines[a.line - 1]);
+            match unique.get(&a.hash) {
+                _ if actual == a.hash => AnchorStatus::Ok,
+                Some(&to) => AnchorStatus::Relocated(to),
+                None => AnchorStatus::Stale(actual),
+            }
+        })
+        .collect()
+}
+
+/// The `LINE:HASH` anchors an edit refers to, in field order (`section:` anchors are left out,
+/// since they are resolved by heading rather than by hash).
+pub fn edit_anchors(edit: &HashlineEdit) -> Vec<&str> {
+    let anchors: Vec<Option<&String>> = match edit {
+        HashlineEdit::SetLine { set_line } => vec![Some(&set_line.anchor)],
+        HashlineEdit::ReplaceLines { replace_lines: e } => vec![Some(&e.start_anchor), Some(&e.end_anchor)],
+        HashlineEdit::InsertAfter { insert_after } => vec![Some(&insert_after.anchor)],
+        HashlineEdit::DeleteLines { delete_lines: e } => vec![Some(&e.start_anchor), e.end_anchor.as_ref()],
+        HashlineEdit::MoveLines { move_lines: e } => {
+            vec![Some(&e.start_anchor), e.end_anchor.as_ref(), e.after.as_ref(), e.before.as_ref()]
+        }
+        HashlineEdit::CopyLines { copy_lines: e } => {
+            vec![Some(&e.start_anchor), e.end_anchor.as_ref(), e.after.as_ref(), e.before.as_ref()]
+        }
+        HashlineEdit::IndentRange { indent_range: e } => vec![Some(&e.start_anchor), e.end_anchor.as_ref()],
+        HashlineEdit::DedentRange { dedent_range: e } => vec![Some(&e.start_anchor), e.end_anchor.as_ref()],
+        HashlineEdit::CommentRange { comment_range: e } => vec![Some(&e.start_anchor), e.end_anchor.as_ref()],
+        HashlineEdit::UncommentRange { uncomment_range: e } => vec![Some(&e.start_anchor), e.end_anchor.as_ref()],
+        HashlineEdit::SortRange { sort_range: e } => vec![Some(&e.start_anchor), Some(&e.end_anchor)],
+        HashlineEdit::DedupeRange { dedupe_range: e } => vec![Some(&e.start_anchor), Some(&e.end_anchor)],
+        HashlineEdit::SwapLines { swap_lines: e } => {
+            vec![Some(&e.start_anchor), e.end_anchor.as_ref(), Some(&e.with_start_anchor), e.with_end_anchor.as_ref()]
+        }
+        HashlineEdit::ReplaceInRange { replace_in_range: e } => vec![Some(&e.start_anchor), Some(&e.end_anchor)],
+        HashlineEdit::CsvSetCell { csv_set_cell } => vec![Some(&csv_set_cell.row_anchor)],
+        HashlineEdit::Plugin { plugin } => vec![Some(&plugin.start_anchor), plugin.end_anchor.as_ref()],
+        HashlineEdit::Script { script } => vec![Some(&script.start_anchor), script.end_anchor.as_ref()],
+        HashlineEdit::OnlyIf { only_if } => {
+            let mut anchors: Vec<&str> = only_if.anchor.iter().map(String::as_str).collect();
+            anchors.extend(edit_anchors(&only_if.edit));
+            return anchors;
+        }
+        HashlineEdit::Append { .. }
+        | HashlineEdit::Prepend { .. }
+        | HashlineEdit::Replace { .. }
+        | HashlineEdit::RegexReplace { .. }
+        | HashlineEdit::JsonSet { .. }
+        | HashlineEdit::JsonDelete { .. }
+        | HashlineEdit::YamlSet { .. }
+        | HashlineEdit::YamlDelete { .. }
+        | HashlineEdit::TomlSet { .. }
+        | HashlineEdit::TomlRemove { .. }
+        | HashlineEdit::ReplaceSection { .. }
+        | HashlineEdit::AppendToSection { .. }
+        | HashlineEdit::CsvAddColumn { .. }
+        | HashlineEdit::KvSet { .. }
+        | HashlineEdit::EnvSet { .. } => Vec::new(),
+    };
+    anchors.into_iter().flatten().map(String::as_str).filter(|a| !a.starts_with("section:")).collect()
+}
+
+fn validate_or_relocate(
+    r: &mut Anchor,
+    lines: &[String],
+    unique: &HashMap<String, usize>,
+    mismatches: &mut Vec<(usize, String, String)>,
+    relocations: &mut usize,
+) -> Result<()> {
+    if r.line < 1 || r.line > lines.len() {
+        bail!("line {} does not exist (file has {} lines)", r.line, lines.len());
+    }
+
+    let actual = compute_line_hash(&lines[r.line - 1]);
+    if actual == r.hash {
+        return Ok(());
+    }
+
+    if let Some(relocated) = unique.get(&r.hash) {
+        info!(hash = %r.hash, from = r.line, to = *relocated, "relocated anchor");
+        *relocations += 1;
+        r.line = *relocated;
+        return Ok(());
+    }
+
+    mismatches.push((r.line, r.hash.clone(), actual));
+    Ok(())
+}
+
+/// The stale-anchor report for `mismatches` (line, expected hash, actual hash): each changed line
+/// with its current anchor, plus the old -> new replacements to retry with.
+pub fn render_mismatch_error(lines: &[String], mismatches: &[(usize, String, String)]) -> String {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "{} line(s) have changed since last read. Re-read the file and use updated LINE:HASH refs.\n\n",
+        mismatches.len()
+    ));
+
+    for (line, expected, actual) in mismatches {
+        let content = lines.get(line - 1).cloned().unwrap_or_default();
+        out.push_str(&format!(
+            ">>> {}:{}|{}\n    expected {}\n\n",
+            line, actual, content, expected
+        ));
+    }
+
+    out.push_str("Quick fix: replace stale refs:\n");
+    for (line, expected, actual) in mismatches {
+        out.push_str(&format!("  {}:{} -> {}:{}\n", line, expected, line, actual));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIVE: &str = "one\ntwo\nthree\nfour\nfive";
+
+    /// Apply `payload` to `text`, with each `{N}` in the payload standing for line N's anchor.
+    fn apply(text: &str, payload: &str, opts: ApplyOptions) -> Result<String> {
+        apply_to(text, text, payload, opts)
+    }
+
+    /// Like `apply`, but the anchors come from `read`, an earlier version of `text`.
+    fn apply_to(read: &str, text: &str, payload: &str, opts: ApplyOptions) -> Result<String> {
+        let mut json = payload.to_string();
+        for (i, line) in read.split('\n').enumerate() {
+            json = json.replace(&format!("{{{}}}", i + 1), &Anchor::for_line(i + 1, line).to_string());
+        }
+        let edits = parse_edits_payload(&json)?.edits;
+        Ok(apply_hashline_edits(text.split('\n').map(String::from).collect(), &edits, opts)?.join("\n"))
+    }
+
+    fn idempotent() -> ApplyOptions<'static> {
+        ApplyOptions { idempotent: true, ..ApplyOptions::default() }
+    }
+
+    fn conflict(text: &str, payload: &str) {
+        let err = apply(text, payload, ApplyOptions::default()).expect_err("edits should conflict");
+        assert!(err.to_string().contains("conflicts with edit"), "unexpected error: {err:#}");
+        assert!(err.downcast_ref::<EditConflict>().is_some(), "not an EditConflict: {err:#}");
+    }
+
+    #[test]
+    fn range_ops_reject_edits_inside_their_range() {
+        for op in [
+            r#"{"sort_range": {"start_anchor": "{1}", "end_anchor": "{5}"}}"#,
+            r#"{"dedupe_range": {"start_anchor": "{1}", "end_anchor": "{5}"}}"#,
+            r#"{"indent_range": {"start_anchor": "{1}", "end_anchor": "{5}", "width": 2}}"#,
+            r#"{"comment_range": {"start_anchor": "{1}", "end_anchor": "{5}", "comment": "//"}}"#,
+            r#"{"replace_in_range": {"start_anchor": "{1}", "end_anchor": "{5}", "old_text": "o", "new_text": "0"}}"#,
+        ] {
+            conflict(FIVE, &format!(r#"[{op}, {{"insert_after": {{"anchor": "{{2}}", "text": "X"}}}}]"#));
+            conflict(FIVE, &format!(r#"[{op}, {{"set_line": {{"anchor": "{{3}}", "new_text": "T"}}}}]"#));
+            conflict(FIVE, &format!(r#"[{{"delete_lines": {{"start_anchor": "{{4}}"}}}}, {op}]"#));
+        }
+    }
+
+    #[test]
+    fn range_ops_reject_overlapping_range_ops() {
+        conflict(
+            FIVE,
+            r#"[{"sort_range": {"start_anchor": "{1}", "end_anchor": "{3}"}},
+                {"dedupe_range": {"start_anchor": "{3}", "end_anchor": "{5}"}}]"#,
+        );
+    }
+
+    #[test]
+    fn sort_range_allows_insert_after_its_last_line() {
+        let payload = r#"[{"sort_range": {"start_anchor": "{1}", "end_anchor": "{3}"}},
+                          {"insert_after": {"anchor": "{3}", "text": "X"}}]"#;
+        assert_eq!(apply(FIVE, payload, ApplyOptions::default()).unwrap(), "one\nthree\ntwo\nX\nfour\nfive");
+    }
+
+    #[test]
+    fn replace_lines_starting_with_its_first_line_is_not_already_applied() {
+        let payload = r#"[{"replace_lines": {"start_anchor": "{2}", "end_anchor": "{4}", "new_text": "two"}}]"#;
+        let once = apply(FIVE, payload, idempotent()).unwrap();
+        assert_eq!(once, "one\ntwo\nfive");
+        assert_eq!(apply_to(FIVE, &once, payload, idempotent()).unwrap(), once);
+    }
+
+    #[test]
+    fn replace_lines_matching_its_whole_range_is_already_applied() {
+        let payload = r#"[{"replace_lines": {"start_anchor": "{2}", "end_anchor": "{3}", "new_text": "two\nthree"}}]"#;
+        assert_eq!(apply(FIVE, payload, idempotent()).unwrap(), FIVE);
+    }
+
+    #[test]
+    fn set_line_reapplied_is_a_no_op() {
+        let payload = r#"[{"set_line": {"anchor": "{3}", "new_text": "THREE"}},
+                          {"insert_after": {"anchor": "{5}", "text": "six"}}]"#;
+        let once = apply(FIVE, payload, idempotent()).unwrap();
+        assert_eq!(once, "one\ntwo\nTHREE\nfour\nfive\nsix");
+        assert_eq!(apply_to(FIVE, &once, payload, idempotent()).unwrap(), once);
+    }
+
+    #[cfg(feature = "lua")]
+    #[test]
+    fn script_runs_out_of_memory_instead_of_exhausting_it() {
+        let lua = "local t = {} for i = 1, 64 do t[i] = string.rep('x', 1 << 24) .. i end return t";
+        let payload = format!(r#"[{{"script": {{"start_anchor": "{{1}}", "lua": "{lua}"}}}}]"#);
+        let err = apply(FIVE, &payload, ApplyOptions::default()).expect_err("script should fail");
+        assert!(format!("{err:#}").contains("memory"), "unexpected error: {err:#}");
+    }
+
+    #[test]
+    fn swap_lines_swaps_ranges() {
+        let payload = r#"[{"swap_lines": {"start_anchor": "{1}", "end_anchor": "{2}", "with_start_anchor": "{5}"}}]"#;
+        assert_eq!(apply(FIVE, payload, ApplyOptions::default()).unwrap(), "five\nthree\nfour\none\ntwo");
+    }
+
+    #[test]
+    fn swap_lines_rejects_set_line_on_a_swapped_line() {
+        conflict(
+            FIVE,
+            r#"[{"swap_lines": {"start_anchor": "{1}", "with_start_anchor": "{5}"}},
+                {"set_line": {"anchor": "{1}", "new_text": "ONE"}}]"#,
+        );
+    }
+
+    #[test]
+    fn swap_lines_rejects_a_range_overlapping_either_side() {
+        conflict(
+            FIVE,
+            r#"[{"swap_lines": {"start_anchor": "{1}", "with_start_anchor": "{3}"}},
+                {"replace_lines": {"start_anchor": "{2}", "end_anchor": "{4}", "new_text": "R"}}]"#,
+        );
+    }
+
+    #[test]
+    fn swap_lines_allows_edits_outside_the_ranges() {
+        let payload = r#"[{"swap_lines": {"start_anchor": "{1}", "with_start_anchor": "{3}"}},
+                          {"set_line": {"anchor": "{5}", "new_text": "FIVE"}},
+                          {"insert_after": {"anchor": "{3}", "text": "X"}}]"#;
+        assert_eq!(apply(FIVE, payload, ApplyOptions::default()).unwrap(), "three\ntwo\none\nX\nfour\nFIVE");
+    }
+
+    fn ok(text: &str, payload: &str) -> String {
+        apply(text, payload, ApplyOptions::default()).unwrap_or_else(|e| panic!("{payload}: {e:#}"))
+    }
+
+    #[test]
+    fn delete_lines_removes_one_line_or_an_inclusive_range() {
+        assert_eq!(ok(FIVE, r#"[{"delete_lines": {"start_anchor": "{2}"}}]"#), "one\nthree\nfour\nfive");
+        assert_eq!(ok(FIVE, r#"[{"delete_lines": {"start_anchor": "{2}", "end_anchor": "{4}"}}]"#), "one\nfive");
+    }
+
+    #[test]
+    fn delete_lines_rejects_a_stale_anchor() {
+        let payload = r#"[{"delete_lines": {"start_anchor": "{2}"}}]"#;
+        let err = apply_to(FIVE, "one\nTWO\nthree\nfour\nfive", payload, ApplyOptions::default())
+            .expect_err("line 2 changed");
+        assert!(err.downcast_ref::<MismatchError>().is_some(), "{err:#}");
+    }
+
+    #[test]
+    fn move_lines_moves_a_range_after_or_before_an_anchor() {
+        let payload = r#"[{"move_lines": {"start_anchor": "{1}", "end_anchor": "{2}", "after": "{4}"}}]"#;
+        assert_eq!(ok(FIVE, payload), "three\nfour\none\ntwo\nfive");
+        assert_eq!(ok(FIVE, r#"[{"move_lines": {"start_anchor": "{4}", "before": "{1}"}}]"#), "four\none\ntwo\nthree\nfive");
+    }
+
+    #[test]
+    fn move_lines_rejects_a_destination_inside_the_moved_lines() {
+        let payload = r#"[{"move_lines": {"start_anchor": "{1}", "end_anchor": "{3}", "after": "{2}"}}]"#;
+        let err = apply(FIVE, payload, ApplyOptions::default()).expect_err("destination inside the range");
+        assert!(err.to_string().contains("inside the moved lines"), "{err:#}");
+    }
+
+    #[test]
+    fn copy_lines_keeps_the_original() {
+        let payload = r#"[{"copy_lines": {"start_anchor": "{1}", "end_anchor": "{2}", "after": "{5}"}}]"#;
+        assert_eq!(ok(FIVE, payload), "one\ntwo\nthree\nfour\nfive\none\ntwo");
+    }
+
+    #[test]
+    fn append_and_prepend_add_lines_at_either_end() {
+        let payload = r#"[{"prepend": {"text": "zero"}}, {"append": {"text": "six"}}]"#;
+        assert_eq!(ok(FIVE, payload), "zero\none\ntwo\nthree\nfour\nfive\nsix");
+    }
+
+    #[test]
+    fn replace_in_range_only_touches_lines_inside_the_range() {
+        let text = "a x\nb x\nc x";
+        let payload = r#"[{"replace_in_range": {"start_anchor": "{2}", "end_anchor": "{2}",
+                                               "old_text": "x", "new_text": "y"}}]"#;
+        assert_eq!(ok(text, payload), "a x\nb y\nc x");
+    }
+
+    #[test]
+    fn regex_replace_expands_capture_groups() {
+        let text = "fn fetch_a_v1()\nfn fetch_b_v1()";
+        let payload = r#"[{"regex_replace": {"pattern": "fetch_(\\w+)_v1", "replacement": "fetch_$1"}}]"#;
+        assert_eq!(ok(text, payload), "fn fetch_a()\nfn fetch_b()");
+        let payload = r#"[{"regex_replace": {"pattern": "fetch_(\\w+)_v1", "replacement": "fetch_$1",
+                                            "max_replacements": 1}}]"#;
+        assert_eq!(ok(text, payload), "fn fetch_a()\nfn fetch_b_v1()");
+    }
+
+    #[test]
+    fn replace_targets_an_occurrence_and_caps_the_count() {
+        assert_eq!(ok("x x x x", r#"[{"replace": {"old_text": "x", "new_text": "y", "occurrence": 3}}]"#), "x x y x");
+        let payload = r#"[{"replace": {"old_text": "x", "new_text": "y", "all": true, "max_count": 2}}]"#;
+        assert_eq!(ok("x x x x", payload), "y y x x");
+    }
+
+    #[test]
+    fn indent_and_dedent_range_round_trip() {
+        let indented = ok(FIVE, r#"[{"indent_range": {"start_anchor": "{2}", "end_anchor": "{3}", "width": 2}}]"#);
+        assert_eq!(indented, "one\n  two\n  three\nfour\nfive");
+        let payload = r#"[{"dedent_range": {"start_anchor": "{2}", "end_anchor": "{3}", "width": 2}}]"#;
+        assert_eq!(ok(&indented, payload), FIVE);
+    }
+
+    #[test]
+    fn comment_range_takes_the_marker_from_the_target() {
+        let payload = r#"[{"comment_range": {"start_anchor": "{1}", "end_anchor": "{2}"}}]"#;
+        let commented = apply(FIVE, payload, ApplyOptions::for_target("cfg.py")).unwrap();
+        assert_eq!(commented, "# one\n# two\nthree\nfour\nfive");
+        let payload = r#"[{"uncomment_range": {"start_anchor": "{1}", "end_anchor": "{2}"}}]"#;
+        assert_eq!(apply(&commented, payload, ApplyOptions::for_target("cfg.py")).unwrap(), FIVE);
+        assert!(apply(FIVE, payload, ApplyOptions::for_target("notes.unknown")).is_err(), "no marker for the extension");
+    }
+
+    #[test]
+    fn sort_range_is_stable_and_optionally_unique() {
+        let text = "c\nB\na\nb";
+        let payload = r#"[{"sort_range": {"start_anchor": "{1}", "end_anchor": "{4}", "case_insensitive": true}}]"#;
+        assert_eq!(ok(text, payload), "a\nB\nb\nc");
+        let payload = r#"[{"sort_range": {"start_anchor": "{1}", "end_anchor": "{4}",
+                                         "case_insensitive": true, "unique": true}}]"#;
+        assert_eq!(ok(text, payload), "a\nB\nc");
+    }
+
+    #[test]
+    fn dedupe_range_collapses_adjacent_or_all_repeats() {
+        let text = "a\na\nb\na";
+        assert_eq!(ok(text, r#"[{"dedupe_range": {"start_anchor": "{1}", "end_anchor": "{4}"}}]"#), "a\nb\na");
+        assert_eq!(ok(text, r#"[{"dedupe_range": {"start_anchor": "{1}", "end_anchor": "{4}", "all": true}}]"#), "a\nb");
+    }
+
+    #[test]
+    fn swap_lines_exchanges_ranges_of_different_lengths() {
+        let payload = r#"[{"swap_lines": {"start_anchor": "{1}", "end_anchor": "{2}", "with_start_anchor": "{4}"}}]"#;
+        assert_eq!(ok(FIVE, payload), "four\nthree\none\ntwo\nfive");
+    }
+
+    #[test]
+    fn expected_text_guards_against_a_hash_collision() {
+        let payload = r#"[{"set_line": {"anchor": "{2}", "new_text": "TWO", "expected_text": "two"}}]"#;
+        assert_eq!(ok(FIVE, payload), "one\nTWO\nthree\nfour\nfive");
+        let payload = r#"[{"set_line": {"anchor": "{2}", "new_text": "TWO", "expected_text": "deux"}}]"#;
+        assert!(apply(FIVE, payload, ApplyOptions::default()).is_err(), "expected_text does not match");
+    }
+
+    #[test]
+    fn only_if_skips_an_edit_whose_condition_fails() {
+        let payload = r#"[{"only_if": {"contains": "zzz", "edit": {"set_line": {"anchor": "{1}", "new_text": "ONE"}}}},
+                          {"only_if": {"contains": "three", "edit": {"set_line": {"anchor": "{2}", "new_text": "TWO"}}}}]"#;
+        assert_eq!(ok(FIVE, payload), "one\nTWO\nthree\nfour\nfive");
+    }
+
+    #[test]
+    fn relocated_anchors_are_counted_in_the_report() {
+        let edits = parse_edits_payload(&format!(
+            r#"[{{"set_line": {{"anchor": "{}", "new_text": "TWO"}}}},
+                {{"set_line": {{"anchor": "{}", "new_text": "1"}}}}]"#,
+            Anchor::for_line(2, "two"),
+            Anchor::for_line(1, "zero"),
+        ))
+        .unwrap()
+        .edits;
+        let lines = "zero\none\ntwo".split('\n').map(String::from).collect();
+        let (lines, report) = apply_hashline_edits_report(lines, &edits, ApplyOptions::default()).unwrap();
+        assert_eq!(lines, ["1", "one", "TWO"]);
+        assert_eq!(report.relocations, 1);
+    }
+
+    #[test]
+    fn json_set_keeps_json5_comments_and_trailing_commas() {
+        let text = "{\n  // the port\n  \"port\": 80, /* old */\n  \"hosts\": [\"a\", \"b\",],\n}";
+        let payload = r#"[{"json_set": {"path": "$.port", "value": 8080}}]"#;
+        let want = "{\n  // the port\n  \"port\": 8080, /* old */\n  \"hosts\": [\"a\", \"b\",],\n}";
+        assert_eq!(ok(text, payload), want);
+        let payload = r#"[{"json_set": {"path": "/hosts/-", "value": "c"}}]"#;
+        let out = ok(text, payload);
+        assert!(out.contains("// the port") && out.contains("/* old */"), "comments lost: {out}");
+        assert_eq!(json5::from_str::<serde_json::Value>(&out).unwrap()["hosts"], serde_json::json!(["a", "b", "c"]));
+    }
+
+    #[test]
+    fn json_delete_removes_a_member_and_its_comma() {
+        let text = "{\n  \"a\": 1,\n  \"b\": 2\n}";
+        assert_eq!(ok(text, r#"[{"json_delete": {"path": "/a"}}]"#), "{\n  \"b\": 2\n}");
+    }
+
+    #[test]
+    fn toml_set_keeps_comments_and_creates_missing_keys() {
+        let text = "# deps\n[dependencies]\nserde = \"1.0\" # pinned\n";
+        let payload = r#"[{"toml_set": {"path": "$.dependencies.serde", "value": "1.1"}}]"#;
+        assert_eq!(ok(text, payload), "# deps\n[dependencies]\nserde = \"1.1\" # pinned\n");
+        let payload = r#"[{"toml_set": {"path": "$.dependencies.anyhow", "value": "1"}}]"#;
+        assert_eq!(ok(text, payload), "# deps\n[dependencies]\nserde = \"1.0\" # pinned\nanyhow = \"1\"\n");
+    }
+
+    #[test]
+    fn yaml_set_rewrites_a_nested_value_and_keeps_comments() {
+        let text = "# config\nserver:\n  port: 80 # default\n  host: localhost\nlist:\n  - a";
+        let payload = r#"[{"yaml_set": {"path": "$.server.port", "value": 8080}}]"#;
+        assert_eq!(ok(text, payload), "# config\nserver:\n  port: 8080 # default\n  host: localhost\nlist:\n  - a");
+        let payload = r#"[{"yaml_set": {"path": "/list/-", "value": "b"}}]"#;
+        assert_eq!(ok(text, payload), format!("{text}\n  - b"));
+    }
+
+    #[test]
+    fn markdown_sections_end_at_the_next_heading_of_the_same_level() {
+        let text = "# Title\n\n## Install\n\nold\n\n### Detail\nx\n\n## Usage\n\n```\n## not a heading\n```";
+        let payload = r###"[{"replace_section": {"section": "## Install", "new_text": "new"}}]"###;
+        assert_eq!(ok(text, payload), "# Title\n\n## Install\n\nnew\n\n## Usage\n\n```\n## not a heading\n```");
+        let payload = r#"[{"append_to_section": {"section": "Usage", "text": "run it"}}]"#;
+        assert!(ok(text, payload).ends_with("```\n## not a heading\n```\nrun it"));
+        let payload = r#"[{"replace_section": {"section": "not a heading", "new_text": "x"}}]"#;
+        assert!(apply(text, payload, ApplyOptions::default()).is_err(), "headings in fences are not sections");
+    }
+
+    #[test]
+    fn csv_set_cell_quotes_only_when_needed_and_honours_the_dialect() {
+        let text = "name,note\nann,\"a, b\"\nbob,plain";
+        let payload = r#"[{"csv_set_cell": {"row_anchor": "{3}", "column": "note", "value": "x, \"y\""}}]"#;
+        assert_eq!(ok(text, payload), "name,note\nann,\"a, b\"\nbob,\"x, \"\"y\"\"\"");
+        let payload = r#"[{"csv_set_cell": {"row_anchor": "{2}", "column": 1, "value": "anne"}}]"#;
+        assert_eq!(ok(text, payload), "name,note\nanne,\"a, b\"\nbob,plain");
+
+        let text = "a\tb\n1\t'x\ty'";
+        let payload = r#"[{"csv_set_cell": {"row_anchor": "{2}", "column": "b", "value": "z",
+                                            "delimiter": "\\t", "quote": "'"}}]"#;
+        assert_eq!(ok(text, payload), "a\tb\n1\tz");
+        let payload = r#"[{"csv_add_column": {"name": "c", "default": "0", "after": "a",
+                                              "delimiter": "\\t", "quote": "'"}}]"#;
+        assert_eq!(ok(text, payload), "a\tc\tb\n1\t0\t'x\ty'");
+    }
+
+    #[test]
+    fn kv_set_rewrites_in_place_or_appends_to_the_section() {
+        let text = "top = 1\n\n[db]\nhost: localhost\n; port\n\n[web]\nport = 80";
+        let payload = r#"[{"kv_set": {"section": "db", "key": "host", "value": "db.local"}}]"#;
+        assert_eq!(ok(text, payload), "top = 1\n\n[db]\nhost: db.local\n; port\n\n[web]\nport = 80");
+        let payload = r#"[{"kv_set": {"section": "db", "key": "port", "value": "5432"}}]"#;
+        assert_eq!(ok(text, payload), "top = 1\n\n[db]\nhost: localhost\nport: 5432\n; port\n\n[web]\nport = 80");
+        let payload = r#"[{"kv_set": {"key": "debug", "value": "true"}}]"#;
+        assert_eq!(ok(text, payload), "top = 1\ndebug = true\n\n[db]\nhost: localhost\n; port\n\n[web]\nport = 80");
+        let payload = r#"[{"kv_set": {"section": "cache", "key": "ttl", "value": "60"}}]"#;
+        assert!(ok(text, payload).ends_with("port = 80\n\n[cache]\nttl = 60"));
+    }
+
+    #[test]
+    fn env_quote_picks_the_weakest_quoting_that_round_trips() {
+        assert_eq!(env_quote("plain-value_1.2"), "plain-value_1.2");
+        assert_eq!(env_quote(""), "''");
+        assert_eq!(env_quote("a # not a comment"), "'a # not a comment'");
+        assert_eq!(env_quote("two words"), "'two words'");
+        assert_eq!(env_quote("$HOME/bin"), "'$HOME/bin'");
+        assert_eq!(env_quote("it's $5"), "\"it's \\$5\"");
+        assert_eq!(env_quote("line\n\"two\""), "\"line\\n\\\"two\\\"\"");
+    }
+
+    #[test]
+    fn env_set_keeps_export_and_replaces_multi_line_values() {
+        let text = "export TOKEN = old\nKEY=\"first\nsecond\"\nLAST=1";
+        let payload = r#"[{"env_set": {"key": "TOKEN", "value": "a#b c"}}]"#;
+        assert_eq!(ok(text, payload), "export TOKEN = 'a#b c'\nKEY=\"first\nsecond\"\nLAST=1");
+        let payload = r#"[{"env_set": {"key": "KEY", "value": "$one"}}]"#;
+        assert_eq!(ok(text, payload), "export TOKEN = old\nKEY='$one'\nLAST=1");
+        let payload = r#"[{"env_set": {"key": "NEW", "value": "x"}}]"#;
+        assert_eq!(ok(&format!("{text}\n"), payload), format!("{text}\nNEW=x\n"));
+        let payload = r#"[{"env_set": {"key": "1BAD", "value": "x"}}]"#;
+        assert!(apply(text, payload, ApplyOptions::default()).is_err());
+    }
+
+    #[test]
+    fn front_matter_rejects_a_line_edit_that_straddles_it() {
+        let text = "---\ntitle: Old\n---\n# Body\ntext";
+        let front_matter = || ApplyOptions { front_matter: true, ..ApplyOptions::default() };
+        let payload = r#"[{"replace_lines": {"start_anchor": "{2}", "end_anchor": "{4}", "new_text": "x"}}]"#;
+        let err = apply(text, payload, front_matter()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "replace_lines 2..4 straddles the front matter (lines 1-3); edit it separately or use yaml_set"
+        );
+        assert!(apply(text, payload, ApplyOptions::default()).is_ok(), "only markdown targets have front matter");
+
+        let payload = r#"[{"yaml_set": {"path": "$.title", "value": "New"}}]"#;
+        assert_eq!(apply(text, payload, front_matter()).unwrap(), "---\ntitle: New\n---\n# Body\ntext");
+    }
+
+    fn parse_error(payload: &str) -> String {
+        parse_edits_payload(payload).expect_err("payload should be rejected").to_string()
+    }
+
+    #[test]
+    fn tagged_and_keyed_edits_parse_the_same() {
+        let keyed = parse_edits_payload(r#"[{"set_line": {"anchor": "1:ab", "new_text": "x"}}]"#).unwrap();
+        let tagged = parse_edits_payload(r#"[{"type": "set_line", "anchor": "1:ab", "new_text": "x"}]"#).unwrap();
+        assert_eq!(format!("{:?}", keyed.edits), format!("{:?}", tagged.edits));
+        let wrapped = parse_edits_payload(r#"{"edits": [{"type": "set_line", "anchor": "1:ab", "new_text": "x"}],
+                                              "idempotency_key": "k1"}"#)
+        .unwrap();
+        assert_eq!(format!("{:?}", wrapped.edits), format!("{:?}", keyed.edits));
+        assert_eq!(wrapped.idempotency_key.as_deref(), Some("k1"));
+    }
+
+    #[test]
+    fn an_unknown_op_suggests_the_nearest_one() {
+        assert_eq!(
+            parse_error(r#"[{"set_lines": {"anchor": "1:ab", "new_text": "x"}}]"#),
+            "1 of 1 edit(s) are invalid:\n  edits[0]: unknown edit op `set_lines`; did you mean `set_line`?"
+        );
+        assert_eq!(
+            parse_error(r#"[{"type": "insrt_after", "anchor": "1:ab", "text": "x"}]"#),
+            "1 of 1 edit(s) are invalid:\n  edits[0]: unknown edit op `insrt_after`; did you mean `insert_after`?"
+        );
+    }
+
+    #[test]
+    fn an_unknown_field_names_the_op_and_the_field() {
+        assert_eq!(
+            parse_error(r#"[{"set_line": {"anchor": "1:ab", "new_text": "x", "colour": 1}}]"#),
+            "1 of 1 edit(s) are invalid:\n  edits[0]: set_line: unknown field `colour`, expected one of `anchor`, \
+             `new_text`, `auto_indent`, `expected_text`, `expected_prefix`, `skip_if_applied`"
+        );
+    }
+
+    #[test]
+    fn every_bad_edit_is_reported_by_index() {
+        let err = parse_error(
+            r#"[{"set_line": {"anchor": "1:ab", "new_text": "x"}},
+                {"insert_after": {"anchor": "1:ab"}},
+                {"delete_lines": {"start_anchor": "1:ab"}},
+                {"set_line": {"anchor": "1:ab", "new_text": "x", "line": 3}}]"#,
+        );
+        let mut lines = err.lines();
+        assert_eq!(lines.next(), Some("2 of 4 edit(s) are invalid:"));
+        assert_eq!(lines.next(), Some("  edits[1]: insert_after: missing field `text`"));
+        assert!(lines.next().is_some_and(|l| l.starts_with("  edits[3]: set_line: unknown field `line`")), "{err}");
+
+        let err = parse_error(
+            r#"{"files": [{"path": "a", "edits": [{"set_line": {"anchor": "1:ab", "new_text": "x"}}]},
+                          {"path": "b", "edits": [{"set_line": {"anchor": "1:ab"}}]}]}"#,
+        );
+        assert_eq!(err, "1 of 2 edit(s) are invalid:\n  files[1].edits[0]: set_line: missing field `new_text`");
+    }
+
+    #[test]
+    fn json5_and_yaml_payloads_are_accepted() {
+        let json5 = parse_edits_payload("[{set_line: {anchor: '1:ab', new_text: 'x'}, }, // trailing\n]").unwrap();
+        let yaml = parse_edits_yaml("- set_line:\n    anchor: '1:ab'\n    new_text: x\n").unwrap();
+        let strict = parse_edits_payload(r#"[{"set_line": {"anchor": "1:ab", "new_text": "x"}}]"#).unwrap();
+        assert_eq!(format!("{:?}", json5.edits), format!("{:?}", strict.edits));
+        assert_eq!(format!("{:?}", yaml.edits), format!("{:?}", strict.edits));
+    }
+}
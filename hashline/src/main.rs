@@ -1,505 +1,333 @@
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
-
-use anyhow::{anyhow, bail, Context, Result};
-use clap::{Parser, Subcommand};
-use serde::Deserialize;
-use xxhash_rust::xxh32::xxh32;
-
-#[derive(Parser, Debug)]
-#[command(name = "hashline")]
-#[command(about = "Hashline read/edit tools (LINE:HASH anchors)")]
-struct Cli {
-    #[command(subcommand)]
-    cmd: Command,
-}
-
-#[derive(Subcommand, Debug)]
-enum Command {
-    /// Read a text file and print hashline-prefixed output: LINE:HASH|content
-    Read {
-        path: PathBuf,
-        /// Start line (1-indexed)
-        #[arg(long)]
-        offset: Option<usize>,
-        /// Max lines
-        #[arg(long)]
-        limit: Option<usize>,
-    },
-
-    /// Apply hashline edits to a text file
-    Edit {
-        path: PathBuf,
-        /// JSON edits payload (either a full object or just an array of edits)
-        #[arg(long, conflicts_with = "edits_file")]
-        edits_json: Option<String>,
-        /// Read JSON edits payload from file
-        #[arg(long)]
-        edits_file: Option<PathBuf>,
-        /// Print a unified diff-like preview (very basic) before applying
-        #[arg(long)]
-        preview: bool,
-    },
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct EditRequest {
-    #[serde(default)]
-    edits: Vec<HashlineEdit>,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-#[serde(untagged)]
-enum HashlineEdit {
-    SetLine { set_line: SetLine },
-    ReplaceLines { replace_lines: ReplaceLines },
-    InsertAfter { insert_after: InsertAfter },
-    Replace { replace: ReplaceText },
+mod bench;
+mod checks;
+mod cli;
+mod diff;
+mod docs;
+mod edit;
+mod editorconfig;
+mod inspect;
+mod journal;
+mod metrics;
+mod read;
+mod sed;
+mod server;
+mod session;
+mod storage;
+mod walk;
+
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use hashline::Plugins;
+use serde::Serialize;
+use tracing::debug;
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::bench::bench_command;
+use crate::cli::{Cli, ColorChoice, Command, ConvertTarget, LogFormat, LogLevel, ReadFormat};
+use crate::diff::{convert_to_diff, convert_to_edits, diff_command, patch_command};
+use crate::docs::{payload_schema, render_prompt, render_schema_summary, render_tool_manifest};
+use crate::edit::{edit_command, explain_command, plan_command, validate_command};
+use crate::inspect::{anchor_command, hash_command, stats_command, verify_command};
+use crate::journal::{history_command, journal_command, JournalMove};
+use crate::metrics::{metrics_enabled, record_metrics, report_command, Metrics};
+use crate::read::{read_command, search_command, watch_command};
+use crate::sed::sed_command;
+use crate::server::{serve_command, Server};
+
+/// Machine-output API version emitted in every JSON envelope.
+const API_VERSION: u32 = 1;
+/// API versions this binary can still produce (for `--api-version` negotiation).
+const SUPPORTED_API_VERSIONS: &[u32] = &[1];
+
+#[derive(Debug, Serialize)]
+struct Envelope<T: Serialize> {
+    api_version: u32,
+    command: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct SetLine {
-    anchor: String,
-    new_text: String,
+/// Options shared by all subcommands.
+#[derive(Debug)]
+struct RunCtx {
+    api_version: u32,
+    quiet: bool,
+    remote: Option<String>,
+    stdout: Palette,
+    stderr: Palette,
+    plugins: Plugins,
+    metrics: Metrics,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct ReplaceLines {
-    start_anchor: String,
-    end_anchor: String,
-    new_text: String,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct InsertAfter {
-    anchor: String,
-    text: String,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct ReplaceText {
-    old_text: String,
-    new_text: String,
-    #[serde(default)]
-    all: Option<bool>,
-}
-
-#[derive(Debug, Clone)]
-struct LineRef {
-    line: usize,
-    hash: String,
-}
-
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    match cli.cmd {
-        Command::Read { path, offset, limit } => {
-            let content = fs::read_to_string(&path)
-                .with_context(|| format!("read: failed to read {}", path.display()))?;
-            let normalized = normalize_to_lf(&content);
-            let lines: Vec<&str> = split_preserve_last_empty(&normalized);
-
-            let start = offset.unwrap_or(1);
-            if start == 0 {
-                bail!("--offset is 1-indexed (must be >= 1)");
-            }
-            if start > lines.len().max(1) {
-                // Allow reading from a past-the-end offset on empty-ish files.
-                bail!("offset {} out of range (file has {} lines)", start, lines.len());
-            }
-
-            let max_lines = limit.unwrap_or(lines.len());
-            let mut printed = 0usize;
-
-            for (i, line) in lines.iter().enumerate() {
-                let line_no = i + 1;
-                if line_no < start {
-                    continue;
-                }
-                if printed >= max_lines {
-                    break;
-                }
-                let hash = compute_line_hash(*line);
-                println!("{}:{}|{}", line_no, hash, line);
-                printed += 1;
-            }
-        }
-
-        Command::Edit {
-            path,
-            edits_json,
-            edits_file,
-            preview,
-        } => {
-            let raw = fs::read_to_string(&path)
-                .with_context(|| format!("edit: failed to read {}", path.display()))?;
-            let line_ending = detect_line_ending(&raw);
-            let had_final_newline = raw.ends_with('\n');
-            let normalized = normalize_to_lf(&raw);
-
-            let edits_payload = if let Some(p) = edits_file {
-                fs::read_to_string(&p).with_context(|| format!("edit: failed to read edits file {}", p.display()))?
-            } else if let Some(s) = edits_json {
-                s
-            } else {
-                bail!("provide --edits-json or --edits-file");
-            };
-
-            let edits: Vec<HashlineEdit> = parse_edits_payload(&edits_payload)
-                .context("edit: failed to parse edits JSON")?;
-
-            let old_lines: Vec<String> = split_preserve_last_empty(&normalized)
-                .into_iter()
-                .map(|s| s.to_string())
-                .collect();
-
-            let new_lines = apply_hashline_edits(old_lines.clone(), &edits)
-                .with_context(|| format!("edit: failed to apply edits to {}", path.display()))?;
-
-            if preview {
-                eprintln!("--- {}\n+++ {}\n", path.display(), path.display());
-                render_basic_diff(&old_lines, &new_lines);
-            }
-
-            if old_lines == new_lines {
-                bail!("no changes made (edits produced identical content)");
-            }
-
-            let mut out = new_lines.join("\n");
-            if had_final_newline {
-                out.push('\n');
-            }
-            out = restore_line_endings(&out, line_ending);
-
-            fs::write(&path, out).with_context(|| format!("edit: failed to write {}", path.display()))?;
-            eprintln!("updated {}", path.display());
+#[cfg(test)]
+impl RunCtx {
+    /// Quiet, uncoloured, and local: the context unit tests run commands in.
+    fn for_tests() -> Self {
+        RunCtx {
+            api_version: API_VERSION,
+            quiet: true,
+            remote: None,
+            stdout: Palette { enabled: false },
+            stderr: Palette { enabled: false },
+            plugins: Plugins::from_config(".hashline/config.toml"),
+            metrics: Metrics::default(),
         }
     }
-
-    Ok(())
 }
 
-fn parse_edits_payload(s: &str) -> Result<Vec<HashlineEdit>> {
-    // Accept either:
-    // - {"edits": [ ... ]}
-    // - [ ... ]
-    if s.trim_start().starts_with('[') {
-        let edits: Vec<HashlineEdit> = serde_json::from_str(s)?;
-        return Ok(edits);
-    }
-    let req: EditRequest = serde_json::from_str(s)?;
-    Ok(req.edits)
+/// ANSI styling for one output stream; a disabled palette returns text unchanged.
+#[derive(Debug, Clone, Copy)]
+struct Palette {
+    enabled: bool,
 }
 
-fn detect_line_ending(s: &str) -> &'static str {
-    if s.contains("\r\n") {
-        "\r\n"
-    } else {
-        "\n"
+impl Palette {
+    fn new(choice: ColorChoice, is_terminal: bool) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        };
+        Palette { enabled }
     }
-}
-
-fn normalize_to_lf(s: &str) -> String {
-    s.replace("\r\n", "\n")
-}
 
-fn restore_line_endings(s: &str, ending: &str) -> String {
-    if ending == "\n" {
-        s.to_string()
-    } else {
-        s.replace("\n", ending)
-    }
-}
-
-fn split_preserve_last_empty(s: &str) -> Vec<&str> {
-    // Like JS `content.split("\n")`: keeps trailing empty line if file ends with \n.
-    // But we do NOT want to treat a trailing newline as an extra addressable empty line.
-    // So we drop exactly one final empty segment if the file ends with "\n".
-    let mut parts: Vec<&str> = s.split('\n').collect();
-    if s.ends_with('\n') {
-        if let Some(last) = parts.last() {
-            if last.is_empty() {
-                parts.pop();
-            }
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
         }
     }
-    parts
-}
 
-fn compute_line_hash(line: &str) -> String {
-    let mut normalized = String::with_capacity(line.len());
-    for ch in line.chars() {
-        if ch == '\r' {
-            continue;
-        }
-        if ch.is_whitespace() {
-            continue;
-        }
-        normalized.push(ch);
+    fn red(&self, text: &str) -> String {
+        self.paint("31", text)
     }
 
-    let h = xxh32(normalized.as_bytes(), 0);
-    let truncated = (h as u32) & 0xffff;
-    format!("{:04x}", truncated)
-}
+    fn green(&self, text: &str) -> String {
+        self.paint("32", text)
+    }
 
-fn parse_line_ref(s: &str) -> Result<LineRef> {
-    let mut it = s.split(':');
-    let line_s = it.next().ok_or_else(|| anyhow!("invalid anchor: {s}"))?;
-    let hash_s = it.next().ok_or_else(|| anyhow!("invalid anchor: {s}"))?;
-    if it.next().is_some() {
-        bail!("invalid anchor (too many ':'): {s}");
+    fn yellow(&self, text: &str) -> String {
+        self.paint("33", text)
     }
 
-    let line: usize = line_s
-        .parse()
-        .map_err(|_| anyhow!("invalid line number in anchor: {s}"))?;
-    if line == 0 {
-        bail!("anchors are 1-indexed (line must be >= 1): {s}");
+    fn cyan(&self, text: &str) -> String {
+        self.paint("36", text)
     }
 
-    let hash = hash_s.trim().to_ascii_lowercase();
-    if hash.is_empty() {
-        bail!("invalid hash in anchor: {s}");
+    fn dim(&self, text: &str) -> String {
+        self.paint("2", text)
     }
 
-    Ok(LineRef { line, hash })
+    fn bold_red(&self, text: &str) -> String {
+        self.paint("1;31", text)
+    }
 }
 
-fn apply_hashline_edits(mut lines: Vec<String>, edits: &[HashlineEdit]) -> Result<Vec<String>> {
-    if edits.is_empty() {
-        return Ok(lines);
-    }
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let stdout = Palette::new(cli.color, std::io::stdout().is_terminal());
+    let stderr = Palette::new(cli.color, std::io::stderr().is_terminal());
+    init_logging(cli.log_level, cli.log_format, cli.quiet, stderr);
+    let api_version = match negotiate_api_version(cli.api_version) {
+        Ok(v) => v,
+        Err(e) => return report_error(stderr, &e),
+    };
+    let ctx = RunCtx {
+        api_version,
+        quiet: cli.quiet,
+        remote: cli.remote,
+        stdout,
+        stderr,
+        plugins: Plugins::from_config(cli.config),
+        metrics: Metrics::default(),
+    };
+    let (command, json_mode) = match &cli.cmd {
+        Command::Read(args) => ("read", args.json || args.format.is_some_and(ReadFormat::is_json)),
+        Command::Edit(args) => ("edit", args.json),
+        Command::Sed(args) => ("sed", args.json),
+        Command::Patch { json, .. } => ("patch", *json),
+        Command::Convert { json, .. } => ("convert", *json),
+        Command::Diff { json, .. } => ("diff", *json),
+        Command::Verify { json, .. } => ("verify", *json),
+        Command::Validate { json, .. } => ("validate", *json),
+        Command::Plan { json, .. } => ("plan", *json),
+        Command::Explain { json, .. } => ("explain", *json),
+        Command::Watch(args) => ("watch", args.json),
+        Command::Search(args) => ("search", args.json),
+        Command::Anchor { json, .. } => ("anchor", *json),
+        Command::Stats { json, .. } => ("stats", *json),
+        Command::Bench { json, .. } => ("bench", *json),
+        Command::Undo { json, .. } => ("undo", *json),
+        Command::Redo { json, .. } => ("redo", *json),
+        Command::History { json, .. } => ("history", *json),
+        Command::Hash { json, .. } => ("hash", *json),
+        Command::Mcp => ("mcp", false),
+        Command::Serve(_) => ("serve", false),
+        Command::Tools { .. } => ("tools", false),
+        Command::Completions { .. } => ("completions", false),
+        Command::Prompt => ("prompt", false),
+        Command::Schema { .. } => ("schema", false),
+        Command::Report { json, .. } => ("report", *json),
+    };
 
-    // Build hash -> unique line map (1-indexed) using current file.
-    let mut counts: HashMap<String, usize> = HashMap::new();
-    let mut first_seen: HashMap<String, usize> = HashMap::new();
-    for (i, line) in lines.iter().enumerate() {
-        let ln = i + 1;
-        let h = compute_line_hash(line);
-        *counts.entry(h.clone()).or_insert(0) += 1;
-        first_seen.entry(h).or_insert(ln);
-    }
-    let mut unique: HashMap<String, usize> = HashMap::new();
-    for (h, c) in counts {
-        if c == 1 {
-            if let Some(ln) = first_seen.get(&h) {
-                unique.insert(h, *ln);
-            }
+    let started = std::time::Instant::now();
+    let result = run(cli.cmd, &ctx);
+    if command != "report" && metrics_enabled() {
+        if let Err(e) = record_metrics(command, &result, started.elapsed(), &ctx.metrics) {
+            debug!(error = %e, "failed to record metrics");
         }
     }
-
-    // Parse and validate all anchors before mutating. Relocate if hash is uniquely found elsewhere.
-    let mut mismatches: Vec<(usize, String, String)> = Vec::new();
-
-    #[derive(Clone)]
-    enum ParsedSpec {
-        Single { r: LineRef, dst: String },
-        Range { start: LineRef, end: LineRef, dst: String },
-        InsertAfter { after: LineRef, dst: String },
-        ReplaceText { old: String, new_: String, all: bool },
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is::<AlreadyReported>() => ExitCode::FAILURE,
+        Err(e) if json_mode => match print_envelope::<()>(api_version, command, Err(format!("{e:#}"))) {
+            Ok(()) => ExitCode::FAILURE,
+            Err(print_err) => report_error(stderr, &print_err),
+        },
+        Err(e) => report_error(stderr, &e),
     }
+}
 
-    let mut parsed: Vec<(usize, ParsedSpec)> = Vec::new();
+/// A failure whose details the command already printed (e.g. as a JSON envelope); `main` only
+/// sets the exit code.
+#[derive(Debug)]
+struct AlreadyReported;
 
-    for (idx, edit) in edits.iter().enumerate() {
-        match edit {
-            HashlineEdit::SetLine { set_line } => {
-                let r = parse_line_ref(&set_line.anchor)?;
-                parsed.push((idx, ParsedSpec::Single { r, dst: set_line.new_text.clone() }));
-            }
-            HashlineEdit::ReplaceLines { replace_lines } => {
-                let start = parse_line_ref(&replace_lines.start_anchor)?;
-                let end = parse_line_ref(&replace_lines.end_anchor)?;
-                parsed.push((
-                    idx,
-                    ParsedSpec::Range { start, end, dst: replace_lines.new_text.clone() },
-                ));
-            }
-            HashlineEdit::InsertAfter { insert_after } => {
-                let after = parse_line_ref(&insert_after.anchor)?;
-                if insert_after.text.is_empty() {
-                    bail!("insert_after.text must be non-empty");
-                }
-                parsed.push((idx, ParsedSpec::InsertAfter { after, dst: insert_after.text.clone() }));
-            }
-            HashlineEdit::Replace { replace } => {
-                if replace.old_text.is_empty() {
-                    bail!("replace.old_text must be non-empty");
-                }
-                parsed.push((
-                    idx,
-                    ParsedSpec::ReplaceText {
-                        old: replace.old_text.clone(),
-                        new_: replace.new_text.clone(),
-                        all: replace.all.unwrap_or(false),
-                    },
-                ));
-            }
-        }
+impl std::fmt::Display for AlreadyReported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("failure already reported")
     }
+}
 
-    // Validate and relocate
-    for (_idx, spec) in parsed.iter_mut() {
-        match spec {
-            ParsedSpec::Single { r, .. } => validate_or_relocate(r, &lines, &unique, &mut mismatches)?,
-            ParsedSpec::Range { start, end, .. } => {
-                validate_or_relocate(start, &lines, &unique, &mut mismatches)?;
-                validate_or_relocate(end, &lines, &unique, &mut mismatches)?;
-                if start.line > end.line {
-                    bail!("replace_lines.start_anchor line must be <= end_anchor line");
-                }
-            }
-            ParsedSpec::InsertAfter { after, .. } => validate_or_relocate(after, &lines, &unique, &mut mismatches)?,
-            ParsedSpec::ReplaceText { .. } => {}
-        }
-    }
+impl std::error::Error for AlreadyReported {}
 
-    if !mismatches.is_empty() {
-        bail!(render_mismatch_error(&lines, &mismatches));
-    }
+fn report_error(palette: Palette, e: &anyhow::Error) -> ExitCode {
+    eprintln!("{} {:?}", palette.bold_red("error:"), e);
+    ExitCode::FAILURE
+}
 
-    // Sort bottom-up so earlier splices don't invalidate later line numbers.
-    // ReplaceText operations run last (they don't use anchors).
-    let sort_key = |spec: &ParsedSpec| -> (usize, usize) {
-        match spec {
-            ParsedSpec::Single { r, .. } => (r.line, 0),
-            ParsedSpec::Range { end, .. } => (end.line, 0),
-            ParsedSpec::InsertAfter { after, .. } => (after.line, 1),
-            ParsedSpec::ReplaceText { .. } => (0, 9),
+fn init_logging(level: LogLevel, format: LogFormat, quiet: bool, stderr: Palette) {
+    let level = if quiet {
+        LevelFilter::ERROR
+    } else {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
         }
     };
-    parsed.sort_by(|a, b| {
-        let a_key = sort_key(&a.1);
-        let b_key = sort_key(&b.1);
-        // descending by line, then precedence
-        b_key.cmp(&a_key)
-    });
-
-    for (_idx, spec) in parsed {
-        match spec {
-            ParsedSpec::Single { r, dst } => {
-                let dst_lines = split_dst_lines(&dst);
-                let at = r.line - 1;
-                if at >= lines.len() {
-                    bail!("line {} does not exist (file has {} lines)", r.line, lines.len());
-                }
-                lines.splice(at..at + 1, dst_lines);
-            }
-            ParsedSpec::Range { start, end, dst } => {
-                let dst_lines = split_dst_lines(&dst);
-                let s = start.line - 1;
-                let e = end.line - 1;
-                if s >= lines.len() || e >= lines.len() {
-                    bail!("range out of bounds (file has {} lines)", lines.len());
-                }
-                if s > e {
-                    bail!("invalid range: start > end");
-                }
-                lines.splice(s..e + 1, dst_lines);
-            }
-            ParsedSpec::InsertAfter { after, dst } => {
-                let dst_lines = split_dst_lines(&dst);
-                let at = after.line; // insert after => index is line (1-indexed) as 0-index insert point
-                if after.line > lines.len() {
-                    bail!("line {} does not exist (file has {} lines)", after.line, lines.len());
-                }
-                lines.splice(at..at, dst_lines);
-            }
-            ParsedSpec::ReplaceText { old, new_, all } => {
-                if all {
-                    lines = lines.join("\n").replace(&old, &new_).split('\n').map(|s| s.to_string()).collect();
-                } else {
-                    let joined = lines.join("\n");
-                    if let Some(pos) = joined.find(&old) {
-                        let mut out = String::with_capacity(joined.len() - old.len() + new_.len());
-                        out.push_str(&joined[..pos]);
-                        out.push_str(&new_);
-                        out.push_str(&joined[pos + old.len()..]);
-                        lines = out.split('\n').map(|s| s.to_string()).collect();
-                    } else {
-                        bail!("replace.old_text not found");
-                    }
-                }
-            }
-        }
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(stderr.enabled)
+        .with_target(false);
+    match format {
+        LogFormat::Text => builder.without_time().init(),
+        LogFormat::Json => builder.json().init(),
     }
-
-    Ok(lines)
 }
 
-fn split_dst_lines(dst: &str) -> Vec<String> {
-    if dst.is_empty() {
-        Vec::new()
-    } else {
-        dst.split('\n').map(|s| s.to_string()).collect()
+fn negotiate_api_version(requested: Option<u32>) -> Result<u32> {
+    match requested {
+        None => Ok(API_VERSION),
+        Some(v) if SUPPORTED_API_VERSIONS.contains(&v) => Ok(v),
+        Some(v) => bail!(
+            "unsupported --api-version {} (supported: {})",
+            v,
+            SUPPORTED_API_VERSIONS
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
     }
 }
 
-fn validate_or_relocate(
-    r: &mut LineRef,
-    lines: &[String],
-    unique: &HashMap<String, usize>,
-    mismatches: &mut Vec<(usize, String, String)>,
+fn print_envelope<T: Serialize>(
+    api_version: u32,
+    command: &'static str,
+    outcome: std::result::Result<T, String>,
 ) -> Result<()> {
-    if r.line < 1 || r.line > lines.len() {
-        bail!("line {} does not exist (file has {} lines)", r.line, lines.len());
-    }
-
-    let actual = compute_line_hash(&lines[r.line - 1]);
-    if actual == r.hash {
-        return Ok(());
-    }
-
-    if let Some(relocated) = unique.get(&r.hash) {
-        r.line = *relocated;
-        return Ok(());
-    }
-
-    mismatches.push((r.line, r.hash.clone(), actual));
+    let (ok, result, error) = match outcome {
+        Ok(r) => (true, Some(r), None),
+        Err(e) => (false, None, Some(e)),
+    };
+    let env = Envelope { api_version, command, ok, result, error };
+    println!("{}", serde_json::to_string(&env)?);
     Ok(())
 }
 
-fn render_mismatch_error(lines: &[String], mismatches: &[(usize, String, String)]) -> String {
-    let mut out = String::new();
-    out.push_str(&format!(
-        "{} line(s) have changed since last read. Re-read the file and use updated LINE:HASH refs.\n\n",
-        mismatches.len()
-    ));
-
-    for (line, expected, actual) in mismatches {
-        let content = lines.get(line - 1).cloned().unwrap_or_default();
-        out.push_str(&format!(
-            ">>> {}:{}|{}\n    expected {}\n\n",
-            line, actual, content, expected
-        ));
-    }
-
-    out.push_str("Quick fix: replace stale refs:\n");
-    for (line, expected, actual) in mismatches {
-        out.push_str(&format!("  {}:{} -> {}:{}\n", line, expected, line, actual));
+fn run(cmd: Command, ctx: &RunCtx) -> Result<()> {
+    match cmd {
+        Command::Read(args) => read_command(args, ctx),
+        Command::Edit(args) => edit_command(args, ctx),
+        Command::Sed(args) => sed_command(args, ctx),
+        Command::Patch { patch, strip, preview, json, no_validate } => {
+            patch_command(patch, strip, preview, json, no_validate, ctx)
+        }
+        Command::Convert { to: ConvertTarget::Edits, path, payload, strip, json } => {
+            convert_to_edits(path, payload, strip, json, ctx)
+        }
+        Command::Convert { to: ConvertTarget::Diff, path, payload, json, .. } => convert_to_diff(path, payload, json, ctx),
+        Command::Diff { old, new, json } => diff_command(old, new, json, ctx),
+        Command::Verify { path, anchors, payload, json } => verify_command(path, anchors, payload, json, ctx),
+        Command::Validate { path, payload, no_validate, diff, json } => {
+            validate_command(path, payload, no_validate, diff, json, ctx)
+        }
+        Command::Plan { path, payload, json } => plan_command(path, payload, json, ctx),
+        Command::Explain { path, payload, json } => explain_command(path, payload, json, ctx),
+        Command::Bench { paths, lines, iterations, edits, json } => {
+            bench_command(paths, lines, iterations, edits, json, ctx)
+        }
+        Command::Mcp => Server::default().serve_stdio(ctx),
+        Command::Serve(args) => serve_command(args, ctx),
+        Command::Tools { format } => {
+            println!("{}", serde_json::to_string_pretty(&render_tool_manifest(format))?);
+            Ok(())
+        }
+        Command::Watch(args) => watch_command(args, ctx),
+        Command::Search(args) => search_command(args, ctx),
+        Command::Anchor { path, lines, content, json } => anchor_command(path, lines, content, json, ctx),
+        Command::Undo { path, json } => journal_command(&path, JournalMove::Undo, json, ctx),
+        Command::Redo { path, json } => journal_command(&path, JournalMove::Redo, json, ctx),
+        Command::History { path, revert_to: Some(n), json } => {
+            journal_command(&path, JournalMove::RevertTo(n), json, ctx)
+        }
+        Command::History { path, revert_to: None, json } => history_command(path, json, ctx),
+        Command::Stats { path, json } => stats_command(path, json, ctx),
+        Command::Hash { lines, number, json } => hash_command(lines, number, json, ctx),
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut <Cli as clap::CommandFactory>::command(), "hashline", &mut std::io::stdout());
+            Ok(())
+        }
+        Command::Prompt => {
+            print!("{}", render_prompt());
+            Ok(())
+        }
+        Command::Schema { summary: false } => {
+            println!("{}", serde_json::to_string_pretty(&payload_schema())?);
+            Ok(())
+        }
+        Command::Schema { summary: true } => {
+            print!("{}", render_schema_summary());
+            Ok(())
+        }
+        Command::Report { metrics, json } => report_command(metrics, json, ctx),
     }
-
-    out
 }
 
-fn render_basic_diff(old_lines: &[String], new_lines: &[String]) {
-    // Very basic: show removed/added lines if lengths differ, else show line-by-line changes.
-    let max = old_lines.len().max(new_lines.len());
-    for i in 0..max {
-        let a = old_lines.get(i);
-        let b = new_lines.get(i);
-        match (a, b) {
-            (Some(x), Some(y)) if x == y => {}
-            (Some(x), Some(y)) => {
-                eprintln!("-{}", x);
-                eprintln!("+{}", y);
-            }
-            (Some(x), None) => eprintln!("-{}", x),
-            (None, Some(y)) => eprintln!("+{}", y),
-            (None, None) => {}
-        }
-    }
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
@@ -0,0 +1,167 @@
+//! The opt-in metrics file (`HASHLINE_METRICS=1`) and `hashline report`.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, bail, Context, Result};
+use hashline::MismatchError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{print_envelope, unix_timestamp, RunCtx};
+
+/// One invocation, appended to the metrics file when `HASHLINE_METRICS=1`. Never contains
+/// paths or content.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct MetricsEntry {
+    timestamp: u64,
+    command: String,
+    ok: bool,
+    duration_ms: f64,
+    edits: usize,
+    mismatches: usize,
+    relocations: usize,
+}
+
+/// Counts for this invocation's metrics entry, added to as files are edited.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) edits: AtomicUsize,
+    pub(crate) relocations: AtomicUsize,
+}
+
+pub(crate) fn metrics_enabled() -> bool {
+    std::env::var("HASHLINE_METRICS").is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+}
+
+/// `$HASHLINE_METRICS_FILE`, else `$XDG_DATA_HOME/hashline/metrics.jsonl` (default `~/.local/share`).
+pub(crate) fn metrics_file() -> Result<PathBuf> {
+    if let Some(p) = std::env::var_os("HASHLINE_METRICS_FILE") {
+        return Ok(PathBuf::from(p));
+    }
+    let data = match std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+        Some(d) => PathBuf::from(d),
+        None => {
+            let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("metrics: neither XDG_DATA_HOME nor HOME is set"))?;
+            PathBuf::from(home).join(".local/share")
+        }
+    };
+    Ok(data.join("hashline").join("metrics.jsonl"))
+}
+
+pub(crate) fn record_metrics(
+    command: &str,
+    result: &Result<()>,
+    elapsed: std::time::Duration,
+    metrics: &Metrics,
+) -> Result<()> {
+    let mismatches = result.as_ref().err().and_then(|e| e.downcast_ref::<MismatchError>()).map_or(0, |m| m.mismatches.len());
+    let entry = MetricsEntry {
+        timestamp: unix_timestamp(),
+        command: command.to_string(),
+        ok: result.is_ok(),
+        duration_ms: elapsed.as_secs_f64() * 1000.0,
+        edits: metrics.edits.load(Ordering::Relaxed),
+        mismatches,
+        relocations: metrics.relocations.load(Ordering::Relaxed),
+    };
+    let path = metrics_file()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut f = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(f, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+pub(crate) fn summarize_metrics(entries: &[MetricsEntry]) -> serde_json::Value {
+    let mut by_command: Vec<(&str, Vec<&MetricsEntry>)> = Vec::new();
+    for e in entries {
+        match by_command.iter_mut().find(|(c, _)| *c == e.command) {
+            Some((_, list)) => list.push(e),
+            None => by_command.push((&e.command, vec![e])),
+        }
+    }
+    let commands: Vec<serde_json::Value> = by_command
+        .iter()
+        .map(|(command, list)| {
+            let mut ms: Vec<f64> = list.iter().map(|e| e.duration_ms).collect();
+            ms.sort_by(f64::total_cmp);
+            let ok = list.iter().filter(|e| e.ok).count();
+            json!({
+                "command": command,
+                "runs": list.len(),
+                "ok": ok,
+                "failure_rate": 1.0 - ok as f64 / list.len() as f64,
+                "avg_ms": ms.iter().sum::<f64>() / ms.len() as f64,
+                "p95_ms": ms[((ms.len() - 1) as f64 * 0.95).round() as usize],
+            })
+        })
+        .collect();
+    let edit_runs: Vec<&MetricsEntry> = entries.iter().filter(|e| e.command == "edit").collect();
+    let edits: usize = edit_runs.iter().map(|e| e.edits).sum();
+    let relocations: usize = edit_runs.iter().map(|e| e.relocations).sum();
+    let stale = edit_runs.iter().filter(|e| e.mismatches > 0).count();
+    let rate = |n: usize, d: usize| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+    json!({
+        "invocations": entries.len(),
+        "commands": commands,
+        "edit_runs": edit_runs.len(),
+        "edits": edits,
+        "stale_anchor_runs": stale,
+        "mismatch_rate": rate(stale, edit_runs.len()),
+        "relocated_anchors": relocations,
+        "relocation_rate": rate(relocations, edits),
+    })
+}
+
+pub(crate) fn print_metrics_report(path: &std::path::Path, summary: &serde_json::Value) {
+    let num = |v: &serde_json::Value, k: &str| v.get(k).and_then(|n| n.as_f64()).unwrap_or(0.0);
+    println!("metrics: {} ({} invocations)", path.display(), num(summary, "invocations"));
+    println!("{:<10} {:>6} {:>8} {:>9} {:>9}", "command", "runs", "failed", "avg ms", "p95 ms");
+    for c in summary["commands"].as_array().into_iter().flatten() {
+        println!(
+            "{:<10} {:>6} {:>7.1}% {:>9.1} {:>9.1}",
+            c["command"].as_str().unwrap_or(""),
+            num(c, "runs"),
+            num(c, "failure_rate") * 100.0,
+            num(c, "avg_ms"),
+            num(c, "p95_ms"),
+        );
+    }
+    println!(
+        "edits: {} across {} edit runs; stale anchors in {} runs ({:.1}%); {} anchors relocated ({:.1}% of edits)",
+        num(summary, "edits"),
+        num(summary, "edit_runs"),
+        num(summary, "stale_anchor_runs"),
+        num(summary, "mismatch_rate") * 100.0,
+        num(summary, "relocated_anchors"),
+        num(summary, "relocation_rate") * 100.0,
+    );
+}
+
+/// `hashline report --metrics`: summarize the metrics file.
+pub(crate) fn report_command(metrics: bool, json: bool, ctx: &RunCtx) -> Result<()> {
+    if !metrics {
+        bail!("report: nothing selected (pass --metrics)");
+    }
+    let path = metrics_file()?;
+    let raw = match fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("report: failed to read {}", path.display())),
+    };
+    let entries: Vec<MetricsEntry> = raw.lines().filter_map(|l| serde_json::from_str(l).ok()).collect();
+    if entries.is_empty() && !json {
+        bail!("report: no metrics recorded in {} (set HASHLINE_METRICS=1 to opt in)", path.display());
+    }
+    let summary = summarize_metrics(&entries);
+    if json {
+        print_envelope(ctx.api_version, "report", Ok(summary))?;
+    } else {
+        print_metrics_report(&path, &summary);
+    }
+    Ok(())
+}
@@ -0,0 +1,1158 @@
+//! `read`, its output formats, `watch`, and `search`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use hashline::{
+    compute_line_hash, file_fingerprint, normalize_to_lf, parse_line_ref, render_mismatch_error, split_preserve_last_empty,
+    verify_anchors, Anchor, AnchorStatus,
+};
+use serde::Serialize;
+use serde_json::json;
+use tracing::debug;
+
+use crate::cli::{ReadArgs, ReadFormat, SearchArgs, WatchArgs};
+use crate::edit::{file_hash_record, parallel_map};
+use crate::editorconfig::rel_glob_matches;
+use crate::session::{issue_session_token, start_session};
+use crate::storage::open_storage;
+use crate::walk::{glob_targets, walk_unignored};
+use crate::{print_envelope, RunCtx};
+
+/// Which lines `read` shows: `--offset`/`--limit`, or the last `--tail` lines.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ReadWindow {
+    pub(crate) offset: Option<usize>,
+    pub(crate) limit: Option<usize>,
+    pub(crate) tail: Option<usize>,
+}
+
+impl ReadWindow {
+    /// Lines to skip and the most lines to show, for a file of `total` lines.
+    pub(crate) fn span(&self, total: usize) -> (usize, usize) {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        match self.tail {
+            Some(n) => (total.saturating_sub(n), n.min(limit)),
+            None => (self.offset.unwrap_or(1) - 1, limit),
+        }
+    }
+
+    /// `read --around`: `context` lines either side of (1-indexed) `line`.
+    pub(crate) fn around(line: usize, context: usize) -> Self {
+        let first = line.saturating_sub(context).max(1);
+        ReadWindow { offset: Some(first), limit: Some(line + context + 1 - first), tail: None }
+    }
+}
+
+/// How `read` shows each line's content: `--anchors-only` and `--max-width`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ReadContent {
+    pub(crate) anchors_only: bool,
+    pub(crate) max_width: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ReadLine<'a> {
+    line: usize,
+    hash: String,
+    /// `None` for `read --anchors-only`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) content: Option<std::borrow::Cow<'a, str>>,
+    /// The whole line's length in bytes, when `--max-width` cut `content` short
+    #[serde(skip_serializing_if = "Option::is_none")]
+    full_bytes: Option<usize>,
+    /// Another line of the file has the same hash
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    duplicate: bool,
+}
+
+impl<'a> ReadLine<'a> {
+    pub(crate) fn new(line: usize, hash: String, text: &'a str, shown: ReadContent) -> Self {
+        if shown.anchors_only {
+            return ReadLine { line, hash, content: None, full_bytes: None, duplicate: false };
+        }
+        match shown.max_width.and_then(|width| text.char_indices().nth(width)) {
+            Some((cut, _)) => {
+                let content = format!("{}… [{} bytes]", &text[..cut], text.len());
+                ReadLine { line, hash, content: Some(content.into()), full_bytes: Some(text.len()), duplicate: false }
+            }
+            None => ReadLine { line, hash, content: Some(text.into()), full_bytes: None, duplicate: false },
+        }
+    }
+
+    /// `LINE:HASH`, with a `*` after a hash that another line shares.
+    pub(crate) fn anchor(&self) -> String {
+        format!("{}:{}{}", self.line, self.hash, if self.duplicate { "*" } else { "" })
+    }
+}
+
+/// Flag the records whose hash appears more than once among the file's line `hashes`. An edit
+/// never relocates to such a line, so its anchor only holds at its own line number.
+pub(crate) fn mark_duplicate_hashes(records: &mut [ReadLine], hashes: &[String]) {
+    let flags = duplicate_flags(hashes);
+    for r in records {
+        r.duplicate = flags[r.line - 1];
+    }
+}
+
+/// Whether each of the file's line `hashes` appears more than once.
+fn duplicate_flags(hashes: &[String]) -> Vec<bool> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for hash in hashes {
+        *counts.entry(hash).or_default() += 1;
+    }
+    hashes.iter().map(|hash| counts[hash.as_str()] > 1).collect()
+}
+
+/// The `file` object that starts each file in `read --format ndjson`.
+pub(crate) fn ndjson_file_header(path: &str, fingerprint: &str, total_lines: usize) -> serde_json::Value {
+    json!({ "type": "file", "path": path, "fingerprint": fingerprint, "total_lines": total_lines })
+}
+
+/// `read --format ndjson`: the file's header object, then one object per line. Returns false once the
+/// reader has gone away (a closed pipe, as with `| head`), so the caller stops quietly.
+pub(crate) fn write_ndjson(
+    out: &mut impl Write,
+    header: &serde_json::Value,
+    path: &str,
+    records: &[ReadLine],
+) -> Result<bool> {
+    let mut write = || -> std::io::Result<()> {
+        writeln!(out, "{}", header)?;
+        for r in records {
+            let mut record = json!({ "type": "line", "path": path, "line": r.line, "hash": r.hash });
+            if let Some(content) = &r.content {
+                record["content"] = json!(content);
+            }
+            if let Some(bytes) = r.full_bytes {
+                record["full_bytes"] = json!(bytes);
+            }
+            if r.duplicate {
+                record["duplicate"] = json!(true);
+            }
+            writeln!(out, "{}", record)?;
+        }
+        out.flush()
+    };
+    keep_writing(write())
+}
+
+/// False for a closed pipe, which ends streamed output quietly.
+fn keep_writing(written: std::io::Result<()>) -> Result<bool> {
+    match written {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `read --format tsv|csv` rows (after the header row, if `header`), with a leading path column
+/// when `path` is given and a trailing content column unless `--anchors-only` left it out. Returns
+/// false once the reader has gone away, as `write_ndjson` does.
+pub(crate) fn write_delimited(
+    out: &mut impl Write,
+    format: ReadFormat,
+    path: Option<&str>,
+    records: &[ReadLine],
+    with_content: bool,
+    header: bool,
+) -> Result<bool> {
+    let field = |value: &str| -> String {
+        match format {
+            ReadFormat::Tsv => value.replace('\\', "\\\\").replace('\t', "\\t").replace('\r', "\\r").replace('\n', "\\n"),
+            _ if value.contains([',', '"', '\r', '\n']) => format!("\"{}\"", value.replace('"', "\"\"")),
+            _ => value.to_string(),
+        }
+    };
+    let separator = if format == ReadFormat::Tsv { "\t" } else { "," };
+    let lead = |first: &str| if path.is_some() { format!("{}{}", first, separator) } else { String::new() };
+    let mut write = || -> std::io::Result<()> {
+        let tail = |content: &str| if with_content { format!("{}{}", separator, content) } else { String::new() };
+        if header {
+            writeln!(out, "{1}line{0}hash{0}duplicate{2}", separator, lead("path"), tail("content"))?;
+        }
+        let lead = lead(&field(path.unwrap_or_default()));
+        for r in records {
+            let content = tail(&field(r.content.as_deref().unwrap_or_default()));
+            writeln!(out, "{1}{2}{0}{3}{0}{4}{5}", separator, lead, r.line, r.hash, r.duplicate, content)?;
+        }
+        out.flush()
+    };
+    keep_writing(write())
+}
+
+/// `read` of several files. Text records carry the path (`path:LINE:HASH|content`), `--json`
+/// lists one entry per file, and `--offset`/`--limit`/`--tail` apply to each file. With `skip_binary`,
+/// files that aren't UTF-8 text are left out instead of failing the read.
+pub(crate) fn read_many(
+    paths: &[PathBuf],
+    window: ReadWindow,
+    format: ReadFormat,
+    shown: ReadContent,
+    file_header: bool,
+    skip_binary: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let json = format == ReadFormat::Json;
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut header = true;
+    // Reading and hashing run in parallel; output stays in path order.
+    let loaded = parallel_map(paths, |path| -> Result<Option<(String, Vec<String>, String)>> {
+        let content = match open_storage(path, ctx.remote.as_deref())?.read() {
+            Ok(loaded) if skip_binary && loaded.content.contains('\0') => return Ok(None),
+            Ok(loaded) => loaded.content,
+            Err(e) if skip_binary && is_invalid_data(&e) => return Ok(None),
+            Err(e) => return Err(e.context(format!("read: failed to read {}", path.display()))),
+        };
+        let normalized = normalize_to_lf(&content);
+        let hashes = split_preserve_last_empty(&normalized).iter().map(|line| compute_line_hash(line)).collect();
+        let fp = file_fingerprint(&normalized);
+        Ok(Some((normalized, hashes, fp)))
+    });
+    let mut files = Vec::with_capacity(paths.len());
+    for (path, loaded) in paths.iter().zip(loaded) {
+        let Some((normalized, hashes, fp)) = loaded? else {
+            debug!(path = %path.display(), "skipping binary file");
+            continue;
+        };
+        let lines: Vec<&str> = split_preserve_last_empty(&normalized);
+        let (skip, take) = window.span(lines.len());
+        let mut records: Vec<ReadLine> = lines
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .take(take)
+            .map(|(i, line)| ReadLine::new(i + 1, hashes[i].clone(), line, shown))
+            .collect();
+        mark_duplicate_hashes(&mut records, &hashes);
+        let display = path.display().to_string();
+        if format == ReadFormat::Ndjson {
+            let mut header = ndjson_file_header(&display, &fp, lines.len());
+            if file_header {
+                header["file_hash"] = json!(file_hash_record(&normalized));
+            }
+            if !write_ndjson(&mut out, &header, &display, &records)? {
+                return Ok(());
+            }
+            continue;
+        }
+        if json {
+            let mut file = json!({
+                "path": display,
+                "fingerprint": fp,
+                "total_lines": lines.len(),
+                "lines": records,
+            });
+            if file_header {
+                file["file_hash"] = json!(file_hash_record(&normalized));
+            }
+            files.push(file);
+            continue;
+        }
+        if !ctx.quiet {
+            eprintln!("fingerprint {} {}", fp, display);
+        }
+        if matches!(format, ReadFormat::Tsv | ReadFormat::Csv) {
+            let with_content = !shown.anchors_only;
+            if !write_delimited(&mut out, format, Some(&display), &records, with_content, std::mem::take(&mut header))? {
+                return Ok(());
+            }
+            continue;
+        }
+        if file_header {
+            println!("{}{}{}", display, ctx.stdout.dim(":"), ctx.stdout.dim(&file_hash_record(&normalized)));
+        }
+        for r in &records {
+            let anchor = ctx.stdout.cyan(&r.anchor());
+            match &r.content {
+                Some(content) => println!("{}{}{}{}{}", display, ctx.stdout.dim(":"), anchor, ctx.stdout.dim("|"), content),
+                None => println!("{}{}{}", display, ctx.stdout.dim(":"), anchor),
+            }
+        }
+    }
+    if json {
+        print_envelope(ctx.api_version, "read", Ok(json!({ "files": files })))?;
+    }
+    Ok(())
+}
+
+/// One `{anchor, status, ...}` record per anchor, as `verify --json` reports them.
+pub(crate) fn verify_records(lines: &[String], anchors: &[Anchor]) -> Vec<serde_json::Value> {
+    let current = |line: usize| format!("{}:{}|{}", line, compute_line_hash(&lines[line - 1]), lines[line - 1]);
+    anchors
+        .iter()
+        .zip(verify_anchors(lines, anchors))
+        .map(|(anchor, status)| {
+            let label = format!("{}:{}", anchor.line, anchor.hash);
+            match status {
+                AnchorStatus::Ok => json!({ "anchor": label, "status": "ok" }),
+                AnchorStatus::Relocated(to) => {
+                    json!({ "anchor": label, "status": "relocated", "line": to, "current": current(to) })
+                }
+                AnchorStatus::Stale(_) => json!({ "anchor": label, "status": "stale", "current": current(anchor.line) }),
+                AnchorStatus::OutOfRange => {
+                    json!({ "anchor": label, "status": "out_of_range", "total_lines": lines.len() })
+                }
+            }
+        })
+        .collect()
+}
+
+/// How `watch` polls and reports.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WatchOptions {
+    /// After the first listing, list only the lines each change inserted or modified
+    pub(crate) changed_only: bool,
+    pub(crate) interval: std::time::Duration,
+    /// How long the files must stay unchanged before a change is reported
+    pub(crate) debounce: std::time::Duration,
+    /// Changes to report before exiting
+    pub(crate) count: Option<usize>,
+    pub(crate) json: bool,
+}
+
+/// One `watch` target, as classified when the watch starts.
+enum WatchTarget {
+    /// A file named outright; while it is missing (mid-rename, say) its last content stands
+    File(PathBuf),
+    /// A directory, rescanned on every poll for the unignored files `--glob` lets through
+    Dir(PathBuf),
+    /// A glob, re-expanded on every poll
+    Glob(PathBuf),
+}
+
+/// A file `watch` has read: its modification time and size when read, and its content.
+struct Watched {
+    stamp: Option<(Option<std::time::SystemTime>, u64)>,
+    normalized: String,
+}
+
+/// `watch`: list the anchors of the files `paths` name, then poll them and list again after
+/// each change. A change is new content, checked only when a file's modification time or size
+/// moves. Changes are collected until the files have been quiet for `opts.debounce`, then
+/// reported together, so a burst of saves is one listing. With `opts.changed_only`, later
+/// listings hold just the inserted or modified lines, plus a note for each deletion. A single
+/// plain file is listed as `read` lists it; anything more gets path-prefixed lines (`--json`:
+/// a `files` array), and removed files are reported.
+pub(crate) fn watch(paths: &[PathBuf], filters: &[String], opts: &WatchOptions, ctx: &RunCtx) -> Result<()> {
+    let targets: Vec<WatchTarget> = paths
+        .iter()
+        .map(|path| {
+            Ok(if path.is_dir() {
+                WatchTarget::Dir(path.clone())
+            } else if glob_targets(path, None)?.is_some() {
+                WatchTarget::Glob(path.clone())
+            } else {
+                WatchTarget::File(path.clone())
+            })
+        })
+        .collect::<Result<_>>()?;
+    let single = matches!(targets.as_slice(), [WatchTarget::File(_)]);
+    let named = |path: &PathBuf| targets.iter().any(|t| matches!(t, WatchTarget::File(p) if p == path));
+    let mut current = std::collections::BTreeMap::new();
+    for path in watch_scan(&targets, filters)? {
+        let stamp = watch_stamp(&path);
+        match watch_load(&path) {
+            Ok(normalized) => {
+                current.insert(path, Watched { stamp, normalized });
+            }
+            Err(e) if named(&path) => return Err(e),
+            Err(e) => debug!(path = %path.display(), error = %e, "watch: skipping an unreadable file"),
+        }
+    }
+    if current.is_empty() {
+        bail!("watch: no files to watch");
+    }
+    let first: Vec<&PathBuf> = current.keys().collect();
+    print_watch_listing(&first, &[], &std::collections::BTreeMap::new(), &current, single, opts, ctx)?;
+    // The content each file had in the last listing.
+    let mut reported: std::collections::BTreeMap<PathBuf, String> =
+        current.iter().map(|(path, watched)| (path.clone(), watched.normalized.clone())).collect();
+    let mut changes = 0;
+    while opts.count.is_none_or(|n| changes < n) {
+        // Wait for a change, then for the files to settle.
+        while !watch_poll(&targets, filters, &mut current) {
+            std::thread::sleep(opts.interval);
+        }
+        let mut quiet_since = std::time::Instant::now();
+        while quiet_since.elapsed() < opts.debounce {
+            std::thread::sleep(opts.interval.min(opts.debounce.saturating_sub(quiet_since.elapsed())));
+            if watch_poll(&targets, filters, &mut current) {
+                quiet_since = std::time::Instant::now();
+            }
+        }
+        let changed: Vec<&PathBuf> = current
+            .iter()
+            .filter(|(path, watched)| reported.get(*path) != Some(&watched.normalized))
+            .map(|(path, _)| path)
+            .collect();
+        let removed: Vec<&PathBuf> = reported.keys().filter(|path| !current.contains_key(*path)).collect();
+        // A change that was undone before the files settled is no change.
+        if changed.is_empty() && removed.is_empty() {
+            continue;
+        }
+        print_watch_listing(&changed, &removed, &reported, &current, single, opts, ctx)?;
+        reported = current.iter().map(|(path, watched)| (path.clone(), watched.normalized.clone())).collect();
+        changes += 1;
+    }
+    Ok(())
+}
+
+fn watch_stamp(path: &std::path::Path) -> Option<(Option<std::time::SystemTime>, u64)> {
+    fs::metadata(path).ok().map(|m| (m.modified().ok(), m.len()))
+}
+
+fn watch_load(path: &std::path::Path) -> Result<String> {
+    let loaded = open_storage(path, None)?.read().with_context(|| format!("watch: failed to read {}", path.display()))?;
+    Ok(normalize_to_lf(&loaded.content))
+}
+
+/// The files `targets` name now: the named files, each directory's unignored files, and each
+/// glob's matches. `filters`, if any, pick among the directory and glob files by their path
+/// relative to the directory (or the glob's), as `.editorconfig` sections do.
+fn watch_scan(targets: &[WatchTarget], filters: &[String]) -> Result<Vec<PathBuf>> {
+    let passes = |base: &std::path::Path, path: &std::path::Path| {
+        let rel = path.strip_prefix(base).unwrap_or(path).to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/");
+        filters.is_empty() || filters.iter().any(|f| rel_glob_matches(f, &rel))
+    };
+    let mut files = Vec::new();
+    for target in targets {
+        match target {
+            WatchTarget::File(path) => files.push(path.clone()),
+            WatchTarget::Dir(dir) => files.extend(walk_unignored(dir)?.into_iter().filter(|p| passes(dir, p))),
+            // Every match may be gone; that is a removal, not an error.
+            WatchTarget::Glob(pattern) => {
+                let matches = glob_targets(pattern, None).ok().flatten().unwrap_or_default();
+                files.extend(matches.into_iter().filter(|p| passes(std::path::Path::new(""), p)));
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Bring `current` up to date with the files on disk; whether anything changed. A directory
+/// that can't be listed is tried again next time, and so is a file that fails to read.
+fn watch_poll(
+    targets: &[WatchTarget],
+    filters: &[String],
+    current: &mut std::collections::BTreeMap<PathBuf, Watched>,
+) -> bool {
+    let files = match watch_scan(targets, filters) {
+        Ok(files) => files,
+        Err(e) => {
+            debug!(error = %e, "watch: rescan failed, retrying");
+            return false;
+        }
+    };
+    let named = |path: &PathBuf| targets.iter().any(|t| matches!(t, WatchTarget::File(p) if p == path));
+    let before = current.len();
+    current.retain(|path, _| files.binary_search(path).is_ok());
+    let mut changed = current.len() != before;
+    for path in files {
+        let stamp = watch_stamp(&path);
+        let known = current.get(&path);
+        if (stamp.is_none() && named(&path)) || known.is_some_and(|w| w.stamp == stamp) {
+            continue;
+        }
+        match watch_load(&path) {
+            Ok(normalized) => {
+                changed |= known.is_none_or(|w| w.normalized != normalized);
+                current.insert(path, Watched { stamp, normalized });
+            }
+            Err(e) => debug!(path = %path.display(), error = %e, "watch: read failed, retrying"),
+        }
+    }
+    changed
+}
+
+/// Print one `watch` listing: each of `changed` (in full, or with `opts.changed_only` just what
+/// moved since its `reported` content), then a note for each of `removed`.
+fn print_watch_listing(
+    changed: &[&PathBuf],
+    removed: &[&PathBuf],
+    reported: &std::collections::BTreeMap<PathBuf, String>,
+    current: &std::collections::BTreeMap<PathBuf, Watched>,
+    single: bool,
+    opts: &WatchOptions,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let mut files = Vec::new();
+    for &path in changed {
+        let normalized = &current[path].normalized;
+        let lines: Vec<String> = split_preserve_last_empty(normalized).into_iter().map(String::from).collect();
+        let fp = file_fingerprint(normalized);
+        let old = reported.get(path).filter(|_| opts.changed_only);
+        let (shown, deleted) = match old {
+            Some(old) => {
+                let old: Vec<String> = split_preserve_last_empty(old).into_iter().map(String::from).collect();
+                changed_lines(&old, &lines)
+            }
+            None => ((0..lines.len()).collect(), Vec::new()),
+        };
+        let prefix = if single { String::new() } else { format!("{}{}", path.display(), ctx.stdout.dim(":")) };
+        if opts.json {
+            let records: Vec<ReadLine> = shown
+                .iter()
+                .map(|&i| ReadLine::new(i + 1, compute_line_hash(&lines[i]), &lines[i], ReadContent::default()))
+                .collect();
+            let mut result = json!({
+                "path": path.display().to_string(),
+                "fingerprint": fp,
+                "total_lines": lines.len(),
+                "lines": records,
+            });
+            if old.is_some() {
+                result["deleted"] = json!(deleted);
+            }
+            files.push(result);
+        } else {
+            if !ctx.quiet {
+                if single {
+                    eprintln!("fingerprint {}", fp);
+                } else {
+                    eprintln!("fingerprint {} {}", fp, path.display());
+                }
+            }
+            for &i in &shown {
+                let anchor = ctx.stdout.cyan(&format!("{}:{}", i + 1, compute_line_hash(&lines[i])));
+                println!("{}{}{}{}", prefix, anchor, ctx.stdout.dim("|"), lines[i]);
+            }
+            for note in &deleted {
+                println!("{}{}", prefix, ctx.stdout.dim(note));
+            }
+        }
+    }
+    if opts.json {
+        let removed: Vec<String> = removed.iter().map(|path| path.display().to_string()).collect();
+        let result = if single {
+            files.pop().expect("a single file's listing")
+        } else {
+            json!({ "files": files, "removed": removed })
+        };
+        print_envelope(ctx.api_version, "watch", Ok(result))?;
+    } else {
+        for path in removed {
+            println!("{}{}{}", path.display(), ctx.stdout.dim(":"), ctx.stdout.dim("removed"));
+        }
+    }
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+/// The 0-based lines of `new` that are inserted or modified relative to `old`, and a note for
+/// each run of deleted lines.
+fn changed_lines(old: &[String], new: &[String]) -> (Vec<usize>, Vec<String>) {
+    let (mut shown, mut deleted) = (Vec::new(), Vec::new());
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, old, new) {
+        let (tag, o, n) = op.as_tag_tuple();
+        match tag {
+            similar::DiffTag::Equal => {}
+            similar::DiffTag::Delete => deleted.push(match n.start {
+                0 => format!("deleted {} line(s) at the top", o.len()),
+                after => format!("deleted {} line(s) after line {}", o.len(), after),
+            }),
+            _ => shown.extend(n),
+        }
+    }
+    (shown, deleted)
+}
+
+/// One file's `search` result.
+pub(crate) struct SearchHit {
+    path: PathBuf,
+    normalized: String,
+    /// 0-based matching lines
+    pub(crate) matches: Vec<usize>,
+    /// Inclusive `(start, end)` line groups: the matches plus context, merged where they touch
+    groups: Vec<(usize, usize)>,
+    /// Every line's hash, and whether another line of the file shares it
+    hashes: Vec<(String, bool)>,
+}
+
+impl SearchHit {
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let lines = split_preserve_last_empty(&self.normalized);
+        let records: Vec<serde_json::Value> = self
+            .groups
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .map(|i| {
+                let hit = self.matches.binary_search(&i).is_ok();
+                let (hash, duplicate) = &self.hashes[i];
+                let mut record = json!({ "line": i + 1, "hash": hash, "content": lines[i], "match": hit });
+                if *duplicate {
+                    record["duplicate"] = json!(true);
+                }
+                record
+            })
+            .collect();
+        json!({
+            "path": self.path.display().to_string(),
+            "fingerprint": file_fingerprint(&self.normalized),
+            "matches": self.matches.len(),
+            "lines": records,
+        })
+    }
+}
+
+/// What `search` looks through: a directory's unignored files, a glob's matches, or one file. The
+/// flag says whether there can be several.
+pub(crate) fn search_targets(path: PathBuf, ctx: &RunCtx) -> Result<(Vec<PathBuf>, bool)> {
+    if ctx.remote.is_none() && path.is_dir() {
+        return Ok((walk_unignored(&path)?, true));
+    }
+    Ok(match glob_targets(&path, ctx.remote.as_deref())? {
+        Some(paths) => (paths, true),
+        None => (vec![path], false),
+    })
+}
+
+/// Files among `paths` with lines matching `re`, each with `context` (before, after) lines around
+/// the matches. With `many`, binary files are skipped.
+pub(crate) fn search_hits(
+    command: &str,
+    paths: &[PathBuf],
+    re: &regex::Regex,
+    context: (usize, usize),
+    many: bool,
+    ctx: &RunCtx,
+) -> Result<Vec<SearchHit>> {
+    let remote = if many { None } else { ctx.remote.as_deref() };
+    let found = parallel_map(paths, |path| -> Result<Option<SearchHit>> {
+        let content = match open_storage(path, remote)?.read() {
+            Ok(loaded) if many && loaded.content.contains('\0') => return Ok(None),
+            Ok(loaded) => loaded.content,
+            Err(e) if many && is_invalid_data(&e) => return Ok(None),
+            Err(e) => return Err(e.context(format!("{}: failed to read {}", command, path.display()))),
+        };
+        let normalized = normalize_to_lf(&content);
+        let lines = split_preserve_last_empty(&normalized);
+        let matches: Vec<usize> = (0..lines.len()).filter(|&i| re.is_match(lines[i])).collect();
+        let mut groups: Vec<(usize, usize)> = Vec::new();
+        for &m in &matches {
+            let (start, end) = (m.saturating_sub(context.0), (m + context.1).min(lines.len() - 1));
+            match groups.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => groups.push((start, end)),
+            }
+        }
+        if matches.is_empty() {
+            return Ok(None);
+        }
+        let hashes: Vec<String> = lines.iter().map(|line| compute_line_hash(line)).collect();
+        let hashes = hashes.iter().cloned().zip(duplicate_flags(&hashes)).collect();
+        Ok(Some(SearchHit { path: path.clone(), normalized, matches, groups, hashes }))
+    });
+    found.into_iter().filter_map(Result::transpose).collect()
+}
+
+/// `search` (or `read --grep`, as `command`) over `paths`: matching lines plus context lines, as
+/// anchors. Groups of lines that aren't adjacent are separated by `--`. With `many`, lines are
+/// prefixed with their path. Finding nothing is an error.
+pub(crate) fn search_files(
+    command: &'static str,
+    paths: &[PathBuf],
+    re: &regex::Regex,
+    context: (usize, usize),
+    many: bool,
+    json: bool,
+    ctx: &RunCtx,
+) -> Result<()> {
+    let hits = search_hits(command, paths, re, context, many, ctx)?;
+    let total: usize = hits.iter().map(|h| h.matches.len()).sum();
+    if total == 0 {
+        bail!("{}: no lines match /{}/", command, re.as_str());
+    }
+    if json {
+        let files: Vec<serde_json::Value> = hits.iter().map(SearchHit::to_json).collect();
+        print_envelope(ctx.api_version, command, Ok(json!({ "matches": total, "files": files })))?;
+        return Ok(());
+    }
+    for hit in &hits {
+        let display = hit.path.display().to_string();
+        if !ctx.quiet {
+            eprintln!("fingerprint {} {}", file_fingerprint(&hit.normalized), display);
+        }
+        let lines = split_preserve_last_empty(&hit.normalized);
+        let prefix = if many { format!("{}{}", display, ctx.stdout.dim(":")) } else { String::new() };
+        for (g, &(start, end)) in hit.groups.iter().enumerate() {
+            if g > 0 {
+                println!("{}", ctx.stdout.dim("--"));
+            }
+            for (i, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+                let (hash, duplicate) = &hit.hashes[i];
+                let anchor = format!("{}:{}{}", i + 1, hash, if *duplicate { "*" } else { "" });
+                let matched = hit.matches.binary_search(&i).is_ok();
+                let anchor = if matched { ctx.stdout.cyan(&anchor) } else { ctx.stdout.dim(&anchor) };
+                println!("{}{}{}{}", prefix, anchor, ctx.stdout.dim("|"), line);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_invalid_data(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::InvalidData)
+}
+
+/// `hashline read`: one file (optionally windowed by anchors), several, a directory, or `--grep` matches.
+pub(crate) fn read_command(args: ReadArgs, ctx: &RunCtx) -> Result<()> {
+    let ReadArgs {
+        paths,
+        offset,
+        limit,
+        tail,
+        around,
+        from_anchor,
+        to_anchor,
+        grep,
+        context,
+        json,
+        format,
+        anchors_only,
+        max_width,
+        file_header,
+        issue_token,
+        session,
+        recursive,
+    } = args;
+    let api_version = ctx.api_version;
+    if let Some(pattern) = grep {
+        let re = regex::Regex::new(&pattern).with_context(|| format!("read: invalid pattern {:?}", pattern))?;
+        let context = context.unwrap_or(0);
+        let (paths, many) = match <[PathBuf; 1]>::try_from(paths) {
+            Ok([path]) if recursive => (walk_unignored(&path)?, true),
+            Ok([path]) => search_targets(path, ctx)?,
+            Err(paths) => {
+                let mut targets = Vec::new();
+                for path in paths {
+                    targets.extend(search_targets(path, ctx)?.0);
+                }
+                (targets, true)
+            }
+        };
+        return search_files("read", &paths, &re, (context, context), many, json, ctx);
+    }
+    let format = format.unwrap_or(if json { ReadFormat::Json } else { ReadFormat::Text });
+    if file_header && matches!(format, ReadFormat::Tsv | ReadFormat::Csv) {
+        bail!("read: --file-header applies to text, json and ndjson output");
+    }
+    let window = ReadWindow { offset, limit, tail };
+    let shown = ReadContent { anchors_only, max_width };
+    let by_anchor = around.is_some() || from_anchor.is_some() || to_anchor.is_some();
+    let path = match <[PathBuf; 1]>::try_from(paths) {
+        Ok([path]) => path,
+        Err(paths) => {
+            if recursive || issue_token || session || by_anchor {
+                bail!(
+                    "read: --recursive, --issue-token, --session, --around and --from/--to-anchor need a single path"
+                );
+            }
+            if offset == Some(0) {
+                bail!("--offset is 1-indexed (must be >= 1)");
+            }
+            let mut targets = Vec::new();
+            for path in paths {
+                match glob_targets(&path, ctx.remote.as_deref())? {
+                    Some(matches) => targets.extend(matches),
+                    None => targets.push(path),
+                }
+            }
+            return read_many(&targets, window, format, shown, file_header, false, ctx);
+        }
+    };
+    if recursive {
+        if ctx.remote.is_some() || !path.is_dir() {
+            bail!("read: --recursive needs a local directory, got {}", path.display());
+        }
+        if issue_token || session {
+            bail!("read: --issue-token and --session need a single file, not --recursive");
+        }
+        if offset == Some(0) {
+            bail!("--offset is 1-indexed (must be >= 1)");
+        }
+        return read_many(&walk_unignored(&path)?, window, format, shown, file_header, true, ctx);
+    }
+    if let Some(paths) = glob_targets(&path, ctx.remote.as_deref())? {
+        if issue_token || session || by_anchor {
+            bail!("read: --issue-token, --session, --around and --from/--to-anchor need a single file, not a glob");
+        }
+        if offset == Some(0) {
+            bail!("--offset is 1-indexed (must be >= 1)");
+        }
+        return read_many(&paths, window, format, shown, file_header, false, ctx);
+    }
+    if (issue_token || session) && path.as_os_str() == "-" {
+        bail!("read: --issue-token and --session need a file, not stdin");
+    }
+    let storage = open_storage(&path, ctx.remote.as_deref())?;
+    let content = storage
+        .read()
+        .with_context(|| format!("read: failed to read {}", path.display()))?
+        .content;
+    let target = storage.id();
+    let normalized = normalize_to_lf(&content);
+    let lines: Vec<&str> = split_preserve_last_empty(&normalized);
+    debug!(path = %path.display(), lines = lines.len(), "read file");
+
+    let start = offset.unwrap_or(1);
+    if start == 0 {
+        bail!("--offset is 1-indexed (must be >= 1)");
+    }
+    if start > lines.len().max(1) {
+        // Allow reading from a past-the-end offset on empty-ish files.
+        bail!("offset {} out of range (file has {} lines)", start, lines.len());
+    }
+
+    // `--around` centres the window on an anchor's current line; `--from-anchor`/`--to-anchor`
+    // bound it. Anchors that moved are followed the way an edit would follow them.
+    let mut anchor_notes = serde_json::Map::new();
+    let window = if by_anchor {
+        let owned: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        let mut locate = |key: &str, text: &str| -> Result<(Anchor, usize, Option<String>)> {
+            let anchor = parse_line_ref(text).with_context(|| format!("read: invalid anchor {:?}", text))?;
+            let (line, status, stale) = match verify_anchors(&owned, std::slice::from_ref(&anchor)).remove(0) {
+                AnchorStatus::Ok => (anchor.line, "ok", None),
+                AnchorStatus::Relocated(to) => (to, "relocated", None),
+                AnchorStatus::Stale(actual) => (anchor.line, "stale", Some(actual)),
+                AnchorStatus::OutOfRange => {
+                    bail!("read: line {} does not exist (file has {} lines)", anchor.line, lines.len())
+                }
+            };
+            if status == "relocated" && !ctx.quiet && !format.is_json() {
+                eprintln!("relocated {} to line {}", anchor, line);
+            }
+            anchor_notes.insert(key.into(), json!({ "anchor": anchor.to_string(), "status": status, "line": line }));
+            Ok((anchor, line, stale))
+        };
+        if let Some(text) = &around {
+            let (anchor, line, stale) = locate("around", text)?;
+            if stale.is_some() && !ctx.quiet && !format.is_json() {
+                eprintln!("stale {}: line {} changed and the hash is not unique elsewhere", anchor, line);
+            }
+            ReadWindow::around(line, context.unwrap_or(10))
+        } else {
+            // A range read is only as good as its bounds, so stale ones fail as an edit would.
+            let mut mismatches = Vec::new();
+            let mut bound = |key: &str, text: &Option<String>, default: usize| -> Result<usize> {
+                let Some(text) = text else { return Ok(default) };
+                let (anchor, line, stale) = locate(key, text)?;
+                if let Some(actual) = stale {
+                    mismatches.push((line, anchor.hash, actual));
+                }
+                Ok(line)
+            };
+            let first = bound("from", &from_anchor, 1)?;
+            let last = bound("to", &to_anchor, lines.len())?;
+            if !mismatches.is_empty() {
+                bail!("read: {}", render_mismatch_error(&owned, &mismatches));
+            }
+            if first > last {
+                bail!("read: --from-anchor (line {}) comes after --to-anchor (line {})", first, last);
+            }
+            ReadWindow { offset: Some(first), limit: Some(last + 1 - first), tail: None }
+        }
+    } else {
+        window
+    };
+    let (skip, max_lines) = window.span(lines.len());
+    let hashes: Vec<String> = lines.iter().map(|line| compute_line_hash(line)).collect();
+    let mut records: Vec<ReadLine> = lines
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .take(max_lines)
+        .map(|(i, line)| ReadLine::new(i + 1, hashes[i].clone(), line, shown))
+        .collect();
+    mark_duplicate_hashes(&mut records, &hashes);
+
+    let fp = file_fingerprint(&normalized);
+    let token = if issue_token { Some(issue_session_token(&target, &content, api_version)?) } else { None };
+    let session = if session { Some(start_session(&target, &content, api_version)?) } else { None };
+    if format == ReadFormat::Ndjson {
+        let display = path.display().to_string();
+        let mut header = ndjson_file_header(&display, &fp, lines.len());
+        if let Some(token) = &token {
+            header["token"] = json!(token);
+        }
+        if let Some(session) = &session {
+            header["session"] = json!(session);
+        }
+        for (key, note) in &anchor_notes {
+            header[key] = note.clone();
+        }
+        if file_header {
+            header["file_hash"] = json!(file_hash_record(&normalized));
+        }
+        write_ndjson(&mut std::io::BufWriter::new(std::io::stdout().lock()), &header, &display, &records)?;
+    } else if format == ReadFormat::Json {
+        let mut result = json!({
+            "path": path.display().to_string(),
+            "fingerprint": fp,
+            "total_lines": lines.len(),
+            "lines": records,
+        });
+        if let Some(token) = &token {
+            result["token"] = json!(token);
+        }
+        if let Some(session) = &session {
+            result["session"] = json!(session);
+        }
+        for (key, note) in anchor_notes {
+            result[key] = note;
+        }
+        if file_header {
+            result["file_hash"] = json!(file_hash_record(&normalized));
+        }
+        print_envelope(api_version, "read", Ok(result))?;
+    } else {
+        if !ctx.quiet {
+            eprintln!("fingerprint {}", fp);
+        }
+        if let Some(token) = &token {
+            eprintln!("token {}", token);
+        }
+        if let Some(session) = &session {
+            eprintln!("session {}", session);
+        }
+        if format != ReadFormat::Text {
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            write_delimited(&mut out, format, None, &records, !anchors_only, true)?;
+            return Ok(());
+        }
+        if file_header {
+            println!("{}", ctx.stdout.dim(&file_hash_record(&normalized)));
+        }
+        for r in &records {
+            let anchor = ctx.stdout.cyan(&r.anchor());
+            match &r.content {
+                Some(content) => println!("{}{}{}", anchor, ctx.stdout.dim("|"), content),
+                None => println!("{}", anchor),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `hashline watch`: check the local files until interrupted or `--count` changes were seen.
+pub(crate) fn watch_command(args: WatchArgs, ctx: &RunCtx) -> Result<()> {
+    let WatchArgs { paths, glob, changed, interval, debounce, count, json } = args;
+    if ctx.remote.is_some() {
+        bail!("watch: only local files can be watched");
+    }
+    let opts = WatchOptions {
+        changed_only: changed,
+        interval: std::time::Duration::from_millis(interval.max(10)),
+        debounce: std::time::Duration::from_millis(debounce),
+        count,
+        json,
+    };
+    watch(&paths, &glob, &opts, ctx)
+}
+
+/// `hashline search`: print the lines matching a pattern in a file, glob, or directory.
+pub(crate) fn search_command(args: SearchArgs, ctx: &RunCtx) -> Result<()> {
+    let SearchArgs { path, pattern, ignore_case, before, after, context, json } = args;
+    let re = regex::RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .with_context(|| format!("search: invalid pattern {:?}", pattern))?;
+    let (before, after) = (before.or(context).unwrap_or(0), after.or(context).unwrap_or(0));
+    let (paths, many) = search_targets(path, ctx)?;
+    search_files("search", &paths, &re, (before, after), many, json, ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hashline-read-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn watch_globs_filter_directory_files_by_name_or_relative_path() {
+        let dir = scratch("watch-scan");
+        fs::create_dir_all(dir.join("src/sub")).unwrap();
+        for file in ["src/a.rs", "src/sub/b.rs", "src/notes.txt", "top.rs"] {
+            fs::write(dir.join(file), "x\n").unwrap();
+        }
+        let names = |filters: &[&str]| -> Vec<String> {
+            let filters: Vec<String> = filters.iter().map(|f| f.to_string()).collect();
+            let files = watch_scan(&[WatchTarget::Dir(dir.clone())], &filters).unwrap();
+            files.iter().map(|f| f.strip_prefix(&dir).unwrap().display().to_string()).collect()
+        };
+        assert_eq!(names(&["*.rs"]), ["src/a.rs", "src/sub/b.rs", "top.rs"]);
+        assert_eq!(names(&["src/*.rs"]), ["src/a.rs"]);
+        assert_eq!(names(&["*.txt", "top.rs"]), ["src/notes.txt", "top.rs"]);
+        assert_eq!(names(&[]).len(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn watch_poll_sees_edits_new_files_and_removals_but_waits_out_a_missing_named_file() {
+        let dir = scratch("watch-poll");
+        let (named, other) = (dir.join("named.txt"), dir.join("sub/other.txt"));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(&named, "a\n").unwrap();
+        let targets = [WatchTarget::File(named.clone()), WatchTarget::Dir(dir.join("sub"))];
+        let mut current = std::collections::BTreeMap::new();
+        assert!(watch_poll(&targets, &[], &mut current), "first poll reads the files");
+        assert!(!watch_poll(&targets, &[], &mut current));
+
+        fs::write(&other, "new\n").unwrap();
+        assert!(watch_poll(&targets, &[], &mut current));
+        assert_eq!(current[&other].normalized, "new\n");
+        fs::remove_file(&other).unwrap();
+        assert!(watch_poll(&targets, &[], &mut current));
+        assert!(!current.contains_key(&other));
+
+        // A save by rename leaves the named file missing for a moment.
+        fs::remove_file(&named).unwrap();
+        assert!(!watch_poll(&targets, &[], &mut current));
+        assert_eq!(current[&named].normalized, "a\n");
+        fs::write(&named, "a\nb\n").unwrap();
+        assert!(watch_poll(&targets, &[], &mut current));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changed_lines_lists_inserted_and_modified_lines_and_notes_deletions() {
+        let lines = |text: &str| -> Vec<String> { text.split('\n').map(String::from).collect() };
+        let (shown, deleted) = changed_lines(&lines("a\nb\nc\nd"), &lines("a\nc\nd\ne"));
+        assert_eq!(shown, [3]);
+        assert_eq!(deleted, ["deleted 1 line(s) after line 1"]);
+        assert_eq!(changed_lines(&lines("a\nb"), &lines("a\nB")), (vec![1], Vec::new()));
+        let (_, deleted) = changed_lines(&lines("a\nb"), &lines("b"));
+        assert_eq!(deleted, ["deleted 1 line(s) at the top"]);
+    }
+
+    #[test]
+    fn verify_records_report_ok_relocated_stale_and_out_of_range() {
+        let lines: Vec<String> = ["zero", "one", "two"].map(String::from).into();
+        let anchors = [
+            Anchor::for_line(2, "one"),
+            Anchor::for_line(1, "one"),
+            Anchor::for_line(3, "deux"),
+            Anchor::for_line(9, "nine"),
+        ];
+        let records = verify_records(&lines, &anchors);
+        let status: Vec<&str> = records.iter().map(|r| r["status"].as_str().unwrap()).collect();
+        assert_eq!(status, ["ok", "relocated", "stale", "out_of_range"]);
+        assert_eq!(records[1]["line"], 2);
+        assert_eq!(records[1]["current"], format!("{}|one", Anchor::for_line(2, "one")));
+        assert_eq!(records[2]["current"], format!("{}|two", Anchor::for_line(3, "two")));
+        assert_eq!(records[3]["total_lines"], 3);
+    }
+
+    #[test]
+    fn read_windows_take_an_offset_and_limit_a_tail_or_the_lines_around_an_anchor() {
+        let window = |offset, limit, tail| ReadWindow { offset, limit, tail };
+        assert_eq!(window(None, None, None).span(10), (0, usize::MAX));
+        assert_eq!(window(Some(3), Some(2), None).span(10), (2, 2));
+        assert_eq!(window(None, None, Some(3)).span(10), (7, 3));
+        assert_eq!(window(None, Some(2), Some(3)).span(10), (7, 2));
+        assert_eq!(window(None, None, Some(30)).span(10), (0, 30));
+        assert_eq!(ReadWindow::around(5, 2).span(10), (2, 5));
+        assert_eq!(ReadWindow::around(2, 10).span(10), (0, 12));
+        assert_eq!(ReadWindow::around(3, 0).span(10), (2, 1));
+    }
+
+    /// Records for `text`, shown as `shown`, with duplicates marked.
+    fn records(text: &str, shown: ReadContent) -> Vec<ReadLine<'_>> {
+        let lines = split_preserve_last_empty(text);
+        let hashes: Vec<String> = lines.iter().map(|l| compute_line_hash(l)).collect();
+        let mut records: Vec<ReadLine> =
+            lines.iter().enumerate().map(|(i, l)| ReadLine::new(i + 1, hashes[i].clone(), l, shown)).collect();
+        mark_duplicate_hashes(&mut records, &hashes);
+        records
+    }
+
+    #[test]
+    fn read_lines_are_cut_at_max_width_and_flag_shared_hashes() {
+        let shown = ReadContent { anchors_only: false, max_width: Some(3) };
+        let lines = records("abcdef\nab\n}\n}", shown);
+        assert_eq!(lines[0].content.as_deref(), Some("abc… [6 bytes]"));
+        assert_eq!(lines[1].content.as_deref(), Some("ab"));
+        assert_eq!(lines[2].anchor(), format!("{}*", Anchor::for_line(3, "}")));
+        assert_eq!(lines[1].anchor(), Anchor::for_line(2, "ab").to_string());
+        let json = serde_json::to_value(&lines[0]).unwrap();
+        assert_eq!(json["full_bytes"], 6);
+        assert!(json.get("duplicate").is_none());
+
+        let bare = records("a\nb", ReadContent { anchors_only: true, max_width: None });
+        assert!(bare.iter().all(|r| r.content.is_none()));
+    }
+
+    #[test]
+    fn ndjson_starts_with_the_file_header_then_one_object_per_line() {
+        let lines = records("x\ny", ReadContent::default());
+        let header = ndjson_file_header("f.txt", "fp", 2);
+        let mut out = Vec::new();
+        assert!(write_ndjson(&mut out, &header, "f.txt", &lines).unwrap());
+        let objects: Vec<serde_json::Value> =
+            String::from_utf8(out).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(objects.len(), 3);
+        assert_eq!(objects[0], json!({ "type": "file", "path": "f.txt", "fingerprint": "fp", "total_lines": 2 }));
+        assert_eq!(objects[2]["type"], "line");
+        assert_eq!(objects[2]["line"], 2);
+        assert_eq!(objects[2]["content"], "y");
+        assert_eq!(objects[2]["hash"], compute_line_hash("y"));
+    }
+
+    #[test]
+    fn tsv_escapes_and_csv_quotes_the_content_column() {
+        let lines = records("a\tb\nsay \"hi\", then", ReadContent::default());
+        let (h1, h2) = (compute_line_hash("a\tb"), compute_line_hash("say \"hi\", then"));
+        let render = |format, path, with_content, header| {
+            let mut out = Vec::new();
+            assert!(write_delimited(&mut out, format, path, &lines, with_content, header).unwrap());
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            render(ReadFormat::Tsv, None, true, true),
+            format!("line\thash\tduplicate\tcontent\n1\t{h1}\tfalse\ta\\tb\n2\t{h2}\tfalse\tsay \"hi\", then\n")
+        );
+        assert_eq!(
+            render(ReadFormat::Csv, Some("d/f.csv"), true, false),
+            format!("d/f.csv,1,{h1},false,a\tb\nd/f.csv,2,{h2},false,\"say \"\"hi\"\", then\"\n")
+        );
+        assert_eq!(
+            render(ReadFormat::Csv, Some("f"), false, true),
+            format!("path,line,hash,duplicate\nf,1,{h1},false\nf,2,{h2},false\n")
+        );
+    }
+
+    #[test]
+    fn search_groups_merge_the_context_of_nearby_matches() {
+        let dir = scratch("search");
+        let path = dir.join("f.txt");
+        fs::write(&path, "a\nhit\nb\nc\nhit\nd\ne\nf\nhit").unwrap();
+        let re = regex::Regex::new("hit").unwrap();
+        let ctx = RunCtx::for_tests();
+        let hits = search_hits("search", std::slice::from_ref(&path), &re, (1, 1), false, &ctx).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].matches, [1, 4, 8]);
+        assert_eq!(hits[0].groups, [(0, 5), (7, 8)]);
+        let json = hits[0].to_json();
+        assert_eq!(json["matches"], 3);
+        assert_eq!(json["lines"].as_array().unwrap().len(), 8);
+        let hash = compute_line_hash("hit");
+        let hit = json!({ "line": 2, "hash": hash, "content": "hit", "match": true, "duplicate": true });
+        assert_eq!(json["lines"][1], hit);
+        assert_eq!(json["lines"][0]["match"], false);
+
+        let none = regex::Regex::new("zzz").unwrap();
+        assert!(search_hits("search", &[path], &none, (0, 0), false, &ctx).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,468 @@
+//! The sed subset behind `hashline sed`.
+
+use std::fs;
+
+use anyhow::{anyhow, bail, Context, Result};
+use hashline::{file_fingerprint, normalize_to_lf, split_preserve_last_empty};
+use serde_json::json;
+
+use crate::cli::{EditArgs, PayloadArgs, SedArgs};
+use crate::diff::line_diff_edits;
+use crate::edit::edit_command;
+use crate::storage::{open_storage, Loaded};
+use crate::{print_envelope, RunCtx};
+
+/// A `sed` address: a line number, `$`, or `/regex/`.
+#[derive(Debug)]
+enum SedAddress {
+    Line(usize),
+    Last,
+    Regex(regex::Regex),
+}
+
+impl SedAddress {
+    fn matches(&self, lineno: usize, total: usize, space: &str) -> bool {
+        match self {
+            SedAddress::Line(n) => lineno == *n,
+            SedAddress::Last => lineno == total,
+            SedAddress::Regex(re) => re.is_match(space),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SedAction {
+    Substitute { re: regex::Regex, replacement: String, global: bool, nth: usize },
+    Transliterate(Vec<(char, char)>),
+    Delete,
+    Append(String),
+    Insert(String),
+    Change(String),
+}
+
+/// One command of a `hashline sed` script: `[addr[,addr]][!]cmd`.
+#[derive(Debug)]
+pub(crate) struct SedCommand {
+    start: Option<SedAddress>,
+    end: Option<SedAddress>,
+    negate: bool,
+    action: SedAction,
+}
+
+impl SedCommand {
+    /// Whether this command applies to the current line; `active` carries range state across lines.
+    fn selects(&self, lineno: usize, total: usize, space: &str, active: &mut bool) -> bool {
+        let hit = match (&self.start, &self.end) {
+            (None, _) => true,
+            (Some(a), None) => a.matches(lineno, total, space),
+            (Some(a), Some(b)) => {
+                if *active {
+                    *active = !match b {
+                        SedAddress::Line(n) => lineno >= *n,
+                        _ => b.matches(lineno, total, space),
+                    };
+                    true
+                } else if a.matches(lineno, total, space) {
+                    *active = match b {
+                        SedAddress::Line(n) => *n > lineno,
+                        SedAddress::Last => lineno < total,
+                        SedAddress::Regex(_) => true,
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        hit != self.negate
+    }
+}
+
+/// Parse the `sed` subset `hashline sed` supports: `s`, `y`, `d`, `a`, `i`, `c` with optional
+/// `N`, `$`, `/re/` addresses, `A,B` ranges, and `!`. Patterns are POSIX basic regular
+/// expressions unless `extended` (`-E`) is set.
+pub(crate) fn parse_sed_script(script: &str, extended: bool) -> Result<Vec<SedCommand>> {
+    let chars: Vec<char> = script.chars().collect();
+    let mut i = 0;
+    let mut commands = Vec::new();
+    let skip_blanks = |i: &mut usize| {
+        while chars.get(*i).is_some_and(|c| *c == ' ' || *c == '\t') {
+            *i += 1;
+        }
+    };
+    loop {
+        while chars.get(i).is_some_and(|c| c.is_whitespace() || *c == ';') {
+            i += 1;
+        }
+        if i >= chars.len() {
+            break;
+        }
+        let start = parse_sed_address(&chars, &mut i, extended)?;
+        let mut end = None;
+        if start.is_some() && chars.get(i) == Some(&',') {
+            i += 1;
+            end = Some(parse_sed_address(&chars, &mut i, extended)?.ok_or_else(|| anyhow!("sed: missing address after ','"))?);
+        }
+        skip_blanks(&mut i);
+        let negate = chars.get(i) == Some(&'!');
+        if negate {
+            i += 1;
+            skip_blanks(&mut i);
+        }
+        let Some(&cmd) = chars.get(i) else {
+            bail!("sed: missing command");
+        };
+        i += 1;
+        let action = match cmd {
+            'd' => SedAction::Delete,
+            'a' | 'i' | 'c' => {
+                let text = parse_sed_text(&chars, &mut i);
+                match cmd {
+                    'a' => SedAction::Append(text),
+                    'i' => SedAction::Insert(text),
+                    _ => SedAction::Change(text),
+                }
+            }
+            's' => {
+                let delim = *chars.get(i).ok_or_else(|| anyhow!("sed: unterminated `s` command"))?;
+                i += 1;
+                let pattern = parse_sed_delimited(&chars, &mut i, delim)?;
+                let replacement = parse_sed_delimited(&chars, &mut i, delim)?;
+                let (mut global, mut icase, mut digits) = (false, false, String::new());
+                while let Some(&c) = chars.get(i) {
+                    match c {
+                        'g' => global = true,
+                        'i' | 'I' => icase = true,
+                        '0'..='9' => digits.push(c),
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                let nth = if digits.is_empty() { 1 } else { digits.parse()? };
+                if nth == 0 {
+                    bail!("sed: `s` occurrence number must be at least 1");
+                }
+                let re = regex::RegexBuilder::new(&sed_pattern(&pattern, extended))
+                    .case_insensitive(icase)
+                    .build()
+                    .with_context(|| format!("sed: invalid pattern {:?}", pattern))?;
+                SedAction::Substitute { re, replacement: sed_replacement(&replacement), global, nth }
+            }
+            'y' => {
+                let delim = *chars.get(i).ok_or_else(|| anyhow!("sed: unterminated `y` command"))?;
+                i += 1;
+                let from: Vec<char> = parse_sed_delimited(&chars, &mut i, delim)?.chars().collect();
+                let to: Vec<char> = parse_sed_delimited(&chars, &mut i, delim)?.chars().collect();
+                if from.len() != to.len() {
+                    bail!("sed: `y` strings have different lengths");
+                }
+                SedAction::Transliterate(from.into_iter().zip(to).collect())
+            }
+            other => bail!("sed: unsupported command `{}` (supported: s, y, d, a, i, c)", other),
+        };
+        skip_blanks(&mut i);
+        if let Some(&c) = chars.get(i).filter(|c| **c != ';' && **c != '\n') {
+            bail!("sed: unexpected `{}` after `{}` command", c, cmd);
+        }
+        commands.push(SedCommand { start, end, negate, action });
+    }
+    Ok(commands)
+}
+
+fn parse_sed_address(chars: &[char], i: &mut usize, extended: bool) -> Result<Option<SedAddress>> {
+    match chars.get(*i) {
+        Some('$') => {
+            *i += 1;
+            Ok(Some(SedAddress::Last))
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let start = *i;
+            while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+                *i += 1;
+            }
+            let n: usize = chars[start..*i].iter().collect::<String>().parse()?;
+            if n == 0 {
+                bail!("sed: invalid line address 0");
+            }
+            Ok(Some(SedAddress::Line(n)))
+        }
+        Some(&delim) if delim == '/' || delim == '\\' => {
+            *i += 1;
+            let delim = if delim == '\\' {
+                *i += 1;
+                *chars.get(*i - 1).ok_or_else(|| anyhow!("sed: unterminated address"))?
+            } else {
+                delim
+            };
+            let pattern = parse_sed_delimited(chars, i, delim)?;
+            let re = regex::Regex::new(&sed_pattern(&pattern, extended))
+                .with_context(|| format!("sed: invalid address pattern {:?}", pattern))?;
+            Ok(Some(SedAddress::Regex(re)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Read up to the next unescaped `delim`; `\delim` becomes a literal `delim`, other escapes are kept.
+fn parse_sed_delimited(chars: &[char], i: &mut usize, delim: char) -> Result<String> {
+    let mut out = String::new();
+    while let Some(&c) = chars.get(*i) {
+        *i += 1;
+        if c == delim {
+            return Ok(out);
+        }
+        if c == '\\' {
+            match chars.get(*i) {
+                Some(&next) if next == delim => out.push(next),
+                Some(&next) => {
+                    out.push('\\');
+                    out.push(next);
+                }
+                None => break,
+            }
+            *i += 1;
+        } else if c == '\n' {
+            break;
+        } else {
+            out.push(c);
+        }
+    }
+    bail!("sed: unterminated `{}`-delimited argument", delim)
+}
+
+/// Text argument of `a`/`i`/`c`: GNU one-liner (`a text`) or classic (`a\` newline `text`), with
+/// backslash-newline continuing the text onto another line.
+fn parse_sed_text(chars: &[char], i: &mut usize) -> String {
+    while chars.get(*i).is_some_and(|c| *c == ' ' || *c == '\t') {
+        *i += 1;
+    }
+    if chars.get(*i) == Some(&'\\') {
+        *i += 1;
+        if chars.get(*i) == Some(&'\n') {
+            *i += 1;
+        }
+    }
+    let mut text = String::new();
+    while let Some(&c) = chars.get(*i).filter(|c| **c != '\n') {
+        *i += 1;
+        match c {
+            '\\' => {
+                if let Some(&next) = chars.get(*i) {
+                    text.push(next);
+                    *i += 1;
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+/// Translate a sed pattern into `regex` syntax. In basic mode `\(`, `\)`, `\{`, `\}`, `\|`, `\+`,
+/// and `\?` are the operators and their bare forms are literals (GNU BRE).
+fn sed_pattern(pattern: &str, extended: bool) -> String {
+    if extended {
+        return pattern.to_string();
+    }
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(op @ ('(' | ')' | '{' | '}' | '|' | '+' | '?')) => out.push(op),
+                Some(other) => {
+                    out.push('\\');
+                    out.push(other);
+                }
+                None => out.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Translate a sed replacement (`&`, `\1`..`\9`, `\n`) into `regex` expansion syntax.
+fn sed_replacement(replacement: &str) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => out.push_str(&format!("${{{}}}", d)),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn sed_substitute(space: &str, re: &regex::Regex, replacement: &str, global: bool, nth: usize) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for (n, caps) in re.captures_iter(space).enumerate() {
+        let m = caps.get(0).expect("capture group 0 always matches");
+        if n + 1 < nth {
+            continue;
+        }
+        out.push_str(&space[last..m.start()]);
+        caps.expand(replacement, &mut out);
+        last = m.end();
+        if !global {
+            break;
+        }
+    }
+    out.push_str(&space[last..]);
+    out
+}
+
+/// Run parsed sed commands over `lines` (one cycle per line, like `sed` without `-n`).
+pub(crate) fn run_sed(commands: &[SedCommand], lines: &[String]) -> Vec<String> {
+    let mut active = vec![false; commands.len()];
+    let mut out = Vec::new();
+    for (idx, line) in lines.iter().enumerate() {
+        let lineno = idx + 1;
+        let mut space = line.clone();
+        let mut appended: Vec<&str> = Vec::new();
+        let mut deleted = false;
+        for (cmd, active) in commands.iter().zip(active.iter_mut()) {
+            if !cmd.selects(lineno, lines.len(), &space, active) {
+                continue;
+            }
+            match &cmd.action {
+                SedAction::Substitute { re, replacement, global, nth } => {
+                    space = sed_substitute(&space, re, replacement, *global, *nth);
+                }
+                SedAction::Transliterate(map) => {
+                    space = space
+                        .chars()
+                        .map(|c| map.iter().find(|(from, _)| *from == c).map_or(c, |(_, to)| *to))
+                        .collect();
+                }
+                SedAction::Insert(text) => out.extend(text.split('\n').map(String::from)),
+                SedAction::Append(text) => appended.push(text),
+                SedAction::Change(text) => {
+                    // A range is replaced as a whole: print the text once, at its last line.
+                    if !*active {
+                        out.extend(text.split('\n').map(String::from));
+                    }
+                    deleted = true;
+                    break;
+                }
+                SedAction::Delete => {
+                    deleted = true;
+                    break;
+                }
+            }
+        }
+        if !deleted {
+            out.extend(space.split('\n').map(String::from));
+        }
+        for text in appended {
+            out.extend(text.split('\n').map(String::from));
+        }
+    }
+    out
+}
+
+/// `hashline sed`: run the script over the file and hand the changed lines to `edit`.
+pub(crate) fn sed_command(args: SedArgs, ctx: &RunCtx) -> Result<()> {
+    let SedArgs { path, expressions, file, extended, in_place: _, preview, json, no_validate } = args;
+    let api_version = ctx.api_version;
+    let mut script = expressions.join("\n");
+    if let Some(f) = &file {
+        let body = fs::read_to_string(f).with_context(|| format!("sed: failed to read script {}", f.display()))?;
+        script.push('\n');
+        script.push_str(&body);
+    }
+    let commands = parse_sed_script(&script, extended)?;
+    let storage = open_storage(&path, ctx.remote.as_deref())?;
+    let Loaded { content: raw, .. } = storage
+        .read()
+        .with_context(|| format!("sed: failed to read {}", path.display()))?;
+    let normalized = normalize_to_lf(&raw);
+    let old_lines: Vec<String> = split_preserve_last_empty(&normalized)
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+    let edits = line_diff_edits(&old_lines, &run_sed(&commands, &old_lines));
+    if edits.is_empty() {
+        if json {
+            let result = json!({ "path": path.display().to_string(), "edits": 0, "noop": true });
+            print_envelope(api_version, "sed", Ok(result))?;
+        } else if !ctx.quiet {
+            eprintln!("no changes to {}", path.display());
+        }
+        return Ok(());
+    }
+    // Hand the result to `edit` as anchored edits, pinned to the content the script saw.
+    edit_command(
+        EditArgs {
+            path: Some(path),
+            payload: PayloadArgs::inline(serde_json::to_string(&edits)?),
+            preview,
+            json,
+            fingerprint: Some(file_fingerprint(&normalized)),
+            no_editorconfig: true,
+            no_validate,
+            ..EditArgs::default()
+        },
+        ctx,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashline::{apply_hashline_edits, ApplyOptions};
+
+    fn sed(script: &str, extended: bool, text: &str) -> String {
+        let lines: Vec<String> = text.split('\n').map(String::from).collect();
+        run_sed(&parse_sed_script(script, extended).unwrap(), &lines).join("\n")
+    }
+
+    #[test]
+    fn scripts_behave_like_sed() {
+        let text = "alpha\nbeta\ngamma\ndelta";
+        assert_eq!(sed("s/a/A/", false, text), "Alpha\nbetA\ngAmma\ndeltA");
+        assert_eq!(sed("s/a/A/g", false, text), "AlphA\nbetA\ngAmmA\ndeltA");
+        assert_eq!(sed("s/a/A/2", false, text), "alphA\nbeta\ngammA\ndelta");
+        assert_eq!(sed(r"s/\(.\)\(.\)/\2\1/", false, text), "lapha\nebta\nagmma\nedlta");
+        assert_eq!(sed("s/(a|e)+$/!/", true, text), "alph!\nbet!\ngamm!\ndelt!");
+        assert_eq!(sed("2,3d", false, text), "alpha\ndelta");
+        assert_eq!(sed("/^g/,$!d", false, text), "gamma\ndelta");
+        assert_eq!(sed("1i\\\ntop\n$a\\\nend", false, text), "top\nalpha\nbeta\ngamma\ndelta\nend");
+        assert_eq!(sed("2,3c\\\nmid", false, text), "alpha\nmid\ndelta");
+        assert_eq!(sed("y/abc/ABC/", false, text), "AlphA\nBetA\ngAmmA\ndeltA");
+    }
+
+    #[test]
+    fn sed_output_round_trips_through_anchored_edits() {
+        let text = "fn a() {}\n\nfn b() {}\nfn c() {}";
+        let old: Vec<String> = text.split('\n').map(String::from).collect();
+        for script in ["s/fn/pub fn/", "/^$/d", "1i\\\n// header", "$a\\\n", "2,3c\\\nfn bc() {}", "/b/!d"] {
+            let new = run_sed(&parse_sed_script(script, false).unwrap(), &old);
+            let edits = line_diff_edits(&old, &new);
+            let applied = apply_hashline_edits(old.clone(), &edits, ApplyOptions::default()).unwrap();
+            assert_eq!(applied, new, "script {script:?}");
+        }
+    }
+
+    #[test]
+    fn unsupported_commands_are_rejected() {
+        for script in ["p", "s/a/b", "s/a/b/q", "3,"] {
+            assert!(parse_sed_script(script, false).is_err(), "{script:?} accepted");
+        }
+    }
+}
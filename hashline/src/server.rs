@@ -0,0 +1,1249 @@
+//! The JSON-RPC server behind `mcp` and `serve` (stdio, Unix socket, HTTP).
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use hashline::{
+    compute_line_hash, edit_anchors, file_fingerprint, normalize_to_lf, parse_edits_value, parse_line_ref,
+    split_preserve_last_empty, HashlineEdit,
+};
+use serde_json::json;
+use tracing::debug;
+
+use crate::cli::{ServeArgs, ToolFormat};
+use crate::docs::{render_tool_manifest, ReadToolInput, SearchToolInput};
+use crate::edit::{edit_file, edit_files, payload_jobs, EditOptions};
+use crate::read::{mark_duplicate_hashes, search_hits, search_targets, verify_records, ReadContent, ReadLine, SearchHit};
+use crate::storage::{is_url, open_storage, split_archive_target};
+#[cfg(feature = "http-server")]
+use crate::Envelope;
+use crate::RunCtx;
+
+/// MCP revision `mcp` reports when the client doesn't name one.
+const MCP_PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// Documents a server keeps; the least recently used goes first.
+const MAX_CACHED_DOCS: usize = 64;
+
+/// A file the server has read, reused while its modification time and size stay the same.
+struct CachedDoc {
+    stamp: (Option<std::time::SystemTime>, u64),
+    lines: Vec<String>,
+    hashes: Vec<String>,
+    fingerprint: String,
+}
+
+/// The documents a server has read, each with the `tick` of its last use.
+#[derive(Default)]
+struct DocCache {
+    docs: HashMap<PathBuf, (u64, Arc<CachedDoc>)>,
+    /// Counts document lookups, to order the cache by recency
+    tick: u64,
+}
+
+/// JSON-RPC front end for `serve` and `mcp`. Methods take the same fields as the tool
+/// definitions, and the MCP methods (`initialize`, `tools/list`, `tools/call`) wrap them as tools.
+/// Local files are cached between requests. Requests can run concurrently; edits to the same
+/// file wait in that file's queue and run one at a time, in the order they arrived.
+#[derive(Default)]
+pub(crate) struct Server {
+    cache: Mutex<DocCache>,
+    /// The queue of each file an edit in flight targets
+    queues: Mutex<HashMap<PathBuf, Arc<FileQueue>>>,
+    /// `serve --rate-limit`
+    limiter: Option<RateLimiter>,
+}
+
+/// How much `serve --socket` and `serve --http` take on at once.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ServeLimits {
+    /// Clients served at the same time; the rest are turned away as busy
+    pub(crate) max_connections: usize,
+    /// Requests each client may make per minute
+    pub(crate) rate_limit: Option<u32>,
+}
+
+/// `serve --tls-cert`/`--tls-key`: PEM files with the certificate chain (leaf first) and its key,
+/// plus the `--tls-client-ca` certificates a client's certificate must chain to.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "http-server"), allow(dead_code))]
+pub(crate) struct TlsFiles {
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+    pub(crate) client_ca: Option<PathBuf>,
+}
+
+/// `serve --token`/`--token-file`: the bearer tokens `serve --http` accepts.
+#[derive(Debug, Default)]
+#[cfg_attr(not(feature = "http-server"), allow(dead_code))]
+pub(crate) struct TokenArgs {
+    pub(crate) tokens: Vec<String>,
+    pub(crate) file: Option<PathBuf>,
+}
+
+/// A bearer token, and the directory the requests that present it are confined to.
+#[cfg(feature = "http-server")]
+#[derive(Debug)]
+struct Token {
+    secret: String,
+    root: PathBuf,
+    /// Names the token in logs and for `--rate-limit`, without giving it away
+    label: String,
+}
+
+/// Shortest bearer token `serve` accepts.
+#[cfg(feature = "http-server")]
+const MIN_TOKEN_LEN: usize = 16;
+
+/// JSON-RPC error code for a path outside the served root.
+const OUTSIDE_ROOT: i64 = -32001;
+
+/// JSON-RPC error code for a client over `--rate-limit`.
+const RATE_LIMITED: i64 = -32002;
+
+/// JSON-RPC error code for a connection over `--max-connections`.
+const BUSY: i64 = -32003;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Admits the edits to one file one at a time, first come first served: each takes a ticket
+/// and waits until it is the ticket being served.
+#[derive(Default)]
+struct FileQueue {
+    /// (next ticket to hand out, ticket being served)
+    tickets: Mutex<(u64, u64)>,
+    turn: Condvar,
+}
+
+/// A request's turn at a `FileQueue`; the next request's turn starts when this drops.
+struct QueueTurn(Arc<FileQueue>);
+
+impl QueueTurn {
+    fn wait(queue: Arc<FileQueue>) -> Self {
+        let mut tickets = lock(&queue.tickets);
+        let ticket = tickets.0;
+        tickets.0 += 1;
+        while tickets.1 != ticket {
+            tickets = queue.turn.wait(tickets).unwrap_or_else(|e| e.into_inner());
+        }
+        drop(tickets);
+        QueueTurn(queue)
+    }
+}
+
+impl Drop for QueueTurn {
+    fn drop(&mut self) {
+        lock(&self.0.tickets).1 += 1;
+        self.0.turn.notify_all();
+    }
+}
+
+/// `serve --rate-limit`: a token bucket per client that holds a minute's worth of requests
+/// and refills continuously.
+struct RateLimiter {
+    per_minute: u32,
+    /// Each client's tokens and when they were last counted
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32) -> Self {
+        RateLimiter { per_minute, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Spend one of `client`'s tokens, or say how long until it has one.
+    fn admit(&self, client: &str) -> std::result::Result<(), Duration> {
+        let (full, rate) = (f64::from(self.per_minute), f64::from(self.per_minute) / 60.0);
+        let now = Instant::now();
+        let level = |tokens: f64, at: Instant| (tokens + now.duration_since(at).as_secs_f64() * rate).min(full);
+        let mut buckets = lock(&self.buckets);
+        if buckets.len() >= 4096 {
+            // A full bucket is the same as no bucket.
+            buckets.retain(|_, (tokens, at)| level(*tokens, *at) < full);
+        }
+        let (tokens, at) = buckets.entry(client.to_string()).or_insert((full, now));
+        *tokens = level(*tokens, *at);
+        *at = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / rate))
+        }
+    }
+}
+
+/// One of the `max` connection slots, given back on drop; `None` when all are taken.
+struct ConnectionSlot<'a>(&'a AtomicUsize);
+
+impl<'a> ConnectionSlot<'a> {
+    fn take(active: &'a AtomicUsize, max: usize) -> Option<Self> {
+        active.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1)).ok()?;
+        Some(ConnectionSlot(active))
+    }
+}
+
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A request path that resolves outside `Server::root`.
+#[derive(Debug)]
+struct OutsideRoot(String);
+
+impl std::fmt::Display for OutsideRoot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: outside the served root", self.0)
+    }
+}
+
+impl std::error::Error for OutsideRoot {}
+
+/// A JSON-RPC error: `code` follows the spec's reserved range.
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        let code = if e.downcast_ref::<OutsideRoot>().is_some() { OUTSIDE_ROOT } else { -32000 };
+        RpcError { code, message: format!("{:#}", e) }
+    }
+}
+
+impl Server {
+    /// Answer newline-delimited requests (or batches) on stdin until it closes.
+    pub(crate) fn serve_stdio(self, ctx: &RunCtx) -> Result<()> {
+        serve_lines(&self, std::io::stdin().lock(), std::io::stdout().lock(), "stdio", ctx)
+    }
+
+    /// Listen on a unix socket, answering each connection as `serve_stdio` answers stdin. Connections
+    /// run on their own threads and share the document cache; past `limits.max_connections`, a new
+    /// client gets one busy error and is disconnected.
+    #[cfg(unix)]
+    pub(crate) fn serve_socket(mut self, path: &std::path::Path, limits: ServeLimits, ctx: &RunCtx) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::{UnixListener, UnixStream};
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("serve: {} is already in use by a running server", path.display());
+            }
+            fs::remove_file(path).with_context(|| format!("serve: failed to remove stale socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path).with_context(|| format!("serve: failed to listen on {}", path.display()))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        if !ctx.quiet {
+            eprintln!("listening on {}", path.display());
+        }
+        self.limiter = limits.rate_limit.map(RateLimiter::new);
+        let (server, active) = (&self, AtomicUsize::new(0));
+        std::thread::scope(|scope| {
+            for (n, stream) in listener.incoming().enumerate() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!(error = %e, "serve: accept failed");
+                        continue;
+                    }
+                };
+                let Some(slot) = ConnectionSlot::take(&active, limits.max_connections) else {
+                    let message = format!("server busy: {} clients are connected", limits.max_connections);
+                    let busy = json!({ "jsonrpc": "2.0", "id": null, "error": { "code": BUSY, "message": message } });
+                    let _ = writeln!(stream, "{}", busy);
+                    continue;
+                };
+                scope.spawn(move || {
+                    let _slot = slot;
+                    let reader = match stream.try_clone() {
+                        Ok(reader) => std::io::BufReader::new(reader),
+                        Err(e) => return debug!(error = %e, "serve: failed to set up a connection"),
+                    };
+                    if let Err(e) = serve_lines(server, reader, stream, &format!("socket client {}", n), ctx) {
+                        debug!(error = %e, "serve: connection ended with an error");
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn serve_socket(self, path: &std::path::Path, _limits: ServeLimits, _ctx: &RunCtx) -> Result<()> {
+        bail!("serve --socket {}: unix sockets are not available on this platform", path.display())
+    }
+
+    /// One JSON-RPC message in, its response out (none for notifications). The stdio and socket
+    /// transports serve the whole file system, so nothing is confined to a root.
+    fn handle_message(&self, text: &str, ctx: &RunCtx) -> Option<serde_json::Value> {
+        let error = |id: serde_json::Value, code: i64, message: String| {
+            json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+        };
+        match serde_json::from_str::<serde_json::Value>(text) {
+            Err(e) => Some(error(serde_json::Value::Null, -32700, format!("parse error: {}", e))),
+            Ok(serde_json::Value::Array(batch)) if batch.is_empty() => {
+                Some(error(serde_json::Value::Null, -32600, "empty batch".to_string()))
+            }
+            Ok(serde_json::Value::Array(batch)) => {
+                let responses: Vec<_> = batch.into_iter().filter_map(|r| self.handle_request(r, ctx)).collect();
+                (!responses.is_empty()).then(|| json!(responses))
+            }
+            Ok(request) => self.handle_request(request, ctx),
+        }
+    }
+
+    fn handle_request(&self, request: serde_json::Value, ctx: &RunCtx) -> Option<serde_json::Value> {
+        let id = request.get("id").cloned();
+        let outcome = match (request.get("method").and_then(|m| m.as_str()), request.get("jsonrpc")) {
+            (Some(method), Some(v)) if v == "2.0" => {
+                let params = request.get("params").cloned().unwrap_or_else(|| json!({}));
+                debug!(method, "serve: request");
+                self.call(method, params, None, ctx)
+            }
+            _ => Err(RpcError { code: -32600, message: "invalid request: expected jsonrpc \"2.0\" and a method".into() }),
+        };
+        // Requests without an id are notifications: run them, but don't answer.
+        let id = id?;
+        Some(match outcome {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code, "message": e.message } }),
+        })
+    }
+
+    /// Run one method; results have the shape of the matching command's `--json` result. With a
+    /// `root`, every file must resolve into it.
+    fn call(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        root: Option<&Path>,
+        ctx: &RunCtx,
+    ) -> Result<serde_json::Value, RpcError> {
+        let invalid = |e: serde_json::Error| RpcError { code: -32602, message: format!("invalid params: {}", e) };
+        match method {
+            "read" => {
+                let input: ReadToolInput = serde_json::from_value(params).map_err(invalid)?;
+                Ok(self.read(&input, root, ctx)?)
+            }
+            "edit" => Ok(self.edit(params, root, ctx)?),
+            "verify" => Ok(self.verify(params, root, ctx)?),
+            "search" => {
+                let input: SearchToolInput = serde_json::from_value(params).map_err(invalid)?;
+                Ok(self.search(&input, root, ctx)?)
+            }
+            "initialize" => Ok(json!({
+                "protocolVersion": params.get("protocolVersion").cloned().unwrap_or_else(|| json!(MCP_PROTOCOL_VERSION)),
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "hashline", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "notifications/initialized" | "ping" => Ok(json!({})),
+            "tools/list" => Ok(render_tool_manifest(ToolFormat::Mcp)),
+            "tools/call" => {
+                let name = params.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+                let method = match name.strip_prefix("hashline_") {
+                    Some(method @ ("read" | "edit" | "verify" | "search")) => method,
+                    _ => return Err(RpcError { code: -32602, message: format!("unknown tool: {:?}", name) }),
+                };
+                let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+                // Tool failures are results the model should see, not protocol errors.
+                Ok(match self.call(method, arguments, root, ctx) {
+                    Ok(result) => json!({
+                        "content": [{ "type": "text", "text": tool_text(method, &result) }],
+                        "structuredContent": result,
+                        "isError": false,
+                    }),
+                    Err(e) => json!({ "content": [{ "type": "text", "text": e.message }], "isError": true }),
+                })
+            }
+            _ => Err(RpcError { code: -32601, message: format!("method not found: {}", method) }),
+        }
+    }
+
+    /// The document for `path`, re-read when the file's modification time or size moved. Remote
+    /// and non-file targets are re-read on every request.
+    fn document(&self, path: &Path, root: Option<&Path>, ctx: &RunCtx) -> Result<Arc<CachedDoc>> {
+        confine(path, root)?;
+        let stamp = fs::metadata(path)
+            .ok()
+            .filter(|m| ctx.remote.is_none() && m.is_file())
+            .map(|m| (m.modified().ok(), m.len()));
+        let tick = {
+            let mut cache = lock(&self.cache);
+            cache.tick += 1;
+            let tick = cache.tick;
+            if let Some((used, doc)) = cache.docs.get_mut(path).filter(|(_, doc)| Some(doc.stamp) == stamp) {
+                *used = tick;
+                return Ok(doc.clone());
+            }
+            tick
+        };
+        let content = open_storage(path, ctx.remote.as_deref())?
+            .read()
+            .with_context(|| format!("failed to read {}", path.display()))?
+            .content;
+        let normalized = normalize_to_lf(&content);
+        let lines: Vec<String> = split_preserve_last_empty(&normalized).into_iter().map(String::from).collect();
+        let doc = Arc::new(CachedDoc {
+            stamp: stamp.unwrap_or((None, 0)),
+            hashes: lines.iter().map(|l| compute_line_hash(l)).collect(),
+            lines,
+            fingerprint: file_fingerprint(&normalized),
+        });
+        if stamp.is_some() {
+            let mut cache = lock(&self.cache);
+            if cache.docs.len() >= MAX_CACHED_DOCS && !cache.docs.contains_key(path) {
+                let oldest = cache.docs.iter().min_by_key(|(_, (used, _))| *used).map(|(p, _)| p.clone());
+                cache.docs.remove(&oldest.expect("cache is not empty"));
+            }
+            cache.docs.insert(path.to_path_buf(), (tick, doc.clone()));
+        }
+        Ok(doc)
+    }
+
+    /// Wait for this request's turn in the queue of each of `paths`. The queues are joined in a
+    /// fixed order, so two multi-file edits can't each hold a turn the other is waiting for.
+    fn queue_turns<'p>(&self, paths: impl Iterator<Item = &'p PathBuf>) -> Vec<QueueTurn> {
+        let mut keys: Vec<PathBuf> = paths.map(|p| p.canonicalize().unwrap_or_else(|_| p.clone())).collect();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .map(|key| {
+                let queue = lock(&self.queues).entry(key).or_default().clone();
+                QueueTurn::wait(queue)
+            })
+            .collect()
+    }
+
+    /// Spend one of `client`'s `--rate-limit` requests, or say how long until it may send one.
+    fn admit(&self, client: &str) -> std::result::Result<(), Duration> {
+        self.limiter.as_ref().map_or(Ok(()), |limiter| limiter.admit(client))
+    }
+
+    fn read(&self, input: &ReadToolInput, root: Option<&Path>, ctx: &RunCtx) -> Result<serde_json::Value> {
+        let path = PathBuf::from(&input.path);
+        let doc = self.document(&path, root, ctx)?;
+        let start = input.offset.unwrap_or(1);
+        if start == 0 {
+            bail!("offset is 1-indexed (must be >= 1)");
+        }
+        if start > doc.lines.len().max(1) {
+            bail!("offset {} out of range (file has {} lines)", start, doc.lines.len());
+        }
+        let mut records: Vec<ReadLine> = doc
+            .lines
+            .iter()
+            .zip(&doc.hashes)
+            .enumerate()
+            .skip(start - 1)
+            .take(input.limit.unwrap_or(usize::MAX))
+            .map(|(i, (line, hash))| ReadLine::new(i + 1, hash.clone(), line, ReadContent::default()))
+            .collect();
+        mark_duplicate_hashes(&mut records, &doc.hashes);
+        Ok(json!({
+            "path": input.path,
+            "fingerprint": doc.fingerprint,
+            "total_lines": doc.lines.len(),
+            "lines": records,
+        }))
+    }
+
+    /// `{path, edits}` or `{files}` (plus `idempotency_key`, `fingerprint`), all-or-nothing.
+    fn edit(&self, mut params: serde_json::Value, root: Option<&Path>, ctx: &RunCtx) -> Result<serde_json::Value> {
+        let text = params.to_string();
+        let mut take = |key: &str| -> Result<Option<String>> {
+            match params.as_object_mut().and_then(|o| o.remove(key)) {
+                None | Some(serde_json::Value::Null) => Ok(None),
+                Some(serde_json::Value::String(s)) => Ok(Some(s)),
+                Some(_) => bail!("`{}` must be a string", key),
+            }
+        };
+        let (path, fingerprint) = (take("path")?.map(PathBuf::from), take("fingerprint")?);
+        let request = parse_edits_value(params).context("edit: invalid edits")?;
+        let single = path.is_some();
+        let jobs = payload_jobs("edit", path, request.edits, request.files, ctx)?;
+        if fingerprint.is_some() && jobs.len() > 1 {
+            bail!("edit: `fingerprint` applies to a single file");
+        }
+        let opts = EditOptions { fingerprint, ..EditOptions::plain(false, true) };
+        for (path, _) in &jobs {
+            confine(path, root)?;
+        }
+        let paths: Vec<PathBuf> = jobs.iter().map(|(path, _)| path.clone()).collect();
+        let turns = self.queue_turns(paths.iter());
+        let result = if single && jobs.len() == 1 {
+            let (path, edits) = jobs.into_iter().next().expect("one job");
+            edit_file(&path, edits, request.idempotency_key, &text, &opts, ctx)
+        } else {
+            edit_files(jobs, request.idempotency_key, &text, &opts, ctx).map(|results| json!({ "files": results }))
+        };
+        // The edit may leave a file with the same modification time and size it was cached with.
+        let mut cache = lock(&self.cache);
+        for path in &paths {
+            cache.docs.remove(path);
+        }
+        drop((cache, turns));
+        lock(&self.queues).retain(|_, queue| Arc::strong_count(queue) > 1);
+        result
+    }
+
+    /// Like `search --json`, except that no matches is not an error. Files a directory or glob
+    /// reaches outside the root are skipped.
+    fn search(&self, input: &SearchToolInput, root: Option<&Path>, ctx: &RunCtx) -> Result<serde_json::Value> {
+        let re = regex::RegexBuilder::new(&input.pattern)
+            .case_insensitive(input.ignore_case)
+            .build()
+            .with_context(|| format!("search: invalid pattern {:?}", input.pattern))?;
+        confine(Path::new(&input.path), root)?;
+        let (mut paths, many) = search_targets(PathBuf::from(&input.path), ctx)?;
+        if many {
+            paths.retain(|p| confine(p, root).is_ok());
+        } else {
+            confine(&paths[0], root)?;
+        }
+        let hits = search_hits("search", &paths, &re, (input.context, input.context), many, ctx)?;
+        let total: usize = hits.iter().map(|h| h.matches.len()).sum();
+        Ok(json!({ "matches": total, "files": hits.iter().map(SearchHit::to_json).collect::<Vec<_>>() }))
+    }
+
+    /// `{path, anchors}` and/or an edits payload; drift is reported, not an error.
+    fn verify(&self, mut params: serde_json::Value, root: Option<&Path>, ctx: &RunCtx) -> Result<serde_json::Value> {
+        let anchors: Vec<String> = match params.as_object_mut().and_then(|o| o.remove("anchors")) {
+            None => Vec::new(),
+            Some(value) => serde_json::from_value(value).context("verify: `anchors` must be an array of strings")?,
+        };
+        let path = params.get("path").and_then(|p| p.as_str()).map(PathBuf::from);
+        let mut jobs: Vec<(PathBuf, Vec<String>)> = Vec::new();
+        if params.get("edits").is_some() || params.get("files").is_some() {
+            let request = parse_edits_value(params).context("verify: invalid edits")?;
+            let anchors_of = |edits: &[HashlineEdit]| -> Vec<String> {
+                edits.iter().flat_map(edit_anchors).map(str::to_string).collect()
+            };
+            match &path {
+                Some(path) if request.files.is_empty() => jobs.push((path.clone(), anchors_of(&request.edits))),
+                _ => jobs.extend(request.files.iter().map(|f| (PathBuf::from(&f.path), anchors_of(&f.edits)))),
+            }
+        }
+        if !anchors.is_empty() {
+            let Some(path) = &path else { bail!("verify: `anchors` needs a `path`") };
+            match jobs.iter_mut().find(|(p, _)| p == path) {
+                Some((_, listed)) => listed.extend(anchors),
+                None => jobs.push((path.clone(), anchors)),
+            }
+        }
+        if jobs.is_empty() {
+            bail!("verify: provide `path` and `anchors`, or an edits payload");
+        }
+        let single = path.is_some() && jobs.len() == 1;
+        let mut drifted = 0;
+        let mut files = Vec::with_capacity(jobs.len());
+        for (path, anchors) in &jobs {
+            let parsed = anchors
+                .iter()
+                .map(|a| parse_line_ref(a).with_context(|| format!("verify: invalid anchor {:?}", a)))
+                .collect::<Result<Vec<_>>>()?;
+            let records = verify_records(&self.document(path, root, ctx)?.lines, &parsed);
+            drifted += records.iter().filter(|r| r["status"] != "ok").count();
+            files.push(json!({ "path": path.display().to_string(), "anchors": records }));
+        }
+        let mut result = if single { files.remove(0) } else { json!({ "files": files }) };
+        result["drifted"] = json!(drifted);
+        Ok(result)
+    }
+}
+
+/// Refuse `path` if it is `-` (stdin belongs to the transport, or to nobody), or if there is a
+/// `root` and the path is not a plain local file (a URL, `s3://` object, or archive member would
+/// reach past the root) or resolves, through symlinks or its nearest existing ancestor for a new
+/// file, outside it. `root` must be canonical.
+fn confine(path: &Path, root: Option<&Path>) -> Result<()> {
+    if path.as_os_str() == "-" {
+        bail!("`-` (stdin) can't be read through the server; pass a file path");
+    }
+    let Some(root) = root else { return Ok(()) };
+    let s3 = path.to_str().is_some_and(|s| s.starts_with("s3://"));
+    if is_url(path) || s3 || split_archive_target(path).is_some() {
+        return Err(OutsideRoot(path.display().to_string()).into());
+    }
+    // A relative path's last ancestor is the empty path, which is the current directory.
+    let existing = path.ancestors().map(|a| if a.as_os_str().is_empty() { Path::new(".") } else { a }).find(|a| a.exists());
+    let resolved = existing.map_or_else(|| Ok(root.to_path_buf()), |a| a.canonicalize())?;
+    if !resolved.starts_with(root) || path.components().any(|c| c == std::path::Component::ParentDir) {
+        return Err(OutsideRoot(path.display().to_string()).into());
+    }
+    Ok(())
+}
+
+/// Answer the JSON-RPC messages on `input`, one per line, until it closes. `client` names the
+/// sender for `--rate-limit`; a message over the limit is answered with an error and not run.
+fn serve_lines(
+    server: &Server,
+    input: impl std::io::BufRead,
+    mut output: impl Write,
+    client: &str,
+    ctx: &RunCtx,
+) -> Result<()> {
+    for line in input.lines() {
+        let line = line.context("serve: failed to read a request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match server.admit(client) {
+            Ok(()) => server.handle_message(&line, ctx),
+            Err(wait) => {
+                let id = serde_json::from_str::<serde_json::Value>(&line).ok().and_then(|m| m.get("id").cloned());
+                let message = format!("rate limit exceeded; retry in {}s", wait.as_secs() + 1);
+                Some(json!({ "jsonrpc": "2.0", "id": id, "error": { "code": RATE_LIMITED, "message": message } }))
+            }
+        };
+        if let Some(response) = response {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Largest HTTP request body `serve --http` accepts.
+#[cfg(feature = "http-server")]
+const MAX_HTTP_BODY: usize = 16 * 1024 * 1024;
+
+/// `serve --http`: a small HTTP/1.1 front end for `Server`, over TLS when `tls` is given (plain
+/// HTTP requests then get 400). Each connection carries one request and runs on its own thread,
+/// up to `limits.max_connections` at once; past that, a connection gets 503, and a client past
+/// `limits.rate_limit` gets 429. Request paths are relative to `root` and may not leave it, or the
+/// narrower root of the request's bearer token. Without tokens or client certificates there is
+/// no authentication, so `addr` must then be a loopback address.
+#[cfg(feature = "http-server")]
+pub(crate) fn serve_http(
+    addr: &str,
+    root: &Path,
+    tls: Option<&TlsFiles>,
+    tokens: &TokenArgs,
+    limits: ServeLimits,
+    ctx: &RunCtx,
+) -> Result<()> {
+    use std::net::ToSocketAddrs;
+
+    let addrs: Vec<std::net::SocketAddr> =
+        addr.to_socket_addrs().with_context(|| format!("serve: invalid --http address {}", addr))?.collect();
+    let root = root.canonicalize().with_context(|| format!("serve: failed to resolve --root {}", root.display()))?;
+    let tokens = load_tokens(tokens, &root)?;
+    let client_certs = tls.is_some_and(|tls| tls.client_ca.is_some());
+    if let Some(open) = addrs.iter().find(|a| !a.ip().is_loopback()) {
+        if tokens.is_empty() && !client_certs {
+            bail!(
+                "serve: refusing to listen on {} ({}) without authentication; pass --token, --token-file or \
+                 --tls-client-ca, or bind a loopback address such as 127.0.0.1:8080",
+                addr,
+                open.ip()
+            );
+        }
+        if tls.is_none() && !ctx.quiet {
+            let warning = ctx.stderr.yellow("warning:");
+            eprintln!("{} listening on {} without --tls-cert, so bearer tokens travel in the clear", warning, open.ip());
+        }
+    }
+    let tls = tls.map(tls_config).transpose()?;
+    std::env::set_current_dir(&root).with_context(|| format!("serve: failed to enter {}", root.display()))?;
+    let listener =
+        std::net::TcpListener::bind(&addrs[..]).with_context(|| format!("serve: failed to listen on {}", addr))?;
+    if !ctx.quiet {
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        eprintln!("listening on {}://{} (root {})", scheme, listener.local_addr()?, root.display());
+    }
+    let server = Server { limiter: limits.rate_limit.map(RateLimiter::new), ..Server::default() };
+    let site = HttpSite { server: &server, root: &root, tokens: &tokens };
+    let (site, tls, active) = (&site, &tls, AtomicUsize::new(0));
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!(error = %e, "serve: accept failed");
+                    continue;
+                }
+            };
+            // A stalled client must not hold a connection slot forever.
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(30)));
+            let Some(slot) = ConnectionSlot::take(&active, limits.max_connections) else {
+                let busy = format!("server busy: {} requests are in progress", limits.max_connections);
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                write_http_response(&mut stream, 503, "serve", Err(busy), &[("Retry-After", "1".into())], ctx);
+                continue;
+            };
+            scope.spawn(move || {
+                let _slot = slot;
+                let client = stream.peer_addr().map(|a| a.ip().to_string()).unwrap_or_default();
+                let Some(config) = tls else { return site.answer(&mut stream, &client, ctx) };
+                if let Some(mut stream) = accept_tls(stream, config, ctx) {
+                    site.answer(&mut stream, &client, ctx);
+                    stream.conn.send_close_notify();
+                    let _ = stream.flush();
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// What `serve --http` serves: the server, the `--root` requests are confined to, and the
+/// tokens that may call it, if any.
+#[cfg(feature = "http-server")]
+struct HttpSite<'a> {
+    server: &'a Server,
+    root: &'a Path,
+    tokens: &'a [Token],
+}
+
+#[cfg(feature = "http-server")]
+impl HttpSite<'_> {
+    /// Read one request from `stream`, authenticate and run it, and send the response.
+    fn answer(&self, stream: &mut (impl std::io::Read + Write), client: &str, ctx: &RunCtx) {
+        let mut headers = Vec::new();
+        let (status, command, outcome) = match read_http_request(stream) {
+            Err(e) => (400, "serve", Err(format!("bad request: {:#}", e))),
+            Ok(request) => match self.authorize(&request) {
+                Err(e) => {
+                    headers.push(("WWW-Authenticate", "Bearer".to_string()));
+                    (401, "serve", Err(e))
+                }
+                Ok((root, who)) => match self.server.admit(who.unwrap_or(client)) {
+                    Ok(()) => http_dispatch(self.server, &request, root, ctx),
+                    Err(wait) => {
+                        let wait = wait.as_secs() + 1;
+                        headers.push(("Retry-After", wait.to_string()));
+                        (429, "serve", Err(format!("rate limit exceeded; retry in {}s", wait)))
+                    }
+                },
+            },
+        };
+        write_http_response(stream, status, command, outcome, &headers, ctx);
+    }
+
+    /// The root `request` is confined to and the label of its token, or why it is refused. With
+    /// no tokens configured, every request gets `--root`.
+    fn authorize(&self, request: &HttpRequest) -> std::result::Result<(&Path, Option<&str>), String> {
+        if self.tokens.is_empty() {
+            return Ok((self.root, None));
+        }
+        let presented = request
+            .authorization
+            .as_deref()
+            .and_then(|value| value.trim().split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+            .ok_or("missing `Authorization: Bearer <token>` header")?;
+        // Compare against every token, so the time taken doesn't tell which one came close.
+        let found = self.tokens.iter().fold(None, |found, token| {
+            let matches = constant_time_eq(token.secret.as_bytes(), presented.as_bytes());
+            found.or(matches.then_some(token))
+        });
+        let token = found.ok_or("unknown bearer token")?;
+        debug!(token = %token.label, "serve: authorized");
+        Ok((&token.root, Some(&token.label)))
+    }
+}
+
+/// Whether `a == b`, taking the same time wherever they first differ.
+#[cfg(feature = "http-server")]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The `--token`s, which may use all of `root`, then the `--token-file` entries: one token per
+/// line, optionally followed by a directory under `root` that the token is confined to. Blank
+/// lines and `#` comments are skipped.
+#[cfg(feature = "http-server")]
+fn load_tokens(args: &TokenArgs, root: &Path) -> Result<Vec<Token>> {
+    let mut tokens: Vec<Token> = args
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(i, secret)| Token { secret: secret.clone(), root: root.to_path_buf(), label: format!("--token #{}", i + 1) })
+        .collect();
+    if let Some(file) = &args.file {
+        let text =
+            fs::read_to_string(file).with_context(|| format!("serve: failed to read --token-file {}", file.display()))?;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let label = format!("{}:{}", file.display(), n + 1);
+            let (secret, dir) = match line.split_once(char::is_whitespace) {
+                Some((secret, dir)) => (secret, Some(dir.trim())),
+                None => (line, None),
+            };
+            let scoped = match dir {
+                None => root.to_path_buf(),
+                Some(dir) => {
+                    let resolved = root.join(dir).canonicalize();
+                    let scoped = resolved.with_context(|| format!("serve: {}: failed to resolve {}", label, dir))?;
+                    if !scoped.starts_with(root) {
+                        bail!("serve: {}: {} is outside --root {}", label, dir, root.display());
+                    }
+                    scoped
+                }
+            };
+            tokens.push(Token { secret: secret.to_string(), root: scoped, label });
+        }
+    }
+    for (i, token) in tokens.iter().enumerate() {
+        if token.secret.len() < MIN_TOKEN_LEN {
+            bail!("serve: {}: bearer tokens must be at least {} characters", token.label, MIN_TOKEN_LEN);
+        }
+        if let Some(first) = tokens[..i].iter().find(|t| t.secret == token.secret) {
+            bail!("serve: {}: the same token as {}", token.label, first.label);
+        }
+    }
+    Ok(tokens)
+}
+
+/// The rustls configuration for `serve --tls-cert`/`--tls-key`.
+#[cfg(feature = "http-server")]
+fn tls_config(files: &TlsFiles) -> Result<Arc<rustls::ServerConfig>> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let certs = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .with_context(|| format!("serve: failed to read certificates from --tls-cert {}", files.cert.display()))?;
+    if certs.is_empty() {
+        bail!("serve: --tls-cert {} holds no PEM certificates", files.cert.display());
+    }
+    let key = PrivateKeyDer::from_pem_file(&files.key)
+        .with_context(|| format!("serve: failed to read a private key from --tls-key {}", files.key.display()))?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+    let builder = match &files.client_ca {
+        None => builder.with_no_client_auth(),
+        Some(ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            let context = || format!("serve: failed to read certificates from --tls-client-ca {}", ca.display());
+            for cert in CertificateDer::pem_file_iter(ca).with_context(context)? {
+                roots.add(cert.with_context(context)?).with_context(context)?;
+            }
+            if roots.is_empty() {
+                bail!("serve: --tls-client-ca {} holds no PEM certificates", ca.display());
+            }
+            let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .with_context(context)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    let config = builder
+        .with_single_cert(certs, key)
+        .with_context(|| {
+            format!("serve: --tls-key {} does not fit --tls-cert {}", files.key.display(), files.cert.display())
+        })?;
+    Ok(Arc::new(config))
+}
+
+/// Start TLS on `stream`. A client speaking plain HTTP instead gets a 400 telling it to use
+/// HTTPS, and `None`.
+#[cfg(feature = "http-server")]
+fn accept_tls(
+    mut stream: std::net::TcpStream,
+    config: &Arc<rustls::ServerConfig>,
+    ctx: &RunCtx,
+) -> Option<rustls::StreamOwned<rustls::ServerConnection, std::net::TcpStream>> {
+    // Every TLS connection opens with a handshake record, type 0x16; an HTTP request can't.
+    let mut first = [0u8; 1];
+    match stream.peek(&mut first) {
+        Ok(1) if first[0] == 0x16 => {}
+        Ok(1) => {
+            // Read the request first, so that closing the connection doesn't discard the answer.
+            let _ = read_http_request(&mut stream);
+            let refusal = "this server only accepts HTTPS; use an https:// URL".to_string();
+            write_http_response(&mut stream, 400, "serve", Err(refusal), &[], ctx);
+            return None;
+        }
+        _ => return None,
+    }
+    match rustls::ServerConnection::new(config.clone()) {
+        Ok(conn) => Some(rustls::StreamOwned::new(conn, stream)),
+        Err(e) => {
+            debug!(error = %e, "serve: failed to start TLS");
+            None
+        }
+    }
+}
+
+/// Send `outcome` as a `--json` envelope with `status`, and close the connection.
+#[cfg(feature = "http-server")]
+fn write_http_response(
+    stream: &mut impl Write,
+    status: u16,
+    command: &'static str,
+    outcome: std::result::Result<serde_json::Value, String>,
+    headers: &[(&str, String)],
+    ctx: &RunCtx,
+) {
+    let (result, error) = match outcome {
+        Ok(result) => (Some(result), None),
+        Err(e) => (None, Some(e)),
+    };
+    let env = Envelope { api_version: ctx.api_version, command, ok: error.is_none(), result, error };
+    let body = serde_json::to_string(&env).expect("envelopes serialize");
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Unprocessable Entity",
+    };
+    let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        headers,
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        debug!(error = %e, "serve: failed to send a response");
+    }
+}
+
+#[cfg(not(feature = "http-server"))]
+pub(crate) fn serve_http(
+    addr: &str,
+    _root: &Path,
+    _tls: Option<&TlsFiles>,
+    _tokens: &TokenArgs,
+    _limits: ServeLimits,
+    _ctx: &RunCtx,
+) -> Result<()> {
+    bail!("serve --http {addr}: the HTTP API requires building hashline with `--features http-server`")
+}
+
+#[cfg(feature = "http-server")]
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    /// The `Authorization` header
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+#[cfg(feature = "http-server")]
+fn read_http_request(stream: &mut impl std::io::Read) -> Result<HttpRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            bail!("connection closed before the request ended");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let httparse::Status::Complete(head) = parsed.parse(&buf)? else {
+            if buf.len() > 64 * 1024 {
+                bail!("request head is too large");
+            }
+            continue;
+        };
+        let length = match parsed.headers.iter().find(|h| h.name.eq_ignore_ascii_case("content-length")) {
+            Some(h) => std::str::from_utf8(h.value)?.trim().parse::<usize>().context("invalid Content-Length")?,
+            None => 0,
+        };
+        if length > MAX_HTTP_BODY {
+            bail!("request body is larger than {} bytes", MAX_HTTP_BODY);
+        }
+        let authorization = match parsed.headers.iter().find(|h| h.name.eq_ignore_ascii_case("authorization")) {
+            Some(h) => Some(String::from_utf8(h.value.to_vec()).context("invalid Authorization header")?),
+            None => None,
+        };
+        let method = parsed.method.unwrap_or_default().to_string();
+        let target = parsed.path.unwrap_or("/").to_string();
+        let mut body = buf.split_off(head);
+        while body.len() < length {
+            let n = stream.read(&mut chunk)?;
+            if n == 0 {
+                bail!("connection closed before the request body ended");
+            }
+            body.extend_from_slice(&chunk[..n]);
+        }
+        body.truncate(length);
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        let query = form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+        return Ok(HttpRequest { method, path: path.to_string(), query, authorization, body });
+    }
+}
+
+/// Route one HTTP request to a `Server` method, confined to `root`: `GET /read` and `GET /search`
+/// take query parameters, and `POST /read|/edit|/verify|/search` take the method's params as a
+/// JSON body. Returns the status, the command name for the envelope, and the outcome.
+#[cfg(feature = "http-server")]
+fn http_dispatch(
+    server: &Server,
+    request: &HttpRequest,
+    root: &Path,
+    ctx: &RunCtx,
+) -> (u16, &'static str, std::result::Result<serde_json::Value, String>) {
+    let command = match request.path.as_str() {
+        "/read" => "read",
+        "/edit" => "edit",
+        "/verify" => "verify",
+        "/search" => "search",
+        other => return (404, "serve", Err(format!("no such endpoint: {}", other))),
+    };
+    let params = match (request.method.as_str(), command) {
+        ("GET", "read" | "search") => {
+            let mut params = serde_json::Map::new();
+            for (key, value) in &request.query {
+                let value = match key.as_str() {
+                    "offset" | "limit" | "context" => match value.parse::<usize>() {
+                        Ok(n) => json!(n),
+                        Err(_) => return (400, command, Err(format!("`{}` must be a number", key))),
+                    },
+                    "ignore_case" => json!(value == "true" || value == "1"),
+                    _ => json!(value),
+                };
+                params.insert(key.clone(), value);
+            }
+            serde_json::Value::Object(params)
+        }
+        ("POST", _) => match serde_json::from_slice(&request.body) {
+            Ok(params) => params,
+            Err(e) => return (400, command, Err(format!("invalid JSON body: {}", e))),
+        },
+        _ => return (405, command, Err(format!("{} does not accept {}", request.path, request.method))),
+    };
+    match server.call(command, params, Some(root), ctx) {
+        Ok(result) => (200, command, Ok(result)),
+        Err(e) if e.code == OUTSIDE_ROOT => (403, command, Err(e.message)),
+        Err(e) => (if e.code == -32000 { 422 } else { 400 }, command, Err(e.message)),
+    }
+}
+
+/// What a model sees of a tool result: `read` and `search` as `LINE:HASH|content` lines (as the
+/// CLI prints them), everything else as JSON.
+fn tool_text(method: &str, result: &serde_json::Value) -> String {
+    let str_of = |v: &serde_json::Value| v.as_str().unwrap_or_default().to_string();
+    let anchors = |file: &serde_json::Value, prefix: &str| -> String {
+        let lines = file["lines"].as_array().into_iter().flatten();
+        lines
+            .map(|l| {
+                let mark = if l["duplicate"] == true { "*" } else { "" };
+                format!("{}{}:{}{}|{}\n", prefix, l["line"], str_of(&l["hash"]), mark, str_of(&l["content"]))
+            })
+            .collect()
+    };
+    match method {
+        "read" => format!("fingerprint {}\n{}", str_of(&result["fingerprint"]), anchors(result, "")),
+        "search" if result["matches"] == 0 => "no matches".to_string(),
+        "search" => result["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|file| {
+                let path = str_of(&file["path"]);
+                format!("fingerprint {} {}\n{}", str_of(&file["fingerprint"]), path, anchors(file, &format!("{}:", path)))
+            })
+            .collect(),
+        _ => serde_json::to_string_pretty(result).unwrap_or_default(),
+    }
+}
+
+/// `hashline serve`: pick the transport and start serving.
+pub(crate) fn serve_command(args: ServeArgs, ctx: &RunCtx) -> Result<()> {
+    let ServeArgs {
+        stdio,
+        socket,
+        http,
+        root,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+        token,
+        token_file,
+        max_connections,
+        rate_limit,
+    } = args;
+    let limits = ServeLimits { max_connections: usize::from(max_connections), rate_limit };
+    if let Some(addr) = http {
+        if ctx.remote.is_some() {
+            bail!("serve: --http serves local files; it can't be combined with --remote");
+        }
+        let tls = tls_cert.zip(tls_key).map(|(cert, key)| TlsFiles { cert, key, client_ca: tls_client_ca });
+        let tokens = TokenArgs { tokens: token, file: token_file };
+        let root = root.unwrap_or_else(|| PathBuf::from("."));
+        return serve_http(&addr, &root, tls.as_ref(), &tokens, limits, ctx);
+    }
+    if let Some(path) = socket {
+        return Server::default().serve_socket(&path, limits, ctx);
+    }
+    if !stdio {
+        bail!("serve: choose a transport (--stdio, --socket PATH, or --http ADDR)");
+    }
+    Server::default().serve_stdio(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_edit_waits_for_the_turn_before_it() {
+        let queue = Arc::new(FileQueue::default());
+        let first = QueueTurn::wait(queue.clone());
+        let done = Arc::new(AtomicUsize::new(0));
+        let waiter = {
+            let (queue, done) = (queue.clone(), done.clone());
+            std::thread::spawn(move || {
+                let _turn = QueueTurn::wait(queue);
+                done.store(1, Ordering::SeqCst);
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(done.load(Ordering::SeqCst), 0, "the second edit ran during the first");
+        drop(first);
+        waiter.join().unwrap();
+        assert_eq!(done.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn rate_limit_allows_a_burst_then_refuses_each_client_separately() {
+        let limiter = RateLimiter::new(2);
+        assert!(limiter.admit("a").is_ok());
+        assert!(limiter.admit("a").is_ok());
+        let wait = limiter.admit("a").expect_err("`a` spent its tokens");
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30), "{wait:?}");
+        assert!(limiter.admit("b").is_ok());
+    }
+
+    /// A fresh directory for one test, canonical so it compares equal to resolved paths.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hashline-server-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn new_files_are_confined_by_their_nearest_existing_directory() {
+        let root = scratch("confine");
+        fs::create_dir_all(root.join("team")).unwrap();
+        let team = root.join("team");
+        assert!(confine(&team.join("new/deeper/f.txt"), Some(&team)).is_ok());
+        assert!(confine(&root.join("new/f.txt"), Some(&team)).unwrap_err().is::<OutsideRoot>());
+        assert!(confine(Path::new("-"), None).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "http-server")]
+    fn request(authorization: Option<&str>) -> HttpRequest {
+        let authorization = authorization.map(str::to_string);
+        HttpRequest { method: "GET".into(), path: "/read".into(), query: Vec::new(), authorization, body: Vec::new() }
+    }
+
+    #[cfg(feature = "http-server")]
+    #[test]
+    fn token_file_entries_are_confined_to_their_directory() {
+        let root = scratch("tokens");
+        fs::create_dir_all(root.join("team")).unwrap();
+        let file = root.join("tokens.txt");
+        fs::write(&file, "# comment\n\nAAAAAAAAAAAAAAAAAAAA team\nBBBBBBBBBBBBBBBBBBBB\n").unwrap();
+        let args = TokenArgs { tokens: vec!["CCCCCCCCCCCCCCCCCCCC".into()], file: Some(file.clone()) };
+        let tokens = load_tokens(&args, &root).unwrap();
+        let roots: Vec<_> = tokens.iter().map(|t| t.root.clone()).collect();
+        assert_eq!(roots, [root.clone(), root.join("team"), root.clone()]);
+
+        let server = Server::default();
+        let site = HttpSite { server: &server, root: &root, tokens: &tokens };
+        let (scoped, label) = site.authorize(&request(Some("bearer AAAAAAAAAAAAAAAAAAAA"))).unwrap();
+        assert_eq!((scoped, label), (root.join("team").as_path(), Some(format!("{}:3", file.display()).as_str())));
+        assert_eq!(site.authorize(&request(None)).unwrap_err(), "missing `Authorization: Bearer <token>` header");
+        assert_eq!(site.authorize(&request(Some("Bearer AAAAAAAAAAAAAAAAAAAB"))).unwrap_err(), "unknown bearer token");
+        assert!(site.authorize(&request(Some("Basic AAAAAAAAAAAAAAAAAAAA"))).is_err());
+        assert!(confine(&root.join("f.txt"), Some(scoped)).unwrap_err().is::<OutsideRoot>());
+        assert!(confine(&root.join("team/f.txt"), Some(scoped)).is_ok());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "http-server")]
+    #[test]
+    fn token_files_are_checked_before_serving() {
+        let root = scratch("bad-tokens");
+        let file = root.join("tokens.txt");
+        let load = |text: &str| {
+            fs::write(&file, text).unwrap();
+            load_tokens(&TokenArgs { tokens: Vec::new(), file: Some(file.clone()) }, &root).unwrap_err().to_string()
+        };
+        assert!(load("short\n").contains("at least 16 characters"));
+        assert!(load("AAAAAAAAAAAAAAAAAAAA ..\n").contains("outside --root"));
+        assert!(load("AAAAAAAAAAAAAAAAAAAA\nAAAAAAAAAAAAAAAAAAAA\n").contains("the same token as"));
+        assert!(load_tokens(&TokenArgs::default(), &root).unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "http-server")]
+    #[test]
+    fn requests_that_escape_the_root_are_forbidden() {
+        let scratch = scratch("escape");
+        let root = scratch.join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("inside.txt"), "in\n").unwrap();
+        fs::write(scratch.join("outside.txt"), "secret\n").unwrap();
+        std::os::unix::fs::symlink(scratch.join("outside.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(&scratch, root.join("up")).unwrap();
+
+        let server = Server::default();
+        let ctx = RunCtx::for_tests();
+        let get = |path: &Path| {
+            let query = vec![("path".to_string(), path.display().to_string())];
+            let request = HttpRequest { query, ..request(None) };
+            let (status, command, outcome) = http_dispatch(&server, &request, &root, &ctx);
+            assert_eq!(command, "read");
+            (status, outcome)
+        };
+        let (status, outcome) = get(&root.join("inside.txt"));
+        assert_eq!(status, 200, "{outcome:?}");
+        assert_eq!(outcome.unwrap()["lines"][0]["content"], "in");
+        let escapes = ["../outside.txt", "link.txt", "up/outside.txt"].map(|p| root.join(p));
+        for escape in escapes.into_iter().chain([scratch.join("outside.txt")]) {
+            let (status, outcome) = get(&escape);
+            assert_eq!(status, 403, "{} was served: {outcome:?}", escape.display());
+        }
+
+        let anchor = hashline::Anchor::for_line(1, "secret").to_string();
+        let body = json!({
+            "path": root.join("../outside.txt"),
+            "edits": [{ "set_line": { "anchor": anchor, "new_text": "gone" } }],
+        });
+        let body = body.to_string().into_bytes();
+        let request = HttpRequest { method: "POST".into(), path: "/edit".into(), body, ..request(None) };
+        let (status, _, outcome) = http_dispatch(&server, &request, &root, &ctx);
+        assert_eq!(status, 403, "{outcome:?}");
+        assert_eq!(fs::read_to_string(scratch.join("outside.txt")).unwrap(), "secret\n");
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn connection_slots_are_given_back_on_drop() {
+        let active = AtomicUsize::new(0);
+        let slot = ConnectionSlot::take(&active, 1).expect("a free slot");
+        assert!(ConnectionSlot::take(&active, 1).is_none());
+        drop(slot);
+        assert!(ConnectionSlot::take(&active, 1).is_some());
+    }
+}
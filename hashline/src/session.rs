@@ -0,0 +1,215 @@
+//! Session tokens (`read --issue-token`) and snapshots (`read --session`).
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hashline::{compute_line_hash, normalize_to_lf, split_preserve_last_empty, HASH_SCHEME};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh64::xxh64;
+
+use crate::edit::content_digest;
+use crate::unix_timestamp;
+
+/// Prefix of session tokens issued by `read --issue-token`.
+const TOKEN_PREFIX: &str = "hl1.";
+
+/// Decoded contents of a session token.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct SessionToken {
+    path: String,
+    digest: String,
+    hash_scheme: String,
+    api_version: u32,
+}
+
+/// What `read --session` returned, stored as `.hashline/sessions/<id>.json` for `edit --session`.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SessionSnapshot {
+    path: String,
+    digest: String,
+    hash_scheme: String,
+    api_version: u32,
+    timestamp: u64,
+    /// Hash of each line, to report which ones changed
+    line_hashes: Vec<String>,
+}
+
+pub(crate) fn canonical_path_string(path: &std::path::Path) -> String {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).display().to_string()
+}
+
+pub(crate) fn issue_session_token(target: &str, raw: &str, api_version: u32) -> Result<String> {
+    let token = SessionToken {
+        path: target.to_string(),
+        digest: content_digest(raw),
+        hash_scheme: HASH_SCHEME.to_string(),
+        api_version,
+    };
+    Ok(format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(serde_json::to_vec(&token)?)))
+}
+
+pub(crate) fn check_session_token(token: &str, target: &str, raw: &str, api_version: u32) -> Result<()> {
+    let body = token
+        .trim()
+        .strip_prefix(TOKEN_PREFIX)
+        .ok_or_else(|| anyhow!("invalid session token (expected prefix {TOKEN_PREFIX})"))?;
+    let decoded: SessionToken = URL_SAFE_NO_PAD
+        .decode(body)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| anyhow!("invalid session token (corrupt payload)"))?;
+
+    if decoded.path != target {
+        bail!("session token was issued for {}, not {}", decoded.path, target);
+    }
+    if decoded.hash_scheme != HASH_SCHEME {
+        bail!(
+            "session token was issued with hash scheme {}, but this binary uses {}",
+            decoded.hash_scheme,
+            HASH_SCHEME
+        );
+    }
+    if decoded.api_version != api_version {
+        bail!(
+            "session token was issued for api version {}, but this invocation uses {}",
+            decoded.api_version,
+            api_version
+        );
+    }
+    if decoded.digest != content_digest(raw) {
+        bail!("file changed since the session token was issued. Re-read the file and retry.");
+    }
+    Ok(())
+}
+
+fn session_file(id: &str) -> Result<PathBuf> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("invalid session id {:?} (expected the hex id printed by `read --session`)", id);
+    }
+    Ok(PathBuf::from(".hashline").join("sessions").join(format!("{id}.json")))
+}
+
+pub(crate) fn write_session(id: &str, snapshot: &SessionSnapshot) -> Result<()> {
+    let file = session_file(id)?;
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("session: failed to create {}", dir.display()))?;
+    }
+    fs::write(&file, serde_json::to_string(snapshot)?)
+        .with_context(|| format!("session: failed to write {}", file.display()))
+}
+
+pub(crate) fn session_snapshot(target: &str, raw: &str, api_version: u32) -> SessionSnapshot {
+    SessionSnapshot {
+        path: target.to_string(),
+        digest: content_digest(raw),
+        hash_scheme: HASH_SCHEME.to_string(),
+        api_version,
+        timestamp: unix_timestamp(),
+        line_hashes: split_preserve_last_empty(&normalize_to_lf(raw)).into_iter().map(compute_line_hash).collect(),
+    }
+}
+
+/// Record what `read` returned and hand back the new session's id.
+pub(crate) fn start_session(target: &str, raw: &str, api_version: u32) -> Result<String> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let id = format!("{:016x}", xxh64(format!("{}\0{}\0{}", target, content_digest(raw), nanos).as_bytes(), 0));
+    write_session(&id, &session_snapshot(target, raw, api_version))?;
+    Ok(id)
+}
+
+/// Refuse unless the file is exactly what session `id` last saw, naming the lines that changed.
+pub(crate) fn check_session(id: &str, target: &str, raw: &str, api_version: u32) -> Result<()> {
+    let file = session_file(id)?;
+    let text = fs::read_to_string(&file).with_context(|| format!("unknown session {} (no {})", id, file.display()))?;
+    let snapshot: SessionSnapshot =
+        serde_json::from_str(&text).with_context(|| format!("session: corrupt snapshot {}", file.display()))?;
+    if snapshot.path != target {
+        bail!("session {} was started for {}, not {}", id, snapshot.path, target);
+    }
+    if snapshot.hash_scheme != HASH_SCHEME || snapshot.api_version != api_version {
+        bail!(
+            "session {} was started with hash scheme {} and api version {}, but this invocation uses {} and {}",
+            id,
+            snapshot.hash_scheme,
+            snapshot.api_version,
+            HASH_SCHEME,
+            api_version
+        );
+    }
+    if snapshot.digest == content_digest(raw) {
+        return Ok(());
+    }
+    let current = session_snapshot(target, raw, api_version).line_hashes;
+    let span = |start: usize, len: usize| match len {
+        1 => format!("line {}", start + 1),
+        _ => format!("lines {}-{}", start + 1, start + len),
+    };
+    let mut drift = Vec::new();
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, &snapshot.line_hashes, &current) {
+        let (tag, old, new) = op.as_tag_tuple();
+        drift.push(match tag {
+            similar::DiffTag::Equal => continue,
+            similar::DiffTag::Delete => format!("{} removed", span(old.start, old.len())),
+            similar::DiffTag::Insert if old.start == 0 => format!("{} line(s) added at the top", new.len()),
+            similar::DiffTag::Insert => format!("{} line(s) added after line {}", new.len(), old.start),
+            similar::DiffTag::Replace => format!("{} changed", span(old.start, old.len())),
+        });
+    }
+    if drift.is_empty() {
+        // Only the line endings or the final newline differ.
+        drift.push("line endings changed".to_string());
+    }
+    let more = drift.len().saturating_sub(5);
+    drift.truncate(5);
+    if more > 0 {
+        drift.push(format!("{} more change(s)", more));
+    }
+    bail!(
+        "file changed since session {} read it ({}; line numbers as read). Re-read the file and retry.",
+        id,
+        drift.join(", ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_session_token_matches_only_the_file_it_was_issued_for() {
+        let token = issue_session_token("/w/a.txt", "one\ntwo\n", 1).unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        check_session_token(&token, "/w/a.txt", "one\ntwo\n", 1).unwrap();
+
+        let err = |target: &str, raw: &str, api_version: u32| {
+            check_session_token(&token, target, raw, api_version).unwrap_err().to_string()
+        };
+        assert_eq!(err("/w/b.txt", "one\ntwo\n", 1), "session token was issued for /w/a.txt, not /w/b.txt");
+        assert_eq!(
+            err("/w/a.txt", "one\ntwo\n", 2),
+            "session token was issued for api version 1, but this invocation uses 2"
+        );
+        assert_eq!(
+            err("/w/a.txt", "one\nTWO\n", 1),
+            "file changed since the session token was issued. Re-read the file and retry."
+        );
+        let corrupt = check_session_token("hl1.!!", "/w/a.txt", "", 1).unwrap_err();
+        assert_eq!(corrupt.to_string(), "invalid session token (corrupt payload)");
+        let unprefixed = check_session_token("abc", "/w/a.txt", "", 1).unwrap_err();
+        assert_eq!(unprefixed.to_string(), "invalid session token (expected prefix hl1.)");
+    }
+
+    #[test]
+    fn session_ids_must_be_hex() {
+        assert!(session_file("0123abcd").is_ok());
+        for id in ["", "../x", "a/b", "zz"] {
+            assert!(session_file(id).is_err(), "{id:?} accepted");
+        }
+    }
+}
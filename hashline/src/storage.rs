@@ -0,0 +1,548 @@
+//! Where targets are read from and written to: local files, URLs, stdin, ssh, archives, and S3.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tracing::debug;
+
+use crate::session::canonical_path_string;
+#[cfg(feature = "archive")]
+use crate::unix_timestamp;
+
+/// Archive suffixes recognized in `archive!member` targets.
+const ARCHIVE_SUFFIXES: &[&str] = &[".zip", ".tar", ".tar.gz", ".tgz"];
+/// Largest remote (URL, object store) source hashline will fetch.
+#[cfg(any(feature = "http", feature = "s3"))]
+const MAX_REMOTE_BYTES: u64 = 8 * 1024 * 1024;
+/// Lifetime of presigned object-store request URLs.
+#[cfg(feature = "s3")]
+const S3_PRESIGN_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Where a read/edit target's text lives.
+pub(crate) trait Storage: Send {
+    /// Stable identity of the target, used by session tokens and the journal.
+    fn id(&self) -> String;
+
+    /// Load the content plus an opaque version (remote cksum, ETag) to pass back to `write`.
+    fn read(&self) -> Result<Loaded>;
+
+    /// Replace the content. Backends that can enforce `version` refuse to overwrite a target
+    /// that changed since it was read. Returns the new content's version, for a later write (such
+    /// as a rollback) to pass back.
+    fn write(&self, content: &str, version: Option<&str>) -> Result<Option<String>>;
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    /// The target as a local filesystem path, for features that consult neighbouring files.
+    fn local_path(&self) -> Option<&std::path::Path> {
+        None
+    }
+}
+
+pub(crate) struct Loaded {
+    pub(crate) content: String,
+    pub(crate) version: Option<String>,
+}
+
+struct LocalFile {
+    path: PathBuf,
+}
+
+struct UrlSource {
+    url: String,
+}
+
+/// `-`: piped content, read once.
+struct StdinSource;
+
+struct SshFile {
+    host: String,
+    path: PathBuf,
+}
+
+struct ArchiveMember {
+    archive: PathBuf,
+    member: String,
+}
+
+#[cfg(feature = "s3")]
+struct S3Object {
+    bucket: String,
+    key: String,
+}
+
+pub(crate) fn open_storage(path: &std::path::Path, remote: Option<&str>) -> Result<Box<dyn Storage>> {
+    if path.as_os_str() == "-" {
+        return Ok(Box::new(StdinSource));
+    }
+    if let Some(host) = remote {
+        return Ok(Box::new(SshFile { host: host.to_string(), path: path.to_path_buf() }));
+    }
+    if is_url(path) {
+        return Ok(Box::new(UrlSource { url: path.to_string_lossy().to_string() }));
+    }
+    if let Some(rest) = path.to_str().and_then(|s| s.strip_prefix("s3://")) {
+        return open_s3(rest);
+    }
+    if let Some((archive, member)) = split_archive_target(path) {
+        return Ok(Box::new(ArchiveMember { archive, member }));
+    }
+    Ok(Box::new(LocalFile { path: path.to_path_buf() }))
+}
+
+impl Storage for LocalFile {
+    fn id(&self) -> String {
+        canonical_path_string(&self.path)
+    }
+
+    fn read(&self) -> Result<Loaded> {
+        Ok(Loaded { content: fs::read_to_string(&self.path)?, version: None })
+    }
+
+    fn write(&self, content: &str, _version: Option<&str>) -> Result<Option<String>> {
+        write_atomically(&self.path, content.as_bytes())?;
+        Ok(None)
+    }
+
+    fn local_path(&self) -> Option<&std::path::Path> {
+        Some(&self.path)
+    }
+}
+
+/// Replace `path` with `content` without ever leaving it half-written: write a temp file next to
+/// it with the old file's permissions, fsync it, rename it into place, then fsync the directory so
+/// the rename survives a crash too. A symlink is followed, so the link itself stays.
+pub(crate) fn write_atomically(path: &std::path::Path, content: &[u8]) -> std::io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let tmp = dir.join(format!(".{}.{}.hashline.tmp", file_name, std::process::id()));
+    let written = (|| {
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        if let Ok(meta) = fs::metadata(&path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return written;
+    }
+    // Not every platform can open a directory to fsync it; the rename is done either way.
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+impl Storage for UrlSource {
+    fn id(&self) -> String {
+        self.url.clone()
+    }
+
+    fn read(&self) -> Result<Loaded> {
+        Ok(Loaded { content: fetch_url(&self.url)?, version: None })
+    }
+
+    fn write(&self, _content: &str, _version: Option<&str>) -> Result<Option<String>> {
+        bail!("URL sources are read-only: {}", self.url)
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+}
+
+impl Storage for StdinSource {
+    fn id(&self) -> String {
+        "-".to_string()
+    }
+
+    fn read(&self) -> Result<Loaded> {
+        Ok(Loaded { content: std::io::read_to_string(std::io::stdin())?, version: None })
+    }
+
+    fn write(&self, _content: &str, _version: Option<&str>) -> Result<Option<String>> {
+        bail!("stdin is read-only")
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+}
+
+impl Storage for SshFile {
+    fn id(&self) -> String {
+        format!("ssh://{}{}", self.host, self.path.display())
+    }
+
+    fn read(&self) -> Result<Loaded> {
+        let (content, cksum) = ssh_read(&self.host, &self.path)?;
+        Ok(Loaded { content, version: Some(cksum) })
+    }
+
+    fn write(&self, content: &str, version: Option<&str>) -> Result<Option<String>> {
+        let cksum = version.ok_or_else(|| anyhow!("remote: write without a prior read of {}", self.path.display()))?;
+        ssh_write(&self.host, &self.path, content, cksum).map(Some)
+    }
+}
+
+impl Storage for ArchiveMember {
+    fn id(&self) -> String {
+        format!("{}!{}", canonical_path_string(&self.archive), self.member)
+    }
+
+    fn read(&self) -> Result<Loaded> {
+        Ok(Loaded { content: archive_read(&self.archive, &self.member)?, version: None })
+    }
+
+    fn write(&self, content: &str, _version: Option<&str>) -> Result<Option<String>> {
+        archive_write(&self.archive, &self.member, content)?;
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "s3")]
+fn open_s3(rest: &str) -> Result<Box<dyn Storage>> {
+    let (bucket, key) = rest
+        .split_once('/')
+        .filter(|(b, k)| !b.is_empty() && !k.is_empty())
+        .ok_or_else(|| anyhow!("invalid S3 target (expected s3://bucket/key): s3://{rest}"))?;
+    Ok(Box::new(S3Object { bucket: bucket.to_string(), key: key.to_string() }))
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_s3(rest: &str) -> Result<Box<dyn Storage>> {
+    bail!("s3://{rest}: S3 targets require building hashline with `--features s3`")
+}
+
+#[cfg(feature = "s3")]
+impl S3Object {
+    /// Resolve the bucket from the standard AWS environment (`AWS_REGION`, `AWS_ENDPOINT_URL`,
+    /// `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY`). A custom endpoint implies path-style URLs.
+    fn bucket(&self) -> Result<(rusty_s3::Bucket, Option<rusty_s3::Credentials>)> {
+        let region = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let (endpoint, style) = match std::env::var("AWS_ENDPOINT_URL") {
+            Ok(e) => (e, rusty_s3::UrlStyle::Path),
+            Err(_) => (format!("https://s3.{region}.amazonaws.com"), rusty_s3::UrlStyle::VirtualHost),
+        };
+        let endpoint = url::Url::parse(&endpoint).with_context(|| format!("s3: invalid endpoint {endpoint}"))?;
+        let bucket = rusty_s3::Bucket::new(endpoint, style, self.bucket.clone(), region)
+            .map_err(|e| anyhow!("s3: invalid bucket {}: {e}", self.bucket))?;
+        Ok((bucket, rusty_s3::Credentials::from_env()))
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Storage for S3Object {
+    fn id(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.key)
+    }
+
+    fn read(&self) -> Result<Loaded> {
+        use rusty_s3::S3Action;
+
+        let (bucket, creds) = self.bucket()?;
+        let url = bucket.get_object(creds.as_ref(), &self.key).sign(S3_PRESIGN_TTL);
+        let mut resp = ureq::get(url.as_str()).call().with_context(|| format!("s3: failed to get {}", self.id()))?;
+        let etag = resp.headers().get("etag").and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+        let content = resp
+            .body_mut()
+            .with_config()
+            .limit(MAX_REMOTE_BYTES)
+            .read_to_string()
+            .with_context(|| format!("s3: failed to read {} (limit {MAX_REMOTE_BYTES} bytes, UTF-8 only)", self.id()))?;
+        Ok(Loaded { content, version: etag })
+    }
+
+    fn write(&self, content: &str, version: Option<&str>) -> Result<Option<String>> {
+        use rusty_s3::S3Action;
+
+        let (bucket, creds) = self.bucket()?;
+        let mut action = bucket.put_object(creds.as_ref(), &self.key);
+        if let Some(etag) = version {
+            action.headers_mut().insert("if-match", etag);
+        }
+        let url = action.sign(S3_PRESIGN_TTL);
+        let mut req = ureq::put(url.as_str());
+        if let Some(etag) = version {
+            req = req.header("If-Match", etag);
+        }
+        match req.send(content.as_bytes()) {
+            Ok(resp) => Ok(resp.headers().get("etag").and_then(|v| v.to_str().ok()).map(|v| v.to_string())),
+            Err(ureq::Error::StatusCode(412)) => {
+                bail!("s3: {} changed since last read (ETag no longer matches); re-read and retry", self.id())
+            }
+            Err(e) => Err(e).with_context(|| format!("s3: failed to put {}", self.id())),
+        }
+    }
+}
+
+pub(crate) fn is_url(path: &std::path::Path) -> bool {
+    let s = path.to_string_lossy();
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+#[cfg(feature = "http")]
+fn fetch_url(url: &str) -> Result<String> {
+    let mut resp = ureq::get(url).call().with_context(|| format!("read: failed to fetch {url}"))?;
+    let body = resp
+        .body_mut()
+        .with_config()
+        .limit(MAX_REMOTE_BYTES)
+        .read_to_string()
+        .with_context(|| format!("read: failed to read body of {url} (limit {MAX_REMOTE_BYTES} bytes, UTF-8 only)"))?;
+    debug!(url, bytes = body.len(), "fetched remote source");
+    Ok(body)
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch_url(url: &str) -> Result<String> {
+    bail!("read: {url}: URL sources require building hashline with `--features http`")
+}
+
+/// Split `archive.zip!dir/file.txt` into the archive path and member name. Only paths whose `!`
+/// prefix ends in a known archive suffix are treated as archive targets.
+pub(crate) fn split_archive_target(path: &std::path::Path) -> Option<(PathBuf, String)> {
+    let s = path.to_str()?;
+    let (archive, member) = s.split_once('!')?;
+    let lower = archive.to_ascii_lowercase();
+    let member = member.trim_start_matches('/');
+    if member.is_empty() || !ARCHIVE_SUFFIXES.iter().any(|ext| lower.ends_with(ext)) {
+        return None;
+    }
+    Some((PathBuf::from(archive), member.to_string()))
+}
+
+#[cfg(feature = "archive")]
+fn is_zip(archive: &std::path::Path) -> bool {
+    archive.to_string_lossy().to_ascii_lowercase().ends_with(".zip")
+}
+
+#[cfg(feature = "archive")]
+fn is_gzip(archive: &std::path::Path) -> bool {
+    let lower = archive.to_string_lossy().to_ascii_lowercase();
+    lower.ends_with(".tar.gz") || lower.ends_with(".tgz")
+}
+
+#[cfg(feature = "archive")]
+fn tar_reader<'a>(archive: &std::path::Path, bytes: &'a [u8]) -> Box<dyn std::io::Read + 'a> {
+    if is_gzip(archive) {
+        Box::new(flate2::read::GzDecoder::new(bytes))
+    } else {
+        Box::new(bytes)
+    }
+}
+
+#[cfg(feature = "archive")]
+fn archive_read(archive: &std::path::Path, member: &str) -> Result<String> {
+    use std::io::Read;
+
+    let bytes = fs::read(archive).with_context(|| format!("archive: failed to read {}", archive.display()))?;
+    let mut content = String::new();
+    if is_zip(archive) {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes))
+            .with_context(|| format!("archive: {} is not a valid zip", archive.display()))?;
+        let mut f = zip
+            .by_name(member)
+            .with_context(|| format!("archive: {} has no member {}", archive.display(), member))?;
+        f.read_to_string(&mut content)
+            .with_context(|| format!("archive: {}!{} is not valid UTF-8 text", archive.display(), member))?;
+        return Ok(content);
+    }
+
+    let mut tar = tar::Archive::new(tar_reader(archive, &bytes));
+    for entry in tar.entries().with_context(|| format!("archive: {} is not a valid tar", archive.display()))? {
+        let mut entry = entry?;
+        if entry.path()? == std::path::Path::new(member) {
+            entry
+                .read_to_string(&mut content)
+                .with_context(|| format!("archive: {}!{} is not valid UTF-8 text", archive.display(), member))?;
+            return Ok(content);
+        }
+    }
+    bail!("archive: {} has no member {}", archive.display(), member)
+}
+
+/// Rewrite the archive with `member` replaced by `content`; every other entry is copied verbatim
+/// (zip entries without recompression). The new archive is renamed over the old one.
+#[cfg(feature = "archive")]
+fn archive_write(archive: &std::path::Path, member: &str, content: &str) -> Result<()> {
+    use std::io::{Read, Write};
+
+    let bytes = fs::read(archive).with_context(|| format!("archive: failed to read {}", archive.display()))?;
+    let mut found = false;
+    let rebuilt = if is_zip(archive) {
+        let mut src = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+        let mut out = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for i in 0..src.len() {
+            let name = src.by_index_raw(i)?.name()?.to_string();
+            if name != member {
+                out.raw_copy_file(src.by_index_raw(i)?)?;
+                continue;
+            }
+            let f = src.by_index(i)?;
+            let mut opts = zip::write::SimpleFileOptions::default().compression_method(f.compression());
+            if let Some(t) = f.last_modified() {
+                opts = opts.last_modified_time(t);
+            }
+            if let Some(mode) = f.unix_mode() {
+                opts = opts.unix_permissions(mode);
+            }
+            drop(f);
+            out.start_file(name, opts)?;
+            out.write_all(content.as_bytes())?;
+            found = true;
+        }
+        out.finish()?.into_inner()
+    } else {
+        let mut src = tar::Archive::new(tar_reader(archive, &bytes));
+        let mut out = tar::Builder::new(Vec::new());
+        for entry in src.entries()? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.into_owned();
+            let mut header = entry.header().clone();
+            let mut data = Vec::new();
+            if entry_path == std::path::Path::new(member) {
+                data.extend_from_slice(content.as_bytes());
+                header.set_size(data.len() as u64);
+                header.set_mtime(unix_timestamp());
+                found = true;
+            } else {
+                entry.read_to_end(&mut data)?;
+            }
+            out.append_data(&mut header, &entry_path, data.as_slice())?;
+        }
+        let tar_bytes = out.into_inner()?;
+        if is_gzip(archive) {
+            let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            gz.write_all(&tar_bytes)?;
+            gz.finish()?
+        } else {
+            tar_bytes
+        }
+    };
+    if !found {
+        bail!("archive: {} has no member {}", archive.display(), member);
+    }
+
+    write_atomically(archive, &rebuilt).with_context(|| format!("archive: failed to replace {}", archive.display()))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "archive"))]
+fn archive_read(archive: &std::path::Path, member: &str) -> Result<String> {
+    bail!(
+        "{}!{}: archive targets require building hashline with `--features archive`",
+        archive.display(),
+        member
+    )
+}
+
+#[cfg(not(feature = "archive"))]
+fn archive_write(archive: &std::path::Path, member: &str, _content: &str) -> Result<()> {
+    archive_read(archive, member).map(|_| ())
+}
+
+/// Quote `s` for a POSIX shell (ssh passes the remote command through the login shell).
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn ssh_command(host: &str, script: &str, args: &[&str]) -> std::process::Command {
+    let mut remote = format!("sh -c {} hashline", sh_quote(script));
+    for a in args {
+        remote.push(' ');
+        remote.push_str(&sh_quote(a));
+    }
+    let mut cmd = std::process::Command::new("ssh");
+    cmd.args(["-o", "BatchMode=yes", host, "--", &remote]);
+    cmd
+}
+
+/// Read a remote file, returning its content and the POSIX `cksum` taken in the same call.
+fn ssh_read(host: &str, path: &std::path::Path) -> Result<(String, String)> {
+    let p = path.to_string_lossy();
+    let out = ssh_command(host, r#"cksum < "$1" && cat < "$1""#, &[&p])
+        .output()
+        .context("remote: failed to run ssh")?;
+    if !out.status.success() {
+        bail!("remote: failed to read {}:{}: {}", host, p, String::from_utf8_lossy(&out.stderr).trim());
+    }
+    let stdout = String::from_utf8(out.stdout).map_err(|_| anyhow!("remote: {}:{} is not valid UTF-8", host, p))?;
+    let (cksum, content) = stdout.split_once('\n').unwrap_or((stdout.as_str(), ""));
+    debug!(host, path = %p, bytes = content.len(), "read remote file");
+    Ok((content.to_string(), cksum.to_string()))
+}
+
+/// Atomically replace a remote file: under a mkdir lock, verify it still has the `cksum` seen at read
+/// time, then write a mode-preserving temp file next to it and rename it into place. Returns the
+/// new content's `cksum`.
+fn ssh_write(host: &str, path: &std::path::Path, content: &str, expected_cksum: &str) -> Result<String> {
+    use std::io::Write;
+
+    const SCRIPT: &str = r#"set -e
+p=$1; expected=$2; lock="$p.hashline.lock"; tmp="$p.hashline.$$"
+mkdir "$lock" 2>/dev/null || { echo "$p is locked by another writer ($lock)" >&2; exit 75; }
+trap 'rm -f "$tmp"; rmdir "$lock"' EXIT
+[ "$(cksum < "$p")" = "$expected" ] || { echo "$p changed since last read; re-read and retry" >&2; exit 76; }
+cp -p "$p" "$tmp"
+cat > "$tmp"
+mv -f "$tmp" "$p"
+cksum < "$p""#;
+
+    let p = path.to_string_lossy();
+    let mut child = ssh_command(host, SCRIPT, &[&p, expected_cksum])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .context("remote: failed to run ssh")?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("remote: ssh stdin unavailable"))?
+        .write_all(content.as_bytes())
+        .context("remote: failed to send content")?;
+    let out = child.wait_with_output().context("remote: failed to wait for ssh")?;
+    if !out.status.success() {
+        bail!("remote: failed to write {}:{}: {}", host, p, String::from_utf8_lossy(&out.stderr).trim());
+    }
+    debug!(host, path = %p, bytes = content.len(), "wrote remote file");
+    Ok(String::from_utf8_lossy(&out.stdout).trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomically_keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("hashline-write-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (real, link) = (dir.join("real.txt"), dir.join("link.txt"));
+        fs::write(&real, "old\n").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        write_atomically(&link, b"new\n").unwrap();
+        assert_eq!(fs::read_to_string(&real).unwrap(), "new\n");
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::metadata(&real).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2, "temp file left behind");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+//! Globs, directory walks, and `.gitignore`/`.hashlineignore` rules.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use crate::editorconfig::{expand_braces, glob_match};
+use crate::storage::{is_url, split_archive_target};
+
+/// Expand a `read`/`edit` target with glob characters (`*`, `?`, `[...]`, `{a,b}`) into the matching
+/// files, sorted. `None` means a literal path: one without glob characters, one that exists as
+/// written, or a remote, URL, S3, or archive target.
+pub(crate) fn glob_targets(path: &std::path::Path, remote: Option<&str>) -> Result<Option<Vec<PathBuf>>> {
+    const GLOB_CHARS: [char; 4] = ['*', '?', '[', '{'];
+    let Some(pattern) = path.to_str() else { return Ok(None) };
+    if remote.is_some()
+        || !pattern.contains(GLOB_CHARS)
+        || path.exists()
+        || is_url(path)
+        || pattern.starts_with("s3://")
+        || split_archive_target(path).is_some()
+    {
+        return Ok(None);
+    }
+    let pattern = pattern.replace(std::path::MAIN_SEPARATOR, "/");
+    let pattern = pattern.strip_prefix("./").unwrap_or(&pattern);
+    // Walk only below the directories named literally at the front of the pattern.
+    let literal: Vec<&str> = pattern.split('/').take_while(|seg| !seg.contains(GLOB_CHARS)).collect();
+    let base = match literal.join("/") {
+        b if b.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        b => b,
+    };
+    let dir = std::path::Path::new(if base.is_empty() { "." } else { &base });
+    let mut files = Vec::new();
+    if dir.is_dir() {
+        walk_files(dir, &base, &mut files)?;
+    }
+    let patterns = expand_braces(pattern);
+    let mut matches: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|f| patterns.iter().any(|p| glob_match(p.as_bytes(), f.as_bytes())))
+        .map(PathBuf::from)
+        .collect();
+    if matches.is_empty() {
+        bail!("no files match {}", pattern);
+    }
+    matches.sort();
+    Ok(Some(matches))
+}
+
+/// Every file below `dir`, as `/`-joined paths starting with `prefix`. Skips `.git` and `.hashline`
+/// and does not follow symlinked directories.
+fn walk_files(dir: &std::path::Path, prefix: &str, out: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to list {}", dir.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let rel = match prefix {
+            "" => name.clone(),
+            p if p.ends_with('/') => format!("{}{}", p, name),
+            p => format!("{}/{}", p, name),
+        };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if name != ".git" && name != ".hashline" {
+                walk_files(&entry.path(), &rel, out)?;
+            }
+        } else if file_type.is_file() || entry.path().is_file() {
+            out.push(rel);
+        }
+    }
+    Ok(())
+}
+
+/// One line of a `.gitignore` or `.hashlineignore`, relative to the directory holding the file.
+struct IgnoreRule {
+    base: PathBuf,
+    pattern: String,
+    negate: bool,
+    dir_only: bool,
+}
+
+/// Parse gitignore syntax: `#` comments, `!` negation, a trailing `/` for directories only, and
+/// patterns with an inner or leading `/` anchored at `base` (others match at any depth).
+fn parse_ignore_file(base: &std::path::Path, text: &str) -> Vec<IgnoreRule> {
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let pattern = match line.strip_prefix('/') {
+            Some(anchored) => anchored.to_string(),
+            None if line.contains('/') => line.to_string(),
+            None => format!("**/{}", line),
+        };
+        rules.push(IgnoreRule { base: base.to_path_buf(), pattern, negate, dir_only });
+    }
+    rules
+}
+
+/// Rules from `.gitignore` and `.hashlineignore` in `dir`, in that order.
+fn load_ignore_rules(dir: &std::path::Path) -> Vec<IgnoreRule> {
+    [".gitignore", ".hashlineignore"]
+        .iter()
+        .filter_map(|name| fs::read_to_string(dir.join(name)).ok())
+        .flat_map(|text| parse_ignore_file(dir, &text))
+        .collect()
+}
+
+/// The last rule matching `path` decides, as in git.
+fn is_ignored(rules: &[IgnoreRule], path: &std::path::Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for rule in rules {
+        if rule.dir_only && !is_dir {
+            continue;
+        }
+        let Ok(rel) = path.strip_prefix(&rule.base) else { continue };
+        let rel = rel.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/");
+        if expand_braces(&rule.pattern).iter().any(|p| glob_match(p.as_bytes(), rel.as_bytes())) {
+            ignored = !rule.negate;
+        }
+    }
+    ignored
+}
+
+/// Every file below `root` that the ignore files don't exclude, sorted. Ignore files in the
+/// directories between `root` and its enclosing git work tree apply too.
+pub(crate) fn walk_unignored(root: &std::path::Path) -> Result<Vec<PathBuf>> {
+    let abs = root.canonicalize().with_context(|| format!("failed to resolve {}", root.display()))?;
+    let mut rules = Vec::new();
+    if let Some(top) = abs.ancestors().find(|d| d.join(".git").exists()) {
+        let mut outer: Vec<&std::path::Path> = abs.ancestors().skip(1).take_while(|d| d.starts_with(top)).collect();
+        outer.reverse();
+        for dir in outer {
+            rules.extend(load_ignore_rules(dir));
+        }
+    }
+    let mut out = Vec::new();
+    let root = if root == std::path::Path::new(".") { std::path::Path::new("") } else { root };
+    walk_unignored_dir(root, &abs, &mut rules, &mut out)?;
+    out.sort();
+    Ok(out)
+}
+
+fn walk_unignored_dir(
+    dir: &std::path::Path,
+    abs: &std::path::Path,
+    rules: &mut Vec<IgnoreRule>,
+    out: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let inherited = rules.len();
+    rules.extend(load_ignore_rules(abs));
+    let listed = if dir.as_os_str().is_empty() { std::path::Path::new(".") } else { dir };
+    for entry in fs::read_dir(listed).with_context(|| format!("failed to list {}", listed.display()))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let (path, entry_abs) = (dir.join(&name), abs.join(&name));
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if name != ".git" && name != ".hashline" && !is_ignored(rules, &entry_abs, true) {
+                walk_unignored_dir(&path, &entry_abs, rules, out)?;
+            }
+        } else if (file_type.is_file() || path.is_file()) && !is_ignored(rules, &entry_abs, false) {
+            out.push(path);
+        }
+    }
+    rules.truncate(inherited);
+    Ok(())
+}